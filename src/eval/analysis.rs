@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use crate::eval::{DynamicContext, Environment, ErrorInfo, Object};
+use crate::eval::expression::{Expression, IndexHint};
+use crate::eval::sequence_type::{ItemType, QNameToTypes, SequenceType, XS_NUMERIC};
+use crate::fns::Param;
+use crate::namespaces::XPATH_FUNCTIONS;
+use crate::parser::errors::ErrorCode;
+use crate::parser::op::Statement;
+use crate::values::{resolve_element_qname, resolve_function_qname, QName, QNameResolved, Types, QN};
+
+type Signature = (Vec<SequenceType>, SequenceType);
+
+pub(crate) fn single(name: QN) -> Option<SequenceType> {
+    Some(SequenceType::exactly_one(ItemType::AtomicOrUnionType(name.into())))
+}
+
+// names and types known before evaluation
+pub(crate) struct StaticContext {
+    env: Box<Environment>,
+    scopes: Vec<HashMap<QNameResolved, Option<SequenceType>>>,
+    functions: HashMap<(QNameResolved, usize), Signature>,
+    // first pass over prolog only collects declarations, so function bodies may call functions declared later
+    pub(crate) declarations_only: bool,
+}
+
+// checks program before evaluation: names of functions and variables, arities and obvious type errors
+pub(crate) fn analyze(statements: &[Statement], env: &Environment) -> Result<(), ErrorInfo> {
+    let mut ctx = StaticContext::new(env);
+
+    for statement in statements {
+        match statement {
+            Statement::Prolog(exprs) => {
+                ctx.declarations_only = true;
+                ctx.analyze_all(exprs)?;
+
+                ctx.declarations_only = false;
+                ctx.analyze_all(exprs)?;
+            }
+            Statement::Program(expr) => {
+                expr.analyze(&mut ctx)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+impl StaticContext {
+    pub(crate) fn new(env: &Environment) -> Self {
+        StaticContext {
+            env: Box::new(env.clone()),
+            scopes: vec![HashMap::new()],
+            functions: HashMap::new(),
+            declarations_only: false,
+        }
+    }
+
+    pub(crate) fn analyze_all(&mut self, exprs: &[Box<dyn Expression>]) -> Result<(), ErrorInfo> {
+        for expr in exprs {
+            expr.analyze(self)?;
+        }
+        Ok(())
+    }
+
+    // evaluate declaration which changes static context, like namespace declaration
+    pub(crate) fn apply(&mut self, expr: &dyn Expression) -> Result<(), ErrorInfo> {
+        let (env, _) = expr.eval(self.env.clone(), &DynamicContext::nothing())?;
+        self.env = env;
+        Ok(())
+    }
+
+    // value of expression which doesn't depend on dynamic context, like arity of named function reference
+    pub(crate) fn constant(&self, expr: &Box<dyn Expression>) -> Option<Object> {
+        match expr.index_hint() {
+            Some(IndexHint::Value) => {
+                expr.eval(self.env.clone(), &DynamicContext::nothing())
+                    .ok()
+                    .map(|(_, value)| value)
+            }
+            _ => None
+        }
+    }
+
+    fn check_prefix(&self, name: &QName) -> Result<(), ErrorInfo> {
+        if name.url.is_none() {
+            if let Some(prefix) = &name.prefix {
+                if self.env.namespaces.by_prefix(prefix).is_none() {
+                    return Err((ErrorCode::XPST0081, format!("unknown namespace prefix {:?}", prefix)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn resolve_element(&self, name: &QName) -> Result<QNameResolved, ErrorInfo> {
+        self.check_prefix(name)?;
+        Ok(resolve_element_qname(name, &self.env))
+    }
+
+    pub(crate) fn resolve_function(&self, name: &QName) -> Result<QNameResolved, ErrorInfo> {
+        self.check_prefix(name)?;
+        Ok(resolve_function_qname(name, &self.env))
+    }
+
+    pub(crate) fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    pub(crate) fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    pub(crate) fn declare_variable(&mut self, name: &QName, st: Option<SequenceType>) -> Result<(), ErrorInfo> {
+        let name = self.resolve_element(name)?;
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name, st);
+        }
+        Ok(())
+    }
+
+    // static type of variable, XPST0008 if it isn't in scope
+    pub(crate) fn variable(&self, name: &QName) -> Result<Option<SequenceType>, ErrorInfo> {
+        let name = self.resolve_element(name)?;
+        for scope in self.scopes.iter().rev() {
+            if let Some(st) = scope.get(&name) {
+                return Ok(st.clone());
+            }
+        }
+        if self.env.get_variable(&name).is_some() {
+            Ok(None)
+        } else {
+            Err((ErrorCode::XPST0008, format!("unknown variable {:?}", name)))
+        }
+    }
+
+    pub(crate) fn declare_function(&mut self, name: &QName, params: &[Param], st: &Option<SequenceType>) -> Result<(), ErrorInfo> {
+        let name = self.resolve_function(name)?;
+        let any = || SequenceType::zero_or_more(ItemType::Item);
+        let signature = (
+            params.iter()
+                .map(|param| param.sequence_type.clone().unwrap_or_else(any))
+                .collect(),
+            st.clone().unwrap_or_else(any)
+        );
+        self.functions.insert((name, params.len()), signature);
+        Ok(())
+    }
+
+    // signature of function, XPST0017 if there is no function with such name and arity
+    pub(crate) fn function(&self, name: &QName, arity: usize) -> Result<Option<Signature>, ErrorInfo> {
+        let resolved = self.resolve_function(name)?;
+
+        if let Some(signature) = self.functions.get(&(resolved.clone(), arity)) {
+            return Ok(Some(signature.clone()));
+        }
+        if let Some(function) = self.env.declared_functions(&resolved, arity) {
+            return Ok(Some(function.signature()));
+        }
+        if let Some((signature, _)) = self.env.get_function(&resolved, arity) {
+            return Ok(Some(signature));
+        }
+        // variadic functions
+        if resolved.url == *XPATH_FUNCTIONS.uri && arity >= 2
+            && (resolved.local_part == "concat" || resolved.local_part == "join")
+        {
+            return Ok(None);
+        }
+        // static call of function held by variable
+        if self.variable(name).is_ok() {
+            return Ok(None);
+        }
+        Err((ErrorCode::XPST0017, format!("no function {:?}#{:?}", resolved, arity)))
+    }
+
+    // raise XPTY0004 for obvious type errors only: empty sequence where item is required
+    // and string or boolean value where number is expected
+    pub(crate) fn check_type(&self, actual: &Option<SequenceType>, expected: &SequenceType) -> Result<(), ErrorInfo> {
+        let actual = match actual {
+            Some(actual) => actual,
+            None => return Ok(())
+        };
+
+        let is_error = match (&actual.item_type, &expected.item_type) {
+            (ItemType::SequenceEmpty, ItemType::SequenceEmpty) => false,
+            (ItemType::SequenceEmpty, _) => !expected.occurrence_indicator.allows_empty(),
+            (actual_type, expected_type) => {
+                match (self.atomic_type(actual_type), self.atomic_type(expected_type)) {
+                    (Some(actual_type), Some(expected_type)) => {
+                        expected_type.derives_from(&Types::Numeric)
+                            && (actual_type.derives_from(&Types::String) || actual_type == Types::Boolean)
+                    }
+                    _ => false
+                }
+            }
+        };
+
+        if is_error {
+            Err((ErrorCode::XPTY0004, format!("expected {:?}, got {:?}", expected, actual)))
+        } else {
+            Ok(())
+        }
+    }
+
+    // operand of arithmetic expression
+    pub(crate) fn check_numeric(&self, actual: &Option<SequenceType>) -> Result<(), ErrorInfo> {
+        let numeric = SequenceType::zero_or_one(ItemType::AtomicOrUnionType(XS_NUMERIC.into()));
+        self.check_type(actual, &numeric)
+    }
+
+    fn atomic_type(&self, item_type: &ItemType) -> Option<Types> {
+        match item_type {
+            ItemType::AtomicOrUnionType(name) => {
+                self.check_prefix(name).ok()?;
+                QNameToTypes.get(&self.env.namespaces.resolve(name)).cloned()
+            }
+            _ => None
+        }
+    }
+}
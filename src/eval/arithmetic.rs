@@ -1,8 +1,11 @@
 use crate::eval::{Object, EvalResult, atomization, Type, string_to_double, Environment, relax, ErrorInfo};
 use crate::parser::errors::ErrorCode;
 use ordered_float::OrderedFloat;
-use bigdecimal::{BigDecimal, ToPrimitive, FromPrimitive, Zero};
+use bigdecimal::{BigDecimal, ToPrimitive, Zero};
+use bigdecimal::num_bigint::BigInt;
 use crate::parser::op::OperatorArithmetic;
+use crate::values::time::Time;
+use chrono::{Date, DateTime, FixedOffset, Months, NaiveDate, NaiveDateTime, TimeZone};
 
 // XS_DOUBLE    XS_DOUBLE       > DOUBLE_DOUBLE
 // XS_DOUBLE    XS_FLOAT        > DOUBLE_FLOAT
@@ -56,6 +59,7 @@ pub(crate) trait Operand {
     fn level(&self) -> u8;
 
     fn to_integer(&self) -> i128;
+    fn to_big_integer(&self) -> BigInt;
     fn to_decimal(&self) -> Result<BigDecimal, ErrorCode>;
     fn to_float(&self) -> f32;
     fn to_double(&self) -> f64;
@@ -221,9 +225,10 @@ impl Operand for VDouble {
         self.number.is_zero()
     }
 
-    fn level(&self) -> u8 { 4 }
+    fn level(&self) -> u8 { 5 }
 
     fn to_integer(&self) -> i128 { panic!("internal error") }
+    fn to_big_integer(&self) -> BigInt { panic!("internal error") }
     fn to_decimal(&self) -> Result<BigDecimal, ErrorCode> { Err(ErrorCode::FOAR0002) }
     fn to_float(&self) -> f32 { panic!("internal error") }
     fn to_double(&self) -> f64 { self.number }
@@ -391,9 +396,10 @@ impl Operand for VFloat {
         self.number.is_zero()
     }
 
-    fn level(&self) -> u8 { 3 }
+    fn level(&self) -> u8 { 4 }
 
     fn to_integer(&self) -> i128 { panic!("internal error") }
+    fn to_big_integer(&self) -> BigInt { panic!("internal error") }
     fn to_decimal(&self) -> Result<BigDecimal, ErrorCode> { Err(ErrorCode::FOAR0002) }
     fn to_float(&self) -> f32 { self.number }
     fn to_double(&self) -> f64 { self.number as f64 }
@@ -591,9 +597,10 @@ impl Operand for VDecimal {
         self.number.is_zero()
     }
 
-    fn level(&self) -> u8 { 2 }
+    fn level(&self) -> u8 { 3 }
 
     fn to_integer(&self) -> i128 { panic!("internal error") }
+    fn to_big_integer(&self) -> BigInt { panic!("internal error") }
     fn to_decimal(&self) -> Result<BigDecimal, ErrorCode> { Ok(self.number.clone()) }
     fn to_float(&self) -> f32 { self.number.to_f32().unwrap() } // TODO: code it
     fn to_double(&self) -> f64 { self.number.to_f64().unwrap() } // TODO: code it
@@ -607,15 +614,30 @@ struct VInteger {
     number: i128
 }
 
+// result of integer operation, it's promoted to big integer on overflow of i128
+fn integer_result(number: Option<i128>, big: impl FnOnce() -> BigInt) -> OperandReturn {
+    match number {
+        Some(number) => Ok(Box::new(VInteger { number })),
+        None => Ok(big_integer(big()))
+    }
+}
+
+fn big_integer(number: BigInt) -> Box<dyn Operand> {
+    match number.to_i128() {
+        Some(number) => Box::new(VInteger { number }),
+        None => Box::new(VBigInteger { number })
+    }
+}
+
 impl Operand for VInteger {
     fn add(&self, rhs: &dyn Operand) -> OperandReturn {
         if self.level() >= rhs.level() {
             let other = rhs.to_integer();
 
-            match self.number.checked_add(other) {
-                Some(number) => Ok(Box::new(VInteger { number })),
-                None => Err(ErrorCode::FOAR0002)
-            }
+            integer_result(
+                self.number.checked_add(other),
+                || BigInt::from(self.number) + other
+            )
         } else {
             rhs.rev_add(self)
         }
@@ -625,10 +647,10 @@ impl Operand for VInteger {
         if self.level() >= rhs.level() {
             let other = rhs.to_integer();
 
-            match self.number.checked_add(other) {
-                Some(number) => Ok(Box::new(VInteger { number })),
-                None => Err(ErrorCode::FOAR0002)
-            }
+            integer_result(
+                other.checked_add(self.number),
+                || BigInt::from(other) + self.number
+            )
         } else {
             Err(ErrorCode::FOAR0002)
         }
@@ -638,10 +660,10 @@ impl Operand for VInteger {
         if self.level() >= rhs.level() {
             let other = rhs.to_integer();
 
-            match self.number.checked_sub(other) {
-                Some(number) => Ok(Box::new(VInteger { number })),
-                None => Err(ErrorCode::FOAR0002)
-            }
+            integer_result(
+                self.number.checked_sub(other),
+                || BigInt::from(self.number) - other
+            )
         } else {
             rhs.rev_sub(self)
         }
@@ -651,10 +673,10 @@ impl Operand for VInteger {
         if self.level() >= rhs.level() {
             let other = rhs.to_integer();
 
-            match other.checked_sub(self.number) {
-                Some(number) => Ok(Box::new(VInteger { number })),
-                None => Err(ErrorCode::FOAR0002)
-            }
+            integer_result(
+                other.checked_sub(self.number),
+                || BigInt::from(other) - self.number
+            )
         } else {
             Err(ErrorCode::FOAR0002)
         }
@@ -664,10 +686,10 @@ impl Operand for VInteger {
         if self.level() >= rhs.level() {
             let other = rhs.to_integer();
 
-            match self.number.checked_mul(other) {
-                Some(number) => Ok(Box::new(VInteger { number })),
-                None => Err(ErrorCode::FOAR0002)
-            }
+            integer_result(
+                self.number.checked_mul(other),
+                || BigInt::from(self.number) * other
+            )
         } else {
             rhs.rev_mul(self)
         }
@@ -677,10 +699,10 @@ impl Operand for VInteger {
         if self.level() >= rhs.level() {
             let other = rhs.to_integer();
 
-            match self.number.checked_mul(other) {
-                Some(number) => Ok(Box::new(VInteger { number })),
-                None => Err(ErrorCode::FOAR0002)
-            }
+            integer_result(
+                other.checked_mul(self.number),
+                || BigInt::from(other) * self.number
+            )
         } else {
             Err(ErrorCode::FOAR0002)
         }
@@ -725,15 +747,12 @@ impl Operand for VInteger {
             if rhs.is_zero() {
                 Err(ErrorCode::FOAR0001)
             } else {
-                let self_number = self.to_decimal()?;
-                let other = rhs.to_decimal()?;
+                let other = rhs.to_integer();
 
-                let number = self_number / other;
-                if let Some(number) = number.to_i128() {
-                    Ok(Box::new(VInteger { number }))
-                } else {
-                    Err(ErrorCode::FOAR0001)
-                }
+                integer_result(
+                    self.number.checked_div(other),
+                    || BigInt::from(self.number) / other
+                )
             }
         } else {
             rhs.rev_idiv(self)
@@ -742,18 +761,15 @@ impl Operand for VInteger {
 
     fn rev_idiv(&self, rhs: &dyn Operand) -> OperandReturn {
         if self.level() >= rhs.level() {
-            if rhs.is_zero() {
+            if self.is_zero() {
                 Err(ErrorCode::FOAR0001)
             } else {
-                let self_number = self.to_decimal()?;
-                let other = rhs.to_decimal()?;
+                let other = rhs.to_integer();
 
-                let number = (other / self_number);
-                if let Some(number) = number.to_i128() {
-                    Ok(Box::new(VInteger { number }))
-                } else {
-                    Err(ErrorCode::FOAR0001)
-                }
+                integer_result(
+                    other.checked_div(self.number),
+                    || BigInt::from(other) / self.number
+                )
             }
         } else {
             Err(ErrorCode::FOAR0002)
@@ -765,29 +781,29 @@ impl Operand for VInteger {
             if rhs.is_zero() {
                 Err(ErrorCode::FOAR0001)
             } else {
-                let self_number = self.to_integer();
                 let other = rhs.to_integer();
 
-                let number = self_number % other;
-
-                Ok(Box::new(VInteger { number }))
+                integer_result(
+                    self.number.checked_rem(other),
+                    || BigInt::from(self.number) % other
+                )
             }
         } else {
-            rhs.rev_div(self)
+            rhs.rev_remainder(self)
         }
     }
 
     fn rev_remainder(&self, rhs: &dyn Operand) -> OperandReturn {
         if self.level() >= rhs.level() {
-            if rhs.is_zero() {
+            if self.is_zero() {
                 Err(ErrorCode::FOAR0001)
             } else {
-                let self_number = self.to_integer();
                 let other = rhs.to_integer();
 
-                let number = other % self_number;
-
-                Ok(Box::new(VInteger { number }))
+                integer_result(
+                    other.checked_rem(self.number),
+                    || BigInt::from(other) % self.number
+                )
             }
         } else {
             Err(ErrorCode::FOAR0002)
@@ -795,8 +811,10 @@ impl Operand for VInteger {
     }
 
     fn negative(&self) -> OperandReturn {
-        let number = -self.number;
-        Ok(Box::new(VInteger { number }))
+        integer_result(
+            self.number.checked_neg(),
+            || -BigInt::from(self.number)
+        )
     }
 
     fn is_zero(&self) -> bool {
@@ -806,11 +824,9 @@ impl Operand for VInteger {
     fn level(&self) -> u8 { 1 }
 
     fn to_integer(&self) -> i128 { self.number }
+    fn to_big_integer(&self) -> BigInt { BigInt::from(self.number) }
     fn to_decimal(&self) -> Result<BigDecimal, ErrorCode> {
-        match BigDecimal::from_i128(self.number) {
-            Some(number) => Ok(number),
-            None => Err(ErrorCode::FOAR0002)
-        }
+        Ok(BigDecimal::from(BigInt::from(self.number)))
     }
     fn to_float(&self) -> f32 { self.number as f32 }
     fn to_double(&self) -> f64 { self.number as f64 }
@@ -820,10 +836,373 @@ impl Operand for VInteger {
     }
 }
 
-struct VDateTime {
+// xs:integer which doesn't fit i128
+struct VBigInteger {
+    number: BigInt
+}
+
+impl Operand for VBigInteger {
+    fn add(&self, rhs: &dyn Operand) -> OperandReturn {
+        if self.level() >= rhs.level() {
+            Ok(big_integer(&self.number + rhs.to_big_integer()))
+        } else {
+            rhs.rev_add(self)
+        }
+    }
+
+    fn rev_add(&self, rhs: &dyn Operand) -> OperandReturn {
+        if self.level() >= rhs.level() {
+            Ok(big_integer(rhs.to_big_integer() + &self.number))
+        } else {
+            Err(ErrorCode::FOAR0002)
+        }
+    }
+
+    fn sub(&self, rhs: &dyn Operand) -> OperandReturn {
+        if self.level() >= rhs.level() {
+            Ok(big_integer(&self.number - rhs.to_big_integer()))
+        } else {
+            rhs.rev_sub(self)
+        }
+    }
+
+    fn rev_sub(&self, rhs: &dyn Operand) -> OperandReturn {
+        if self.level() >= rhs.level() {
+            Ok(big_integer(rhs.to_big_integer() - &self.number))
+        } else {
+            Err(ErrorCode::FOAR0002)
+        }
+    }
+
+    fn mul(&self, rhs: &dyn Operand) -> OperandReturn {
+        if self.level() >= rhs.level() {
+            Ok(big_integer(&self.number * rhs.to_big_integer()))
+        } else {
+            rhs.rev_mul(self)
+        }
+    }
+
+    fn rev_mul(&self, rhs: &dyn Operand) -> OperandReturn {
+        if self.level() >= rhs.level() {
+            Ok(big_integer(rhs.to_big_integer() * &self.number))
+        } else {
+            Err(ErrorCode::FOAR0002)
+        }
+    }
+
+    fn div(&self, rhs: &dyn Operand) -> OperandReturn {
+        if self.level() >= rhs.level() {
+            if rhs.is_zero() {
+                Err(ErrorCode::FOAR0001)
+            } else {
+                let number = self.to_decimal()? / rhs.to_decimal()?;
+
+                Ok(Box::new(VDecimal { number }))
+            }
+        } else {
+            rhs.rev_div(self)
+        }
+    }
+
+    fn rev_div(&self, rhs: &dyn Operand) -> OperandReturn {
+        if self.level() >= rhs.level() {
+            let number = rhs.to_decimal()? / self.to_decimal()?;
+
+            Ok(Box::new(VDecimal { number }))
+        } else {
+            Err(ErrorCode::FOAR0002)
+        }
+    }
+
+    fn idiv(&self, rhs: &dyn Operand) -> OperandReturn {
+        if self.level() >= rhs.level() {
+            if rhs.is_zero() {
+                Err(ErrorCode::FOAR0001)
+            } else {
+                Ok(big_integer(&self.number / rhs.to_big_integer()))
+            }
+        } else {
+            rhs.rev_idiv(self)
+        }
+    }
+
+    fn rev_idiv(&self, rhs: &dyn Operand) -> OperandReturn {
+        if self.level() >= rhs.level() {
+            Ok(big_integer(rhs.to_big_integer() / &self.number))
+        } else {
+            Err(ErrorCode::FOAR0002)
+        }
+    }
+
+    fn remainder(&self, rhs: &dyn Operand) -> OperandReturn {
+        if self.level() >= rhs.level() {
+            if rhs.is_zero() {
+                Err(ErrorCode::FOAR0001)
+            } else {
+                Ok(big_integer(&self.number % rhs.to_big_integer()))
+            }
+        } else {
+            rhs.rev_remainder(self)
+        }
+    }
+
+    fn rev_remainder(&self, rhs: &dyn Operand) -> OperandReturn {
+        if self.level() >= rhs.level() {
+            Ok(big_integer(rhs.to_big_integer() % &self.number))
+        } else {
+            Err(ErrorCode::FOAR0002)
+        }
+    }
+
+    fn negative(&self) -> OperandReturn {
+        Ok(big_integer(-&self.number))
+    }
+
+    fn is_zero(&self) -> bool {
+        // zero always fits i128
+        false
+    }
+
+    fn level(&self) -> u8 { 2 }
+
+    fn to_integer(&self) -> i128 { panic!("internal error") }
+    fn to_big_integer(&self) -> BigInt { self.number.clone() }
+    fn to_decimal(&self) -> Result<BigDecimal, ErrorCode> { Ok(BigDecimal::from(self.number.clone())) }
+    fn to_float(&self) -> f32 { self.number.to_f32().unwrap_or(f32::NAN) }
+    fn to_double(&self) -> f64 { self.number.to_f64().unwrap_or(f64::NAN) }
+
+    fn to_atomic(&self) -> Type {
+        Type::BigInteger(self.number.clone())
+    }
+}
+
+const MILLISECONDS_PER_DAY: i64 = 86_400_000;
+
+// operand of date, time or duration arithmetic
+enum Temporal {
+    DateTime(DateTime<FixedOffset>, bool),
+    Date(Date<FixedOffset>, bool),
+    Time(Time<FixedOffset>, bool),
+    // signed number of months
+    YearMonth(i64),
+    // signed number of milliseconds
+    DayTime(i64),
+    Number(f64),
+}
+
+impl Temporal {
+    fn from(t: &Type) -> Result<Temporal, ErrorInfo> {
+        match t {
+            Type::DateTime { dt, offset } => Ok(Temporal::DateTime(dt.clone(), *offset)),
+            Type::Date { date, offset } => Ok(Temporal::Date(date.clone(), *offset)),
+            Type::Time { time, offset } => Ok(Temporal::Time(time.clone(), *offset)),
+            Type::YearMonthDuration { positive, years, months } => {
+                let months = *years as i64 * 12 + *months as i64;
+                Ok(Temporal::YearMonth(if *positive { months } else { -months }))
+            }
+            Type::DayTimeDuration { positive, days, hours, minutes, seconds, microseconds } => {
+                let ms = *microseconds as i64 + 1000 * (*seconds as i64 + 60 * (*minutes as i64 + 60 * (*hours as i64 + 24 * *days as i64)));
+                Ok(Temporal::DayTime(if *positive { ms } else { -ms }))
+            }
+            // xs:duration itself has no arithmetic, only its subtypes
+            Type::Duration { .. } => Err((ErrorCode::XPTY0004, format!("arithmetic on {:?}", t))),
+            _ => Ok(Temporal::Number(type_to_number(t.clone())?.to_double()))
+        }
+    }
+}
+
+// values without timezone are taken to be in implicit timezone
+fn instant(dt: &DateTime<FixedOffset>, offset: bool, implicit_timezone: &FixedOffset) -> NaiveDateTime {
+    if offset {
+        dt.naive_utc()
+    } else {
+        dt.naive_local() - chrono::Duration::seconds(implicit_timezone.local_minus_utc() as i64)
+    }
+}
+
+// times are compared as dateTimes on the same reference date
+fn time_instant(time: &Time<FixedOffset>, offset: bool, implicit_timezone: &FixedOffset) -> NaiveDateTime {
+    let dt = NaiveDate::from_ymd(1972, 12, 31).and_time(time.time);
+    let offset = if offset { &time.offset } else { implicit_timezone };
+    dt - chrono::Duration::seconds(offset.local_minus_utc() as i64)
+}
+
+pub(crate) fn year_month_duration(months: i64) -> Result<Type, ErrorInfo> {
+    let positive = months >= 0;
+    let months = months.unsigned_abs();
+    match u32::try_from(months / 12) {
+        Ok(years) => Ok(Type::YearMonthDuration { positive, years, months: (months % 12) as u32 }),
+        Err(_) => Err((ErrorCode::FODT0002, String::from("yearMonthDuration overflow")))
+    }
+}
+
+pub(crate) fn day_time_duration(ms: i64) -> Result<Type, ErrorInfo> {
+    let positive = ms >= 0;
+    let ms = ms.unsigned_abs() as i64;
+    match u32::try_from(ms / MILLISECONDS_PER_DAY) {
+        Ok(days) => {
+            let ms = ms % MILLISECONDS_PER_DAY;
+            Ok(Type::DayTimeDuration {
+                positive,
+                days,
+                hours: (ms / 3_600_000) as u32,
+                minutes: (ms / 60_000 % 60) as u32,
+                seconds: (ms / 1000 % 60) as u32,
+                microseconds: (ms % 1000) as u32,
+            })
+        }
+        Err(_) => Err((ErrorCode::FODT0002, String::from("dayTimeDuration overflow")))
+    }
 }
 
-struct VDuration {
+// duration multiplied or divided by number, rounded half up to the unit of duration
+fn scale(value: i64, factor: f64) -> Result<i64, ErrorInfo> {
+    if factor.is_nan() {
+        return Err((ErrorCode::FOCA0005, String::from("NaN factor of duration")));
+    }
+    let result = (value as f64 * factor + 0.5).floor();
+    if result.is_finite() && result.abs() < i64::MAX as f64 {
+        Ok(result as i64)
+    } else {
+        Err((ErrorCode::FODT0002, String::from("duration overflow")))
+    }
+}
+
+fn duration_ratio(left: i64, right: i64) -> Result<Type, ErrorInfo> {
+    if right == 0 {
+        Err((ErrorCode::FOAR0001, String::from("division by zero duration")))
+    } else {
+        Ok(Type::Decimal(BigDecimal::from(left) / BigDecimal::from(right)))
+    }
+}
+
+fn add_months(dt: &DateTime<FixedOffset>, months: i64) -> Option<DateTime<FixedOffset>> {
+    let local = dt.naive_local();
+    let date = if months >= 0 {
+        local.date().checked_add_months(Months::new(u32::try_from(months).ok()?))?
+    } else {
+        local.date().checked_sub_months(Months::new(u32::try_from(-months).ok()?))?
+    };
+    dt.offset().from_local_datetime(&date.and_time(local.time())).single()
+}
+
+fn add_milliseconds(dt: &DateTime<FixedOffset>, ms: i64) -> Option<DateTime<FixedOffset>> {
+    dt.checked_add_signed(chrono::Duration::milliseconds(ms))
+}
+
+fn date_time_overflow() -> ErrorInfo {
+    (ErrorCode::FODT0001, String::from("dateTime overflow"))
+}
+
+pub(crate) fn is_temporal(t: &Type) -> bool {
+    match t {
+        Type::DateTime { .. } |
+        Type::Date { .. } |
+        Type::Time { .. } |
+        Type::Duration { .. } |
+        Type::YearMonthDuration { .. } |
+        Type::DayTimeDuration { .. } => true,
+        _ => false
+    }
+}
+
+// operators on dates, times and durations (op:add-yearMonthDurations, op:subtract-dates and others)
+pub(crate) fn eval_temporal(operator: OperatorArithmetic, left: &Type, right: &Type, implicit_timezone: &FixedOffset) -> Result<Type, ErrorInfo> {
+    use OperatorArithmetic::*;
+
+    let left = Temporal::from(left)?;
+    let right = Temporal::from(right)?;
+
+    match (operator, left, right) {
+        (Plus, Temporal::YearMonth(l), Temporal::YearMonth(r)) => year_month_duration(l + r),
+        (Minus, Temporal::YearMonth(l), Temporal::YearMonth(r)) => year_month_duration(l - r),
+        (Multiply, Temporal::YearMonth(d), Temporal::Number(n)) |
+        (Multiply, Temporal::Number(n), Temporal::YearMonth(d)) => year_month_duration(scale(d, n)?),
+        (Divide, Temporal::YearMonth(d), Temporal::Number(n)) => {
+            if n == 0.0 {
+                Err((ErrorCode::FODT0002, String::from("division of duration by zero")))
+            } else {
+                year_month_duration(scale(d, 1.0 / n)?)
+            }
+        }
+        (Divide, Temporal::YearMonth(l), Temporal::YearMonth(r)) => duration_ratio(l, r),
+
+        (Plus, Temporal::DayTime(l), Temporal::DayTime(r)) => day_time_duration(l + r),
+        (Minus, Temporal::DayTime(l), Temporal::DayTime(r)) => day_time_duration(l - r),
+        (Multiply, Temporal::DayTime(d), Temporal::Number(n)) |
+        (Multiply, Temporal::Number(n), Temporal::DayTime(d)) => day_time_duration(scale(d, n)?),
+        (Divide, Temporal::DayTime(d), Temporal::Number(n)) => {
+            if n == 0.0 {
+                Err((ErrorCode::FODT0002, String::from("division of duration by zero")))
+            } else {
+                day_time_duration(scale(d, 1.0 / n)?)
+            }
+        }
+        (Divide, Temporal::DayTime(l), Temporal::DayTime(r)) => duration_ratio(l, r),
+
+        (Minus, Temporal::DateTime(l, l_offset), Temporal::DateTime(r, r_offset)) => {
+            day_time_duration((instant(&l, l_offset, implicit_timezone) - instant(&r, r_offset, implicit_timezone)).num_milliseconds())
+        }
+        (Minus, Temporal::Date(l, l_offset), Temporal::Date(r, r_offset)) => {
+            let l = instant(&l.and_hms(0, 0, 0), l_offset, implicit_timezone);
+            let r = instant(&r.and_hms(0, 0, 0), r_offset, implicit_timezone);
+            day_time_duration((l - r).num_milliseconds())
+        }
+        (Minus, Temporal::Time(l, l_offset), Temporal::Time(r, r_offset)) => {
+            day_time_duration((time_instant(&l, l_offset, implicit_timezone) - time_instant(&r, r_offset, implicit_timezone)).num_milliseconds())
+        }
+
+        (Plus, Temporal::DateTime(dt, offset), Temporal::YearMonth(months)) |
+        (Plus, Temporal::YearMonth(months), Temporal::DateTime(dt, offset)) => {
+            let dt = add_months(&dt, months).ok_or_else(date_time_overflow)?;
+            Ok(Type::DateTime { dt, offset })
+        }
+        (Minus, Temporal::DateTime(dt, offset), Temporal::YearMonth(months)) => {
+            let dt = add_months(&dt, -months).ok_or_else(date_time_overflow)?;
+            Ok(Type::DateTime { dt, offset })
+        }
+        (Plus, Temporal::DateTime(dt, offset), Temporal::DayTime(ms)) |
+        (Plus, Temporal::DayTime(ms), Temporal::DateTime(dt, offset)) => {
+            let dt = add_milliseconds(&dt, ms).ok_or_else(date_time_overflow)?;
+            Ok(Type::DateTime { dt, offset })
+        }
+        (Minus, Temporal::DateTime(dt, offset), Temporal::DayTime(ms)) => {
+            let dt = add_milliseconds(&dt, -ms).ok_or_else(date_time_overflow)?;
+            Ok(Type::DateTime { dt, offset })
+        }
+
+        (Plus, Temporal::Date(date, offset), Temporal::YearMonth(months)) |
+        (Plus, Temporal::YearMonth(months), Temporal::Date(date, offset)) => {
+            let dt = add_months(&date.and_hms(0, 0, 0), months).ok_or_else(date_time_overflow)?;
+            Ok(Type::Date { date: dt.date(), offset })
+        }
+        (Minus, Temporal::Date(date, offset), Temporal::YearMonth(months)) => {
+            let dt = add_months(&date.and_hms(0, 0, 0), -months).ok_or_else(date_time_overflow)?;
+            Ok(Type::Date { date: dt.date(), offset })
+        }
+        (Plus, Temporal::Date(date, offset), Temporal::DayTime(ms)) |
+        (Plus, Temporal::DayTime(ms), Temporal::Date(date, offset)) => {
+            let dt = add_milliseconds(&date.and_hms(0, 0, 0), ms).ok_or_else(date_time_overflow)?;
+            Ok(Type::Date { date: dt.date(), offset })
+        }
+        (Minus, Temporal::Date(date, offset), Temporal::DayTime(ms)) => {
+            let dt = add_milliseconds(&date.and_hms(0, 0, 0), -ms).ok_or_else(date_time_overflow)?;
+            Ok(Type::Date { date: dt.date(), offset })
+        }
+
+        // time wraps around midnight, days of duration are ignored
+        (Plus, Temporal::Time(time, offset), Temporal::DayTime(ms)) |
+        (Plus, Temporal::DayTime(ms), Temporal::Time(time, offset)) => {
+            let (t, _) = time.time.overflowing_add_signed(chrono::Duration::milliseconds(ms % MILLISECONDS_PER_DAY));
+            Ok(Type::Time { time: Time::from(t, time.offset), offset })
+        }
+        (Minus, Temporal::Time(time, offset), Temporal::DayTime(ms)) => {
+            let (t, _) = time.time.overflowing_sub_signed(chrono::Duration::milliseconds(ms % MILLISECONDS_PER_DAY));
+            Ok(Type::Time { time: Time::from(t, time.offset), offset })
+        }
+
+        (operator, _, _) => Err((ErrorCode::XPTY0004, format!("{:?} isn't defined for these operands", operator)))
+    }
 }
 
 // TODO: delete
@@ -873,6 +1252,13 @@ pub fn eval_arithmetic_item<'a>(env: Box<Environment>, operator: OperatorArithme
     let left = atomization(&env, left)?;
     let right = atomization(&env, right)?;
 
+    if let (Object::Atomic(l), Object::Atomic(r)) = (&left, &right) {
+        if is_temporal(l) || is_temporal(r) {
+            let result = eval_temporal(operator, l, r, &env.implicit_timezone())?;
+            return Ok((env, Object::Atomic(result)));
+        }
+    }
+
     let left_value = object_to_number(left)?;
     let right_value = object_to_number(right)?;
 
@@ -899,6 +1285,21 @@ pub fn eval_unary(env: Box<Environment>, object: Object, sign_is_positive: bool)
         Err(e) => return Err(e)
     };
 
+    match &object {
+        Object::Atomic(Type::YearMonthDuration { positive, years, months }) => {
+            let t = Type::YearMonthDuration { positive: *positive == sign_is_positive, years: *years, months: *months };
+            return Ok((env, Object::Atomic(t)));
+        }
+        Object::Atomic(Type::DayTimeDuration { positive, days, hours, minutes, seconds, microseconds }) => {
+            let t = Type::DayTimeDuration {
+                positive: *positive == sign_is_positive,
+                days: *days, hours: *hours, minutes: *minutes, seconds: *seconds, microseconds: *microseconds
+            };
+            return Ok((env, Object::Atomic(t)));
+        }
+        _ => {}
+    }
+
     let value = object_to_number(object)?;
 
     if sign_is_positive {
@@ -932,14 +1333,8 @@ pub(crate) fn type_to_number(t: Type) -> Result<Box<dyn Operand>, ErrorInfo> {
                 _ => Err((ErrorCode::FORG0001, String::from("TODO")))
             }
         }
-        // Type::dateTime() => {}
-        // Type::dateTimeStamp() => {}
-        // Type::Date { .. } => {}
-        // Type::Time { .. } => {}
-        // Type::Duration { .. } => {}
-        // Type::YearMonthDuration { .. } => {}
-        // Type::DayTimeDuration { .. } => {}
         Type::Integer(number) => Ok(Box::new( VInteger { number } )),
+        Type::BigInteger(number) => Ok(Box::new( VBigInteger { number } )),
         Type::Decimal(number) => Ok(Box::new( VDecimal { number } )),
         Type::Float(number) => Ok(Box::new( VFloat { number: number.into_inner() } )),
         Type::Double(number) => Ok(Box::new( VDouble { number: number.into_inner() } )),
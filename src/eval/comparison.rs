@@ -4,8 +4,9 @@ use std::cmp::Ordering;
 use crate::parser::op::{Comparison, OperatorComparison};
 use crate::eval::arithmetic::object_to_items;
 use crate::parser::errors::ErrorCode;
-use crate::values::{QName, Types};
+use crate::values::{QName, Types, Collation, typed_value_of_node};
 use crate::tree::Reference;
+use crate::eval::helpers::flat_items;
 
 type ObjectInEnv<'a> = (&'a Box<Environment>, Object);
 type ObjectRefInEnv<'a> = (&'a Box<Environment>, &'a Object);
@@ -274,6 +275,7 @@ fn is_untyped(value: &Type) -> bool {
 fn is_numeric(value: &Type) -> bool {
     match value {
         Type::Integer(..) |
+        Type::BigInteger(..) |
         Type::Decimal(..) |
         Type::Float(..) |
         Type::Double(..) => true,
@@ -281,12 +283,97 @@ fn is_numeric(value: &Type) -> bool {
     }
 }
 
-pub(crate) fn value_comparison_for_types(op: Comparison, left: &Type, right: &Type) -> Result<bool, ErrorInfo> {
-    let cmp_result = left.value_comparison(right)?;
+pub(crate) fn string_value(value: &Type) -> Option<&str> {
+    match value {
+        Type::Untyped(str) |
+        Type::String(str) |
+        Type::NormalizedString(str) |
+        Type::AnyURI(str) => Some(str),
+        _ => None
+    }
+}
+
+// strings are compared by collation, other values by their types
+pub(crate) fn collation_comparison(collation: &dyn Collation, left: &Type, right: &Type) -> Result<ValueOrdering, ErrorInfo> {
+    match (string_value(left), string_value(right)) {
+        (Some(l_str), Some(r_str)) => Ok(ValueOrdering::from(collation.compare(l_str, r_str))),
+        _ => left.value_comparison(right)
+    }
+}
+
+fn default_collation_comparison(env: &Box<Environment>, left: &Type, right: &Type) -> Result<ValueOrdering, ErrorInfo> {
+    if string_value(left).is_some() && string_value(right).is_some() {
+        let collation = env.collation(None)?;
+        collation_comparison(collation.as_ref(), left, right)
+    } else {
+        left.value_comparison(right)
+    }
+}
+
+// atomized items of value, used as sort key
+pub(crate) fn sort_key(env: &Box<Environment>, value: Object) -> Result<Vec<Type>, ErrorInfo> {
+    let mut key = vec![];
+    for item in flat_items(value) {
+        match item {
+            Object::Atomic(t) => key.push(t),
+            Object::Node(rf) => key.extend(rf.to_typed_value()?),
+            Object::Array(members) => {
+                for member in members {
+                    key.extend(sort_key(env, member)?);
+                }
+            }
+            _ => return Err((ErrorCode::FOTY0013, format!("can't atomize {:?}", item)))
+        }
+    }
+    Ok(key)
+}
+
+// NaN is less than other values, values of incomparable types raise XPTY0004
+pub(crate) fn sort_order(collation: &dyn Collation, left: &Type, right: &Type) -> Result<Ordering, ErrorInfo> {
+    match (left.is_nan(), right.is_nan()) {
+        (true, true) => Ok(Ordering::Equal),
+        (true, false) => Ok(Ordering::Less),
+        (false, true) => Ok(Ordering::Greater),
+        (false, false) => match collation_comparison(collation, left, right)? {
+            ValueOrdering::Less => Ok(Ordering::Less),
+            ValueOrdering::Equal |
+            ValueOrdering::QNameEqual => Ok(Ordering::Equal),
+            ValueOrdering::Greater => Ok(Ordering::Greater),
+            ValueOrdering::QNameNotEqual |
+            ValueOrdering::AlwaysNotEqual => Err((ErrorCode::XPTY0004, format!("{:?} and {:?} are not ordered", left, right))),
+        }
+    }
+}
+
+// stable sort of items by keys, keys are compared item by item
+pub(crate) fn sort_by_keys<T>(collation: &dyn Collation, items: &mut Vec<(Vec<Type>, T)>) -> Result<(), ErrorInfo> {
+    let mut error = None;
+    items.sort_by(|(left, _), (right, _)| {
+        for (l, r) in left.iter().zip(right.iter()) {
+            match sort_order(collation, l, r) {
+                Ok(Ordering::Equal) => {}
+                Ok(ordering) => return ordering,
+                Err(e) => {
+                    error.get_or_insert(e);
+                    return Ordering::Equal;
+                }
+            }
+        }
+        left.len().cmp(&right.len())
+    });
+
+    match error {
+        Some(e) => Err(e),
+        None => Ok(())
+    }
+}
+
+pub(crate) fn value_comparison_for_types(env: &Box<Environment>, op: Comparison, left: &Type, right: &Type) -> Result<bool, ErrorInfo> {
+    let cmp_result = default_collation_comparison(env, left, right)?;
     op.is_it(cmp_result)
 }
 
-fn general_comparison_for_types(op: &OperatorComparison, left: &Type, right: &Type) -> Result<bool, ErrorInfo> {
+fn general_comparison_for_types(env: &Box<Environment>, op: &OperatorComparison, left: &Type, right: &Type) -> Result<bool, ErrorInfo> {
     let is_untyped_left = is_untyped(left);
     let is_untyped_right = is_untyped(right);
     if is_untyped_left != is_untyped_right {
@@ -296,17 +383,17 @@ fn general_comparison_for_types(op: &OperatorComparison, left: &Type, right: &Ty
             } else {
                 left.convert(right.to_type())?
             };
-            value_comparison_for_types(op.to_comparison(), &l, right)
+            value_comparison_for_types(env, op.to_comparison(), &l, right)
         } else {
             let r = if is_numeric(left) {
                 right.convert(Types::Double)?
             } else {
                 right.convert(left.to_type())?
             };
-            value_comparison_for_types(op.to_comparison(), left, &r)
+            value_comparison_for_types(env, op.to_comparison(), left, &r)
         }
     } else {
-        value_comparison_for_types(op.to_comparison(), left, right)
+        value_comparison_for_types(env, op.to_comparison(), left, right)
     }
 }
 
@@ -343,7 +430,7 @@ fn value_comparison(op: &OperatorComparison, left: ObjectRefInEnv, right: Object
             match right.1 {
                 Object::Empty => Ok(Object::Empty),
                 Object::Atomic(rt) => {
-                    let cmp_result = lt.value_comparison(rt)?;
+                    let cmp_result = default_collation_comparison(left.0, lt, rt)?;
                     let value = op.to_comparison().is_it(cmp_result)?;
                     Ok(Object::Atomic(Type::Boolean(value)))
                 },
@@ -363,32 +450,26 @@ pub(crate) fn general_comparison(op: &OperatorComparison, left: ObjectRefInEnv,
             match right.1 {
                 Object::Empty => Ok(false),
                 Object::Atomic(rt) => {
-                    general_comparison_for_types(op, lt, rt)
+                    general_comparison_for_types(left.0, op, lt, rt)
                 }
                 Object::Range { min, max } => {
                     type_in_range(lt, min, max)
                 }
                 Object::Node(r_rf) => {
-                    let rv = match r_rf.to_typed_value() {
-                        Ok(data) => Type::Untyped(data),
-                        Err(msg) => return Err((ErrorCode::TODO, msg))
-                    };
-                    general_comparison_for_types(op, lt, &rv)
+                    let rv = typed_value_of_node(r_rf)?;
+                    general_comparison(op, left, (right.0, &rv))
                 }
                 Object::Sequence(items) => {
                     for item in items {
                         match item {
                             Object::Atomic(rt) => {
-                                if general_comparison_for_types(op, lt, rt)? {
+                                if general_comparison_for_types(left.0, op, lt, rt)? {
                                     return Ok(true);
                                 }
                             },
                             Object::Node(rf) => {
-                                let rv = match rf.to_typed_value() {
-                                    Ok(data) => Type::Untyped(data),
-                                    Err(msg) => return Err((ErrorCode::TODO, msg))
-                                };
-                                if general_comparison_for_types(op, lt, &rv)? {
+                                let rv = typed_value_of_node(rf)?;
+                                if general_comparison(op, left, (right.0, &rv))? {
                                     return Ok(true);
                                 }
                             },
@@ -424,11 +505,13 @@ pub(crate) fn general_comparison(op: &OperatorComparison, left: ObjectRefInEnv,
                 _ => todo!() // panic!("{:?} vs {:?}", left.1, right.1) // Err((ErrorCode::XPTY0004, String::from("TODO")))
             }
         }
-        Object::Array(left_items) |
-        Object::Sequence(left_items) => {
+        Object::Array(..) |
+        Object::Sequence(..) => {
+            let left_items = sequence_items(left.1);
             match right.1 {
                 Object::Empty => Ok(false),
-                Object::Atomic(..) => {
+                Object::Atomic(..) |
+                Object::Node(..) => {
                     for lo in left_items {
                         if general_comparison(op, (left.0, lo), right)? {
                             return Ok(true);
@@ -436,10 +519,11 @@ pub(crate) fn general_comparison(op: &OperatorComparison, left: ObjectRefInEnv,
                     }
                     Ok(false)
                 },
-                Object::Array(right_items) |
-                Object::Sequence(right_items) => {
-                    for left_item in left_items {
-                        for right_item in right_items {
+                Object::Array(..) |
+                Object::Sequence(..) => {
+                    let right_items = sequence_items(right.1);
+                    for left_item in &left_items {
+                        for right_item in &right_items {
                             if general_comparison(op, (left.0, left_item), (right.0, &right_item))? {
                                 return Ok(true);
                             }
@@ -453,32 +537,15 @@ pub(crate) fn general_comparison(op: &OperatorComparison, left: ObjectRefInEnv,
         Object::Node(l_rf) => {
             match right.1 {
                 Object::Empty => Ok(false),
-                Object::Atomic(rt) => {
-                    match l_rf.to_typed_value() {
-                        Ok(l_str) => general_comparison_for_types(op, &Type::Untyped(l_str), rt),
-                        Err(msg) => Err((ErrorCode::XPTY0004, msg))
-                    }
+                Object::Node(r_rf) if l_rf.cmp(r_rf) == Ordering::Equal => {
+                    let cmp_result = ValueOrdering::from(Ordering::Equal);
+                    op.to_comparison().is_it(cmp_result)
                 }
-                Object::Node(r_rf) => {
-                    match l_rf.cmp(r_rf) {
-                        Ordering::Equal => {
-                            let cmp_result = ValueOrdering::from(Ordering::Equal);
-                            op.to_comparison().is_it(cmp_result)
-                        },
-                        _ => {
-                            let lv = match l_rf.to_typed_value() {
-                                Ok(data) => Type::Untyped(data),
-                                Err(msg) => return Err((ErrorCode::TODO, msg))
-                            };
-                            let rv = match r_rf.to_typed_value() {
-                                Ok(data) => Type::Untyped(data),
-                                Err(msg) => return Err((ErrorCode::TODO, msg))
-                            };
-                            general_comparison_for_types(op, &lv, &rv)
-                        }
-                    }
+                _ => {
+                    // typed values, untyped ones are compared as xs:untypedAtomic
+                    let lv = typed_value_of_node(l_rf)?;
+                    general_comparison(op, (left.0, &lv), right)
                 }
-                _ => todo!() // panic!("{:?} vs {:?}", left.1, right.1) // Err((ErrorCode::XPTY0004, String::from("TODO")))
             }
         }
         _ => todo!() // panic!("{:?} vs {:?}", left.1, right.1) // Err((ErrorCode::XPTY0004, String::from("TODO")))
@@ -486,6 +553,11 @@ pub(crate) fn general_comparison(op: &OperatorComparison, left: ObjectRefInEnv,
 }
 
 pub(crate) fn deep_eq(left: ObjectRefInEnv, right: ObjectRefInEnv) -> Result<bool, ErrorInfo> {
+    let collation = left.0.collation(None)?;
+    deep_eq_with_collation(left, right, collation.as_ref())
+}
+
+pub(crate) fn deep_eq_with_collation(left: ObjectRefInEnv, right: ObjectRefInEnv, collation: &dyn Collation) -> Result<bool, ErrorInfo> {
     if left.1 == right.1 {
         Ok(true)
     } else {
@@ -496,10 +568,16 @@ pub(crate) fn deep_eq(left: ObjectRefInEnv, right: ObjectRefInEnv) -> Result<boo
                     _ => Ok(false)
                 }
             }
-            Object::Atomic(..) => {
+            Object::Atomic(lt) => {
                 match right.1 {
-                    Object::Atomic(..) => {
-                        Ok(eq(left, right)? || (left.1.is_nan() && right.1.is_nan()))
+                    Object::Atomic(rt) => {
+                        match collation_comparison(collation, lt, rt) {
+                            Ok(ValueOrdering::Equal) |
+                            Ok(ValueOrdering::QNameEqual) => Ok(true),
+                            // values of incomparable types are not equal
+                            Ok(..) | Err((ErrorCode::XPTY0004, ..)) => Ok(left.1.is_nan() && right.1.is_nan()),
+                            Err(e) => Err(e)
+                        }
                     }
                     _ => Ok(false)
                 }
@@ -530,7 +608,7 @@ pub(crate) fn deep_eq(left: ObjectRefInEnv, right: ObjectRefInEnv) -> Result<boo
                             loop {
                                 if let Some(left_item) = left_it.next() {
                                     if let Some(right_item) = right_it.next() {
-                                        if deep_eq((&left.0, left_item), (&right.0, right_item))? {
+                                        if !deep_eq_with_collation((&left.0, left_item), (&right.0, right_item), collation)? {
                                             return Ok(false);
                                         }
                                     } else {
@@ -628,4 +706,12 @@ fn object_to_qname_if_qname(obj: &Object) -> Option<QName> {
         }
         _ => None
     }
-}
\ No newline at end of file
+}
+// items of sequence or array
+fn sequence_items(object: &Object) -> Vec<&Object> {
+    match object {
+        Object::Array(items) => items.iter().collect(),
+        Object::Sequence(items) => items.iter().collect(),
+        _ => vec![object]
+    }
+}
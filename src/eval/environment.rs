@@ -1,12 +1,20 @@
 use std::collections::HashMap;
 use std::rc::Rc;
 use std::sync::{Mutex, MutexGuard};
-use crate::values::{QName, QNameResolved};
+use chrono::FixedOffset;
+use crate::values::{QName, QNameResolved, Collation, CODEPOINT_COLLATION, resolve_collation};
 use crate::eval::Object;
-use crate::eval::prolog::{BoundarySpace, ConstructionMode, DecimalFormatPropertyName, EmptyOrderMode, InheritMode, OrderingMode, PreserveMode};
-use crate::fns::{Function, FUNCTION, FunctionsRegister};
+use crate::eval::update::PendingUpdateList;
+use crate::eval::prolog::{BoundarySpace, ConstructionMode, EmptyOrderMode, InheritMode, OrderingMode, PreserveMode};
+use crate::fns::{DecimalFormat, Function, FUNCTION, FunctionsRegister, Languages, Localization};
 use crate::namespaces::*;
-use crate::tree::{InMemoryXMLTree, Reference, XMLTreeWriter};
+use crate::tree::{IndexConfig, InMemoryStorage, InMemoryXMLTree, Reference, XMLStorage, XMLTreeWriter};
+use crate::eval::ErrorInfo;
+use crate::eval::limits::{Cancellation, Guard, Limits};
+use crate::parser::errors::ErrorCode;
+
+// nesting of function calls at which evaluation fails with XPDY0130
+pub const DEFAULT_RECURSION_LIMIT: usize = 4096;
 
 #[derive(Clone)]
 pub struct Environment {
@@ -19,19 +27,45 @@ pub struct Environment {
     pub ordering_mode: Option<OrderingMode>,
     pub empty_order_mode: Option<EmptyOrderMode>,
     pub copy_namespaces: Option<(PreserveMode, InheritMode)>,
-    pub decimal_formats: Option<HashMap<Option<QName>, HashMap<DecimalFormatPropertyName, String>>>,
+    pub(crate) decimal_formats: Option<HashMap<Option<QNameResolved>, DecimalFormat>>,
 
     pub xml_tree: Rc<Mutex<Box<dyn XMLTreeWriter>>>,
+    pub storage: Rc<Mutex<Box<dyn XMLStorage>>>,
 
     pub namespaces: Namespaces,
     vars: HashMap<QNameResolved, Object>,
     pub functions: FunctionsRegister,
 
+    // only root ones are in use
+    pending_updates: PendingUpdateList,
+    documents: HashMap<String, Reference>,
+    indexes: Option<IndexConfig>,
+
     sequence: usize,
+
+    // nesting of function calls
+    depth: usize,
+    recursion_limit: usize,
+
+    // timezone of dates and times which don't have one
+    implicit_timezone: FixedOffset,
+
+    // languages of format functions
+    languages: Rc<Languages>,
+
+    // seed of random-number-generator without arguments
+    random_seed: u64,
+
+    guard: Rc<Guard>,
 }
 
 impl Environment {
+    // documents are kept in memory, access to files is given by create_with_storage
     pub fn create() -> Box<Self> {
+        Environment::create_with_storage(Box::new(InMemoryStorage::new()))
+    }
+
+    pub fn create_with_storage(storage: Box<dyn XMLStorage>) -> Box<Self> {
         Box::new(
             Environment {
                 prev: None,
@@ -46,17 +80,34 @@ impl Environment {
                 decimal_formats: None,
 
                 xml_tree: InMemoryXMLTree::create(1),
+                storage: Rc::new(Mutex::new(storage)),
 
                 namespaces: Namespaces::new(),
                 vars: HashMap::new(),
                 functions: FunctionsRegister::new(),
+                pending_updates: PendingUpdateList::new(),
+                documents: HashMap::new(),
+                indexes: None,
                 sequence: 1,
+                depth: 0,
+                recursion_limit: DEFAULT_RECURSION_LIMIT,
+                implicit_timezone: FixedOffset::east(0),
+                languages: Rc::new(Languages::new()),
+                random_seed: rand::random(),
+                guard: Rc::new(Guard::new(Limits::default(), Cancellation::default())),
             }
         )
     }
 
     pub fn next(mut self) -> Box<Environment> {
         let sequence = self.next_id();
+        let storage = self.storage.clone();
+        let depth = self.depth;
+        let recursion_limit = self.recursion_limit;
+        let implicit_timezone = self.implicit_timezone;
+        let languages = self.languages.clone();
+        let random_seed = self.random_seed;
+        let guard = self.guard.clone();
         Box::new(
             Environment {
                 prev: Some(Box::new(self)),
@@ -71,21 +122,114 @@ impl Environment {
                 decimal_formats: None,
 
                 xml_tree: InMemoryXMLTree::create(sequence),
+                storage,
 
                 namespaces: Namespaces::new(),
                 vars: HashMap::new(),
-                functions: FunctionsRegister::new(),
+                functions: FunctionsRegister::empty(),
+                pending_updates: PendingUpdateList::new(),
+                documents: HashMap::new(),
+                indexes: None,
                 sequence: 0,
+                depth,
+                recursion_limit,
+                implicit_timezone,
+                languages,
+                random_seed,
+                guard,
             }
         )
     }
 
+    // environment for body of called function, fails when calls are nested deeper than the limit
+    pub(crate) fn call_frame(self) -> Result<Box<Environment>, ErrorInfo> {
+        if self.depth >= self.recursion_limit {
+            return Err((ErrorCode::XPDY0130, format!("function calls are nested deeper than {}", self.recursion_limit)));
+        }
+        self.step()?;
+        let depth = self.depth + 1;
+
+        let mut env = self.next();
+        env.depth = depth;
+        Ok(env)
+    }
+
+    pub fn set_recursion_limit(&mut self, limit: usize) {
+        self.recursion_limit = limit;
+    }
+
+    // UTC unless the host sets it
+    pub fn set_implicit_timezone(&mut self, timezone: FixedOffset) {
+        self.implicit_timezone = timezone;
+    }
+
+    pub(crate) fn implicit_timezone(&self) -> FixedOffset {
+        self.implicit_timezone
+    }
+
+    // English is always available, other languages are added by the host
+    pub fn add_language(&mut self, localization: Rc<dyn Localization>) {
+        Rc::make_mut(&mut self.languages).add(localization);
+    }
+
+    // English unless the host sets it
+    pub fn set_default_language(&mut self, language: &str) {
+        Rc::make_mut(&mut self.languages).set_default(language);
+    }
+
+    pub(crate) fn languages(&self) -> &Languages {
+        &self.languages
+    }
+
+    // random for every execution unless the host sets it
+    pub fn set_random_seed(&mut self, seed: u64) {
+        self.random_seed = seed;
+    }
+
+    pub(crate) fn random_seed(&self) -> u64 {
+        self.random_seed
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        let cancellation = self.guard.cancellation().clone();
+        self.guard = Rc::new(Guard::new(limits, cancellation));
+    }
+
+    pub fn cancellation(&self) -> Cancellation {
+        self.guard.cancellation().clone()
+    }
+
+    pub(crate) fn start_evaluation(&self) {
+        self.guard.start()
+    }
+
+    // count step of evaluation, fails when limits are exceeded or evaluation is cancelled
+    pub(crate) fn step(&self) -> Result<(), ErrorInfo> {
+        self.guard.step()
+    }
+
+    pub(crate) fn construct_node(&self) -> Result<(), ErrorInfo> {
+        self.guard.node()
+    }
+
+    pub(crate) fn check_sequence(&self, length: usize) -> Result<(), ErrorInfo> {
+        self.guard.sequence(length)
+    }
+
+    pub(crate) fn check_string(&self, str: &str) -> Result<(), ErrorInfo> {
+        self.guard.string(str)
+    }
+
+    pub(crate) fn check_octets(&self, length: usize) -> Result<(), ErrorInfo> {
+        self.guard.octets(length)
+    }
+
     pub fn set_option(&self, name: QName, value: String) {
         // TODO
     }
 
-    pub fn prev(self) -> Box<Environment> {
-        match self.prev {
+    pub fn prev(mut self) -> Box<Environment> {
+        match self.prev.take() {
             Some(env) => env,
             None => panic!("internal error")
         }
@@ -104,6 +248,34 @@ impl Environment {
         }
     }
 
+    // uri of default collation, declared one or codepoint collation
+    pub(crate) fn default_collation(&self) -> String {
+        self.unwind(|env| env.default_collation.clone())
+            .unwrap_or_else(|| String::from(CODEPOINT_COLLATION))
+    }
+
+    // declared decimal format by name, unnamed one falls back to defaults
+    pub(crate) fn decimal_format(&self, name: &Option<QNameResolved>) -> Option<DecimalFormat> {
+        let format = self.unwind(|env| env.decimal_formats.as_ref().and_then(|formats| formats.get(name).cloned()));
+        match format {
+            None if name.is_none() => Some(DecimalFormat::default()),
+            format => format
+        }
+    }
+
+    // empty sequence is ordered as least value unless it's declared otherwise
+    pub(crate) fn empty_greatest(&self) -> bool {
+        matches!(self.unwind(|env| env.empty_order_mode.clone()), Some(EmptyOrderMode::Greatest))
+    }
+
+    // collation by uri, the default collation when uri is not given
+    pub(crate) fn collation(&self, uri: Option<&str>) -> Result<Box<dyn Collation>, ErrorInfo> {
+        match uri {
+            Some(uri) => resolve_collation(uri),
+            None => resolve_collation(&self.default_collation())
+        }
+    }
+
     pub fn xml_writer<F>(&mut self, mutation: F) -> Reference
         where F: FnOnce(&mut MutexGuard<Box<dyn XMLTreeWriter>>) -> Reference
     {
@@ -115,14 +287,68 @@ impl Environment {
         self.xml_tree.lock().unwrap().id()
     }
 
+    // chain of environments is as long as nesting of calls, so root is found without recursion
+    fn root_mut(&mut self) -> &mut Environment {
+        let mut env = self;
+        while env.prev.is_some() {
+            env = env.prev.as_mut().unwrap();
+        }
+        env
+    }
+
     pub fn next_id(&mut self) -> usize {
-        match &mut self.prev {
-            Some(prev) => prev.next_id(),
-            None => {
-                self.sequence += 1;
-                self.sequence
+        let root = self.root_mut();
+        root.sequence += 1;
+        root.sequence
+    }
+
+    pub fn pending_updates(&mut self) -> &mut PendingUpdateList {
+        &mut self.root_mut().pending_updates
+    }
+
+    // indexes to build for documents opened from storage
+    pub fn index_documents(&mut self, config: IndexConfig) {
+        self.root_mut().indexes = Some(config)
+    }
+
+    // document from storage, same uri gives same document during evaluation
+    pub fn open_document(&mut self, uri: &str) -> Result<Option<Reference>, ErrorInfo> {
+        if self.prev.is_some() {
+            return self.root_mut().open_document(uri);
+        }
+
+        if let Some(rf) = self.documents.get(uri) {
+            return Ok(Some(rf.clone()));
+        }
+
+        let id = self.next_id();
+        let tree = self.storage.lock().unwrap().open(uri, id)?;
+        if let Some(tree) = tree {
+            if let Some(config) = &self.indexes {
+                tree.lock().unwrap().index(config.clone());
+            }
+            let document = tree.lock().unwrap().as_reader().first();
+            if let Some(rf) = document {
+                self.documents.insert(uri.to_string(), rf.clone());
+                return Ok(Some(rf));
             }
         }
+        Ok(None)
+    }
+
+    // save opened documents of updated trees back to storage
+    pub fn write_back(&mut self, trees: &Vec<usize>) -> Result<(), ErrorInfo> {
+        if self.prev.is_some() {
+            return self.root_mut().write_back(trees);
+        }
+
+        let mut storage = self.storage.lock().unwrap();
+        for (uri, rf) in &self.documents {
+            if trees.contains(&rf.xml_tree_id()) {
+                storage.save(uri, rf)?;
+            }
+        }
+        Ok(())
     }
 
     pub fn default_namespace_for_element(&self) -> String {
@@ -148,19 +374,46 @@ impl Environment {
         self.unwind(|env| env.vars.get(name).map(|val| val.clone()))
     }
 
+    // variables bound below root environment, inner ones shadow outer ones
+    pub(crate) fn local_variables(&self) -> Vec<(QNameResolved, Object)> {
+        let mut result: Vec<(QNameResolved, Object)> = vec![];
+        let mut env = self;
+        while let Some(prev) = &env.prev {
+            for (name, value) in &env.vars {
+                if !result.iter().any(|(n, _)| n == name) {
+                    result.push((name.clone(), value.clone()));
+                }
+            }
+            env = prev;
+        }
+        result
+    }
+
     pub fn get_function(&self, name: &QNameResolved, arity: usize) -> Option<FUNCTION> {
         self.unwind(|env| env.functions.get(name, arity).map(|val| val.clone()))
     }
 
     pub fn declared_functions(&self, name: &QNameResolved, arity: usize) -> Option<&Function> {
-        // TODO self.unwind(|env| env.functions.declared(name, arity))
-        let obj = self.functions.declared(name, arity);
-        if obj.is_some() {
-            obj
-        } else if let Some(prev) = &self.prev {
-            prev.declared_functions(name, arity)
-        } else {
-            None
+        // not by unwind, result is borrowed from environment
+        let mut env = self;
+        loop {
+            if let Some(obj) = env.functions.declared(name, arity) {
+                break Some(obj);
+            } else if let Some(prev) = &env.prev {
+                env = prev
+            } else {
+                break None
+            }
+        }
+    }
+}
+
+impl Drop for Environment {
+    // chain of environments is as long as nesting of calls, so it's dropped without recursion
+    fn drop(&mut self) {
+        let mut prev = self.prev.take();
+        while let Some(mut env) = prev {
+            prev = env.prev.take();
         }
     }
 }
\ No newline at end of file
@@ -1,19 +1,146 @@
 use std::fmt::Debug;
 use dyn_clone::DynClone;
 
-use crate::eval::{Environment, DynamicContext, EvalResult, Object};
-use crate::tree::Reference;
+use crate::eval::{Axis, Consumer, Environment, DynamicContext, ErrorInfo, EvalResult, Object, StreamResult, INS};
+use crate::eval::analysis::StaticContext;
+use crate::eval::optimizer::Optimizer;
+use crate::eval::prolog::{Clause, PrimaryExprSuffix};
+use crate::eval::sequence_type::SequenceType;
+use crate::fns::Function;
+use crate::parser::op::OperatorComparison;
+use crate::tree::{IndexKey, NodeType, Reference};
+use crate::values::QName;
 
 pub trait Expression: DynClone + Debug {
     fn eval(&self, env: Box<Environment>, context: &DynamicContext) -> EvalResult;
 
     fn predicate(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult;
+
+    // pass value to consumer part by part, evaluation stops as soon as consumer breaks
+    fn stream(&self, env: Box<Environment>, context: &DynamicContext, consumer: &mut Consumer) -> StreamResult {
+        let (env, value) = self.eval(env, context)?;
+        consumer(env, value)
+    }
+
+    // evaluate expression in tail position of function body, call of declared function is left to the caller
+    fn eval_tail(&self, env: Box<Environment>, context: &DynamicContext) -> TailResult {
+        let (env, value) = self.eval(env, context)?;
+        Ok((env, Tail::Value(value)))
+    }
+
+    // shape of expression for lookups in document indexes
+    fn index_hint(&self) -> Option<IndexHint> {
+        None
+    }
+
+    // static analysis before evaluation, returns static type of expression when it's known
+    fn analyze(&self, _ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        Ok(None)
+    }
+
+    // rewrite into equivalent expression which is cheaper to evaluate, None keeps expression as is;
+    // expressions without rewrite rules are treated as opaque, see `Usage`
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        ctx.opaque();
+        None
+    }
+
+    // shape of expression for rewrites in optimizer
+    fn shape(&self) -> Option<Shape<'_>> {
+        None
+    }
+}
+
+pub type TailResult = Result<(Box<Environment>, Tail), ErrorInfo>;
+
+pub enum Tail {
+    Value(Object),
+    // declared function and evaluated arguments
+    Call(Function, Vec<Object>),
+}
+
+pub enum IndexHint {
+    // axis step without predicates
+    Step(Axis, Box<dyn NodeTest>),
+    // value doesn't depend on context item
+    Value,
+    // general comparison of key and value expression, key is at left side
+    Comparison(IndexKey, OperatorComparison, Box<dyn Expression>),
+}
+
+pub(crate) enum Shape<'a> {
+    // literal or folded value
+    Constant(Object),
+    // variable reference
+    Variable(&'a QName),
+    // static function call
+    Call(&'a QName, &'a [Box<dyn Expression>]),
+    // call of declared function with body copied to call site
+    InlineCall(&'a [Box<dyn Expression>]),
+    // clauses of FLWOR expression with its return expression
+    Flwor(&'a [Clause], &'a dyn Expression),
+    // axis step with its predicates
+    Step(Axis, Box<dyn NodeTest>, &'a [PrimaryExprSuffix]),
+    // steps of relative path
+    Steps(&'a [Box<dyn Expression>]),
+    // step after "/" or "//"
+    Path(&'a Option<INS>, &'a Box<dyn Expression>),
+    // parenthesized expression
+    Sequence(&'a Box<dyn Expression>),
+    // value is single xs:boolean
+    Boolean,
+    // argument placeholder of partial function application
+    Placeholder,
 }
 
 dyn_clone::clone_trait_object!(Expression);
 
 pub trait NodeTest: DynClone + Debug {
     fn test_node(&self, rf: &Reference) -> bool;
+
+    // name to look up in name index, only for plain name test
+    fn indexed_name(&self) -> Option<&QName> {
+        None
+    }
+
+    // for subtype checks of kind tests: "node()" test
+    fn is_any_node(&self) -> bool {
+        false
+    }
+
+    // kind of nodes matched by the test
+    fn node_type(&self) -> Option<NodeType> {
+        None
+    }
+
+    // name required by element or attribute test
+    fn node_name(&self) -> Option<&QName> {
+        None
+    }
+
+    // type required by element or attribute test
+    fn type_name(&self) -> Option<&QName> {
+        None
+    }
+
+    // target required by processing-instruction test
+    fn pi_target(&self) -> Option<&str> {
+        None
+    }
+
+    // test of document element required by document-node test
+    fn child_test(&self) -> Option<&dyn NodeTest> {
+        None
+    }
+
+    // schema-element and schema-attribute tests
+    fn is_schema_test(&self) -> bool {
+        false
+    }
+
+    fn is_namespace_node(&self) -> bool {
+        false
+    }
 }
 
 dyn_clone::clone_trait_object!(NodeTest);
\ No newline at end of file
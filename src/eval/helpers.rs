@@ -1,6 +1,10 @@
-use crate::eval::{Environment, Object, EvalResult, object_owned_to_sequence};
+use std::ops::ControlFlow;
+use crate::eval::{Environment, Object, EvalResult, DynamicContext, object_owned_to_sequence};
+use crate::eval::expression::Expression;
 
 pub(crate) fn relax(env: Box<Environment>, mut items: Vec<Object>) -> EvalResult {
+    env.check_sequence(items.len())?;
+
     if items.len() == 0 {
         Ok((env, Object::Empty))
     } else if items.len() == 1 {
@@ -11,6 +15,37 @@ pub(crate) fn relax(env: Box<Environment>, mut items: Vec<Object>) -> EvalResult
     }
 }
 
+// items of value with nested sequences and ranges flattened, ranges are not materialized
+pub(crate) fn flat_items(value: Object) -> Box<dyn Iterator<Item = Object>> {
+    Box::new(value.into_iter().flat_map(|item| match item {
+        Object::Range { .. } |
+        Object::Sequence(..) => flat_items(item),
+        Object::Nothing |
+        Object::Empty => Box::new(std::iter::empty()),
+        _ => Box::new(std::iter::once(item)),
+    }))
+}
+
+// evaluate only first `limit` items of expression
+pub(crate) fn eval_first(expr: &Box<dyn Expression>, env: Box<Environment>, context: &DynamicContext, limit: usize) -> EvalResult {
+    if limit == 0 {
+        return Ok((env, Object::Empty));
+    }
+
+    let mut result = vec![];
+    let (env, _) = expr.stream(env, context, &mut |env, value| {
+        for item in flat_items(value) {
+            result.push(item);
+            if result.len() >= limit {
+                return Ok((env, ControlFlow::Break(())));
+            }
+        }
+        Ok((env, ControlFlow::Continue(())))
+    })?;
+
+    relax(env, result)
+}
+
 pub(crate) fn process_items<F>(env: Box<Environment>, object: Object, op: F) -> EvalResult
     where F: Fn(Box<Environment>, Object, usize, Option<usize>) -> EvalResult
 {
@@ -23,6 +58,7 @@ pub(crate) fn process_items<F>(env: Box<Environment>, object: Object, op: F) ->
     let mut position = 0;
 
     for item in items {
+        current_env.step()?;
         position += 1;
 
         let (new_env, object) = op(current_env, item, position, last)?;
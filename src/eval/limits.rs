@@ -0,0 +1,120 @@
+use std::cell::Cell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use crate::eval::ErrorInfo;
+use crate::parser::errors::ErrorCode;
+
+// bounds of evaluation, exceeding any of them fails evaluation with XPDY0130
+#[derive(Clone, Debug, Default)]
+pub struct Limits {
+    // wall-clock time of evaluation
+    pub timeout: Option<Duration>,
+    // iterations of loops and function calls
+    pub max_steps: Option<u64>,
+    pub max_sequence_length: Option<usize>,
+    // nodes made by constructors
+    pub max_nodes: Option<usize>,
+    // length of built strings in characters and of binary data in octets
+    pub max_string_length: Option<usize>,
+}
+
+// handle to stop running evaluation from another thread
+#[derive(Clone, Debug, Default)]
+pub struct Cancellation {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Cancellation {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+// limits and counters of evaluation, shared by all environments of it
+pub(crate) struct Guard {
+    limits: Limits,
+    cancellation: Cancellation,
+    deadline: Cell<Option<Instant>>,
+    steps: Cell<u64>,
+    nodes: Cell<usize>,
+}
+
+impl Guard {
+    pub(crate) fn new(limits: Limits, cancellation: Cancellation) -> Self {
+        Guard {
+            limits,
+            cancellation,
+            deadline: Cell::new(None),
+            steps: Cell::new(0),
+            nodes: Cell::new(0),
+        }
+    }
+
+    pub(crate) fn cancellation(&self) -> &Cancellation {
+        &self.cancellation
+    }
+
+    // counters and timeout start from beginning of evaluation
+    pub(crate) fn start(&self) {
+        self.deadline.set(self.limits.timeout.map(|timeout| Instant::now() + timeout));
+        self.steps.set(0);
+        self.nodes.set(0);
+    }
+
+    pub(crate) fn step(&self) -> Result<(), ErrorInfo> {
+        if self.cancellation.is_cancelled() {
+            return Err((ErrorCode::XPDY0130, String::from("evaluation is cancelled")));
+        }
+        if let Some(deadline) = self.deadline.get() {
+            if Instant::now() > deadline {
+                return Err(exceeded("timeout", self.limits.timeout.unwrap_or_default().as_millis(), "ms"));
+            }
+        }
+
+        let steps = self.steps.get() + 1;
+        self.steps.set(steps);
+        check("evaluation steps", steps, self.limits.max_steps)
+    }
+
+    pub(crate) fn node(&self) -> Result<(), ErrorInfo> {
+        let nodes = self.nodes.get() + 1;
+        self.nodes.set(nodes);
+        check("constructed nodes", nodes, self.limits.max_nodes)?;
+
+        self.step()
+    }
+
+    pub(crate) fn sequence(&self, length: usize) -> Result<(), ErrorInfo> {
+        check("sequence length", length, self.limits.max_sequence_length)
+    }
+
+    pub(crate) fn octets(&self, length: usize) -> Result<(), ErrorInfo> {
+        check("binary length", length, self.limits.max_string_length)
+    }
+
+    pub(crate) fn string(&self, str: &str) -> Result<(), ErrorInfo> {
+        if let Some(max) = self.limits.max_string_length {
+            // byte length is upper bound of number of characters
+            if str.len() > max {
+                return check("string length", str.chars().count(), Some(max));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check<T: PartialOrd + std::fmt::Display>(what: &str, value: T, max: Option<T>) -> Result<(), ErrorInfo> {
+    match max {
+        Some(max) if value > max => Err(exceeded(what, max, "")),
+        _ => Ok(())
+    }
+}
+
+fn exceeded<T: std::fmt::Display>(what: &str, max: T, unit: &str) -> ErrorInfo {
+    (ErrorCode::XPDY0130, format!("limit of {} {}{} is exceeded", what, max, unit))
+}
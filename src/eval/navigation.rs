@@ -1,6 +1,7 @@
 use crate::eval::expression::Expression;
 use crate::eval::{Environment, DynamicContext, EvalResult, Object};
 use crate::eval::helpers::relax;
+use crate::eval::optimizer::Optimizer;
 use crate::parser::errors::ErrorCode;
 
 #[derive(Clone, Debug)]
@@ -46,4 +47,8 @@ impl Expression for NodeParent {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn optimize(&self, _ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        None
+    }
 }
\ No newline at end of file
@@ -0,0 +1,180 @@
+use std::collections::{HashMap, HashSet};
+use crate::eval::{DynamicContext, Environment};
+use crate::eval::expression::{Expression, Shape};
+use crate::eval::prolog::Constant;
+use crate::fns::Function;
+use crate::namespaces::XPATH_FUNCTIONS;
+use crate::parser::op::Statement;
+use crate::values::{resolve_element_qname, resolve_function_qname, QName, QNameResolved};
+
+// biggest body (in number of expressions) of user function which is inlined at call sites
+const INLINE_LIMIT: usize = 32;
+
+type FunctionKey = (QNameResolved, usize);
+
+// what is known about optimized expression
+pub(crate) struct Usage {
+    // variables referenced by expression, including ones bound inside of it
+    pub(crate) references: HashSet<QNameResolved>,
+    // expression may not be moved or evaluated fewer times, like node constructors
+    pub(crate) opaque: bool,
+    // expression calls fn:position or fn:last
+    pub(crate) positional: bool,
+}
+
+// user function which body is copied to call sites
+#[derive(Clone)]
+struct Inlinable {
+    size: usize,
+    calls: Vec<FunctionKey>,
+}
+
+// state of rewrites over parsed program
+pub(crate) struct Optimizer {
+    env: Box<Environment>,
+    references: Vec<QNameResolved>,
+    calls: Vec<FunctionKey>,
+    size: usize,
+    opaque: bool,
+    positional: bool,
+    inlinable: HashMap<FunctionKey, Inlinable>,
+}
+
+// rewrite program into equivalent one which is cheaper to evaluate
+pub(crate) fn optimize(statements: Vec<Statement>, env: &Environment) -> Vec<Statement> {
+    let mut ctx = Optimizer::new(env);
+
+    statements.into_iter()
+        .map(|statement| match statement {
+            Statement::Prolog(exprs) => Statement::Prolog(ctx.optimize_all(&exprs)),
+            Statement::Program(expr) => Statement::Program(ctx.optimize(&expr)),
+        })
+        .collect()
+}
+
+pub(crate) fn is_constant(expr: &Box<dyn Expression>) -> bool {
+    matches!(expr.shape(), Some(Shape::Constant(..)))
+}
+
+impl Optimizer {
+    pub(crate) fn new(env: &Environment) -> Self {
+        Optimizer {
+            env: Box::new(env.clone()),
+            references: vec![],
+            calls: vec![],
+            size: 0,
+            opaque: false,
+            positional: false,
+            inlinable: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn optimize(&mut self, expr: &Box<dyn Expression>) -> Box<dyn Expression> {
+        self.size += 1;
+        expr.optimize(self).unwrap_or_else(|| expr.clone())
+    }
+
+    pub(crate) fn optimize_all(&mut self, exprs: &[Box<dyn Expression>]) -> Vec<Box<dyn Expression>> {
+        exprs.iter().map(|expr| self.optimize(expr)).collect()
+    }
+
+    pub(crate) fn optimize_with_usage(&mut self, expr: &Box<dyn Expression>) -> (Box<dyn Expression>, Usage) {
+        let references = self.references.len();
+        let opaque = std::mem::replace(&mut self.opaque, false);
+        let positional = std::mem::replace(&mut self.positional, false);
+
+        let expr = self.optimize(expr);
+
+        let usage = Usage {
+            references: self.references[references..].iter().cloned().collect(),
+            opaque: self.opaque,
+            positional: self.positional,
+        };
+        self.opaque |= opaque;
+        self.positional |= positional;
+
+        (expr, usage)
+    }
+
+    // evaluate declaration which changes static context, like namespace or function declaration
+    pub(crate) fn apply(&mut self, expr: &dyn Expression) {
+        if let Ok((env, _)) = expr.eval(self.env.clone(), &DynamicContext::nothing()) {
+            self.env = env;
+        }
+    }
+
+    pub(crate) fn opaque(&mut self) {
+        self.opaque = true;
+    }
+
+    pub(crate) fn positional(&mut self) {
+        self.positional = true;
+    }
+
+    pub(crate) fn reference(&mut self, name: &QName) {
+        let name = self.resolve_variable(name);
+        self.references.push(name);
+    }
+
+    pub(crate) fn resolve_variable(&self, name: &QName) -> QNameResolved {
+        resolve_element_qname(name, &self.env)
+    }
+
+    pub(crate) fn is_function(&self, name: &QName, local_part: &str) -> bool {
+        let name = resolve_function_qname(name, &self.env);
+        name.url == *XPATH_FUNCTIONS.uri && name.local_part == local_part
+    }
+
+    // replace expression by its value when all operands are known before evaluation
+    pub(crate) fn fold(&self, expr: Box<dyn Expression>, is_constant: bool) -> Box<dyn Expression> {
+        if is_constant {
+            // errors are left to be raised at evaluation
+            if let Ok((_, value)) = expr.eval(self.env.clone(), &DynamicContext::nothing()) {
+                return Box::new(Constant { value });
+            }
+        }
+        expr
+    }
+
+    // optimize body of declared function and remember whether it's small enough to be inlined
+    pub(crate) fn declare_function(&mut self, name: &QName, arity: usize, body: &Box<dyn Expression>) -> Box<dyn Expression> {
+        let key = (resolve_function_qname(name, &self.env), arity);
+
+        let calls = self.calls.len();
+        let size = self.size;
+
+        let body = self.optimize(body);
+
+        let size = self.size - size;
+        let calls: Vec<FunctionKey> = self.calls.drain(calls..).collect();
+
+        if size <= INLINE_LIMIT && !calls.contains(&key) {
+            self.inlinable.insert(key, Inlinable { size, calls });
+        }
+        body
+    }
+
+    // declared function to be inlined at static call, None when function must be called by name
+    pub(crate) fn inline(&mut self, name: &QName, arity: usize) -> Option<Function> {
+        let key = (resolve_function_qname(name, &self.env), arity);
+
+        if key.0.url == *XPATH_FUNCTIONS.uri || self.env.get_function(&key.0, arity).is_some() {
+            return None;
+        }
+        // user functions may construct nodes
+        self.opaque = true;
+
+        let function = self.env.declared_functions(&key.0, arity).cloned();
+        match (function, self.inlinable.get(&key).cloned()) {
+            (Some(function), Some(Inlinable { size, calls })) => {
+                self.size += size;
+                self.calls.extend(calls);
+                Some(function)
+            }
+            _ => {
+                self.calls.push(key);
+                None
+            }
+        }
+    }
+}
@@ -1,7 +1,11 @@
-use crate::eval::{Object, Environment, EvalResult, DynamicContext, Type, range_to_sequence};
+use std::cmp::Ordering;
+use std::ops::ControlFlow;
+use crate::eval::{Object, Environment, ErrorInfo, EvalResult, DynamicContext, Type, Consumer, StreamResult};
+use crate::eval::sequence_type::SequenceType;
 use std::slice::Iter;
-use crate::values::{QNameResolved, resolve_element_qname};
-use crate::eval::helpers::{relax, insert_into_sequences};
+use crate::values::{QNameResolved, resolve_element_qname, resolve_collation, atomization};
+use crate::eval::comparison::sort_order;
+use crate::eval::helpers::{relax, insert_into_sequences, flat_items};
 use crate::eval::prolog::*;
 use crate::eval::expression::Expression;
 use crate::parser::errors::ErrorCode;
@@ -35,130 +39,202 @@ pub(crate) struct Pipe {
     pub next: Option<Box<Pipe>>,
 }
 
-pub(crate) fn eval_pipe<'a>(pipe: Box<Pipe>, env: Box<Environment>, context: &DynamicContext) -> EvalResult {
+pub(crate) fn eval_pipe(pipe: &Pipe, env: Box<Environment>, context: &DynamicContext) -> EvalResult {
+    let mut result = vec![];
+
+    let (current_env, _) = stream_pipe(pipe, env, context, &mut |env, answer| {
+        insert_into_sequences(&mut result, answer);
+        env.check_sequence(result.len())?;
+
+        Ok((env, ControlFlow::Continue(())))
+    })?;
+
+    relax(current_env, result)
+}
+
+// pass answers of return expression to consumer, bindings are evaluated lazily
+pub(crate) fn stream_pipe(pipe: &Pipe, env: Box<Environment>, context: &DynamicContext, consumer: &mut Consumer) -> StreamResult {
     let mut current_env = env;
 
-    let next = pipe.next;
-    if let Some(binding) = pipe.binding {
+    let next = pipe.next.as_deref();
+    if let Some(binding) = &pipe.binding {
         match binding {
             Binding::For { name, values, st, allowing_empty, positional_var } => {
-                let name = resolve_element_qname(&name, &current_env);
+                let name = resolve_element_qname(name, &current_env);
                 let positional_var = if let Some(positional_var) = positional_var {
-                    Some(resolve_element_qname(&positional_var, &current_env))
+                    Some(resolve_element_qname(positional_var, &current_env))
                 } else {
                     None
                 };
 
-                let (new_env, evaluated) = values.eval(current_env, context)?;
-                current_env = new_env;
+                let next = if let Some(next) = next {
+                    next
+                } else {
+                    let (new_env, _) = values.eval(current_env, context)?;
+                    return Ok((new_env, ControlFlow::Continue(())));
+                };
 
-                let mut result = vec![];
+                let mut pos = 0;
+                let (new_env, flow) = values.stream(current_env, context, &mut |env, evaluated| {
+                    // own scope, values may be produced by expression with variables of its own
+                    let mut current_env = env.next();
 
-                if let Some(next) = next {
-                    let items = range_to_sequence(evaluated);
-                    if items.len() == 0 {
-                        if allowing_empty {
-
-                            let item = if let Some(st) = st.as_ref() {
-                                if st.is_castable(&current_env, &Object::Empty)? {
-                                    Object::Empty
-                                } else {
-                                    return Err((ErrorCode::XPTY0004, String::from("TODO")))
-                                }
-                            } else {
-                                Object::Empty
-                            };
-
-                            current_env.set_variable(name.clone(), item);
-                            if let Some(positional_var) = positional_var.clone() {
-                                current_env.set_variable(positional_var, Object::Atomic(Type::Integer(0)));
-                            }
-
-                            let (new_env, answer) = eval_pipe(next.clone(), current_env, context)?;
-                            current_env = new_env;
-
-                            insert_into_sequences(&mut result, answer);
+                    for item in flat_items(evaluated) {
+                        current_env.step()?;
+                        pos += 1;
+
+                        let item = check_binding(&current_env, st, item)?;
+
+                        current_env.set_variable(name.clone(), item);
+                        if let Some(positional_var) = positional_var.clone() {
+                            current_env.set_variable(positional_var, Object::Atomic(Type::Integer(pos)));
                         }
-                    } else {
-                        let mut pos = 0;
-                        for item in items {
-                            pos += 1;
-
-                            let item = if let Some(st) = st.as_ref() {
-                                if st.is_castable(&current_env, &item)? {
-                                    item
-                                } else {
-                                    return Err((ErrorCode::XPTY0004, String::from("TODO")))
-                                }
-                            } else {
-                                item
-                            };
-
-                            current_env.set_variable(name.clone(), item);
-                            if let Some(positional_var) = positional_var.clone() {
-                                current_env.set_variable(positional_var, Object::Atomic(Type::Integer(pos)));
-                            }
-
-                            let (new_env, answer) = eval_pipe(next.clone(), current_env, context)?;
-                            current_env = new_env;
-
-                            insert_into_sequences(&mut result, answer);
+
+                        let (new_env, flow) = stream_pipe(next, current_env, context, consumer)?;
+                        current_env = new_env;
+
+                        if flow.is_break() {
+                            return Ok((current_env.prev(), flow));
                         }
                     }
-                }
+                    Ok((current_env.prev(), ControlFlow::Continue(())))
+                })?;
+                current_env = new_env;
 
-                relax(current_env, result)
+                if pos == 0 && *allowing_empty {
+                    let item = check_binding(&current_env, st, Object::Empty)?;
+
+                    current_env.set_variable(name, item);
+                    if let Some(positional_var) = positional_var {
+                        current_env.set_variable(positional_var, Object::Atomic(Type::Integer(0)));
+                    }
+
+                    stream_pipe(next, current_env, context, consumer)
+                } else {
+                    Ok((current_env, flow))
+                }
             },
             Binding::Let { name, st: type_declaration, value } => {
                 let (new_env, item) = value.eval(current_env.next(), context)?;
-                // let item = match item {
-                //     Object::Node(rf) => {
-                //         if rf.storage.is_none() {
-                //             let storage = new_env.xml_tree.clone();
-                //             Object::Node(
-                //                 Reference { storage: Some(storage), storage_id: rf.storage_id, id: rf.id, attr_name: rf.attr_name }
-                //             )
-                //         } else {
-                //             Object::Node(rf)
-                //         }
-                //     },
-                //     _ => item,
-                // };
                 current_env = new_env.prev();
 
                 // TODO: handle typeDeclaration
 
-                let name = resolve_element_qname(&name, &current_env);
+                let name = resolve_element_qname(name, &current_env);
                 current_env.set_variable(name, item);
 
                 if let Some(next) = next {
-                    eval_pipe(next, current_env, context)
+                    stream_pipe(next, current_env, context, consumer)
                 } else {
-                    Ok((current_env, Object::Empty))
+                    Ok((current_env, ControlFlow::Continue(())))
                 }
             },
         }
-    } else if let Some(expr) = pipe.where_expr {
+    } else if let Some(expr) = &pipe.where_expr {
         let (new_env, v) = expr.eval(current_env, context)?;
         current_env = new_env;
 
         if v.effective_boolean_value()? {
             if let Some(next) = next {
-                eval_pipe(next, current_env, context)
-            } else {
-                Ok((current_env, Object::Empty))
+                return stream_pipe(next, current_env, context, consumer);
             }
-        } else {
-            Ok((current_env, Object::Empty))
         }
+        Ok((current_env, ControlFlow::Continue(())))
 
-    } else if let Some(expr) = pipe.return_expr {
+    } else if let Some(expr) = &pipe.return_expr {
         if let Some(..) = next {
             panic!("internal error");
         }
 
-        expr.eval(current_env, context)
+        expr.stream(current_env, context, consumer)
     } else {
         panic!("internal error")
     }
-}
\ No newline at end of file
+}
+
+// tuples of variables are collected and sorted by order specs, the rest is evaluated for each tuple in order
+pub(crate) fn stream_ordered(pipe: &Pipe, specs: &[OrderSpec], rest: &dyn Expression, env: Box<Environment>, context: &DynamicContext, consumer: &mut Consumer) -> StreamResult {
+    let mut collations = Vec::with_capacity(specs.len());
+    for spec in specs {
+        let collation = match &spec.collation {
+            Some(uri) => resolve_collation(uri)
+                .map_err(|_| (ErrorCode::XQST0076, format!("unknown collation {}", uri)))?,
+            None => env.collation(None)?
+        };
+        collations.push(collation);
+    }
+    let empty_greatest = env.empty_greatest();
+
+    let mut tuples = vec![];
+    let (mut current_env, _) = stream_pipe(pipe, env, context, &mut |env, _| {
+        let mut current_env = env;
+
+        let mut keys = Vec::with_capacity(specs.len());
+        for spec in specs {
+            let (new_env, value) = spec.expr.eval(current_env, context)?;
+            current_env = new_env;
+
+            let key = match atomization(&current_env, value)? {
+                Object::Empty => None,
+                Object::Atomic(Type::Untyped(str)) => Some(Type::String(str)),
+                Object::Atomic(t) => Some(t),
+                value => return Err((ErrorCode::XPTY0004, format!("order key is not single atomic value {:?}", value)))
+            };
+            keys.push(key);
+        }
+
+        tuples.push((keys, current_env.local_variables()));
+        current_env.check_sequence(tuples.len())?;
+
+        Ok((current_env, ControlFlow::Continue(())))
+    })?;
+
+    let mut error = None;
+    tuples.sort_by(|(left, _), (right, _)| {
+        for (i, spec) in specs.iter().enumerate() {
+            let empty_greatest = spec.empty_greatest.unwrap_or(empty_greatest);
+            let ordering = match (&left[i], &right[i]) {
+                (None, None) => Ok(Ordering::Equal),
+                (None, Some(..)) => Ok(if empty_greatest { Ordering::Greater } else { Ordering::Less }),
+                (Some(..), None) => Ok(if empty_greatest { Ordering::Less } else { Ordering::Greater }),
+                (Some(l), Some(r)) => sort_order(collations[i].as_ref(), l, r)
+            };
+            match ordering {
+                Ok(Ordering::Equal) => {}
+                Ok(ordering) => return if spec.descending { ordering.reverse() } else { ordering },
+                Err(e) => {
+                    error.get_or_insert(e);
+                    return Ordering::Equal;
+                }
+            }
+        }
+        Ordering::Equal
+    });
+    if let Some(e) = error {
+        return Err(e);
+    }
+
+    for (_, variables) in tuples {
+        let mut tuple_env = current_env.next();
+        for (name, value) in variables {
+            tuple_env.set_variable(name, value);
+        }
+
+        let (new_env, flow) = rest.stream(tuple_env, context, consumer)?;
+        current_env = new_env.prev();
+
+        if flow.is_break() {
+            return Ok((current_env, flow));
+        }
+    }
+    Ok((current_env, ControlFlow::Continue(())))
+}
+
+fn check_binding(env: &Box<Environment>, st: &Option<SequenceType>, item: Object) -> Result<Object, ErrorInfo> {
+    if let Some(st) = st.as_ref() {
+        if !st.is_castable(env, &item)? {
+            return Err((ErrorCode::XPTY0004, String::from("TODO")))
+        }
+    }
+    Ok(item)
+}
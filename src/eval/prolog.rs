@@ -1,23 +1,29 @@
-use crate::eval::expression::{Expression, NodeTest};
+use crate::eval::expression::{Expression, IndexHint, NodeTest, Shape, Tail, TailResult};
+use crate::eval::optimizer::{is_constant, Optimizer, Usage};
 use crate::parser::op::{Representation, OperatorArithmetic, OperatorComparison};
-use bigdecimal::BigDecimal;
+use bigdecimal::{BigDecimal, ToPrimitive};
+use bigdecimal::num_bigint::BigInt;
 use ordered_float::OrderedFloat;
-use crate::values::{QName, resolve_function_qname, resolve_element_qname, Types, QNameResolved, atomization};
-use crate::fns::{Param, call};
-use crate::eval::{Environment, DynamicContext, EvalResult, Object, Type, eval_predicates, Axis, step_and_test, object_to_qname, object_owned_to_sequence, object_to_integer, ErrorInfo, INS, comparison};
+use crate::values::{OrderedMap, QName, resolve_function_qname, resolve_element_qname, Types, QNameResolved, atomization, resolve_collation, CODEPOINT_COLLATION};
+use crate::fns::{DecimalFormat, Param, Function as FunctionDeclared, call, call_declared, call_item, is_partial_on_first, first_items_needed, function_exists, partial_application};
+use crate::eval::{Environment, DynamicContext, EvalResult, Object, Type, eval_predicates, lookup, Axis, step_and_test, step_and_test_by_value, object_to_qname, object_owned_to_sequence, object_to_integer, ErrorInfo, INS, comparison, Consumer, StreamResult};
 use crate::serialization::{object_to_string};
 use crate::serialization::to_string::object_to_string_xml;
-use crate::eval::helpers::{relax, relax_sequences, sort_and_dedup, process_items, join_sequences};
-use std::collections::HashMap;
+use crate::eval::helpers::{relax, relax_sequences, sort_and_dedup, process_items, join_sequences, flat_items, eval_first, insert_into_sequences};
+use std::collections::{HashMap, HashSet};
+use im_rc::Vector;
 use std::ops::ControlFlow;
 use crate::eval::arithmetic::{eval_unary, eval_arithmetic};
 use crate::eval::comparison::{eval_comparison, eval_comparison_item};
-use crate::eval::piping::{Pipe, eval_pipe};
+use crate::eval::piping::{Pipe, eval_pipe, stream_pipe, stream_ordered};
 use crate::parser::errors::{CustomError, ErrorCode};
-use crate::eval::sequence_type::{ItemType, OccurrenceIndicator, SequenceType, XS_ANY_ATOMIC_TYPE, XS_ANY_SIMPLE_TYPE, XS_NOTATION};
+use crate::eval::sequence_type::{ItemType, OccurrenceIndicator, SequenceType, XS_ANY_ATOMIC_TYPE, XS_ANY_SIMPLE_TYPE, XS_BOOLEAN, XS_DECIMAL, XS_DOUBLE, XS_INTEGER, XS_NOTATION, XS_STRING};
+use crate::eval::analysis::{single, StaticContext};
 use linked_hash_map::LinkedHashMap;
-use crate::namespaces::{Namespace, NS_heap};
+use crate::namespaces::{Namespace, NS_heap, XPATH_FUNCTIONS};
 use crate::eval::sequence_type::QNameToTypes;
+use crate::eval::update;
+use crate::tree::{IndexKey, InsertPosition};
 use crate::parser::errors::ErrorCode::*;
 
 //internal
@@ -45,6 +51,18 @@ impl Expression for CharRef {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn index_hint(&self) -> Option<IndexHint> {
+        Some(IndexHint::Value)
+    }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        Ok(single(XS_STRING))
+    }
+
+    fn optimize(&self, _ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        None
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -66,6 +84,18 @@ impl Expression for EntityRef {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn index_hint(&self) -> Option<IndexHint> {
+        Some(IndexHint::Value)
+    }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        Ok(single(XS_STRING))
+    }
+
+    fn optimize(&self, _ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        None
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -79,6 +109,18 @@ impl Expression for EscapeQuot {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn index_hint(&self) -> Option<IndexHint> {
+        Some(IndexHint::Value)
+    }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        Ok(single(XS_STRING))
+    }
+
+    fn optimize(&self, _ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        None
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -92,6 +134,18 @@ impl Expression for EscapeApos {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn index_hint(&self) -> Option<IndexHint> {
+        Some(IndexHint::Value)
+    }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        Ok(single(XS_STRING))
+    }
+
+    fn optimize(&self, _ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        None
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -181,6 +235,8 @@ impl Expression for DeclareDefaultCollation {
     fn eval<'a>(&self, mut env: Box<Environment>, context: &DynamicContext) -> EvalResult {
         if env.default_collation.is_some() {
             Err((ErrorCode::XQST0038, String::from("TODO")))
+        } else if resolve_collation(&self.uri).is_err() {
+            Err((ErrorCode::XQST0038, format!("unknown collation {}", self.uri)))
         } else {
             env.default_collation = Some(self.uri.clone());
             Ok((env, Object::Nothing))
@@ -190,6 +246,19 @@ impl Expression for DeclareDefaultCollation {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        if ctx.declarations_only {
+            ctx.apply(self)?;
+        }
+        Ok(None)
+    }
+
+    // comparisons of constant strings are folded with declared collation
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        ctx.apply(self);
+        None
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -383,13 +452,13 @@ impl DeclareDecimalFormat {
 
 impl Expression for DeclareDecimalFormat {
     fn eval<'a>(&self, mut env: Box<Environment>, context: &DynamicContext) -> EvalResult {
-        if env.decimal_formats.is_none() {
-            env.decimal_formats = Some(HashMap::new());
-        };
+        let name = self.name.as_ref().map(|name| resolve_element_qname(name, &env));
+        let format = DecimalFormat::from_properties(&self.properties)?;
 
-        if let Some(map) = &mut env.decimal_formats {
-            map.insert(self.name.clone(), self.properties.clone());
-        };
+        let formats = env.decimal_formats.get_or_insert_with(HashMap::new);
+        if formats.insert(name, format).is_some() {
+            return Err((XQST0111, String::from("decimal format is declared more than once")));
+        }
 
         Ok((env, Object::Nothing))
     }
@@ -397,6 +466,19 @@ impl Expression for DeclareDecimalFormat {
     fn predicate(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        if ctx.declarations_only {
+            ctx.apply(self)?;
+        }
+        Ok(None)
+    }
+
+    // calls of format-number are folded with declared formats
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        ctx.apply(self);
+        None
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -429,6 +511,18 @@ impl Expression for DeclareNamespace {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        if ctx.declarations_only {
+            ctx.apply(self)?;
+        }
+        Ok(None)
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        ctx.apply(self);
+        None
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -465,12 +559,24 @@ impl Expression for DeclareDefaultNamespace {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        if ctx.declarations_only {
+            ctx.apply(self)?;
+        }
+        Ok(None)
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        ctx.apply(self);
+        None
+    }
 }
 
 //prolog
 #[derive(Clone, Debug)]
 pub(crate) struct AnnotatedDecl {
-    pub(crate) annotations: Vec<Box<dyn Expression>>,
+    pub(crate) annotations: Vec<Annotation>,
     pub(crate) decl: Box<dyn Expression>
 }
 
@@ -483,6 +589,17 @@ impl Expression for AnnotatedDecl {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        self.decl.analyze(ctx)
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        Some(Box::new(AnnotatedDecl {
+            annotations: self.annotations.clone(),
+            decl: ctx.optimize(&self.decl)
+        }))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -514,6 +631,29 @@ impl Expression for VarDecl {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        if ctx.declarations_only {
+            ctx.declare_variable(&self.name, self.type_declaration.clone())?;
+        } else if let Some(expr) = &self.value {
+            let st = expr.analyze(ctx)?;
+            if let Some(type_declaration) = &self.type_declaration {
+                ctx.check_type(&st, type_declaration)?;
+            } else {
+                ctx.declare_variable(&self.name, st)?;
+            }
+        }
+        Ok(None)
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        Some(Box::new(VarDecl {
+            name: self.name.clone(),
+            type_declaration: self.type_declaration.clone(),
+            external: self.external,
+            value: self.value.as_ref().map(|value| ctx.optimize(value))
+        }))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -522,6 +662,7 @@ pub(crate) struct FunctionDecl {
     pub(crate) params: Vec<Param>,
     pub(crate) st: Option<SequenceType>,
     pub(crate) external: bool,
+    pub(crate) updating: bool,
     pub(crate) body: Option<Box<dyn Expression>>
 }
 
@@ -532,7 +673,7 @@ impl Expression for FunctionDecl {
         // TODO: handle typeDeclaration
 
         if let Some(body) = self.body.clone() {
-            env.functions.put(name, self.params.clone(), self.st.clone(), body);
+            env.functions.put(name, self.params.clone(), self.st.clone(), self.updating, body);
 
         } else {
             todo!()
@@ -544,6 +685,38 @@ impl Expression for FunctionDecl {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        if ctx.declarations_only {
+            ctx.declare_function(&self.name, &self.params, &self.st)?;
+        } else if let Some(body) = &self.body {
+            ctx.push_scope();
+            for param in &self.params {
+                ctx.declare_variable(&param.name, param.sequence_type.clone())?;
+            }
+            let st = body.analyze(ctx)?;
+            ctx.pop_scope();
+
+            if let Some(expected) = &self.st {
+                ctx.check_type(&st, expected)?;
+            }
+        }
+        Ok(None)
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        let decl = FunctionDecl {
+            name: self.name.clone(),
+            params: self.params.clone(),
+            st: self.st.clone(),
+            external: self.external,
+            updating: self.updating,
+            body: self.body.as_ref().map(|body| ctx.declare_function(&self.name, self.params.len(), body))
+        };
+        ctx.apply(&decl);
+
+        Some(Box::new(decl))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -607,9 +780,55 @@ impl Expression for Body {
         }
     }
 
+    fn stream(&self, env: Box<Environment>, context: &DynamicContext, consumer: &mut Consumer) -> StreamResult {
+        let mut current_env = env;
+        for expr in &self.exprs {
+            let (new_env, flow) = expr.stream(current_env, context, consumer)?;
+            current_env = new_env;
+
+            if flow.is_break() {
+                return Ok((current_env, flow));
+            }
+        }
+        Ok((current_env, ControlFlow::Continue(())))
+    }
+
+    fn eval_tail(&self, env: Box<Environment>, context: &DynamicContext) -> TailResult {
+        if self.exprs.len() == 1 {
+            self.exprs[0].eval_tail(env, context)
+        } else {
+            let (env, value) = self.eval(env, context)?;
+            Ok((env, Tail::Value(value)))
+        }
+    }
+
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        if self.exprs.len() == 1 {
+            self.exprs[0].analyze(ctx)
+        } else {
+            ctx.analyze_all(&self.exprs)?;
+            Ok(None)
+        }
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        let exprs = ctx.optimize_all(&self.exprs);
+        let is_constant = exprs.iter().all(is_constant);
+
+        Some(ctx.fold(Box::new(Body { exprs }), is_constant))
+    }
+
+    fn shape(&self) -> Option<Shape<'_>> {
+        if self.exprs.len() == 1 {
+            Some(Shape::Sequence(&self.exprs[0]))
+        } else {
+            None
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -632,9 +851,27 @@ impl Expression for EnclosedExpr {
         Ok((env, value))
     }
 
+    fn stream(&self, env: Box<Environment>, context: &DynamicContext, consumer: &mut Consumer) -> StreamResult {
+        let (new_env, flow) = self.expr.stream(env.next(), context, consumer)?;
+        Ok((new_env.prev(), flow))
+    }
+
+    fn eval_tail(&self, env: Box<Environment>, context: &DynamicContext) -> TailResult {
+        let (new_env, tail) = self.expr.eval_tail(env.next(), context)?;
+        Ok((new_env.prev(), tail))
+    }
+
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        self.expr.analyze(ctx)
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        Some(EnclosedExpr::new(ctx.optimize(&self.expr)))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -672,6 +909,14 @@ impl Expression for ExtensionExpr {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        if let Some(expr) = &self.expr {
+            expr.analyze(ctx)
+        } else {
+            Ok(None)
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -722,6 +967,11 @@ impl Expression for ValidateExpr {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        self.expr.analyze(ctx)?;
+        Ok(None)
+    }
 }
 
 //navigation
@@ -746,6 +996,10 @@ impl Expression for Root {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn optimize(&self, _ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        None
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -781,6 +1035,19 @@ impl Expression for Steps {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        ctx.analyze_all(&self.steps)?;
+        Ok(None)
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        Some(Steps::new(ctx.optimize_all(&self.steps)))
+    }
+
+    fn shape(&self) -> Option<Shape<'_>> {
+        Some(Shape::Steps(&self.steps))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -808,6 +1075,36 @@ impl Expression for InitialPath {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        self.expr.analyze(ctx)?;
+        Ok(None)
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        if self.initial_node_sequence == INS::RootDescendantOrSelf {
+            let (expr, fused) = match self.expr.shape() {
+                Some(Shape::Steps(steps)) => {
+                    let (first, fused) = fuse_descendant(&steps[0], ctx);
+
+                    let mut optimized = vec![first];
+                    optimized.extend(ctx.optimize_all(&steps[1..]));
+
+                    let expr: Box<dyn Expression> = Steps::new(optimized);
+                    (expr, fused)
+                }
+                _ => fuse_descendant(&self.expr, ctx)
+            };
+
+            let initial_node_sequence = if fused { INS::Root } else { INS::RootDescendantOrSelf };
+            Some(Box::new(InitialPath { initial_node_sequence, expr }))
+        } else {
+            Some(Box::new(InitialPath {
+                initial_node_sequence: self.initial_node_sequence.clone(),
+                expr: ctx.optimize(&self.expr)
+            }))
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -832,6 +1129,31 @@ impl Expression for Path {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        self.expr.analyze(ctx)?;
+        Ok(None)
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        if self.initial_node_sequence == Some(INS::DescendantOrSelf) {
+            let (expr, fused) = fuse_descendant(&self.expr, ctx);
+            if fused {
+                Some(expr)
+            } else {
+                Some(Box::new(Path { initial_node_sequence: self.initial_node_sequence.clone(), expr }))
+            }
+        } else {
+            Some(Box::new(Path {
+                initial_node_sequence: self.initial_node_sequence.clone(),
+                expr: ctx.optimize(&self.expr)
+            }))
+        }
+    }
+
+    fn shape(&self) -> Option<Shape<'_>> {
+        Some(Shape::Path(&self.initial_node_sequence, &self.expr))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -850,6 +1172,22 @@ impl Expression for AxisStep {
     fn eval<'a>(&self, env: Box<Environment>, context: &DynamicContext) -> EvalResult {
         let mut current_env = env;
 
+        // first predicate may be answered by range index, which orders strings by codepoints
+        let is_codepoint = current_env.default_collation() == CODEPOINT_COLLATION;
+        if let (true, Some(IndexHint::Step(axis, test)), Some(first)) = (is_codepoint, self.step.index_hint(), self.predicates.first()) {
+            if let (Some(predicate), None, None) = (&first.predicate, &first.argument_list, &first.lookup) {
+                if let Some(IndexHint::Comparison(key, operator, value)) = predicate.index_hint() {
+                    let (new_env, value) = value.eval(current_env, context)?;
+                    current_env = new_env;
+
+                    if let Some(result) = step_and_test_by_value(&axis, &test, &key, &operator, &value, context) {
+                        let (new_env, value) = relax(current_env, result)?;
+                        return eval_predicates(&self.predicates[1..], new_env, value, context);
+                    }
+                }
+            }
+        }
+
         let (new_env, value) = self.step.eval(current_env, context)?;
         current_env = new_env;
 
@@ -859,6 +1197,35 @@ impl Expression for AxisStep {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn index_hint(&self) -> Option<IndexHint> {
+        if self.predicates.is_empty() {
+            self.step.index_hint()
+        } else {
+            None
+        }
+    }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        self.step.analyze(ctx)?;
+        analyze_suffixes(&self.predicates, ctx)?;
+        Ok(None)
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        let step = ctx.optimize(&self.step);
+        let (predicates, _) = optimize_suffixes(&self.predicates, ctx);
+
+        Some(AxisStep::boxed(step, predicates))
+    }
+
+    fn shape(&self) -> Option<Shape<'_>> {
+        if let Some(IndexHint::Step(axis, test)) = self.step.index_hint() {
+            Some(Shape::Step(axis, test, &self.predicates))
+        } else {
+            None
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -874,6 +1241,14 @@ impl Expression for ForwardStep {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn index_hint(&self) -> Option<IndexHint> {
+        Some(IndexHint::Step(self.axis.clone(), self.test.clone()))
+    }
+
+    fn optimize(&self, _ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        None
+    }
 }
 
 //spec
@@ -901,6 +1276,18 @@ impl Expression for Boolean {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        Ok(single(XS_BOOLEAN))
+    }
+
+    fn optimize(&self, _ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        None
+    }
+
+    fn shape(&self) -> Option<Shape<'_>> {
+        Some(Shape::Constant(Object::Atomic(Type::Boolean(self.bool))))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -936,7 +1323,13 @@ impl Expression for Integer {
                         Ok((env, Object::Atomic(Type::Integer(num))))
                     }
                 },
-                Object::Array(items) |
+                Object::Array(items) => {
+                    if let Some(item) = items.get((pos - 1) as usize) {
+                        Ok((env, item.clone()))
+                    } else {
+                        Ok((env, Object::Empty))
+                    }
+                },
                 Object::Sequence(items) => {
                     if let Some(item) = items.get((pos - 1) as usize) {
                         Ok((env, item.clone()))
@@ -948,6 +1341,53 @@ impl Expression for Integer {
             }
         }
     }
+
+    fn index_hint(&self) -> Option<IndexHint> {
+        Some(IndexHint::Value)
+    }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        Ok(single(XS_INTEGER))
+    }
+
+    fn optimize(&self, _ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        None
+    }
+
+    fn shape(&self) -> Option<Shape<'_>> {
+        Some(Shape::Constant(Object::Atomic(Type::Integer(self.number))))
+    }
+}
+
+// integer literal out of i128 range
+#[derive(Clone, Debug)]
+pub(crate) struct BigInteger { pub(crate) number: BigInt }
+
+impl Expression for BigInteger {
+    fn eval<'a>(&self, env: Box<Environment>, _context: &DynamicContext) -> EvalResult {
+        Ok((env, Object::Atomic(Type::BigInteger(self.number.clone()))))
+    }
+
+    fn predicate<'a>(&self, env: Box<Environment>, _context: &DynamicContext, _value: Object) -> EvalResult {
+        // no sequence is that long
+        Ok((env, Object::Empty))
+    }
+
+    fn index_hint(&self) -> Option<IndexHint> {
+        Some(IndexHint::Value)
+    }
+
+    fn analyze(&self, _ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        Ok(single(XS_INTEGER))
+    }
+
+    fn optimize(&self, _ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        None
+    }
+
+    fn shape(&self) -> Option<Shape<'_>> {
+        Some(Shape::Constant(Object::Atomic(Type::BigInteger(self.number.clone()))))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -961,6 +1401,22 @@ impl Expression for Decimal {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn index_hint(&self) -> Option<IndexHint> {
+        Some(IndexHint::Value)
+    }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        Ok(single(XS_DECIMAL))
+    }
+
+    fn optimize(&self, _ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        None
+    }
+
+    fn shape(&self) -> Option<Shape<'_>> {
+        Some(Shape::Constant(Object::Atomic(Type::Decimal(self.number.clone()))))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -974,6 +1430,67 @@ impl Expression for Double {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn index_hint(&self) -> Option<IndexHint> {
+        Some(IndexHint::Value)
+    }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        Ok(single(XS_DOUBLE))
+    }
+
+    fn optimize(&self, _ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        None
+    }
+
+    fn shape(&self) -> Option<Shape<'_>> {
+        Some(Shape::Constant(Object::Atomic(Type::Double(self.number))))
+    }
+}
+
+// value computed before evaluation by optimizer
+#[derive(Clone, Debug)]
+pub(crate) struct Constant { pub(crate) value: Object }
+
+impl Expression for Constant {
+    fn eval<'a>(&self, env: Box<Environment>, _context: &DynamicContext) -> EvalResult {
+        Ok((env, self.value.clone()))
+    }
+
+    fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
+        // numeric value selects item at that position
+        let position = match &self.value {
+            Object::Atomic(Type::Integer(number)) => Some(*number as f64),
+            Object::Atomic(Type::Decimal(number)) => number.to_f64(),
+            Object::Atomic(Type::Float(number)) => Some(number.into_inner() as f64),
+            Object::Atomic(Type::Double(number)) => Some(number.into_inner()),
+            _ => None
+        };
+
+        if let Some(position) = position {
+            if position.fract() == 0.0 {
+                Integer { number: position as i128 }.predicate(env, context, value)
+            } else {
+                Ok((env, Object::Empty))
+            }
+        } else if self.value.effective_boolean_value()? {
+            Ok((env, value))
+        } else {
+            Ok((env, Object::Empty))
+        }
+    }
+
+    fn index_hint(&self) -> Option<IndexHint> {
+        Some(IndexHint::Value)
+    }
+
+    fn optimize(&self, _ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        None
+    }
+
+    fn shape(&self) -> Option<Shape<'_>> {
+        Some(Shape::Constant(self.value.clone()))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -1006,6 +1523,30 @@ impl Expression for StringComplex {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn index_hint(&self) -> Option<IndexHint> {
+        for expr in &self.exprs {
+            match expr.index_hint() {
+                Some(IndexHint::Value) => {}
+                _ => return None
+            }
+        }
+        Some(IndexHint::Value)
+    }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        for expr in &self.exprs {
+            expr.analyze(ctx)?;
+        }
+        Ok(single(XS_STRING))
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        let exprs = ctx.optimize_all(&self.exprs);
+        let is_constant = exprs.iter().all(is_constant);
+
+        Some(ctx.fold(Box::new(StringComplex { exprs }), is_constant))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -1043,6 +1584,22 @@ impl Expression for StringExpr {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn index_hint(&self) -> Option<IndexHint> {
+        Some(IndexHint::Value)
+    }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        Ok(single(XS_STRING))
+    }
+
+    fn optimize(&self, _ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        None
+    }
+
+    fn shape(&self) -> Option<Shape<'_>> {
+        Some(Shape::Constant(Object::Atomic(Type::String(self.string.clone()))))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -1069,7 +1626,25 @@ impl Expression for ContextItem {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
-}
+
+    fn optimize(&self, _ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        None
+    }
+}
+
+// value of tail evaluation is flattened as by `eval`, only a call which is the whole result is left as is
+fn flat_tail(env: Box<Environment>, tail: Tail) -> TailResult {
+    match tail {
+        Tail::Value(value) => {
+            let items = object_owned_to_sequence(value);
+            let mut result = Vec::with_capacity(items.len());
+            relax_sequences(&mut result, items);
+            let (env, value) = relax(env, result)?;
+            Ok((env, Tail::Value(value)))
+        },
+        Tail::Call(..) => Ok((env, tail))
+    }
+}
 
 #[derive(Clone, Debug)]
 pub(crate) struct Sequence { pub(crate) expr: Box<dyn Expression> }
@@ -1094,9 +1669,33 @@ impl Expression for Sequence {
         relax(new_env, result)
     }
 
+    fn stream(&self, env: Box<Environment>, context: &DynamicContext, consumer: &mut Consumer) -> StreamResult {
+        self.expr.stream(env, context, consumer)
+    }
+
+    fn eval_tail(&self, env: Box<Environment>, context: &DynamicContext) -> TailResult {
+        let (new_env, tail) = self.expr.eval_tail(env, context)?;
+        flat_tail(new_env, tail)
+    }
+
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        self.expr.analyze(ctx)
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        let expr = ctx.optimize(&self.expr);
+        let is_constant = is_constant(&expr);
+
+        Some(ctx.fold(Sequence::new(expr), is_constant))
+    }
+
+    fn shape(&self) -> Option<Shape<'_>> {
+        Some(Shape::Sequence(&self.expr))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -1110,6 +1709,18 @@ impl Expression for SequenceEmpty {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        Ok(Some(SequenceType::exactly_one(ItemType::SequenceEmpty)))
+    }
+
+    fn optimize(&self, _ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        None
+    }
+
+    fn shape(&self) -> Option<Shape<'_>> {
+        Some(Shape::Constant(Object::Empty))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -1135,35 +1746,143 @@ impl Expression for Range {
             _ => {}
         }
 
-        let min = match object_to_integer(&current_env, evaluated_from) {
-            Ok(num) => num,
-            Err(e) => return Err(e)
-        };
-
-        let max = match object_to_integer(&current_env, evaluated_till) {
-            Ok(num) => num,
-            Err(e) => return Err(e)
+        // bounds may be out of i128 range
+        let to_big_integer = |object| match object {
+            Object::Atomic(Type::BigInteger(number)) => Ok(number),
+            _ => object_to_integer(&current_env, object).map(BigInt::from)
         };
+        let min = to_big_integer(evaluated_from)?;
+        let max = to_big_integer(evaluated_till)?;
 
         if min > max {
-            Ok((current_env, Object::Empty))
-        } else if min == max {
-            Ok((current_env, Object::Atomic(Type::Integer(min))))
-        } else {
-            Ok((current_env, Object::Range { min, max }))
+            return Ok((current_env, Object::Empty));
+        }
+
+        let length = (&max - &min + 1u32).to_usize().unwrap_or(usize::MAX);
+        match (min.to_i128(), max.to_i128()) {
+            (Some(min), Some(max)) if min == max => Ok((current_env, Object::Atomic(Type::Integer(min)))),
+            (Some(min), Some(max)) => {
+                current_env.check_sequence(length)?;
+
+                Ok((current_env, Object::Range { min, max }))
+            }
+            // range is kept by i128 bounds, so ones out of i128 range are materialized
+            _ => {
+                current_env.check_sequence(length)?;
+
+                let mut items = vec![];
+                if length == usize::MAX || items.try_reserve(length).is_err() {
+                    return Err((ErrorCode::FOAR0002, format!("range {} to {} is too long", min, max)));
+                }
+                let mut number = min;
+                while number <= max {
+                    items.push(Object::Atomic(Type::integer(number.clone())));
+                    number += 1u32;
+                }
+                relax(current_env, items)
+            }
         }
     }
 
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        let from = self.from.analyze(ctx)?;
+        let till = self.till.analyze(ctx)?;
+
+        let integer = SequenceType::zero_or_one(ItemType::AtomicOrUnionType(XS_INTEGER.into()));
+        ctx.check_type(&from, &integer)?;
+        ctx.check_type(&till, &integer)?;
+
+        Ok(Some(SequenceType::zero_or_more(ItemType::AtomicOrUnionType(XS_INTEGER.into()))))
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        let from = ctx.optimize(&self.from);
+        let till = ctx.optimize(&self.till);
+        let is_constant = is_constant(&from) && is_constant(&till);
+
+        Some(ctx.fold(Box::new(Range { from, till }), is_constant))
+    }
 }
 
 #[derive(Clone, Debug)]
 pub(crate) struct PrimaryExprSuffix {
     pub(crate) predicate: Option<Box<dyn Expression>>,
     pub(crate) argument_list: Option<Vec<Box<dyn Expression>>>,
-    pub(crate) lookup: Option<Box<dyn Expression>>,
+    pub(crate) lookup: Option<KeySpecifier>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) enum KeySpecifier {
+    Wildcard,
+    Key(Box<dyn Expression>),
+}
+
+impl KeySpecifier {
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<(), ErrorInfo> {
+        if let KeySpecifier::Key(key) = self {
+            key.analyze(ctx)?;
+        }
+        Ok(())
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> KeySpecifier {
+        match self {
+            KeySpecifier::Wildcard => KeySpecifier::Wildcard,
+            KeySpecifier::Key(key) => KeySpecifier::Key(ctx.optimize(key))
+        }
+    }
+}
+
+// "?" KeySpecifier applied to context item
+#[derive(Clone, Debug)]
+pub(crate) struct UnaryLookup {
+    pub(crate) key: KeySpecifier,
+}
+
+impl UnaryLookup {
+    pub(crate) fn boxed(key: KeySpecifier) -> Box<dyn Expression> {
+        Box::new(UnaryLookup { key })
+    }
+}
+
+impl Expression for UnaryLookup {
+    fn eval<'a>(&self, env: Box<Environment>, context: &DynamicContext) -> EvalResult {
+        if context.item == Object::Nothing {
+            return Err((ErrorCode::XPDY0002, String::from("context item is absent")));
+        }
+        lookup(env, context.item.clone(), &self.key, context)
+    }
+
+    fn predicate<'a>(&self, env: Box<Environment>, _context: &DynamicContext, value: Object) -> EvalResult {
+        process_items(env, value, |env, item, position, last| {
+
+            let current_context = DynamicContext {
+                initial_node_sequence: None,
+                item: item.clone(), position: Some(position), last
+            };
+
+            let (new_env, result) = self.eval(env, &current_context)?;
+
+            if is_selected(&result, position)? {
+                Ok((new_env, item))
+            } else {
+                Ok((new_env, Object::Nothing))
+            }
+        })
+    }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        self.key.analyze(ctx)?;
+        Ok(None)
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        Some(UnaryLookup::boxed(self.key.optimize(ctx)))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -1193,6 +1912,22 @@ impl Expression for InstanceOf {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        self.expr.analyze(ctx)?;
+        Ok(single(XS_BOOLEAN))
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        let expr = ctx.optimize(&self.expr);
+        let is_constant = is_constant(&expr);
+
+        Some(ctx.fold(Box::new(InstanceOf { expr, st: self.st.clone() }), is_constant))
+    }
+
+    fn shape(&self) -> Option<Shape<'_>> {
+        Some(Shape::Boolean)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -1224,6 +1959,18 @@ impl Expression for Treat {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        self.expr.analyze(ctx)?;
+        Ok(Some(self.st.clone()))
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        let expr = ctx.optimize(&self.expr);
+        let is_constant = is_constant(&expr);
+
+        Some(ctx.fold(Box::new(Treat { expr, st: self.st.clone() }), is_constant))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -1255,6 +2002,22 @@ impl Expression for Castable {
             }
         })
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        self.expr.analyze(ctx)?;
+        Ok(single(XS_BOOLEAN))
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        let expr = ctx.optimize(&self.expr);
+        let is_constant = is_constant(&expr);
+
+        Some(ctx.fold(Box::new(Castable { expr, st: self.st.clone() }), is_constant))
+    }
+
+    fn shape(&self) -> Option<Shape<'_>> {
+        Some(Shape::Boolean)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -1295,6 +2058,18 @@ impl Expression for Cast {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        self.expr.analyze(ctx)?;
+        Ok(Some(self.st.clone()))
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        let expr = ctx.optimize(&self.expr);
+        let is_constant = is_constant(&expr);
+
+        Some(ctx.fold(Box::new(Cast { expr, st: self.st.clone() }), is_constant))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -1302,7 +2077,11 @@ pub(crate) struct Postfix { pub(crate) primary: Box<dyn Expression>, pub(crate)
 
 impl Expression for Postfix {
     fn eval<'a>(&self, env: Box<Environment>, context: &DynamicContext) -> EvalResult {
-        let (new_env, value) = self.primary.eval(env, context)?;
+        // `expr[n]` needs only first n items of expr
+        let (new_env, value) = match self.suffix.first().and_then(constant_position) {
+            Some(position) => eval_first(&self.primary, env, context, position)?,
+            None => self.primary.eval(env, context)?
+        };
 
         eval_predicates(&self.suffix, new_env, value, context)
     }
@@ -1310,6 +2089,24 @@ impl Expression for Postfix {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        self.primary.analyze(ctx)?;
+        analyze_suffixes(&self.suffix, ctx)?;
+        Ok(None)
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        let primary = ctx.optimize(&self.primary);
+        let (suffix, is_filter) = optimize_suffixes(&self.suffix, ctx);
+
+        if is_filter && !suffix.is_empty() {
+            if let Some(expr) = push_predicates(&primary, &suffix) {
+                return Some(expr);
+            }
+        }
+        Some(Box::new(Postfix { primary, suffix }))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -1336,6 +2133,15 @@ impl Expression for Union {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        ctx.analyze_all(&self.exprs)?;
+        Ok(None)
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        Some(Box::new(Union { exprs: ctx.optimize_all(&self.exprs) }))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -1349,6 +2155,20 @@ impl Expression for IntersectExcept {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        self.left.analyze(ctx)?;
+        self.right.analyze(ctx)?;
+        Ok(None)
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        Some(Box::new(IntersectExcept {
+            left: ctx.optimize(&self.left),
+            is_intersect: self.is_intersect,
+            right: ctx.optimize(&self.right)
+        }))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -1365,6 +2185,8 @@ impl NodeDocument {
 impl Expression for NodeDocument {
     fn eval<'a>(&self, env: Box<Environment>, context: &DynamicContext) -> EvalResult {
         let mut current_env = env.next();
+        current_env.construct_node()?;
+
         let rf = current_env.xml_writer(|w| w.start_document());
 
         let (new_env, obj) = self.expr.eval(current_env, context)?;
@@ -1414,6 +2236,11 @@ impl Expression for NodeDocument {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        self.expr.analyze(ctx)?;
+        Ok(None)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -1471,7 +2298,11 @@ impl NodeElement {
         let mut elements = elements;
         match object {
             Object::Empty => {},
-            Object::Array(items) |
+            Object::Array(items) => {
+                for item in items {
+                    elements = self.processing(env, item, elements);
+                }
+            },
             Object::Sequence(items) => {
                 for item in items {
                     elements = self.processing(env, item, elements);
@@ -1507,6 +2338,7 @@ impl Expression for NodeElement {
 
         let name = object_to_qname(evaluated_name);
 
+        current_env.construct_node()?;
         let rf = current_env.xml_writer(|w| w.start_element(name));
 
         if let Some(attributes) = &self.attributes {
@@ -1534,6 +2366,17 @@ impl Expression for NodeElement {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        self.name.analyze(ctx)?;
+        if let Some(attributes) = &self.attributes {
+            for (_, value) in attributes.pairs.iter() {
+                value.analyze(ctx)?;
+            }
+        }
+        ctx.analyze_all(&self.children)?;
+        Ok(None)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -1559,6 +2402,7 @@ impl Expression for NodeAttribute {
 
         let value = object_to_string(&current_env, &evaluated_value);
 
+        current_env.construct_node()?;
         let rf = current_env.xml_writer(|w| w.attribute(name, value));
 
         Ok((current_env, Object::Node(rf) ))
@@ -1567,6 +2411,12 @@ impl Expression for NodeAttribute {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        self.name.analyze(ctx)?;
+        self.value.analyze(ctx)?;
+        Ok(None)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -1589,6 +2439,9 @@ impl Expression for NodeText {
         if content.is_empty() {
             Ok((new_env, Object::Empty))
         } else {
+            new_env.construct_node()?;
+            new_env.check_string(&content)?;
+
             let pointer = new_env.xml_writer(|w| w.text(content));
 
             Ok((new_env, Object::Node(pointer)))
@@ -1598,6 +2451,11 @@ impl Expression for NodeText {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        self.content.analyze(ctx)?;
+        Ok(None)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -1622,6 +2480,8 @@ impl Expression for NodeComment {
 
         let content = object_to_string(&new_env, &evaluated);
 
+        new_env.construct_node()?;
+        new_env.check_string(&content)?;
         let rf = new_env.xml_writer(|w| w.comment(content));
 
         Ok((new_env, Object::Node(rf) ))
@@ -1630,6 +2490,11 @@ impl Expression for NodeComment {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        self.content.analyze(ctx)?;
+        Ok(None)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -1658,6 +2523,7 @@ impl Expression for NodePI {
 
         let content = object_to_string(&current_env, &evaluated);
 
+        current_env.construct_node()?;
         let rf = current_env.xml_writer(|w| w.pi(target, content));
 
         Ok(( current_env, Object::Node(rf) ))
@@ -1666,6 +2532,12 @@ impl Expression for NodePI {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        self.target.analyze(ctx)?;
+        self.content.analyze(ctx)?;
+        Ok(None)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -1694,6 +2566,7 @@ impl Expression for NodeNS {
 
         let url = object_to_string(&current_env, &url);
 
+        current_env.construct_node()?;
         let rf = current_env.xml_writer(|w| w.ns(prefix, url));
 
         Ok(( current_env, Object::Node(rf) ))
@@ -1702,6 +2575,12 @@ impl Expression for NodeNS {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        self.prefix.analyze(ctx)?;
+        self.url.analyze(ctx)?;
+        Ok(None)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -1711,7 +2590,7 @@ impl Expression for Map {
     fn eval<'a>(&self, env: Box<Environment>, context: &DynamicContext) -> EvalResult {
         let mut current_env = env;
 
-        let mut map = HashMap::new();
+        let mut map = OrderedMap::new();
         for MapEntry { key, value } in &self.entries {
 
             let (new_env, evaluated_key) = key.eval(current_env, context)?;
@@ -1722,6 +2601,9 @@ impl Expression for Map {
 
             match evaluated_key {
                 Object::Atomic(key_object) => {
+                    if map.contains_key(&key_object) {
+                        return Err((ErrorCode::XQDY0137, format!("duplicate key in map constructor: {:?}", key_object)));
+                    }
                     map.insert(key_object, evaluated_value);
                 }
                 _ => panic!("wrong expression") //TODO: proper code
@@ -1734,6 +2616,25 @@ impl Expression for Map {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        for MapEntry { key, value } in &self.entries {
+            key.analyze(ctx)?;
+            value.analyze(ctx)?;
+        }
+        Ok(Some(SequenceType::exactly_one(ItemType::Map(None))))
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        let mut entries = Vec::with_capacity(self.entries.len());
+        for MapEntry { key, value } in &self.entries {
+            entries.push(MapEntry { key: ctx.optimize(key), value: ctx.optimize(value) });
+        }
+        let is_constant = entries.iter()
+            .all(|MapEntry { key, value }| is_constant(key) && is_constant(value));
+
+        Some(ctx.fold(Box::new(Map { entries }), is_constant))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -1756,12 +2657,12 @@ impl Expression for SquareArrayConstructor {
     fn eval<'a>(&self, env: Box<Environment>, context: &DynamicContext) -> EvalResult {
         let mut current_env = env;
 
-        let mut values = Vec::with_capacity(self.items.len());
+        let mut values = Vector::new();
         for item in &self.items {
             let (new_env, evaluated) = item.eval(current_env, context)?;
             current_env = new_env;
 
-            values.push(evaluated);
+            values.push_back(evaluated);
         }
 
         Ok((current_env, Object::Array(values)))
@@ -1770,6 +2671,18 @@ impl Expression for SquareArrayConstructor {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        ctx.analyze_all(&self.items)?;
+        Ok(Some(SequenceType::exactly_one(ItemType::Array(None))))
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        let items = ctx.optimize_all(&self.items);
+        let is_constant = items.iter().all(is_constant);
+
+        Some(ctx.fold(Box::new(SquareArrayConstructor { items }), is_constant))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -1779,19 +2692,8 @@ impl Expression for CurlyArrayConstructor {
     fn eval<'a>(&self, env: Box<Environment>, context: &DynamicContext) -> EvalResult {
         let (new_env, evaluated) = self.expr.eval(env, context)?;
 
-        let values = match evaluated {
-            Object::Empty => vec![],
-            Object::Array(items) |
-            Object::Sequence(items) => items,
-            Object::Range {..} |
-            Object::Node(_) |
-            Object::Atomic(_) => {
-                let mut items = Vec::with_capacity(1);
-                items.push(evaluated);
-                items
-            }
-            _ => panic!("can't convert to array {:?}", evaluated)
-        };
+        // members are items of the sequence
+        let values = flat_items(evaluated).collect();
 
         Ok((new_env, Object::Array(values)))
     }
@@ -1799,6 +2701,18 @@ impl Expression for CurlyArrayConstructor {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        self.expr.analyze(ctx)?;
+        Ok(Some(SequenceType::exactly_one(ItemType::Array(None))))
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        let expr = ctx.optimize(&self.expr);
+        let is_constant = is_constant(&expr);
+
+        Some(ctx.fold(Box::new(CurlyArrayConstructor { expr }), is_constant))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -1822,6 +2736,19 @@ impl Expression for Unary {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        let st = self.expr.analyze(ctx)?;
+        ctx.check_numeric(&st)?;
+        Ok(None)
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        let expr = ctx.optimize(&self.expr);
+        let is_constant = is_constant(&expr);
+
+        Some(ctx.fold(Box::new(Unary { expr, sign_is_positive: self.sign_is_positive }), is_constant))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -1847,6 +2774,22 @@ impl Expression for Binary {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        let left = self.left.analyze(ctx)?;
+        let right = self.right.analyze(ctx)?;
+        ctx.check_numeric(&left)?;
+        ctx.check_numeric(&right)?;
+        Ok(None)
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        let left = ctx.optimize(&self.left);
+        let right = ctx.optimize(&self.right);
+        let is_constant = is_constant(&left) && is_constant(&right);
+
+        Some(ctx.fold(Box::new(Binary { left, operator: self.operator.clone(), right }), is_constant))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -1905,6 +2848,76 @@ impl Expression for Comparison {
 
         relax(current_env, evaluated)
     }
+
+    fn index_hint(&self) -> Option<IndexHint> {
+        // `key op value` or `value op key`
+        let (key, operator, value) = match (self.left.index_hint(), self.right.index_hint()) {
+            (Some(IndexHint::Step(axis, test)), Some(IndexHint::Value)) => {
+                ((axis, test), self.operator.clone(), self.right.clone())
+            }
+            (Some(IndexHint::Value), Some(IndexHint::Step(axis, test))) => {
+                ((axis, test), self.operator.swap(), self.left.clone())
+            }
+            _ => return None
+        };
+
+        let (axis, test) = key;
+        let name = test.indexed_name()?.clone();
+        let key = match axis {
+            Axis::ForwardChild => IndexKey::Child(name),
+            Axis::ForwardAttribute => IndexKey::Attribute(name),
+            _ => return None
+        };
+
+        match operator {
+            OperatorComparison::GeneralEquals |
+            OperatorComparison::GeneralNotEquals |
+            OperatorComparison::GeneralLessThan |
+            OperatorComparison::GeneralLessOrEquals |
+            OperatorComparison::GeneralGreaterThan |
+            OperatorComparison::GeneralGreaterOrEquals => Some(IndexHint::Comparison(key, operator, value)),
+            _ => None
+        }
+    }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        self.left.analyze(ctx)?;
+        self.right.analyze(ctx)?;
+        Ok(None)
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        let left = ctx.optimize(&self.left);
+        let right = ctx.optimize(&self.right);
+
+        // `count(x) > 0` is `exists(x)` and `count(x) = 0` is `empty(x)`
+        if let (Some(Shape::Call(name, [argument])), Some(Shape::Constant(Object::Atomic(Type::Integer(number))))) = (left.shape(), right.shape()) {
+            if ctx.is_function(name, "count") {
+                let function = match (&self.operator, number) {
+                    (OperatorComparison::GeneralGreaterThan | OperatorComparison::ValueGreaterThan, 0) |
+                    (OperatorComparison::GeneralNotEquals | OperatorComparison::ValueNotEquals, 0) |
+                    (OperatorComparison::GeneralGreaterOrEquals | OperatorComparison::ValueGreaterOrEquals, 1) => Some("exists"),
+                    (OperatorComparison::GeneralEquals | OperatorComparison::ValueEquals, 0) |
+                    (OperatorComparison::GeneralLessOrEquals | OperatorComparison::ValueLessOrEquals, 0) |
+                    (OperatorComparison::GeneralLessThan | OperatorComparison::ValueLessThan, 1) => Some("empty"),
+                    _ => None
+                };
+                if let Some(function) = function {
+                    return Some(Box::new(Call {
+                        function: QName::ns(&XPATH_FUNCTIONS, function.to_string()),
+                        arguments: vec![argument.clone()]
+                    }));
+                }
+            }
+        }
+
+        let is_constant = is_constant(&left) && is_constant(&right);
+        Some(ctx.fold(Box::new(Comparison { left, operator: self.operator.clone(), right }), is_constant))
+    }
+
+    fn shape(&self) -> Option<Shape<'_>> {
+        Some(Shape::Boolean)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -1929,6 +2942,26 @@ impl Expression for If {
         Ok((new_env, evaluated))
     }
 
+    fn stream(&self, env: Box<Environment>, context: &DynamicContext, consumer: &mut Consumer) -> StreamResult {
+        let (new_env, evaluated) = self.condition.eval(env, context)?;
+
+        if evaluated.effective_boolean_value()? {
+            self.consequence.stream(new_env, context, consumer)
+        } else {
+            self.alternative.stream(new_env, context, consumer)
+        }
+    }
+
+    fn eval_tail(&self, env: Box<Environment>, context: &DynamicContext) -> TailResult {
+        let (new_env, evaluated) = self.condition.eval(env, context)?;
+
+        if evaluated.effective_boolean_value()? {
+            self.consequence.eval_tail(new_env, context)
+        } else {
+            self.alternative.eval_tail(new_env, context)
+        }
+    }
+
     fn predicate<'a>(&self, env: Box<Environment>, _context: &DynamicContext, value: Object) -> EvalResult {
         process_items(env, value, |env, item, position, last| {
 
@@ -1954,75 +2987,518 @@ impl Expression for If {
             }
         })
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        self.condition.analyze(ctx)?;
+        self.consequence.analyze(ctx)?;
+        self.alternative.analyze(ctx)?;
+        Ok(None)
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        let condition = ctx.optimize(&self.condition);
+
+        if let Some(Shape::Constant(value)) = condition.shape() {
+            if let Ok(value) = value.effective_boolean_value() {
+                return if value {
+                    Some(ctx.optimize(&self.consequence))
+                } else {
+                    Some(ctx.optimize(&self.alternative))
+                };
+            }
+        }
+
+        Some(Box::new(If {
+            condition,
+            consequence: ctx.optimize(&self.consequence),
+            alternative: ctx.optimize(&self.alternative)
+        }))
+    }
 }
 
 #[derive(Clone, Debug)]
-pub(crate) struct Function {
-    pub(crate) arguments: Vec<Param>,
-    pub(crate) st: Option<SequenceType>,
-    pub(crate) body: Box<dyn Expression>
+pub(crate) struct InsertExpr {
+    pub(crate) source: Box<dyn Expression>,
+    pub(crate) position: InsertPosition,
+    pub(crate) target: Box<dyn Expression>
 }
 
-impl Expression for Function {
+impl InsertExpr {
+    pub(crate) fn boxed(source: Box<dyn Expression>, position: InsertPosition, target: Box<dyn Expression>) -> Box<dyn Expression> {
+        Box::new(InsertExpr { source, position, target })
+    }
+}
+
+impl Expression for InsertExpr {
     fn eval<'a>(&self, env: Box<Environment>, context: &DynamicContext) -> EvalResult {
-        Ok((env, Object::Function { parameters: self.arguments.clone(), st: self.st.clone(), body: self.body.clone() }))
+        let (new_env, source) = self.source.eval(env, context)?;
+        let (mut new_env, target) = self.target.eval(new_env, context)?;
+
+        update::insert(&mut new_env, source, self.position.clone(), target)?;
+
+        Ok((new_env, Object::Empty))
     }
 
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        self.source.analyze(ctx)?;
+        self.target.analyze(ctx)?;
+        Ok(None)
+    }
 }
 
 #[derive(Clone, Debug)]
-pub(crate) struct Call {
-    pub(crate) function: QName,
-    pub(crate) arguments: Vec<Box<dyn Expression>>
+pub(crate) struct DeleteExpr {
+    pub(crate) target: Box<dyn Expression>
 }
 
-impl Expression for Call {
-    fn eval<'a>(&self, env: Box<Environment>, context: &DynamicContext) -> EvalResult {
-        let mut current_env = env;
+impl DeleteExpr {
+    pub(crate) fn boxed(target: Box<dyn Expression>) -> Box<dyn Expression> {
+        Box::new(DeleteExpr { target })
+    }
+}
 
-        let name = resolve_function_qname(&self.function, &current_env);
+impl Expression for DeleteExpr {
+    fn eval<'a>(&self, env: Box<Environment>, context: &DynamicContext) -> EvalResult {
+        let (mut new_env, target) = self.target.eval(env, context)?;
 
-        let (parameters, body) = match current_env.get_variable(&name) {
-            Some(Object::Function {parameters, st, body}) => (parameters, body),
-            None => {
-                let mut evaluated_arguments = Vec::with_capacity(self.arguments.len());
-                for argument in &self.arguments {
-                    let (new_env, value) = argument.eval(current_env, context)?;
-                    current_env = new_env;
+        update::delete(&mut new_env, target)?;
 
-                    evaluated_arguments.push(value);
-                }
+        Ok((new_env, Object::Empty))
+    }
 
-                return call(current_env, name, evaluated_arguments, context);
-            }
-            _ => panic!("error")
-        };
+    fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
+        todo!()
+    }
 
-        assert_eq!(parameters.len(), self.arguments.len(), "wrong number of parameters");
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        self.target.analyze(ctx)?;
+        Ok(None)
+    }
+}
 
-        let mut arguments = Vec::with_capacity(parameters.len());
+#[derive(Clone, Debug)]
+pub(crate) struct ReplaceExpr {
+    pub(crate) value_of: bool,
+    pub(crate) target: Box<dyn Expression>,
+    pub(crate) with: Box<dyn Expression>
+}
 
-        for (parameter, argument) in parameters.into_iter().zip(self.arguments.clone().into_iter()) {
-            let (new_env, new_result) = argument.eval(current_env, context)?;
-            current_env = new_env;
+impl ReplaceExpr {
+    pub(crate) fn boxed(value_of: bool, target: Box<dyn Expression>, with: Box<dyn Expression>) -> Box<dyn Expression> {
+        Box::new(ReplaceExpr { value_of, target, with })
+    }
+}
 
-            let name = resolve_function_qname(&parameter.name, &current_env);
+impl Expression for ReplaceExpr {
+    fn eval<'a>(&self, env: Box<Environment>, context: &DynamicContext) -> EvalResult {
+        let (new_env, target) = self.target.eval(env, context)?;
+        let (mut new_env, with) = self.with.eval(new_env, context)?;
 
-            arguments.push((name, new_result));
+        if self.value_of {
+            update::replace_value(&mut new_env, target, with)?;
+        } else {
+            update::replace_node(&mut new_env, target, with)?;
         }
 
-        let mut fn_env = current_env.next();
-        for (name, value) in arguments {
-            fn_env.set_variable(name, value);
-        }
+        Ok((new_env, Object::Empty))
+    }
 
-        let (new_env, result) = body.eval(fn_env, context)?;
-        current_env = new_env.prev();
+    fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
+        todo!()
+    }
 
-        Ok((current_env, result))
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        self.target.analyze(ctx)?;
+        self.with.analyze(ctx)?;
+        Ok(None)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct RenameExpr {
+    pub(crate) target: Box<dyn Expression>,
+    pub(crate) name: Box<dyn Expression>
+}
+
+impl RenameExpr {
+    pub(crate) fn boxed(target: Box<dyn Expression>, name: Box<dyn Expression>) -> Box<dyn Expression> {
+        Box::new(RenameExpr { target, name })
+    }
+}
+
+impl Expression for RenameExpr {
+    fn eval<'a>(&self, env: Box<Environment>, context: &DynamicContext) -> EvalResult {
+        let (new_env, target) = self.target.eval(env, context)?;
+        let (mut new_env, name) = self.name.eval(new_env, context)?;
+
+        update::rename(&mut new_env, target, name)?;
+
+        Ok((new_env, Object::Empty))
+    }
+
+    fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
+        todo!()
+    }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        self.target.analyze(ctx)?;
+        self.name.analyze(ctx)?;
+        Ok(None)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct CopyModifyExpr {
+    pub(crate) bindings: Vec<(QName, Box<dyn Expression>)>,
+    pub(crate) modify: Box<dyn Expression>,
+    pub(crate) return_expr: Box<dyn Expression>
+}
+
+impl CopyModifyExpr {
+    pub(crate) fn boxed(bindings: Vec<(QName, Box<dyn Expression>)>, modify: Box<dyn Expression>, return_expr: Box<dyn Expression>) -> Box<dyn Expression> {
+        Box::new(CopyModifyExpr { bindings, modify, return_expr })
+    }
+}
+
+impl Expression for CopyModifyExpr {
+    fn eval<'a>(&self, env: Box<Environment>, context: &DynamicContext) -> EvalResult {
+        let mut current_env = env.next();
+
+        let mut trees = Vec::with_capacity(self.bindings.len());
+        for (name, expr) in &self.bindings {
+            let (new_env, value) = expr.eval(current_env, context)?;
+            current_env = new_env;
+
+            let copy = match value {
+                Object::Node(rf) => update::copy(&mut current_env, &rf),
+                _ => None
+            };
+            let copy = match copy {
+                Some(copy) => copy,
+                None => return Err((ErrorCode::XUTY0013, String::from("copy source must be a single node")))
+            };
+            trees.push(copy.xml_tree_id());
+
+            let name = resolve_element_qname(name, &current_env);
+            current_env.set_variable(name, Object::Node(copy));
+        }
+
+        // modify clause has own pending update list
+        let outer = std::mem::take(current_env.pending_updates());
+        let (mut new_env, _) = self.modify.eval(current_env, context)?;
+        let updates = std::mem::replace(new_env.pending_updates(), outer);
+
+        for target in updates.targets() {
+            if !trees.contains(&target.xml_tree_id()) {
+                return Err((ErrorCode::XUDY0014, String::from("modify clause can update only copied nodes")));
+            }
+        }
+        updates.apply(&new_env.storage)?;
+
+        // return clause must not be updating expression
+        let updates = new_env.pending_updates().len();
+        let (mut new_env, result) = self.return_expr.eval(new_env, context)?;
+        if new_env.pending_updates().len() != updates {
+            return Err((ErrorCode::XUST0001, String::from("updating expression in return clause of copy modify expression")));
+        }
+
+        Ok((new_env.prev(), result))
+    }
+
+    fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
+        todo!()
+    }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        ctx.push_scope();
+        for (name, expr) in &self.bindings {
+            let st = expr.analyze(ctx)?;
+            ctx.declare_variable(name, st)?;
+        }
+        self.modify.analyze(ctx)?;
+        let st = self.return_expr.analyze(ctx);
+        ctx.pop_scope();
+
+        st
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Function {
+    pub(crate) arguments: Vec<Param>,
+    pub(crate) st: Option<SequenceType>,
+    pub(crate) body: Box<dyn Expression>,
+    // variables referenced by body, known after optimization
+    pub(crate) captures: Option<Vec<QNameResolved>>,
+}
+
+impl Expression for Function {
+    fn eval<'a>(&self, env: Box<Environment>, context: &DynamicContext) -> EvalResult {
+        let parameters: Vec<QNameResolved> = self.arguments.iter()
+            .map(|param| resolve_element_qname(&param.name, &env))
+            .collect();
+
+        // closure captures values of in-scope variables at the time of creation
+        let candidates = match &self.captures {
+            Some(names) => names.iter()
+                .filter_map(|name| env.get_variable(name).map(|value| (name.clone(), value)))
+                .collect(),
+            None => env.local_variables()
+        };
+        let captured: Vec<(QNameResolved, Object)> = candidates.into_iter()
+            .filter(|(name, _)| !parameters.contains(name))
+            .collect();
+
+        let body = if captured.is_empty() {
+            self.body.clone()
+        } else {
+            Box::new(Closure { captured, body: self.body.clone() })
+        };
+
+        Ok((env, Object::Function { parameters: self.arguments.clone(), st: self.st.clone(), body }))
+    }
+
+    fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
+        // value doesn't depend on context item
+        let (new_env, evaluated) = self.eval(env, context)?;
+        Constant { value: evaluated }.predicate(new_env, context, value)
+    }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        ctx.push_scope();
+        for param in &self.arguments {
+            ctx.declare_variable(&param.name, param.sequence_type.clone())?;
+        }
+        let st = self.body.analyze(ctx);
+        ctx.pop_scope();
+
+        if let Some(expected) = &self.st {
+            ctx.check_type(&st?, expected)?;
+        } else {
+            st?;
+        }
+
+        Ok(Some(SequenceType::exactly_one(ItemType::Function {
+            args: Some(self.arguments.iter()
+                .map(|param| param.sequence_type.clone().unwrap_or_else(|| SequenceType::zero_or_more(ItemType::Item)))
+                .collect()),
+            st: self.st.clone().map(Box::new)
+        })))
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        let (body, usage) = ctx.optimize_with_usage(&self.body);
+        Some(Box::new(Function {
+            arguments: self.arguments.clone(),
+            st: self.st.clone(),
+            body,
+            captures: Some(usage.references.into_iter().collect())
+        }))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Call {
+    pub(crate) function: QName,
+    pub(crate) arguments: Vec<Box<dyn Expression>>
+}
+
+impl Expression for Call {
+    fn eval<'a>(&self, env: Box<Environment>, context: &DynamicContext) -> EvalResult {
+        let mut current_env = env;
+
+        let name = resolve_function_qname(&self.function, &current_env);
+
+        let function = current_env.get_variable(&name);
+
+        let mut evaluated_arguments = Vec::with_capacity(self.arguments.len());
+        if function.is_none() && is_partial_on_first(&name, self.arguments.len()) {
+            for argument in &self.arguments[1..] {
+                let (new_env, value) = argument.eval(current_env, context)?;
+                current_env = new_env;
+
+                evaluated_arguments.push(value);
+            }
+
+            let (new_env, value) = match first_items_needed(&name, &evaluated_arguments) {
+                Some(limit) => eval_first(&self.arguments[0], current_env, context, limit)?,
+                None => self.arguments[0].eval(current_env, context)?
+            };
+            current_env = new_env;
+
+            evaluated_arguments.insert(0, value);
+        } else {
+            for argument in &self.arguments {
+                let (new_env, value) = argument.eval(current_env, context)?;
+                current_env = new_env;
+
+                evaluated_arguments.push(value);
+            }
+        }
+
+        if evaluated_arguments.iter().any(|argument| matches!(argument, Object::Placeholder)) {
+            let function = match function {
+                Some(function) => function,
+                None => {
+                    if !function_exists(&current_env, &name, evaluated_arguments.len()) {
+                        return Err((ErrorCode::XPST0017, format!("no function {:?}#{:?}", name, evaluated_arguments.len())));
+                    }
+                    Object::FunctionRef { name, arity: evaluated_arguments.len() }
+                }
+            };
+            let function = partial_application(&current_env, function, evaluated_arguments)?;
+            return Ok((current_env, function));
+        }
+
+        match function {
+            Some(function) => call_item(current_env, function, evaluated_arguments, context),
+            None => call(current_env, name, evaluated_arguments, context)
+        }
+    }
+
+    fn eval_tail(&self, env: Box<Environment>, context: &DynamicContext) -> TailResult {
+        let mut current_env = env;
+
+        let name = resolve_function_qname(&self.function, &current_env);
+
+        let function = if current_env.get_variable(&name).is_none() && !self.arguments.iter().any(is_argument_placeholder) {
+            current_env.declared_functions(&name, self.arguments.len()).cloned()
+        } else {
+            None
+        };
+
+        if let Some(function) = function {
+            let mut evaluated_arguments = Vec::with_capacity(self.arguments.len());
+            for argument in &self.arguments {
+                let (new_env, value) = argument.eval(current_env, context)?;
+                current_env = new_env;
+
+                evaluated_arguments.push(value);
+            }
+            Ok((current_env, Tail::Call(function, evaluated_arguments)))
+        } else {
+            let (env, value) = self.eval(current_env, context)?;
+            Ok((env, Tail::Value(value)))
+        }
+    }
+
+    fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
+        let mut current_env = env;
+
+        // `expr[last()]` selects last item, range doesn't need to be materialized for that
+        let name = resolve_function_qname(&self.function, &current_env);
+        if self.arguments.is_empty() && current_env.get_variable(&name).is_none()
+            && name.url == *XPATH_FUNCTIONS.uri && name.local_part == "last" {
+            return match value {
+                Object::Range { max, .. } => Ok((current_env, Object::Atomic(Type::Integer(max)))),
+                _ => Ok((current_env, flat_items(value).last().unwrap_or(Object::Empty)))
+            };
+        }
+
+        let items: Vec<Object> = flat_items(value).collect();
+
+        let mut evaluated = vec![];
+
+        let last = Some(items.len());
+        let mut position = 0;
+        for item in items {
+            position += 1;
+            let context = DynamicContext {
+                initial_node_sequence: None,
+                item, position: Some(position), last
+            };
+
+            let (new_env, result) = self.eval(current_env, &context)?;
+            current_env = new_env;
+
+            if is_selected(&result, position)? {
+                evaluated.push(context.item)
+            }
+        }
+
+        relax(current_env, evaluated)
+    }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        let mut arguments = Vec::with_capacity(self.arguments.len());
+        for argument in &self.arguments {
+            arguments.push(argument.analyze(ctx)?);
+        }
+
+        if let Some((params, st)) = ctx.function(&self.function, self.arguments.len())? {
+            for (argument, param) in arguments.iter().zip(params.iter()) {
+                ctx.check_type(argument, param)?;
+            }
+            if arguments.iter().any(is_placeholder) {
+                // partial function application
+                Ok(None)
+            } else {
+                Ok(Some(st))
+            }
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        let arguments = ctx.optimize_all(&self.arguments);
+
+        if ctx.is_function(&self.function, "position") || ctx.is_function(&self.function, "last") {
+            ctx.positional();
+        }
+
+        if arguments.iter().any(is_argument_placeholder) {
+            Some(Box::new(Call { function: self.function.clone(), arguments }))
+        } else if let Some(function) = ctx.inline(&self.function, arguments.len()) {
+            Some(Box::new(InlineCall { function, arguments }))
+        } else {
+            Some(Box::new(Call { function: self.function.clone(), arguments }))
+        }
+    }
+
+    fn shape(&self) -> Option<Shape<'_>> {
+        Some(Shape::Call(&self.function, &self.arguments))
+    }
+}
+
+// static call of user function which body is copied from declaration by optimizer
+#[derive(Clone, Debug)]
+pub(crate) struct InlineCall {
+    pub(crate) function: FunctionDeclared,
+    pub(crate) arguments: Vec<Box<dyn Expression>>
+}
+
+impl Expression for InlineCall {
+    fn eval<'a>(&self, env: Box<Environment>, context: &DynamicContext) -> EvalResult {
+        let mut current_env = env;
+
+        let mut evaluated_arguments = Vec::with_capacity(self.arguments.len());
+        for argument in &self.arguments {
+            let (new_env, value) = argument.eval(current_env, context)?;
+            current_env = new_env;
+
+            evaluated_arguments.push(value);
+        }
+
+        call_declared(current_env, &self.function, evaluated_arguments, context)
+    }
+
+    fn eval_tail(&self, env: Box<Environment>, context: &DynamicContext) -> TailResult {
+        let mut current_env = env;
+
+        let mut evaluated_arguments = Vec::with_capacity(self.arguments.len());
+        for argument in &self.arguments {
+            let (new_env, value) = argument.eval(current_env, context)?;
+            current_env = new_env;
+
+            evaluated_arguments.push(value);
+        }
+
+        Ok((current_env, Tail::Call(self.function.clone(), evaluated_arguments)))
     }
 
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
@@ -2051,6 +3527,18 @@ impl Expression for Call {
 
         relax(current_env, evaluated)
     }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        ctx.opaque();
+        Some(Box::new(InlineCall {
+            function: self.function.clone(),
+            arguments: ctx.optimize_all(&self.arguments)
+        }))
+    }
+
+    fn shape(&self) -> Option<Shape<'_>> {
+        Some(Shape::InlineCall(&self.arguments))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -2071,6 +3559,19 @@ impl Expression for ArgumentPlaceholder {
     fn predicate(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        // marks argument of partial function application, see `is_placeholder`
+        Ok(Some(SequenceType::none()))
+    }
+
+    fn optimize(&self, _ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        None
+    }
+
+    fn shape(&self) -> Option<Shape<'_>> {
+        Some(Shape::Placeholder)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -2098,6 +3599,9 @@ impl Expression for NamedFunctionRef {
         };
 
         let name = resolve_function_qname(&self.name, &current_env);
+        if !function_exists(&current_env, &name, arity) {
+            return Err((ErrorCode::XPST0017, format!("no function {:?}#{:?}", name, arity)));
+        }
 
         Ok((current_env, Object::FunctionRef { name, arity }))
     }
@@ -2105,6 +3609,24 @@ impl Expression for NamedFunctionRef {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        if let Some(arity) = ctx.constant(&self.arity) {
+            if let Ok(arity) = arity.to_integer() {
+                if arity >= 0 {
+                    ctx.function(&self.name, arity as usize)?;
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        if ctx.is_function(&self.name, "position") || ctx.is_function(&self.name, "last") {
+            ctx.positional();
+        }
+        None
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -2123,6 +3645,109 @@ impl Expression for Annotation {
     }
 }
 
+// body of function item produced by function coercion, passes parameters to original function
+#[derive(Clone, Debug)]
+pub(crate) struct FunctionCoercion {
+    pub(crate) function: Box<Object>,
+    pub(crate) parameters: Vec<QName>,
+}
+
+impl Expression for FunctionCoercion {
+    fn eval<'a>(&self, env: Box<Environment>, context: &DynamicContext) -> EvalResult {
+        let mut arguments = Vec::with_capacity(self.parameters.len());
+        for parameter in &self.parameters {
+            let name = resolve_element_qname(parameter, &env);
+            if let Some(value) = env.get_variable(&name) {
+                arguments.push(value);
+            } else {
+                return Err((ErrorCode::XPST0008, format!("unknown variable {:?}", name)));
+            }
+        }
+
+        call_item(env, (*self.function).clone(), arguments, context)
+    }
+
+    fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
+        todo!()
+    }
+}
+
+// body of inline function item with values of variables captured at its creation
+#[derive(Clone, Debug)]
+pub(crate) struct Closure {
+    pub(crate) captured: Vec<(QNameResolved, Object)>,
+    pub(crate) body: Box<dyn Expression>,
+}
+
+impl Expression for Closure {
+    fn eval<'a>(&self, env: Box<Environment>, context: &DynamicContext) -> EvalResult {
+        let mut current_env = env;
+        for (name, value) in &self.captured {
+            current_env.set_variable(name.clone(), value.clone());
+        }
+        self.body.eval(current_env, context)
+    }
+
+    fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
+        let mut current_env = env;
+        for (name, value) in &self.captured {
+            current_env.set_variable(name.clone(), value.clone());
+        }
+        self.body.predicate(current_env, context, value)
+    }
+}
+
+// body of function item produced by partial function application,
+// fixed arguments are passed as is and placeholders are filled by parameters in order
+#[derive(Clone, Debug)]
+pub(crate) struct PartialApplication {
+    pub(crate) function: Box<Object>,
+    pub(crate) arguments: Vec<Object>,
+    pub(crate) parameters: Vec<QName>,
+}
+
+impl Expression for PartialApplication {
+    fn eval<'a>(&self, env: Box<Environment>, context: &DynamicContext) -> EvalResult {
+        let mut parameters = self.parameters.iter();
+
+        let mut arguments = Vec::with_capacity(self.arguments.len());
+        for argument in &self.arguments {
+            if let Object::Placeholder = argument {
+                let name = match parameters.next() {
+                    Some(parameter) => resolve_element_qname(parameter, &env),
+                    None => panic!("internal error")
+                };
+                match env.get_variable(&name) {
+                    Some(value) => arguments.push(value),
+                    None => return Err((ErrorCode::XPST0008, format!("unknown variable {:?}", name)))
+                }
+            } else {
+                arguments.push(argument.clone());
+            }
+        }
+
+        call_item(env, (*self.function).clone(), arguments, context)
+    }
+
+    fn predicate<'a>(&self, env: Box<Environment>, _context: &DynamicContext, value: Object) -> EvalResult {
+        process_items(env, value, |env, item, position, last| {
+
+            let current_context = DynamicContext {
+                initial_node_sequence: None,
+                item: item.clone(), position: Some(position), last
+            };
+
+            let (new_env, result) = self.eval(env, &current_context)?;
+
+            if is_selected(&result, position)? {
+                Ok((new_env, item))
+            } else {
+                Ok((new_env, Object::Nothing))
+            }
+        })
+    }
+}
+
 #[derive(Clone, Debug)]
 pub(crate) struct VarRef { pub(crate) name: QName }
 
@@ -2130,15 +3755,34 @@ impl Expression for VarRef {
     fn eval<'a>(&self, env: Box<Environment>, _context: &DynamicContext) -> EvalResult {
         let name = resolve_element_qname(&self.name, &env);
 
-        if let Some(value) = env.get_variable(&name) {
-            Ok((env, value))
-        } else {
-            Err((ErrorCode::XPST0008, format!("unknown variable {:?}", name)))
-        }
+        if let Some(value) = env.get_variable(&name) {
+            Ok((env, value))
+        } else {
+            Err((ErrorCode::XPST0008, format!("unknown variable {:?}", name)))
+        }
+    }
+
+    fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
+        // value doesn't depend on context item
+        let (new_env, evaluated) = self.eval(env, context)?;
+        Constant { value: evaluated }.predicate(new_env, context, value)
+    }
+
+    fn index_hint(&self) -> Option<IndexHint> {
+        Some(IndexHint::Value)
+    }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        ctx.variable(&self.name)
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        ctx.reference(&self.name);
+        None
     }
 
-    fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
-        todo!()
+    fn shape(&self) -> Option<Shape<'_>> {
+        Some(Shape::Variable(&self.name))
     }
 }
 
@@ -2194,6 +3838,22 @@ impl Expression for Or {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        ctx.analyze_all(&self.exprs)?;
+        Ok(single(XS_BOOLEAN))
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        let exprs = ctx.optimize_all(&self.exprs);
+        let is_constant = exprs.iter().all(is_constant);
+
+        Some(ctx.fold(Box::new(Or { exprs }), is_constant))
+    }
+
+    fn shape(&self) -> Option<Shape<'_>> {
+        Some(Shape::Boolean)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -2253,6 +3913,22 @@ impl Expression for And {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        ctx.analyze_all(&self.exprs)?;
+        Ok(single(XS_BOOLEAN))
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        let exprs = ctx.optimize_all(&self.exprs);
+        let is_constant = exprs.iter().all(is_constant);
+
+        Some(ctx.fold(Box::new(And { exprs }), is_constant))
+    }
+
+    fn shape(&self) -> Option<Shape<'_>> {
+        Some(Shape::Boolean)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -2279,9 +3955,10 @@ impl Expression for StringConcat {
                 let object = sequence.remove(0);
                 Ok((current_env, object))
             } else {
-                let str = sequence.into_iter()
+                let str: String = sequence.into_iter()
                     .map(|item| object_to_string(&current_env, &item))
                     .collect();
+                current_env.check_string(&str)?;
 
                 Ok((current_env, Object::Atomic(Type::String(str))))
             }
@@ -2291,6 +3968,18 @@ impl Expression for StringConcat {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        ctx.analyze_all(&self.exprs)?;
+        Ok(single(XS_STRING))
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        let exprs = ctx.optimize_all(&self.exprs);
+        let is_constant = exprs.iter().all(is_constant);
+
+        Some(ctx.fold(Box::new(StringConcat { exprs }), is_constant))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -2315,6 +4004,8 @@ impl Expression for SimpleMap {
                 let last = Some(it.len());
                 let mut position = 0;
                 for item in it {
+                    current_env.step()?;
+
                     position += 1;
                     let current_context = DynamicContext {
                         initial_node_sequence: None,
@@ -2339,13 +4030,32 @@ impl Expression for SimpleMap {
     fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        ctx.analyze_all(&self.exprs)?;
+        Ok(None)
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        Some(Box::new(SimpleMap { exprs: ctx.optimize_all(&self.exprs) }))
+    }
 }
 
 #[derive(Clone, Debug)]
 pub(crate) enum Clause {
     For(Vec<Binding>),
     Let(Vec<Binding>),
-    Where(Box<dyn Expression>)
+    Where(Box<dyn Expression>),
+    OrderBy(Vec<OrderSpec>),
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct OrderSpec {
+    pub(crate) expr: Box<dyn Expression>,
+    pub(crate) descending: bool,
+    // None for default order of empty sequence
+    pub(crate) empty_greatest: Option<bool>,
+    pub(crate) collation: Option<String>,
 }
 
 #[derive(Clone, Debug)]
@@ -2357,40 +4067,163 @@ pub(crate) enum Binding {
 #[derive(Clone, Debug)]
 pub(crate) struct FLWOR { pub(crate) clauses: Vec<Clause>, pub(crate) return_expr: Box<dyn Expression> }
 
+impl FLWOR {
+    fn pipe(&self) -> Pipe {
+        pipe_of(&self.clauses, self.return_expr.clone())
+    }
+
+    // clauses before first order by clause, its specs and the rest of expression
+    fn split_at_order_by(&self) -> Option<(Pipe, &Vec<OrderSpec>, FLWOR)> {
+        let position = self.clauses.iter().position(|clause| matches!(clause, Clause::OrderBy(..)))?;
+        if let Clause::OrderBy(specs) = &self.clauses[position] {
+            let pipe = pipe_of(&self.clauses[..position], Box::new(SequenceEmpty {}));
+            let rest = FLWOR { clauses: self.clauses[position + 1..].to_vec(), return_expr: self.return_expr.clone() };
+            Some((pipe, specs, rest))
+        } else {
+            None
+        }
+    }
+}
+
+fn pipe_of(clauses: &[Clause], return_expr: Box<dyn Expression>) -> Pipe {
+    // TODO: handle  GroupByClause | CountClause
+
+    let mut pipe = Pipe { binding: None, where_expr: None, return_expr: Some(return_expr), next: None };
+    for clause in clauses.iter().cloned().rev() {
+        match clause {
+            Clause::For(bindings) => {
+                for binding in bindings.into_iter().rev() {
+                    pipe = Pipe { binding: Some(binding), where_expr: None, return_expr: None, next: Some(Box::new(pipe)) }
+                }
+            },
+            Clause::Let(bindings) => {
+                for binding in bindings.into_iter().rev() {
+                    pipe = Pipe { binding: Some(binding), where_expr: None, return_expr: None, next: Some(Box::new(pipe)) }
+                }
+            },
+            Clause::Where(expr) => {
+                pipe = Pipe { where_expr: Some(expr), binding: None, return_expr: None, next: Some(Box::new(pipe)) }
+            }
+            Clause::OrderBy(..) => panic!("internal error")
+        }
+    }
+    pipe
+}
+
 impl Expression for FLWOR {
     fn eval<'a>(&self, env: Box<Environment>, context: &DynamicContext) -> EvalResult {
-        let mut current_env = env;
+        if let Some((pipe, specs, rest)) = self.split_at_order_by() {
+            let mut result = vec![];
+            let (new_env, _) = stream_ordered(&pipe, specs, &rest, env.next(), context, &mut |env, answer| {
+                insert_into_sequences(&mut result, answer);
+                env.check_sequence(result.len())?;
+
+                Ok((env, ControlFlow::Continue(())))
+            })?;
+            return relax(new_env.prev(), result);
+        }
+
+        let (new_env, answer) = eval_pipe(&self.pipe(), env.next(), context)?;
+        let current_env = new_env.prev();
+
+        Ok((current_env, answer))
+    }
+
+    fn stream(&self, env: Box<Environment>, context: &DynamicContext, consumer: &mut Consumer) -> StreamResult {
+        let (new_env, flow) = if let Some((pipe, specs, rest)) = self.split_at_order_by() {
+            stream_ordered(&pipe, specs, &rest, env.next(), context, consumer)?
+        } else {
+            stream_pipe(&self.pipe(), env.next(), context, consumer)?
+        };
+        Ok((new_env.prev(), flow))
+    }
 
-        // TODO: new env?
-        // TODO: handle  WhereClause | GroupByClause | OrderByClause | CountClause
+    fn eval_tail(&self, env: Box<Environment>, context: &DynamicContext) -> TailResult {
+        // return expression is evaluated once only when there are no `for` and `where` clauses
+        if !self.clauses.iter().all(|clause| matches!(clause, Clause::Let(..))) {
+            let (env, value) = self.eval(env, context)?;
+            return Ok((env, Tail::Value(value)));
+        }
+
+        let mut current_env = env.next();
+        for clause in &self.clauses {
+            if let Clause::Let(bindings) = clause {
+                for binding in bindings {
+                    if let Binding::Let { name, value, .. } = binding {
+                        let (new_env, item) = value.eval(current_env.next(), context)?;
+                        current_env = new_env.prev();
+
+                        let name = resolve_element_qname(name, &current_env);
+                        current_env.set_variable(name, item);
+                    }
+                }
+            }
+        }
+
+        let (new_env, tail) = self.return_expr.eval_tail(current_env, context)?;
+        flat_tail(new_env.prev(), tail)
+    }
+
+    fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
+        todo!()
+    }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        ctx.push_scope();
+        let result = analyze_clauses(&self.clauses, ctx)
+            .and_then(|_| self.return_expr.analyze(ctx));
+        ctx.pop_scope();
+
+        result?;
+        Ok(None)
+    }
 
-        let mut pipe = Pipe { binding: None, where_expr: None, return_expr: Some(self.return_expr.clone()), next: None };
-        for clause in self.clauses.clone().into_iter().rev() {
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        let mut clauses = vec![];
+        for clause in &self.clauses {
             match clause {
                 Clause::For(bindings) => {
-                    for binding in bindings.into_iter().rev() {
-                        pipe = Pipe { binding: Some(binding), where_expr: None, return_expr: None, next: Some(Box::new(pipe)) }
+                    for binding in bindings {
+                        let (binding, usage) = optimize_binding(binding, ctx);
+                        clauses.push((Clause::For(vec![binding]), usage));
                     }
-                },
+                }
                 Clause::Let(bindings) => {
-                    for binding in bindings.into_iter().rev() {
-                        pipe = Pipe { binding: Some(binding), where_expr: None, return_expr: None, next: Some(Box::new(pipe)) }
+                    for binding in bindings {
+                        let (binding, usage) = optimize_binding(binding, ctx);
+                        clauses.push((Clause::Let(vec![binding]), usage));
                     }
-                },
+                }
                 Clause::Where(expr) => {
-                    pipe = Pipe { where_expr: Some(expr), binding: None, return_expr: None, next: Some(Box::new(pipe)) }
+                    let (expr, usage) = ctx.optimize_with_usage(expr);
+                    clauses.push((Clause::Where(expr), usage));
+                }
+                Clause::OrderBy(specs) => {
+                    let mut usage = Usage { references: HashSet::new(), opaque: false, positional: false };
+                    let mut optimized = vec![];
+                    for spec in specs {
+                        let (expr, spec_usage) = ctx.optimize_with_usage(&spec.expr);
+                        usage.references.extend(spec_usage.references);
+                        usage.opaque |= spec_usage.opaque;
+                        usage.positional |= spec_usage.positional;
+
+                        optimized.push(OrderSpec { expr, ..spec.clone() });
+                    }
+                    clauses.push((Clause::OrderBy(optimized), usage));
                 }
             }
         }
 
-        let (new_env, answer) = eval_pipe(Box::new(pipe), current_env.next(), context)?;
-        let current_env = new_env.prev();
+        hoist_lets(&mut clauses, ctx);
 
-        Ok((current_env, answer))
+        Some(Box::new(FLWOR {
+            clauses: clauses.into_iter().map(|(clause, _)| clause).collect(),
+            return_expr: ctx.optimize(&self.return_expr)
+        }))
     }
 
-    fn predicate<'a>(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
-        todo!()
+    fn shape(&self) -> Option<Shape<'_>> {
+        Some(Shape::Flwor(&self.clauses, self.return_expr.as_ref()))
     }
 }
 
@@ -2425,39 +4258,7 @@ impl QuantifiedExpr {
 
 fn process_next(env: Box<Environment>, context: &DynamicContext, op: &QuantifiedOp, index: usize, vars: &Vec<(QName, Option<SequenceType>, Box<dyn Expression>)>, satisfies: &Box<dyn Expression>) -> Result<(Box<Environment>, ControlFlow<bool, bool>), ErrorInfo> {
     if let Some((name, st, seq)) = vars.get(index) {
-
-        let mut current_env = env;
-
-        let (new_env, evaluated) = seq.eval(current_env, context)?;
-        current_env = new_env.next();
-
-        let mut result = if op == &QuantifiedOp::Some { false } else { true };
-
-        let name = current_env.namespaces.resolve(&name);
-        for mut item in evaluated.into_iter() {
-            item = if let Some(st) = &st {
-                st.cascade(&current_env, item)?
-            } else {
-                item
-            };
-            current_env.set_variable(name.clone(), item);
-
-            let (new_env, state) = process_next(current_env, context, op, index + 1, vars, satisfies)?;
-            current_env = new_env;
-
-            match state {
-                ControlFlow::Break(_) => {
-                    current_env = current_env.prev();
-                    return Ok((current_env, state))
-                }
-                ControlFlow::Continue(v) => result = v,
-            }
-        }
-
-        current_env = current_env.prev();
-
-        Ok((current_env, ControlFlow::Continue(result)))
-
+        process_binding(env, context, op, (name, st, seq), index + 1, vars, satisfies)
     } else {
         let (env, value) = satisfies.eval(env, context)?;
 
@@ -2478,43 +4279,88 @@ fn process_next(env: Box<Environment>, context: &DynamicContext, op: &Quantified
     }
 }
 
-impl Expression for QuantifiedExpr {
-    fn eval<'a>(&self, env: Box<Environment>, context: &DynamicContext) -> EvalResult {
-        let mut current_env = env;
+// bind items of sequence one by one, sequence is evaluated only till the answer is known
+fn process_binding(env: Box<Environment>, context: &DynamicContext, op: &QuantifiedOp, binding: (&QName, &Option<SequenceType>, &Box<dyn Expression>), index: usize, vars: &Vec<(QName, Option<SequenceType>, Box<dyn Expression>)>, satisfies: &Box<dyn Expression>) -> Result<(Box<Environment>, ControlFlow<bool, bool>), ErrorInfo> {
+    let (name, st, seq) = binding;
+
+    let mut result = op == &QuantifiedOp::Every;
+    let mut answer = None;
 
-        let (new_env, evaluated) = self.seq.eval(current_env, context)?;
-        current_env = new_env.next();
+    let (current_env, _) = seq.stream(env, context, &mut |env, evaluated| {
+        let mut current_env = env.next();
 
-        let mut result = if self.op == QuantifiedOp::Some { false } else { true };
+        let name = current_env.namespaces.resolve(name);
+        for mut item in flat_items(evaluated) {
+            current_env.step()?;
 
-        let name = current_env.namespaces.resolve(&self.name);
-        for mut item in evaluated.into_iter() {
-            item = if let Some(st) = &self.st {
+            item = if let Some(st) = st {
                 st.cascade(&current_env, item)?
             } else {
                 item
             };
             current_env.set_variable(name.clone(), item);
 
-            let (new_env, state) = process_next(current_env, context, &self.op, 0, &self.vars, &self.satisfies)?;
+            let (new_env, state) = process_next(current_env, context, op, index, vars, satisfies)?;
             current_env = new_env;
 
             match state {
                 ControlFlow::Break(v) => {
-                    result = v;
-                    break;
+                    answer = Some(v);
+                    return Ok((current_env.prev(), ControlFlow::Break(())));
                 }
                 ControlFlow::Continue(v) => result = v,
             }
         }
+        Ok((current_env.prev(), ControlFlow::Continue(())))
+    })?;
 
-        current_env = current_env.prev();
+    match answer {
+        Some(v) => Ok((current_env, ControlFlow::Break(v))),
+        None => Ok((current_env, ControlFlow::Continue(result)))
+    }
+}
+
+impl Expression for QuantifiedExpr {
+    fn eval<'a>(&self, env: Box<Environment>, context: &DynamicContext) -> EvalResult {
+        let binding = (&self.name, &self.st, &self.seq);
+        let (current_env, state) = process_binding(env, context, &self.op, binding, 0, &self.vars, &self.satisfies)?;
+
+        let result = match state {
+            ControlFlow::Break(v) |
+            ControlFlow::Continue(v) => v
+        };
         Ok((current_env, Object::Atomic(Type::Boolean(result))))
     }
 
     fn predicate(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        ctx.push_scope();
+        let result = analyze_quantified(self, ctx);
+        ctx.pop_scope();
+
+        result?;
+        Ok(single(XS_BOOLEAN))
+    }
+
+    fn optimize(&self, ctx: &mut Optimizer) -> Option<Box<dyn Expression>> {
+        Some(Box::new(QuantifiedExpr {
+            op: self.op.clone(),
+            name: self.name.clone(),
+            st: self.st.clone(),
+            seq: ctx.optimize(&self.seq),
+            vars: self.vars.iter()
+                .map(|(name, st, seq)| (name.clone(), st.clone(), ctx.optimize(seq)))
+                .collect(),
+            satisfies: ctx.optimize(&self.satisfies),
+        }))
+    }
+
+    fn shape(&self) -> Option<Shape<'_>> {
+        Some(Shape::Boolean)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -2555,53 +4401,21 @@ impl Expression for ArrowExpr {
 
         if let Some(name) = self.name.as_ref() {
             let name = resolve_function_qname(name, &current_env);
+            if evaluated_arguments.iter().any(|argument| matches!(argument, Object::Placeholder)) {
+                let function = Object::FunctionRef { name, arity: evaluated_arguments.len() };
+                let function = partial_application(&current_env, function, evaluated_arguments)?;
+                return Ok((current_env, function));
+            }
             return call(current_env, name, evaluated_arguments, context);
         } else if let Some(expr) = self.expr.as_ref() {
             let (new_env, value) = expr.eval(current_env, context)?;
             current_env = new_env;
 
-            match value {
-                Object::FunctionRef { name, arity } => {
-                    if arity != evaluated_arguments.len() {
-                        todo!("raise error")
-                    } else {
-                        return call(current_env, name, evaluated_arguments, context);
-                    }
-                }
-                Object::Map(map) => {
-                    if evaluated_arguments.len() != 1 {
-                        todo!("raise error")
-                    } else {
-                        match evaluated_arguments.remove(0) {
-                            Object::Atomic(t) => {
-                                return match map.get(&t) {
-                                    Some(v) => Ok((current_env, v.clone())),
-                                    None => Ok((current_env, Object::Empty))
-                                };
-                            }
-                            _ => todo!("raise error")
-                        }
-                    }
-                    todo!()
-                }
-                Object::Array(items) => {
-                    if evaluated_arguments.len() != 1 {
-                        todo!("raise error")
-                    } else {
-                        let index = evaluated_arguments.remove(0).to_integer()?;
-                        if index >= 1 {
-                            return if let Some(item) = items.get((index - 1) as usize) {
-                                Ok((current_env, item.clone()))
-                            } else {
-                                Ok((current_env, Object::Empty))
-                            };
-                        } else {
-                            todo!("raise error?")
-                        }
-                    }
-                    todo!()
-                }
-                _ => return Err((ErrorCode::XPTY0004, format!("{:?}", value)))
+            if evaluated_arguments.iter().any(|argument| matches!(argument, Object::Placeholder)) {
+                let function = partial_application(&current_env, value, evaluated_arguments)?;
+                Ok((current_env, function))
+            } else {
+                call_item(current_env, value, evaluated_arguments, context)
             }
         } else {
             panic!("internal error")
@@ -2611,6 +4425,29 @@ impl Expression for ArrowExpr {
     fn predicate(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        let source = self.source.analyze(ctx)?;
+
+        let mut arguments = vec![source];
+        for argument in &self.arguments {
+            arguments.push(argument.analyze(ctx)?);
+        }
+
+        if let Some(name) = &self.name {
+            if let Some((params, st)) = ctx.function(name, arguments.len())? {
+                for (argument, param) in arguments.iter().zip(params.iter()) {
+                    ctx.check_type(argument, param)?;
+                }
+                if !arguments.iter().any(is_placeholder) {
+                    return Ok(Some(st));
+                }
+            }
+        } else if let Some(expr) = &self.expr {
+            expr.analyze(ctx)?;
+        }
+        Ok(None)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -2662,6 +4499,16 @@ impl Expression for SwitchExpr {
     fn predicate(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        self.source.analyze(ctx)?;
+        for clause in &self.clauses {
+            ctx.analyze_all(&clause.operands)?;
+            clause.expr.analyze(ctx)?;
+        }
+        self.default_expr.analyze(ctx)?;
+        Ok(None)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -2745,6 +4592,26 @@ impl Expression for TypeswitchExpr {
     fn predicate(&self, env: Box<Environment>, context: &DynamicContext, value: Object) -> EvalResult {
         todo!()
     }
+
+    fn analyze(&self, ctx: &mut StaticContext) -> Result<Option<SequenceType>, ErrorInfo> {
+        self.source.analyze(ctx)?;
+
+        let clauses = self.clauses.iter()
+            .map(|clause| (&clause.name, &clause.expr))
+            .chain(std::iter::once((&self.default_name, &self.default_expr)));
+
+        for (name, expr) in clauses {
+            ctx.push_scope();
+            let result = match name {
+                Some(name) => ctx.declare_variable(name, None),
+                None => Ok(())
+            }.and_then(|_| expr.analyze(ctx));
+            ctx.pop_scope();
+
+            result?;
+        }
+        Ok(None)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -2762,4 +4629,262 @@ impl CaseClause {
     ) -> Self {
         CaseClause { name, stu, expr }
     }
-}
\ No newline at end of file
+}
+fn analyze_suffixes(suffixes: &[PrimaryExprSuffix], ctx: &mut StaticContext) -> Result<(), ErrorInfo> {
+    for PrimaryExprSuffix { predicate, argument_list, lookup } in suffixes {
+        if let Some(predicate) = predicate {
+            predicate.analyze(ctx)?;
+        }
+        if let Some(arguments) = argument_list {
+            ctx.analyze_all(arguments)?;
+        }
+        if let Some(lookup) = lookup {
+            lookup.analyze(ctx)?;
+        }
+    }
+    Ok(())
+}
+
+fn analyze_clauses(clauses: &[Clause], ctx: &mut StaticContext) -> Result<(), ErrorInfo> {
+    for clause in clauses {
+        match clause {
+            Clause::For(bindings) |
+            Clause::Let(bindings) => {
+                for binding in bindings {
+                    match binding {
+                        Binding::For { name, values, st, positional_var, .. } => {
+                            let values = values.analyze(ctx)?;
+                            let item = values.map(|values| SequenceType::exactly_one(values.item_type));
+                            ctx.declare_variable(name, st.clone().or(item))?;
+                            if let Some(positional_var) = positional_var {
+                                ctx.declare_variable(positional_var, single(XS_INTEGER))?;
+                            }
+                        }
+                        Binding::Let { name, st, value } => {
+                            let value = value.analyze(ctx)?;
+                            if let Some(st) = st {
+                                ctx.check_type(&value, st)?;
+                            }
+                            ctx.declare_variable(name, st.clone().or(value))?;
+                        }
+                    }
+                }
+            }
+            Clause::Where(expr) => {
+                expr.analyze(ctx)?;
+            }
+            Clause::OrderBy(specs) => {
+                for spec in specs {
+                    spec.expr.analyze(ctx)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn analyze_quantified(expr: &QuantifiedExpr, ctx: &mut StaticContext) -> Result<(), ErrorInfo> {
+    expr.seq.analyze(ctx)?;
+    ctx.declare_variable(&expr.name, expr.st.clone())?;
+    for (name, st, seq) in &expr.vars {
+        seq.analyze(ctx)?;
+        ctx.declare_variable(name, st.clone())?;
+    }
+    expr.satisfies.analyze(ctx)?;
+    Ok(())
+}
+
+fn is_argument_placeholder(expr: &Box<dyn Expression>) -> bool {
+    matches!(expr.shape(), Some(Shape::Placeholder))
+}
+
+fn is_placeholder(st: &Option<SequenceType>) -> bool {
+    matches!(st, Some(SequenceType { item_type: ItemType::None, .. }))
+}
+
+// `//x` is `descendant::x` when predicates of the step don't depend on position;
+// returns optimized step and whether leading "//" is fused into it
+fn fuse_descendant(expr: &Box<dyn Expression>, ctx: &mut Optimizer) -> (Box<dyn Expression>, bool) {
+    if let Some(Shape::Step(Axis::ForwardChild, test, predicates)) = expr.shape() {
+        let (predicates, is_filter) = optimize_suffixes(predicates, ctx);
+        let axis = if is_filter { Axis::ForwardDescendant } else { Axis::ForwardChild };
+
+        (AxisStep::boxed(Box::new(ForwardStep { axis, test }), predicates), is_filter)
+    } else {
+        (ctx.optimize(expr), false)
+    }
+}
+
+// returns optimized suffixes and whether all of them are predicates which don't depend on position
+fn optimize_suffixes(suffixes: &[PrimaryExprSuffix], ctx: &mut Optimizer) -> (Vec<PrimaryExprSuffix>, bool) {
+    let mut optimized = Vec::with_capacity(suffixes.len());
+    let mut is_filter = true;
+
+    for PrimaryExprSuffix { predicate, argument_list, lookup } in suffixes {
+        let predicate = match predicate {
+            Some(predicate) => {
+                let (predicate, usage) = ctx.optimize_with_usage(predicate);
+                is_filter &= !usage.positional && is_boolean(&predicate);
+                Some(predicate)
+            }
+            None => {
+                is_filter = false;
+                None
+            }
+        };
+        let argument_list = argument_list.as_ref()
+            .map(|arguments| ctx.optimize_all(arguments));
+        let lookup = lookup.as_ref()
+            .map(|lookup| lookup.optimize(ctx));
+
+        optimized.push(PrimaryExprSuffix { predicate, argument_list, lookup });
+    }
+    (optimized, is_filter)
+}
+
+fn constant_position(suffix: &PrimaryExprSuffix) -> Option<usize> {
+    match suffix.predicate.as_ref()?.shape() {
+        Some(Shape::Constant(Object::Atomic(Type::Integer(position)))) => usize::try_from(position.max(0)).ok(),
+        _ => None
+    }
+}
+
+// numeric value of predicate selects item at that position, other values by effective boolean value
+fn is_selected(result: &Object, position: usize) -> Result<bool, ErrorInfo> {
+    let number = match result {
+        Object::Atomic(Type::Integer(number)) => return Ok(usize::try_from(*number) == Ok(position)),
+        Object::Atomic(Type::Decimal(number)) => number.to_f64(),
+        Object::Atomic(Type::Float(number)) => Some(number.into_inner() as f64),
+        Object::Atomic(Type::Double(number)) => Some(number.into_inner()),
+        _ => None
+    };
+    match number {
+        Some(number) => Ok(number == position as f64),
+        None => result.effective_boolean_value()
+    }
+}
+
+fn is_boolean(expr: &Box<dyn Expression>) -> bool {
+    matches!(expr.shape(), Some(Shape::Boolean) | Some(Shape::Constant(Object::Atomic(Type::Boolean(..)))))
+}
+
+// `(a/b)[p]` is `a/b[p]` when predicates don't depend on position
+fn push_predicates(expr: &Box<dyn Expression>, predicates: &[PrimaryExprSuffix]) -> Option<Box<dyn Expression>> {
+    match expr.shape()? {
+        Shape::Sequence(expr) => push_predicates(expr, predicates),
+        Shape::Step(axis, test, current) => {
+            let mut current = current.to_vec();
+            current.extend(predicates.iter().cloned());
+
+            Some(AxisStep::boxed(Box::new(ForwardStep { axis, test }), current))
+        }
+        Shape::Path(initial_node_sequence, expr) => {
+            Some(Box::new(Path {
+                initial_node_sequence: initial_node_sequence.clone(),
+                expr: push_predicates(expr, predicates)?
+            }))
+        }
+        Shape::Steps(steps) => {
+            let (last, steps) = steps.split_last()?;
+
+            let mut steps = steps.to_vec();
+            steps.push(push_predicates(last, predicates)?);
+
+            Some(Steps::new(steps))
+        }
+        _ => None
+    }
+}
+
+fn optimize_binding(binding: &Binding, ctx: &mut Optimizer) -> (Binding, Usage) {
+    match binding {
+        Binding::For { name, values, st, allowing_empty, positional_var } => {
+            let (values, usage) = ctx.optimize_with_usage(values);
+            let binding = Binding::For {
+                name: name.clone(),
+                values,
+                st: st.clone(),
+                allowing_empty: *allowing_empty,
+                positional_var: positional_var.clone()
+            };
+            (binding, usage)
+        }
+        Binding::Let { name, st, value } => {
+            let (value, usage) = ctx.optimize_with_usage(value);
+            (Binding::Let { name: name.clone(), st: st.clone(), value }, usage)
+        }
+    }
+}
+
+// variables bound by single binding clause
+fn bound_names(clause: &Clause, ctx: &Optimizer) -> Vec<QNameResolved> {
+    let mut names = vec![];
+    match clause {
+        Clause::For(bindings) |
+        Clause::Let(bindings) => {
+            for binding in bindings {
+                match binding {
+                    Binding::For { name, positional_var, .. } => {
+                        names.push(ctx.resolve_variable(name));
+                        if let Some(positional_var) = positional_var {
+                            names.push(ctx.resolve_variable(positional_var));
+                        }
+                    }
+                    Binding::Let { name, .. } => names.push(ctx.resolve_variable(name))
+                }
+            }
+        }
+        Clause::Where(..) |
+        Clause::OrderBy(..) => {}
+    }
+    names
+}
+
+// value which can't raise error and is cheap, so it may be evaluated even when `for` has no items
+fn is_safe_to_hoist(expr: &dyn Expression) -> bool {
+    match expr.shape() {
+        Some(Shape::Constant(..)) |
+        Some(Shape::Variable(..)) => true,
+        Some(Shape::Sequence(expr)) => is_safe_to_hoist(expr.as_ref()),
+        _ => false
+    }
+}
+
+// move `let` which doesn't depend on variables of preceding `for` in front of it,
+// so it's evaluated once instead of at each iteration
+fn hoist_lets(clauses: &mut Vec<(Clause, Usage)>, ctx: &Optimizer) {
+    for i in 0..clauses.len() {
+        let (clause, usage) = &clauses[i];
+        let is_safe = match clause {
+            Clause::Let(bindings) => bindings.iter().all(|binding| match binding {
+                Binding::Let { value, .. } => is_safe_to_hoist(value.as_ref()),
+                Binding::For { .. } => false
+            }),
+            _ => false
+        };
+        if !is_safe || usage.opaque {
+            continue;
+        }
+        let names = bound_names(clause, ctx);
+
+        let mut target = i;
+        while target > 0 {
+            let (previous, previous_usage) = &clauses[target - 1];
+            let previous_names = bound_names(previous, ctx);
+
+            let is_independent = previous_names.iter()
+                .all(|name| !usage.references.contains(name) && !names.contains(name))
+                && names.iter().all(|name| !previous_usage.references.contains(name));
+
+            if !is_independent {
+                break;
+            }
+            target -= 1;
+        }
+
+        if clauses[target..i].iter().any(|(clause, _)| matches!(clause, Clause::For(..))) {
+            let clause = clauses.remove(i);
+            clauses.insert(target, clause);
+        }
+    }
+}
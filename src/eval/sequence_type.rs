@@ -1,6 +1,8 @@
 use crate::eval::{Environment, ErrorInfo, Object, Type};
 use crate::values::*;
-use crate::eval::expression::{NodeTest, Expression};
+use crate::eval::expression::NodeTest;
+use crate::eval::prolog::FunctionCoercion;
+use crate::fns::Param;
 use crate::tree::{NodeType, Reference};
 use crate::namespaces::{Namespace, SCHEMA};
 use std::collections::HashMap;
@@ -268,25 +270,232 @@ impl ItemType {
             }
             ItemType::Node(_) => {
                 match right {
-                    ItemType::Node(_) => todo!(),
+                    ItemType::Node(_) => self.is_subtype(env, right) && right.is_subtype(env, self),
                     _ => false
                 }
             }
-            ItemType::SchemaAttribute(_) => {
+            ItemType::SchemaAttribute(l_name) => {
                 match right {
-                    ItemType::SchemaAttribute(_) => todo!(),
+                    ItemType::SchemaAttribute(r_name) => {
+                        env.namespaces.resolve(l_name) == env.namespaces.resolve(r_name)
+                    },
                     _ => false
                 }
             }
             ItemType::Function { .. } => {
                 match right {
-                    ItemType::Function { .. } => todo!(),
+                    ItemType::Function { .. } => self.is_subtype(env, right) && right.is_subtype(env, self),
                     _ => false
                 }
             }
         }
 
     }
+
+    // https://www.w3.org/TR/xpath-31/#id-itemtype-subtype
+    pub(crate) fn is_subtype(&self, env: &Environment, other: &ItemType) -> bool {
+        match (self, other) {
+            (ItemType::None, _) => true,
+            (ItemType::SequenceEmpty, ItemType::SequenceEmpty) => true,
+            (ItemType::SequenceEmpty, _) |
+            (_, ItemType::SequenceEmpty) => false,
+            (_, ItemType::Item) => true,
+
+            (ItemType::AnyAtomicType, ItemType::AnyAtomicType) |
+            (ItemType::AtomicOrUnionType(_), ItemType::AnyAtomicType) => true,
+            (ItemType::AtomicOrUnionType(l_name), ItemType::AtomicOrUnionType(r_name)) => {
+                let l_name = env.namespaces.resolve(l_name);
+                let r_name = env.namespaces.resolve(r_name);
+                if l_name == r_name || r_name.is_same_qn(&XS_ANY_ATOMIC_TYPE) {
+                    true
+                } else if let (Some(l_type), Some(r_type)) = (QNameToTypes.get(&l_name), QNameToTypes.get(&r_name)) {
+                    l_type.derives_from(r_type)
+                } else {
+                    false
+                }
+            }
+
+            (ItemType::AnyKind, ItemType::AnyKind) |
+            (ItemType::Node(_), ItemType::AnyKind) |
+            (ItemType::SchemaAttribute(_), ItemType::AnyKind) => true,
+            (ItemType::AnyKind, ItemType::Node(r_test)) => r_test.is_any_node(),
+            (ItemType::Node(l_test), ItemType::Node(r_test)) => {
+                node_test_is_subtype(env, l_test.as_ref(), r_test.as_ref())
+            }
+            (ItemType::SchemaAttribute(l_name), ItemType::SchemaAttribute(r_name)) => {
+                env.namespaces.resolve(l_name) == env.namespaces.resolve(r_name)
+            }
+
+            (ItemType::Map(l_st), ItemType::Map(r_st)) => {
+                let (l_k, l_v) = map_entry_types(l_st);
+                let (r_k, r_v) = map_entry_types(r_st);
+                l_k.is_subtype(env, &r_k) && l_v.is_subtype(env, &r_v)
+            }
+            (ItemType::Array(l_st), ItemType::Array(r_st)) => {
+                array_member_type(l_st).is_subtype(env, &array_member_type(r_st))
+            }
+
+            (ItemType::Function { .. }, ItemType::Function { args: None, .. }) |
+            (ItemType::Map(_), ItemType::Function { args: None, .. }) |
+            (ItemType::Array(_), ItemType::Function { args: None, .. }) => true,
+            (ItemType::Function { args: None, .. }, ItemType::Function { .. }) => false,
+            (ItemType::Function { args: Some(l_args), st: l_st }, ItemType::Function { args: Some(r_args), st: r_st }) => {
+                // arguments are contravariant, result is covariant
+                l_args.len() == r_args.len()
+                    && r_args.iter().zip(l_args.iter()).all(|(r_arg, l_arg)| r_arg.is_subtype(env, l_arg))
+                    && function_result_type(l_st).is_subtype(env, &function_result_type(r_st))
+            }
+            // map(K, V) is function(xs:anyAtomicType) as V?
+            (ItemType::Map(l_st), ItemType::Function { .. }) => {
+                let (_, value) = map_entry_types(l_st);
+                let st = SequenceType { item_type: value.item_type, occurrence_indicator: value.occurrence_indicator.with_empty() };
+                ItemType::Function {
+                    args: Some(vec![SequenceType::exactly_one(ItemType::AnyAtomicType)]),
+                    st: Some(Box::new(st))
+                }.is_subtype(env, other)
+            }
+            // array(X) is function(xs:integer) as X
+            (ItemType::Array(l_st), ItemType::Function { .. }) => {
+                ItemType::Function {
+                    args: Some(vec![SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_INTEGER.into()))]),
+                    st: Some(Box::new(array_member_type(l_st)))
+                }.is_subtype(env, other)
+            }
+
+            _ => false
+        }
+    }
+
+    // type of function item, map or array as function test
+    fn of_function(env: &Environment, obj: &Object) -> Option<ItemType> {
+        let (args, st) = match obj {
+            Object::Function { parameters, st, .. } => {
+                let args = parameters.iter()
+                    .map(|param| param.sequence_type.clone().unwrap_or_else(|| SequenceType::zero_or_more(ItemType::Item)))
+                    .collect();
+                (args, function_result_type(&st.clone().map(Box::new)))
+            }
+            Object::FunctionRef { name, arity } => {
+                if let Some(function) = env.declared_functions(name, *arity) {
+                    function.signature()
+                } else if let Some((signature, _)) = env.get_function(name, *arity) {
+                    signature
+                } else {
+                    return None;
+                }
+            }
+            _ => return None
+        };
+        Some(ItemType::Function { args: Some(args), st: Some(Box::new(st)) })
+    }
+
+    // function item, map or array matches this function test
+    fn matches_function(&self, env: &Environment, obj: &Object) -> Result<bool, ErrorInfo> {
+        let (args, st) = match self {
+            ItemType::Function { args: Some(args), st } => (args, function_result_type(st)),
+            ItemType::Function { args: None, .. } => {
+                return Ok(matches!(obj, Object::Function { .. } | Object::FunctionRef { .. } | Object::Map(_) | Object::Array(_)))
+            }
+            _ => return Ok(false)
+        };
+
+        match obj {
+            Object::Function { .. } |
+            Object::FunctionRef { .. } => {
+                if let Some(item_type) = ItemType::of_function(env, obj) {
+                    Ok(item_type.is_subtype(env, self))
+                } else {
+                    Ok(false)
+                }
+            }
+            // absent key gives empty sequence, so result type must allow it
+            Object::Map(map) => {
+                if args.len() != 1
+                    || !args[0].is_subtype(env, &SequenceType::exactly_one(ItemType::AnyAtomicType))
+                    || !st.occurrence_indicator.allows_empty()
+                {
+                    return Ok(false);
+                }
+                for value in map.values() {
+                    if !st.check(env, value)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            Object::Array(items) => {
+                if args.len() != 1
+                    || !args[0].is_subtype(env, &SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_INTEGER.into())))
+                {
+                    return Ok(false);
+                }
+                for item in items {
+                    if !st.check(env, item)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            _ => Ok(false)
+        }
+    }
+
+    // function coercion: wrap function item which doesn't match this function test
+    // into one with expected signature, arguments and result are checked at call time
+    fn coerce_function(&self, env: &Environment, obj: Object) -> Result<Object, ErrorInfo> {
+        let arity = match &obj {
+            Object::Function { parameters, .. } => parameters.len(),
+            Object::FunctionRef { arity, .. } => *arity,
+            Object::Map(_) |
+            Object::Array(_) => 1,
+            _ => return Err((ErrorCode::XPTY0004, format!("expected function, got {:?}", obj)))
+        };
+
+        let (args, st) = match self {
+            ItemType::Function { args: Some(args), st } => (args, st),
+            _ => return Ok(obj)
+        };
+
+        if args.len() != arity {
+            return Err((ErrorCode::XPTY0004, format!("expected function with {} arguments, got {}", args.len(), arity)));
+        }
+
+        if self.matches_function(env, &obj)? {
+            return Ok(obj);
+        }
+
+        let names: Vec<QName> = (1..=arity)
+            .map(|i| QName { prefix: None, url: None, local_part: format!("#{}", i) })
+            .collect();
+
+        Ok(Object::Function {
+            parameters: names.iter().zip(args.iter())
+                .map(|(name, st)| Param { name: name.clone(), sequence_type: Some(st.clone()) })
+                .collect(),
+            st: st.as_ref().map(|st| (**st).clone()),
+            body: Box::new(FunctionCoercion { function: Box::new(obj), parameters: names })
+        })
+    }
+}
+
+fn map_entry_types(st: &Option<(Box<SequenceType>, Box<SequenceType>)>) -> (SequenceType, SequenceType) {
+    if let Some((k, v)) = st {
+        ((**k).clone(), (**v).clone())
+    } else {
+        (SequenceType::exactly_one(ItemType::AnyAtomicType), SequenceType::zero_or_more(ItemType::Item))
+    }
+}
+
+fn array_member_type(st: &Option<Box<SequenceType>>) -> SequenceType {
+    if let Some(st) = st {
+        (**st).clone()
+    } else {
+        SequenceType::zero_or_more(ItemType::Item)
+    }
+}
+
+fn function_result_type(st: &Option<Box<SequenceType>>) -> SequenceType {
+    array_member_type(st)
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -297,6 +506,45 @@ pub enum OccurrenceIndicator {
     OneOrMore, // +
 }
 
+impl OccurrenceIndicator {
+    pub(crate) fn allows_empty(&self) -> bool {
+        match self {
+            OccurrenceIndicator::ExactlyOne |
+            OccurrenceIndicator::OneOrMore => false,
+            OccurrenceIndicator::ZeroOrOne |
+            OccurrenceIndicator::ZeroOrMore => true,
+        }
+    }
+
+    pub(crate) fn allows(&self, count: usize) -> bool {
+        match self {
+            OccurrenceIndicator::ExactlyOne => count == 1,
+            OccurrenceIndicator::ZeroOrOne => count <= 1,
+            OccurrenceIndicator::ZeroOrMore => true,
+            OccurrenceIndicator::OneOrMore => count >= 1,
+        }
+    }
+
+    fn with_empty(&self) -> OccurrenceIndicator {
+        match self {
+            OccurrenceIndicator::ExactlyOne |
+            OccurrenceIndicator::ZeroOrOne => OccurrenceIndicator::ZeroOrOne,
+            OccurrenceIndicator::OneOrMore |
+            OccurrenceIndicator::ZeroOrMore => OccurrenceIndicator::ZeroOrMore,
+        }
+    }
+
+    fn is_subset(&self, other: &OccurrenceIndicator) -> bool {
+        match (self, other) {
+            (_, OccurrenceIndicator::ZeroOrMore) |
+            (OccurrenceIndicator::ExactlyOne, _) |
+            (OccurrenceIndicator::ZeroOrOne, OccurrenceIndicator::ZeroOrOne) |
+            (OccurrenceIndicator::OneOrMore, OccurrenceIndicator::OneOrMore) => true,
+            _ => false
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct SequenceType {
     pub(crate) item_type: ItemType,
@@ -402,10 +650,11 @@ impl SequenceType {
                     _ => false
                 }
             }
-            ItemType::Node(l_nt) => {
+            ItemType::Node(_) => {
                 match &other.item_type {
-                    ItemType::Node(r_nt) => {
-                        todo!()
+                    ItemType::Node(_) => {
+                        self.occurrence_indicator == other.occurrence_indicator
+                            && self.item_type.is_same(env, &other.item_type)
                     }
                     _ => false
                 }
@@ -418,11 +667,11 @@ impl SequenceType {
                     _ => false
                 }
             }
-            ItemType::Function { args: l_args, st: l_st } => {
+            ItemType::Function { .. } => {
                 match &other.item_type {
-                    ItemType::Function { args: r_args, st: r_st } => {
-                        // l_args == r_args && l_st == r_st && self.occurrence_indicator == other.occurrence_indicator
-                        todo!()
+                    ItemType::Function { .. } => {
+                        self.occurrence_indicator == other.occurrence_indicator
+                            && self.item_type.is_same(env, &other.item_type)
                     }
                     _ => false
                 }
@@ -430,6 +679,17 @@ impl SequenceType {
         }
     }
 
+    pub(crate) fn is_subtype(&self, env: &Environment, other: &SequenceType) -> bool {
+        match (&self.item_type, &other.item_type) {
+            (ItemType::SequenceEmpty, ItemType::SequenceEmpty) => true,
+            (ItemType::SequenceEmpty, _) => other.occurrence_indicator.allows_empty(),
+            _ => {
+                self.occurrence_indicator.is_subset(&other.occurrence_indicator)
+                    && self.item_type.is_subtype(env, &other.item_type)
+            }
+        }
+    }
+
     pub fn is_not_same(&self, env: &Environment, other: &SequenceType) -> bool {
         !self.is_same(env, other)
     }
@@ -499,11 +759,7 @@ impl SequenceType {
                     }
                     Object::Atomic(_) => Ok(true),
                     Object::Node(rf) => {
-                        if let Ok(data) = rf.to_typed_value() {
-                            Ok(true)
-                        } else {
-                            Ok(false)
-                        }
+                        Ok(rf.to_typed_value().is_ok())
                     }
                     Object::Sequence(items) => {
                         let result = match self.occurrence_indicator {
@@ -554,6 +810,7 @@ impl SequenceType {
                         Object::Atomic(Type::NonPositiveInteger(_)) |
                         Object::Atomic(Type::NegativeInteger(_)) |
                         Object::Atomic(Type::Integer(_)) |
+                        Object::Atomic(Type::BigInteger(_)) |
                         Object::Atomic(Type::Decimal(_)) |
                         Object::Atomic(Type::Float(_)) |
                         Object::Atomic(Type::Double(_)) => Ok(true),
@@ -624,11 +881,7 @@ impl SequenceType {
                             Err((ErrorCode::FOTY0013, String::from("TODO")))
                         }
                         Object::Node(rf) => {
-                            let str = match rf.to_typed_value() {
-                                Ok(str) => str,
-                                Err(msg) => todo!("{}", msg)
-                            };
-                            self.check(env, &Object::Atomic(Type::Untyped(str)))
+                            self.check(env, &typed_value_of_node(&rf)?)
                         }
                         _ => todo!("{:?}", obj),
                     }
@@ -638,11 +891,14 @@ impl SequenceType {
                 match obj {
                     Object::Map(items) => {
                         if self.occurrence_indicator == OccurrenceIndicator::ExactlyOne {
-                            if let Some(item_st) = st {
-                                todo!()
-                            } else {
-                                Ok(true)
+                            if let Some((key_st, value_st)) = st {
+                                for (key, value) in items.iter() {
+                                    if !key_st.check(env, &Object::Atomic(key.clone()))? || !value_st.check(env, value)? {
+                                        return Ok(false);
+                                    }
+                                }
                             }
+                            Ok(true)
                         } else {
                             Ok(false)
                         }
@@ -672,64 +928,21 @@ impl SequenceType {
                     _ => Ok(false)
                 }
             }
-            ItemType::Function { args, st } => {
+            ItemType::Function { .. } => {
                 match obj {
-                    Object::FunctionRef { name, arity } => {
-                        if let Some(((fn_args, fn_st), body)) = env.get_function(&name, *arity) {
-                            println!("FN:\n {:?}\n {:?}", fn_args, fn_st);
-                            if let Some(st) = st {
-                                if st.is_not_same(env, &fn_st) {
-                                    panic!("raise error?")
-                                }
-                            }
-                            if let Some(args) = args {
-                                if args.len() != fn_args.len() {
-                                    panic!("raise error?")
-                                }
-                                for (st, fn_st) in args.into_iter().zip(fn_args.into_iter()) {
-                                    if st.is_not_same(env, &fn_st) {
-                                        panic!("raise error?")
-                                    }
-                                }
-                            }
-                            Ok(true)
-                        } else {
-                            Ok(false)
-                        }
-                    }
-                    Object::Function { parameters, st: fn_st, body } => {
-                        if let Some(st) = st {
-                            if let Some(fn_st) = fn_st.as_ref() {
-                                if st.is_not_same(env, fn_st) {
-                                    panic!("raise error?")
-                                }
-                            } else {
-                                panic!("raise error?")
-                            }
+                    Object::Empty => Ok(self.occurrence_indicator.allows_empty()),
+                    Object::Sequence(items) => {
+                        if !self.occurrence_indicator.allows(items.len()) {
+                            return Ok(false);
                         }
-                        if let Some(args) = args {
-                            if args.len() != parameters.len() {
-                                panic!("raise error?")
-                            }
-                            for (st, param) in args.into_iter().zip(parameters.clone().into_iter()) {
-                                if let Some(fn_st) = &param.sequence_type {
-                                    if st.is_not_same(env, fn_st) {
-                                        panic!("raise error?")
-                                    }
-                                } else {
-                                    todo!()
-                                }
+                        for item in items {
+                            if !self.item_type.matches_function(env, item)? {
+                                return Ok(false);
                             }
                         }
                         Ok(true)
                     }
-                    Object::Map(_) => {
-                        todo!()
-                    }
-                    Object::Array(_) => {
-                        todo!()
-                    }
-                    _ => panic!("TODO: {:?}", obj)
+                    _ => self.item_type.matches_function(env, obj)
                 }
             }
             ItemType::Node(test) => {
@@ -848,18 +1061,14 @@ impl SequenceType {
                             } {
                                 Ok(obj)
                             } else {
-                                panic!("raise error?")
+                                Err((ErrorCode::XPTY0004, format!("{:?} doesn't match occurrence of {:?}", obj, self)))
                             }
                         }
                     }
                     Object::CharRef { .. } |
                     Object::Atomic(_) => Ok(obj),
                     Object::Node(rf) => {
-                        if let Ok(data) = rf.to_typed_value() {
-                            Ok(Object::Atomic(Type::Untyped(data)))
-                        } else {
-                            todo!("raise error?")
-                        }
+                        typed_value_of_node(&rf)
                     }
                     Object::Sequence(items) => {
                         if type_only {
@@ -873,10 +1082,15 @@ impl SequenceType {
                             } {
                                 Ok(Object::Sequence(items))
                             } else {
-                                panic!("raise error?")
+                                Err((ErrorCode::XPTY0004, format!("sequence of {} items doesn't match occurrence of {:?}", items.len(), self)))
                             }
                         }
                     }
+                    // array is atomized to its members
+                    Object::Array(items) => self.cascade(env, Object::Sequence(items.into_iter().collect())),
+                    Object::Map(_) |
+                    Object::Function { .. } |
+                    Object::FunctionRef { .. } => Err((ErrorCode::FOTY0013, format!("{:?} can't be atomized", obj))),
                     _ => todo!("raise error? {:?} {:?}", self.item_type, obj)
                 }
             }
@@ -923,6 +1137,7 @@ impl SequenceType {
                         Object::Atomic(Type::NonPositiveInteger(_)) |
                         Object::Atomic(Type::NegativeInteger(_)) |
                         Object::Atomic(Type::Integer(_)) |
+                        Object::Atomic(Type::BigInteger(_)) |
                         Object::Atomic(Type::Decimal(_)) |
                         Object::Atomic(Type::Float(_)) |
                         Object::Atomic(Type::Double(_)) => Ok(obj),
@@ -1004,18 +1219,14 @@ impl SequenceType {
                                         self.cascade(env, item)?
                                     );
                                 }
-                                Ok(Object::Array(result))
+                                Ok(Object::Array(result.into()))
                             }
                         }
                         Object::Map(items) => {
                             Err((ErrorCode::FOTY0013, String::from("TODO")))
                         }
                         Object::Node(rf) => {
-                            let str = match rf.to_typed_value() {
-                                Ok(str) => str,
-                                Err(msg) => todo!("{}", msg)
-                            };
-                            self.cascade(env, Object::Atomic(Type::Untyped(str)))
+                            self.cascade(env, typed_value_of_node(&rf)?)
                         }
                         _ => todo!("{:?}", obj),
                     }
@@ -1024,102 +1235,72 @@ impl SequenceType {
             ItemType::Map(st) => {
                 match obj {
                     Object::Map(items) => {
-                        if self.occurrence_indicator == OccurrenceIndicator::ExactlyOne {
-                            if let Some(item_st) = st {
-                                todo!()
-                            } else {
-                                Ok(Object::Map(items))
-                            }
+                        let obj = Object::Map(items);
+                        if st.is_none() || self.check(env, &obj)? {
+                            Ok(obj)
                         } else {
-                            panic!("raise error?")
+                            Err((ErrorCode::XPTY0004, format!("map doesn't match {:?}", self)))
                         }
                     }
-                    _ => todo!()
+                    Object::Empty if self.occurrence_indicator.allows_empty() => Ok(obj),
+                    Object::Sequence(items) if self.occurrence_indicator.allows(items.len()) => {
+                        let item_st = SequenceType::exactly_one(self.item_type.clone());
+                        let mut result = Vec::with_capacity(items.len());
+                        for item in items {
+                            result.push(item_st.cascade(env, item)?);
+                        }
+                        Ok(Object::Sequence(result))
+                    }
+                    _ => Err((ErrorCode::XPTY0004, format!("expected map, got {:?}", obj)))
                 }
             }
             ItemType::Array(st) => {
                 match obj {
                     Object::Array(items) => {
-                        if self.occurrence_indicator == OccurrenceIndicator::ExactlyOne {
-                            if let Some(item_st) = st {
-                                let mut result = Vec::with_capacity(items.len());
-                                for item in items {
-                                    result.push(
-                                        item_st.cascade(env, item)?
-                                    );
-                                }
-                                Ok(Object::Array(result))
-                            } else {
-                                Ok(Object::Array(items))
+                        if let Some(item_st) = st {
+                            let mut result = Vec::with_capacity(items.len());
+                            for item in items {
+                                result.push(
+                                    item_st.cascade(env, item)?
+                                );
                             }
+                            Ok(Object::Array(result.into()))
                         } else {
-                            panic!("raise error?")
+                            Ok(Object::Array(items))
                         }
                     },
-                    Object::Sequence(_) => {
-                        todo!()
+                    Object::Empty if self.occurrence_indicator.allows_empty() => Ok(obj),
+                    Object::Sequence(items) if self.occurrence_indicator.allows(items.len()) => {
+                        let item_st = SequenceType::exactly_one(self.item_type.clone());
+                        let mut result = Vec::with_capacity(items.len());
+                        for item in items {
+                            result.push(item_st.cascade(env, item)?);
+                        }
+                        Ok(Object::Sequence(result))
                     }
-                    _ => panic!("raise error?")
+                    _ => Err((ErrorCode::XPTY0004, format!("expected array, got {:?}", obj)))
                 }
             }
-            ItemType::Function { args, st } => {
+            ItemType::Function { .. } => {
                 match obj {
-                    Object::FunctionRef { name, arity } => {
-                        if let Some(((fn_args, fn_st), body)) = env.get_function(&name, arity) {
-                            println!("FN:\n {:?}\n {:?}", fn_args, fn_st);
-                            if let Some(st) = st {
-                                if st.is_not_same(env, &fn_st) {
-                                    panic!("raise error?")
-                                }
-                            }
-                            if let Some(args) = args {
-                                if args.len() != fn_args.len() {
-                                    panic!("raise error?")
-                                }
-                                for (st, fn_st) in args.into_iter().zip(fn_args.into_iter()) {
-                                    if st.is_not_same(env, &fn_st) {
-                                        panic!("raise error?")
-                                    }
-                                }
-                            }
-                            Ok(Object::FunctionRef { name: name.clone(), arity })
+                    Object::Empty => {
+                        if self.occurrence_indicator.allows_empty() {
+                            Ok(obj)
                         } else {
-                            todo!("raise error?")
+                            Err((ErrorCode::XPTY0004, format!("expected {:?}, got empty sequence", self)))
                         }
                     }
-                    Object::Function { parameters, st: fn_st, body } => {
-                        if let Some(st) = st {
-                            if let Some(fn_st) = fn_st.as_ref() {
-                                if st.is_not_same(env, fn_st) {
-                                    panic!("raise error?")
-                                }
-                            } else {
-                                panic!("raise error?")
-                            }
+                    Object::Sequence(items) => {
+                        if !self.occurrence_indicator.allows(items.len()) {
+                            return Err((ErrorCode::XPTY0004, format!("expected {:?}, got {} items", self, items.len())));
                         }
-                        if let Some(args) = args {
-                            if args.len() != parameters.len() {
-                                panic!("raise error?")
-                            }
-                            for (st, param) in args.into_iter().zip(parameters.clone().into_iter()) {
-                                if let Some(fn_st) = &param.sequence_type {
-                                    if st.is_not_same(env, fn_st) {
-                                        panic!("raise error?")
-                                    }
-                                } else {
-                                    todo!()
-                                }
-                            }
+                        let mut result = Vec::with_capacity(items.len());
+                        for item in items {
+                            result.push(self.item_type.coerce_function(env, item)?);
                         }
-                        Ok(Object::Function { parameters, st: fn_st, body })
-                    }
-                    Object::Map(_) => {
-                        todo!()
-                    }
-                    Object::Array(_) => {
-                        todo!()
+                        Ok(Object::Sequence(result))
                     }
-                    _ => panic!("TODO: {:?}", obj)
+                    _ => self.item_type.coerce_function(env, obj)
                 }
             }
             ItemType::Node(test) => {
@@ -1517,7 +1698,8 @@ impl SequenceType {
                                         || name == XS_BOOLEAN
                                         || name == XS_DOUBLE || name == XS_FLOAT || name == XS_DECIMAL || name == XS_INTEGER,
 
-                                Type::Integer(_) =>
+                                Type::Integer(_) |
+                                Type::BigInteger(_) =>
                                     name == XS_NUMERIC
                                         || name == XS_UNTYPED_ATOMIC
                                         || name == XS_STRING || name == XS_NORMALIZED_STRING || name == XS_TOKEN
@@ -1650,11 +1832,7 @@ impl SequenceType {
                             return Err((ErrorCode::FOTY0013, String::from("TODO")));
                         }
                         Object::Node(rf) => {
-                            let str = match rf.to_typed_value() {
-                                Ok(str) => str,
-                                Err(msg) => todo!("{}", msg)
-                            };
-                            self.is_castable_internal(env, &Object::Atomic(Type::Untyped(str)), false, false)?
+                            self.is_castable_internal(env, &typed_value_of_node(&rf)?, false, false)?
                         }
                         _ => todo!("{:?}", obj),
                     }
@@ -1664,7 +1842,7 @@ impl SequenceType {
                 match obj {
                     Object::Map(items) => {
                         if let Some((k_st, v_st)) = st {
-                            for (k, v) in items {
+                            for (k, v) in items.iter() {
                                 if k_st.occurrence_indicator == OccurrenceIndicator::ExactlyOne
                                     // TODO: optimize!!!
                                     && !k_st.is_castable_internal(env,&Object::Atomic(k.clone()), true, false)?
@@ -1719,87 +1897,11 @@ impl SequenceType {
                     _ => false
                 }
             }
-            ItemType::Function { args, st } => {
-                match obj {
-                    Object::FunctionRef { name, arity } => {
-                        if let Some(((fn_args, fn_st), body)) = env.get_function(name, *arity) {
-                            println!("FN:\n {:?}\n {:?}", fn_args, fn_st);
-                            if let Some(st) = st {
-                                if st.is_not_same(env, &fn_st) {
-                                    return Ok(false)
-                                }
-                            }
-                            if let Some(args) = args {
-                                if args.len() != fn_args.len() {
-                                    return Ok(false)
-                                }
-                                for (st, fn_st) in args.into_iter().zip(fn_args.into_iter()) {
-                                    if st.is_not_same(env, &fn_st) {
-                                        return Ok(false)
-                                    }
-                                }
-                            }
-                            return Ok(true)
-                        } else {
-                            todo!("raise error?")
-                        }
-                    }
-                    Object::Function { parameters, st: fn_st, .. } => {
-                        if let Some(st) = st {
-                            if let Some(fn_st) = fn_st {
-                                if st.is_not_same(env, fn_st) {
-                                    return Ok(false)
-                                }
-                            } else {
-                                return Ok(false)
-                            }
-                        }
-                        if let Some(args) = args {
-                            if args.len() != parameters.len() {
-                                return Ok(false)
-                            }
-                            for (st, param) in args.into_iter().zip(parameters.into_iter()) {
-                                if let Some(fn_st) = &param.sequence_type {
-                                    if st.is_not_same(env, fn_st) {
-                                        return Ok(false)
-                                    }
-                                } else {
-                                    todo!()
-                                }
-                            }
-                        }
-                        true
-                    }
-                    Object::Map(items) => {
-                        todo!()
-                    }
-                    Object::Array(_) => {
-                        if let Some(args) = args {
-                            match args.as_slice() {
-                                [SequenceType { item_type: ItemType::AtomicOrUnionType(arg_name), occurrence_indicator: OccurrenceIndicator::ExactlyOne }] => {
-                                    let arg_name = env.namespaces.resolve(arg_name);
-                                    if arg_name.is_same_qn(&XS_INTEGER) {
-                                        if let Some(st) = st {
-                                            match **st {
-                                                SequenceType { item_type: ItemType::Item, occurrence_indicator: OccurrenceIndicator::ZeroOrMore } => {
-                                                    true
-                                                },
-                                                _ => false
-                                            }
-                                        } else {
-                                            false
-                                        }
-                                    } else {
-                                        false
-                                    }
-                                }
-                                _ => false
-                            }
-                        } else {
-                            true
-                        }
-                    }
-                    _ => panic!("TODO: {:?}", obj)
+            ItemType::Function { .. } => {
+                if type_only {
+                    self.item_type.matches_function(env, obj)?
+                } else {
+                    self.check(env, obj)?
                 }
             }
             _ => panic!("TODO: {:?}", self.item_type)
@@ -1808,6 +1910,54 @@ impl SequenceType {
     }
 }
 
+// every node matched by left test is matched by right one; tests are compared by kind,
+// resolved names and nested tests
+fn node_test_is_subtype(env: &Environment, l_test: &dyn NodeTest, r_test: &dyn NodeTest) -> bool {
+    if r_test.is_any_node() {
+        return true;
+    }
+    if l_test.is_any_node() {
+        return false;
+    }
+    if l_test.is_namespace_node() || r_test.is_namespace_node() {
+        return l_test.is_namespace_node() && r_test.is_namespace_node();
+    }
+
+    let same_name = |l: Option<&QName>, r: Option<&QName>| match (l, r) {
+        (_, None) => true,
+        (Some(l_name), Some(r_name)) => env.namespaces.resolve(l_name) == env.namespaces.resolve(r_name),
+        (None, Some(_)) => false,
+    };
+
+    match (l_test.node_type(), r_test.node_type()) {
+        (Some(l_type), Some(r_type)) if l_type == r_type => {
+            // element(a) isn't known to be declared by schema
+            if r_test.is_schema_test() && !l_test.is_schema_test() {
+                return false;
+            }
+            let same_type = match (l_test.type_name(), r_test.type_name()) {
+                (_, None) => true,
+                (Some(l_name), Some(r_name)) => {
+                    ItemType::AtomicOrUnionType(l_name.clone()).is_subtype(env, &ItemType::AtomicOrUnionType(r_name.clone()))
+                }
+                (None, Some(_)) => false,
+            };
+            let same_target = match (l_test.pi_target(), r_test.pi_target()) {
+                (_, None) => true,
+                (Some(l_target), Some(r_target)) => l_target == r_target,
+                (None, Some(_)) => false,
+            };
+            let same_child = match (l_test.child_test(), r_test.child_test()) {
+                (_, None) => true,
+                (Some(l_child), Some(r_child)) => node_test_is_subtype(env, l_child, r_child),
+                (None, Some(_)) => false,
+            };
+            same_name(l_test.node_name(), r_test.node_name()) && same_type && same_target && same_child
+        }
+        _ => false
+    }
+}
+
 #[derive(Clone, Debug)]
 pub(crate) struct AnyKindTest { }
 
@@ -1821,6 +1971,10 @@ impl NodeTest for AnyKindTest {
     fn test_node(&self, rf: &Reference) -> bool {
         true
     }
+
+    fn is_any_node(&self) -> bool {
+        true
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -1838,6 +1992,14 @@ impl NodeTest for DocumentTest {
     fn test_node(&self, rf: &Reference) -> bool {
         todo!()
     }
+
+    fn node_type(&self) -> Option<NodeType> {
+        Some(NodeType::Document)
+    }
+
+    fn child_test(&self) -> Option<&dyn NodeTest> {
+        self.child.as_deref()
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -1854,6 +2016,10 @@ impl NodeTest for TextTest {
     fn test_node(&self, rf: &Reference) -> bool {
         rf.is_text()
     }
+
+    fn node_type(&self) -> Option<NodeType> {
+        Some(NodeType::Text)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -1870,6 +2036,10 @@ impl NodeTest for CommentTest {
     fn test_node(&self, rf: &Reference) -> bool {
         rf.is_comment()
     }
+
+    fn node_type(&self) -> Option<NodeType> {
+        Some(NodeType::Comment)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -1886,16 +2056,20 @@ impl NodeTest for NamespaceNodeTest {
     fn test_node(&self, rf: &Reference) -> bool {
         rf.is_namespace()
     }
+
+    fn is_namespace_node(&self) -> bool {
+        true
+    }
 }
 
 #[derive(Clone, Debug)]
 pub(crate) struct PITest {
-    content: Option<Box<dyn Expression>>
+    target: Option<String>
 }
 
 impl PITest {
-    pub(crate) fn boxed(content: Option<Box<dyn Expression>>) -> Box<dyn NodeTest> {
-        Box::new(PITest { content })
+    pub(crate) fn boxed(target: Option<String>) -> Box<dyn NodeTest> {
+        Box::new(PITest { target })
     }
 }
 
@@ -1903,6 +2077,14 @@ impl NodeTest for PITest {
     fn test_node(&self, rf: &Reference) -> bool {
         todo!()
     }
+
+    fn node_type(&self) -> Option<NodeType> {
+        Some(NodeType::PI)
+    }
+
+    fn pi_target(&self) -> Option<&str> {
+        self.target.as_deref()
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -1940,6 +2122,17 @@ impl NodeTest for ElementTest {
         }
         return true;
     }
+
+    fn node_type(&self) -> Option<NodeType> {
+        Some(NodeType::Element)
+    }
+
+    fn node_name(&self) -> Option<&QName> {
+        self.name.as_ref()
+    }
+    fn type_name(&self) -> Option<&QName> {
+        self.type_annotation.as_ref()
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -1974,6 +2167,17 @@ impl NodeTest for AttributeTest {
             false
         }
     }
+
+    fn node_type(&self) -> Option<NodeType> {
+        Some(NodeType::Attribute)
+    }
+
+    fn node_name(&self) -> Option<&QName> {
+        self.name.as_ref()
+    }
+    fn type_name(&self) -> Option<&QName> {
+        self.type_annotation.as_ref()
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -1991,6 +2195,18 @@ impl NodeTest for SchemaElementTest {
     fn test_node(&self, rf: &Reference) -> bool {
         todo!()
     }
+
+    fn node_type(&self) -> Option<NodeType> {
+        Some(NodeType::Element)
+    }
+
+    fn node_name(&self) -> Option<&QName> {
+        Some(&self.name)
+    }
+
+    fn is_schema_test(&self) -> bool {
+        true
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -2008,6 +2224,18 @@ impl NodeTest for SchemaAttributeTest {
     fn test_node(&self, rf: &Reference) -> bool {
         todo!()
     }
+
+    fn node_type(&self) -> Option<NodeType> {
+        Some(NodeType::Attribute)
+    }
+
+    fn node_name(&self) -> Option<&QName> {
+        Some(&self.name)
+    }
+
+    fn is_schema_test(&self) -> bool {
+        true
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -2028,4 +2256,8 @@ impl NodeTest for NameTest {
             false
         }
     }
-}
\ No newline at end of file
+
+    fn indexed_name(&self) -> Option<&QName> {
+        Some(&self.name)
+    }
+}
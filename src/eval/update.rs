@@ -0,0 +1,495 @@
+use std::rc::Rc;
+use std::sync::Mutex;
+use crate::eval::{Environment, ErrorInfo, Object, Type, atomization, object_owned_to_sequence};
+use crate::parser::errors::ErrorCode;
+use crate::tree::{copy_node, InMemoryXMLTree, InsertPosition, NodeType, Reference, XMLStorage, XMLTreeWriter, DLN};
+use crate::values::QName;
+
+// update primitives of XQuery Update Facility 3.0
+#[derive(Debug, Clone)]
+pub(crate) enum UpdatePrimitive {
+    Insert { target: Reference, position: InsertPosition, nodes: Vec<Reference> },
+    InsertAttributes { target: Reference, attributes: Vec<Reference> },
+    Delete { target: Reference },
+    ReplaceNode { target: Reference, replacement: Vec<Reference> },
+    ReplaceValue { target: Reference, value: String },
+    ReplaceElementContent { target: Reference, text: Option<Reference> },
+    Rename { target: Reference, name: QName },
+    Put { node: Reference, uri: String },
+}
+
+impl UpdatePrimitive {
+    pub(crate) fn target(&self) -> &Reference {
+        match self {
+            UpdatePrimitive::Insert { target, .. } |
+            UpdatePrimitive::InsertAttributes { target, .. } |
+            UpdatePrimitive::Delete { target } |
+            UpdatePrimitive::ReplaceNode { target, .. } |
+            UpdatePrimitive::ReplaceValue { target, .. } |
+            UpdatePrimitive::ReplaceElementContent { target, .. } |
+            UpdatePrimitive::Rename { target, .. } => target,
+            UpdatePrimitive::Put { node, .. } => node,
+        }
+    }
+
+    fn target_mut(&mut self) -> &mut Reference {
+        match self {
+            UpdatePrimitive::Insert { target, .. } |
+            UpdatePrimitive::InsertAttributes { target, .. } |
+            UpdatePrimitive::Delete { target } |
+            UpdatePrimitive::ReplaceNode { target, .. } |
+            UpdatePrimitive::ReplaceValue { target, .. } |
+            UpdatePrimitive::ReplaceElementContent { target, .. } |
+            UpdatePrimitive::Rename { target, .. } => target,
+            UpdatePrimitive::Put { node, .. } => node,
+        }
+    }
+
+    // order of application, see upd:applyUpdates
+    fn stage(&self) -> usize {
+        match self {
+            UpdatePrimitive::Insert { position, .. } => {
+                if position == &InsertPosition::Into { 1 } else { 2 }
+            }
+            UpdatePrimitive::InsertAttributes { .. } |
+            UpdatePrimitive::ReplaceValue { .. } |
+            UpdatePrimitive::Rename { .. } => 1,
+            UpdatePrimitive::ReplaceNode { .. } => 3,
+            UpdatePrimitive::ReplaceElementContent { .. } => 4,
+            UpdatePrimitive::Delete { .. } => 5,
+            UpdatePrimitive::Put { .. } => 6,
+        }
+    }
+
+    fn conflicts(&self, other: &UpdatePrimitive) -> Option<ErrorCode> {
+        match (self, other) {
+            (UpdatePrimitive::Rename { target: a, .. }, UpdatePrimitive::Rename { target: b, .. }) => {
+                if a == b { Some(ErrorCode::XUDY0015) } else { None }
+            }
+            (UpdatePrimitive::ReplaceNode { target: a, .. }, UpdatePrimitive::ReplaceNode { target: b, .. }) => {
+                if a == b { Some(ErrorCode::XUDY0016) } else { None }
+            }
+            (UpdatePrimitive::ReplaceValue { target: a, .. }, UpdatePrimitive::ReplaceValue { target: b, .. }) |
+            (UpdatePrimitive::ReplaceElementContent { target: a, .. }, UpdatePrimitive::ReplaceElementContent { target: b, .. }) => {
+                if a == b { Some(ErrorCode::XUDY0017) } else { None }
+            }
+            (UpdatePrimitive::Put { uri: a, .. }, UpdatePrimitive::Put { uri: b, .. }) => {
+                if a == b { Some(ErrorCode::XUDY0031) } else { None }
+            }
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PendingUpdateList {
+    primitives: Vec<UpdatePrimitive>,
+}
+
+impl PendingUpdateList {
+    pub fn new() -> Self {
+        PendingUpdateList { primitives: vec![] }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.primitives.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.primitives.len()
+    }
+
+    pub(crate) fn add(&mut self, primitive: UpdatePrimitive) -> Result<(), ErrorInfo> {
+        for current in &self.primitives {
+            if let Some(code) = current.conflicts(&primitive) {
+                return Err((code, format!("incompatible updates of {:?}", primitive.target())));
+            }
+        }
+        self.primitives.push(primitive);
+        Ok(())
+    }
+
+    pub(crate) fn targets(&self) -> impl Iterator<Item = &Reference> {
+        self.primitives.iter().map(|primitive| primitive.target())
+    }
+
+    // ids of trees changed by this list
+    pub(crate) fn trees(&self) -> Vec<usize> {
+        let mut trees = vec![];
+        for primitive in &self.primitives {
+            if let UpdatePrimitive::Put { .. } = primitive {
+                continue;
+            }
+            let id = primitive.target().xml_tree_id();
+            if !trees.contains(&id) {
+                trees.push(id);
+            }
+        }
+        trees
+    }
+
+    pub(crate) fn apply(self, documents: &Rc<Mutex<Box<dyn XMLStorage>>>) -> Result<(), ErrorInfo> {
+        let mut rest = self.primitives;
+        // stable sort keeps order of evaluation inside of every stage
+        rest.sort_by_key(|primitive| primitive.stage());
+
+        while !rest.is_empty() {
+            let primitive = rest.remove(0);
+            let (storage, moves) = apply_primitive(primitive, documents)?;
+            // renumbered siblings must be followed by the rest of primitives
+            for batch in moves {
+                for primitive in rest.iter_mut() {
+                    let target = primitive.target_mut();
+                    if Rc::ptr_eq(&target.storage, &storage) {
+                        target.id = moved(&target.id, &batch);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn apply_primitive(
+    primitive: UpdatePrimitive,
+    documents: &Rc<Mutex<Box<dyn XMLStorage>>>
+) -> Result<(Rc<Mutex<Box<dyn XMLTreeWriter>>>, Vec<Vec<(DLN, DLN)>>), ErrorInfo> {
+    let storage = primitive.target().storage.clone();
+    let moves = match primitive {
+        UpdatePrimitive::Insert { target, position, nodes } => {
+            vec![storage.lock().unwrap().insert(&target, position, &nodes)?]
+        }
+        UpdatePrimitive::InsertAttributes { target, attributes } => {
+            storage.lock().unwrap().insert_attributes(&target, &attributes)?;
+            vec![]
+        }
+        UpdatePrimitive::Delete { target } => {
+            vec![storage.lock().unwrap().delete(&target)?]
+        }
+        UpdatePrimitive::ReplaceNode { target, replacement } => {
+            if target.attr_name.is_some() {
+                let element = Reference { storage: target.storage.clone(), id: target.id.clone(), attr_name: None };
+                let mut writer = storage.lock().unwrap();
+                writer.delete(&target)?;
+                writer.insert_attributes(&element, &replacement)?;
+                vec![]
+            } else {
+                let mut writer = storage.lock().unwrap();
+                let inserted = writer.insert(&target, InsertPosition::Before, &replacement)?;
+                let target = Reference { storage: target.storage.clone(), id: moved(&target.id, &inserted), attr_name: None };
+                let deleted = writer.delete(&target)?;
+                vec![inserted, deleted]
+            }
+        }
+        UpdatePrimitive::ReplaceValue { target, value } => {
+            storage.lock().unwrap().replace_value(&target, value)?;
+            vec![]
+        }
+        UpdatePrimitive::ReplaceElementContent { target, text } => {
+            let children = target.forward(&None, &crate::eval::Axis::ForwardChild);
+            let mut writer = storage.lock().unwrap();
+            // last child first, so ids of preceding ones stay the same
+            for child in children.iter().rev() {
+                writer.delete(child)?;
+            }
+            if let Some(text) = text {
+                writer.insert(&target, InsertPosition::Into, &vec![text])?;
+            }
+            // children of target are gone, no one else is moved
+            vec![]
+        }
+        UpdatePrimitive::Rename { target, name } => {
+            storage.lock().unwrap().rename(&target, name)?;
+            vec![]
+        }
+        UpdatePrimitive::Put { node, uri } => {
+            documents.lock().unwrap().save(&uri, &node)?;
+            vec![]
+        }
+    };
+    Ok((storage, moves))
+}
+
+fn is_within(id: &DLN, root: &DLN) -> bool {
+    id.level_ids().starts_with(&root.level_ids())
+}
+
+fn moved(id: &DLN, moves: &Vec<(DLN, DLN)>) -> DLN {
+    for (from, to) in moves {
+        if is_within(id, from) {
+            return id.rebase(from, to);
+        }
+    }
+    id.clone()
+}
+
+fn node_type(rf: &Reference) -> Option<NodeType> {
+    if rf.attr_name.is_some() {
+        Some(NodeType::Attribute)
+    } else {
+        rf.get_type()
+    }
+}
+
+fn parent_of(rf: &Reference) -> Option<Reference> {
+    if rf.attr_name.is_some() {
+        let element = Reference { storage: rf.storage.clone(), id: rf.id.clone(), attr_name: None };
+        // standalone attribute is kept by unnamed holder
+        if element.name().is_some() { Some(element) } else { None }
+    } else {
+        rf.parent()
+    }
+}
+
+fn single_target(target: Object, code: ErrorCode) -> Result<Reference, ErrorInfo> {
+    let mut items = object_owned_to_sequence(target);
+    if items.is_empty() {
+        return Err((ErrorCode::XUDY0027, String::from("target is empty sequence")));
+    }
+    if items.len() != 1 {
+        return Err((code, String::from("target must be a single node")));
+    }
+    match items.remove(0) {
+        Object::Node(rf) => Ok(rf.resolve()),
+        _ => Err((code, String::from("target must be a node")))
+    }
+}
+
+// copies of nodes, adjacent atomic values are joined into text node
+fn copy_sequence(env: &mut Box<Environment>, items: Vec<Object>) -> Result<Vec<Reference>, ErrorInfo> {
+    let mut result = vec![];
+    let mut atomics: Vec<String> = vec![];
+    for item in items {
+        match item {
+            Object::Node(rf) => {
+                copy_text(env, &mut atomics, &mut result);
+
+                let storage = InMemoryXMLTree::create(env.next_id());
+                let mut writer = storage.lock().unwrap();
+                if let Some(copy) = copy_node(&mut writer, &rf) {
+                    result.push(copy);
+                }
+            }
+            Object::Atomic(..) => atomics.push(item.to_string()?),
+            _ => return Err((ErrorCode::XPTY0004, format!("can't copy {:?}", item)))
+        }
+    }
+    copy_text(env, &mut atomics, &mut result);
+    Ok(result)
+}
+
+fn copy_text(env: &mut Box<Environment>, atomics: &mut Vec<String>, result: &mut Vec<Reference>) {
+    if !atomics.is_empty() {
+        let storage = InMemoryXMLTree::create(env.next_id());
+        let mut writer = storage.lock().unwrap();
+        result.push(writer.text(atomics.join(" ")));
+        atomics.clear();
+    }
+}
+
+// copy of node into new tree, used by copy-modify expression
+pub(crate) fn copy(env: &mut Box<Environment>, rf: &Reference) -> Option<Reference> {
+    let storage = InMemoryXMLTree::create(env.next_id());
+    let mut writer = storage.lock().unwrap();
+    copy_node(&mut writer, rf)
+}
+
+pub(crate) fn insert(env: &mut Box<Environment>, source: Object, position: InsertPosition, target: Object) -> Result<(), ErrorInfo> {
+    let items = object_owned_to_sequence(source);
+
+    let mut seen_content = false;
+    for item in &items {
+        match item {
+            Object::Node(rf) if node_type(rf) == Some(NodeType::Attribute) => {
+                if seen_content {
+                    return Err((ErrorCode::XUTY0004, String::from("attribute nodes must precede other nodes")));
+                }
+            }
+            _ => seen_content = true
+        }
+    }
+
+    let copies = copy_sequence(env, items)?;
+    let (attributes, content): (Vec<Reference>, Vec<Reference>) = copies.into_iter()
+        .partition(|rf| rf.attr_name.is_some());
+
+    match position {
+        InsertPosition::Into |
+        InsertPosition::AsFirstInto |
+        InsertPosition::AsLastInto => {
+            let target = single_target(target, ErrorCode::XUTY0005)?;
+            match node_type(&target) {
+                Some(NodeType::Element) => {}
+                Some(NodeType::Document) => {
+                    if !attributes.is_empty() {
+                        return Err((ErrorCode::XUTY0022, String::from("attributes can't be inserted into document node")));
+                    }
+                }
+                _ => return Err((ErrorCode::XUTY0005, String::from("target must be element or document node")))
+            }
+            if !attributes.is_empty() {
+                env.pending_updates().add(UpdatePrimitive::InsertAttributes { target: target.clone(), attributes })?;
+            }
+            if !content.is_empty() {
+                env.pending_updates().add(UpdatePrimitive::Insert { target, position, nodes: content })?;
+            }
+        }
+        InsertPosition::Before |
+        InsertPosition::After => {
+            let target = single_target(target, ErrorCode::XUTY0006)?;
+            match node_type(&target) {
+                Some(NodeType::Element) |
+                Some(NodeType::Text) |
+                Some(NodeType::Comment) |
+                Some(NodeType::PI) => {}
+                _ => return Err((ErrorCode::XUTY0006, String::from("target must be element, text, comment or processing-instruction node")))
+            }
+            let parent = match parent_of(&target) {
+                Some(parent) => parent,
+                None => return Err((ErrorCode::XUDY0029, String::from("target node has no parent")))
+            };
+            if !attributes.is_empty() {
+                if node_type(&parent) != Some(NodeType::Element) {
+                    return Err((ErrorCode::XUDY0030, String::from("attributes can't be inserted into document node")));
+                }
+                env.pending_updates().add(UpdatePrimitive::InsertAttributes { target: parent, attributes })?;
+            }
+            if !content.is_empty() {
+                env.pending_updates().add(UpdatePrimitive::Insert { target, position, nodes: content })?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn delete(env: &mut Box<Environment>, target: Object) -> Result<(), ErrorInfo> {
+    for item in object_owned_to_sequence(target) {
+        match item {
+            Object::Node(rf) => {
+                let rf = rf.resolve();
+                if parent_of(&rf).is_some() {
+                    env.pending_updates().add(UpdatePrimitive::Delete { target: rf })?;
+                }
+            }
+            _ => return Err((ErrorCode::XUTY0007, String::from("target must be sequence of nodes")))
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn replace_node(env: &mut Box<Environment>, target: Object, with: Object) -> Result<(), ErrorInfo> {
+    let target = single_target(target, ErrorCode::XUTY0008)?;
+    let target_type = node_type(&target);
+    if target_type.is_none() || target_type == Some(NodeType::Document) {
+        return Err((ErrorCode::XUTY0008, String::from("target can't be document node")));
+    }
+    if parent_of(&target).is_none() {
+        return Err((ErrorCode::XUDY0009, String::from("target node has no parent")));
+    }
+
+    let items = object_owned_to_sequence(with);
+    let replacement = copy_sequence(env, items)?;
+    if target_type == Some(NodeType::Attribute) {
+        if replacement.iter().any(|rf| rf.attr_name.is_none()) {
+            return Err((ErrorCode::XUTY0011, String::from("attribute can be replaced only by attributes")));
+        }
+    } else if replacement.iter().any(|rf| rf.attr_name.is_some()) {
+        return Err((ErrorCode::XUTY0010, String::from("node can't be replaced by attributes")));
+    }
+
+    env.pending_updates().add(UpdatePrimitive::ReplaceNode { target, replacement })
+}
+
+pub(crate) fn replace_value(env: &mut Box<Environment>, target: Object, with: Object) -> Result<(), ErrorInfo> {
+    let target = single_target(target, ErrorCode::XUTY0008)?;
+    let value = match atomization(env, with)? {
+        Object::Empty => String::new(),
+        value => value.to_string()?
+    };
+
+    match node_type(&target) {
+        Some(NodeType::Element) => {
+            let text = if value.is_empty() {
+                None
+            } else {
+                let storage = InMemoryXMLTree::create(env.next_id());
+                let mut writer = storage.lock().unwrap();
+                Some(writer.text(value))
+            };
+            env.pending_updates().add(UpdatePrimitive::ReplaceElementContent { target, text })
+        }
+        Some(NodeType::Comment) => {
+            if value.contains("--") || value.ends_with("-") {
+                return Err((ErrorCode::XQDY0072, String::from("invalid comment content")));
+            }
+            env.pending_updates().add(UpdatePrimitive::ReplaceValue { target, value })
+        }
+        Some(NodeType::PI) => {
+            if value.contains("?>") {
+                return Err((ErrorCode::XQDY0026, String::from("invalid processing-instruction content")));
+            }
+            env.pending_updates().add(UpdatePrimitive::ReplaceValue { target, value })
+        }
+        Some(NodeType::Attribute) |
+        Some(NodeType::Text) => {
+            env.pending_updates().add(UpdatePrimitive::ReplaceValue { target, value })
+        }
+        _ => Err((ErrorCode::XUTY0008, String::from("target can't be document node")))
+    }
+}
+
+pub(crate) fn rename(env: &mut Box<Environment>, target: Object, name: Object) -> Result<(), ErrorInfo> {
+    let target = single_target(target, ErrorCode::XUTY0012)?;
+    match node_type(&target) {
+        Some(NodeType::Element) |
+        Some(NodeType::Attribute) |
+        Some(NodeType::PI) => {}
+        _ => return Err((ErrorCode::XUTY0012, String::from("target must be element, attribute or processing-instruction node")))
+    }
+
+    let name = match atomization(env, name)? {
+        Object::Atomic(Type::QName { prefix, url, local_part }) => QName { prefix, url, local_part },
+        Object::Atomic(Type::String(str)) |
+        Object::Atomic(Type::Untyped(str)) => lexical_qname(env, str.trim())?,
+        _ => return Err((ErrorCode::XPTY0004, String::from("new name must be xs:QName or xs:string")))
+    };
+
+    env.pending_updates().add(UpdatePrimitive::Rename { target, name })
+}
+
+fn lexical_qname(env: &Box<Environment>, str: &str) -> Result<QName, ErrorInfo> {
+    let is_ncname = |s: &str| {
+        let mut chars = s.chars();
+        match chars.next() {
+            Some(c) if c.is_alphabetic() || c == '_' => {
+                chars.all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == '.')
+            }
+            _ => false
+        }
+    };
+
+    let parts: Vec<&str> = str.split(":").collect();
+    match parts.as_slice() {
+        [local_part] if is_ncname(local_part) => Ok(QName::local_part(*local_part)),
+        [prefix, local_part] if is_ncname(prefix) && is_ncname(local_part) => {
+            match env.namespaces.by_prefix(prefix) {
+                Some(ns) => Ok(QName::full(prefix.to_string(), local_part.to_string(), ns.uri.to_string())),
+                None => Err((ErrorCode::XQDY0074, format!("unknown prefix {:?}", prefix)))
+            }
+        }
+        _ => Err((ErrorCode::XQDY0074, format!("invalid name {:?}", str)))
+    }
+}
+
+pub(crate) fn put(env: &mut Box<Environment>, node: Reference, uri: String) -> Result<(), ErrorInfo> {
+    let node = node.resolve();
+    match node_type(&node) {
+        Some(NodeType::Document) |
+        Some(NodeType::Element) => {}
+        _ => return Err((ErrorCode::FOUP0001, String::from("first operand of fn:put must be document or element node")))
+    }
+    if uri.is_empty() || uri.contains(char::is_whitespace) {
+        return Err((ErrorCode::FOUP0002, format!("invalid uri {:?}", uri)));
+    }
+    env.pending_updates().add(UpdatePrimitive::Put { node, uri })
+}
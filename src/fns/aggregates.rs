@@ -1,10 +1,13 @@
 use crate::eval::{Environment, Object, Type, EvalResult, DynamicContext, comparison, ErrorInfo};
 use crate::eval::sequence_type::*;
+use crate::eval::helpers::flat_items;
 use crate::fns::FUNCTION;
 
 use bigdecimal::{BigDecimal, FromPrimitive};
+use bigdecimal::num_bigint::BigInt;
 use crate::parser::errors::ErrorCode;
-use crate::values::Types;
+use crate::parser::op::OperatorArithmetic;
+use crate::values::{Types, typed_value_of_node};
 
 // fn:count($arg as item()*) as xs:integer
 pub(crate) fn FN_COUNT() -> FUNCTION {
@@ -26,14 +29,8 @@ pub(crate) fn fn_count(env: Box<Environment>, arguments: Vec<Object>, _context:
             Ok((env, Object::Atomic(Type::Integer(1))))
         }
         [Object::Range { min, max}] => {
-            let min = *min;
-            let max = *max;
-            let count = if min <= max {
-                max - min + 1
-            } else {
-                min - max + 1
-            };
-            Ok((env, Object::Atomic(Type::Integer(count))))
+            let count = BigInt::from(max.abs_diff(*min)) + 1u32;
+            Ok((env, Object::Atomic(Type::integer(count))))
         },
         [Object::Sequence(items)] => {
             let mut count = 0;
@@ -51,7 +48,7 @@ pub(crate) fn fn_count(env: Box<Environment>, arguments: Vec<Object>, _context:
             }
             Ok((env, Object::Atomic(Type::Integer(count))))
         },
-        [Object::Node(..)] => {
+        [Object::Node(..) | Object::Map(..) | Object::Array(..) | Object::Function { .. } | Object::FunctionRef { .. }] => {
             Ok((env, Object::Atomic(Type::Integer(1))))
         }
         _ => panic!("error {:?}", arguments)
@@ -232,6 +229,19 @@ pub(crate) fn FN_SUM_2() -> FUNCTION {
     )
 }
 
+// items with nodes replaced by their typed values, list types give several items
+fn atomized_items(arg: Object) -> impl Iterator<Item = Result<Object, ErrorInfo>> {
+    arg.into_iter().flat_map(|item| -> Box<dyn Iterator<Item = Result<Object, ErrorInfo>>> {
+        match item {
+            Object::Node(rf) => match typed_value_of_node(&rf) {
+                Ok(value) => Box::new(flat_items(value).map(Ok)),
+                Err(e) => Box::new(std::iter::once(Err(e)))
+            },
+            _ => Box::new(std::iter::once(Ok(item)))
+        }
+    })
+}
+
 fn extract_number_or_duration(obj: Object) -> Result<Type, ErrorInfo> {
     match obj {
         Object::Atomic(t) => {
@@ -252,6 +262,7 @@ fn extract_number_or_duration(obj: Object) -> Result<Type, ErrorInfo> {
                 Type::NegativeInteger(_) |
 
                 Type::Integer(_) |
+                Type::BigInteger(_) |
                 Type::Decimal(_) |
                 Type::Float(_) |
                 Type::Double(_) |
@@ -259,9 +270,18 @@ fn extract_number_or_duration(obj: Object) -> Result<Type, ErrorInfo> {
                 Type::YearMonthDuration  { .. } |
                 Type::DayTimeDuration { .. }  => Ok(t),
 
+                // untyped values are cast to xs:double
+                Type::Untyped(_) => t.convert(Types::Double),
+
                 _ => Err(ErrorCode::forg0006(format!("{:?}", t)))
             }
         }
+        Object::Node(rf) => {
+            match typed_value_of_node(&rf)? {
+                Object::Atomic(t) => extract_number_or_duration(Object::Atomic(t)),
+                value => Err((ErrorCode::XPTY0004, format!("single atomic value expected but got {:?}", value)))
+            }
+        }
         _ => Err(ErrorCode::forg0006(obj.to_short_string()))
     }
 }
@@ -274,15 +294,18 @@ pub(crate) fn fn_sum(env: Box<Environment>, mut arguments: Vec<Object>, _context
     //     Type::Integer(0)
     // };
 
-    let mut it = arg.into_iter();
-    let result = if let Some(obj) = it.next() {
+    let mut it = atomized_items(arg);
+    let result = if let Some(obj) = it.next().transpose()? {
 
         let mut sum = extract_number_or_duration(obj)?;
         loop {
-            if let Some(operand) = it.next() {
+            if let Some(operand) = it.next().transpose()? {
                 sum = match sum.to_type() {
-                    Types::YearMonthDuration => todo!(),
-                    Types::DayTimeDuration  => todo!(),
+                    Types::YearMonthDuration |
+                    Types::DayTimeDuration => {
+                        let operand = extract_number_or_duration(operand)?;
+                        crate::eval::arithmetic::eval_temporal(OperatorArithmetic::Plus, &sum, &operand, &env.implicit_timezone())?
+                    }
 
                     Types::UnsignedByte |
                     Types::UnsignedShort |
@@ -304,7 +327,7 @@ pub(crate) fn fn_sum(env: Box<Environment>, mut arguments: Vec<Object>, _context
                     Types::Float |
                     Types::Double => {
                         let n1 = crate::eval::arithmetic::type_to_number(sum)?;
-                        let n2 = crate::eval::arithmetic::object_to_number(operand)?;
+                        let n2 = crate::eval::arithmetic::type_to_number(extract_number_or_duration(operand)?)?;
 
                         match n1.add(&*n2) {
                             Ok(number) => number.to_atomic(),
@@ -1,8 +1,23 @@
 use bigdecimal::ToPrimitive;
-use crate::eval::{Object, Type, EvalResult, DynamicContext};
+use im_rc::Vector;
+use crate::eval::{Object, Type, EvalResult, DynamicContext, ErrorInfo};
 use crate::eval::Environment;
+use crate::eval::helpers::flat_items;
 use crate::eval::sequence_type::*;
-use crate::fns::FUNCTION;
+use crate::fns::{FUNCTION, call_item, collation_argument};
+use crate::eval::comparison::{sort_by_keys, sort_key};
+use crate::parser::errors::ErrorCode;
+
+// zero-based index of one-based position, position after the last member is valid for insertion
+fn index_of(array: &Vector<Object>, position: &Object, insertion: bool) -> Result<usize, ErrorInfo> {
+    let position = position.to_integer()?;
+    let max = if insertion { array.len() + 1 } else { array.len() };
+    if position < 1 || position > max as i128 {
+        Err((ErrorCode::FOAY0001, format!("position {} is outside of bounds", position)))
+    } else {
+        Ok((position - 1) as usize)
+    }
+}
 
 // array:size($array as array(*)) as xs:integer
 pub(crate) fn FN_ARRAY_SIZE() -> FUNCTION {
@@ -76,9 +91,11 @@ pub(crate) fn FN_ARRAY_PUT() -> FUNCTION {
 }
 
 pub(crate) fn array_put(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    match arguments.as_slice() {
-        [Object::Array(array), item] => {
-            todo!()
+    let mut arguments = arguments.into_iter();
+    match (arguments.next(), arguments.next(), arguments.next()) {
+        (Some(Object::Array(array)), Some(position), Some(member)) => {
+            let index = index_of(&array, &position, false)?;
+            Ok((env, Object::Array(array.update(index, member))))
         }
 
         _ => panic!("error")
@@ -103,7 +120,7 @@ pub(crate) fn array_append(env: Box<Environment>, arguments: Vec<Object>, _conte
     match arguments.as_slice() {
         [Object::Array(array), item] => {
             let mut result = array.clone();
-            result.push(item.clone());
+            result.push_back(item.clone());
 
             Ok((env, Object::Array(result)))
         }
@@ -142,9 +159,30 @@ pub(crate) fn FN_ARRAY_SUBARRAY_3() -> FUNCTION {
 }
 
 pub(crate) fn array_subarray(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    match arguments.as_slice() {
-        [Object::Array(array), item] => {
-            todo!()
+    let mut arguments = arguments.into_iter();
+    match (arguments.next(), arguments.next(), arguments.next()) {
+        (Some(Object::Array(array)), Some(start), length) => {
+            let start = start.to_integer()?;
+            if start < 1 || start > array.len() as i128 + 1 {
+                return Err((ErrorCode::FOAY0001, format!("start {} is outside of bounds", start)));
+            }
+            let start = (start - 1) as usize;
+
+            let end = match length {
+                Some(length) => {
+                    let length = length.to_integer()?;
+                    if length < 0 {
+                        return Err((ErrorCode::FOAY0002, format!("negative length {}", length)));
+                    }
+                    if start as i128 + length > array.len() as i128 {
+                        return Err((ErrorCode::FOAY0001, format!("length {} is outside of bounds", length)));
+                    }
+                    start + length as usize
+                }
+                None => array.len()
+            };
+
+            Ok((env, Object::Array(array.skip(start).take(end - start))))
         }
 
         _ => panic!("error")
@@ -166,9 +204,22 @@ pub(crate) fn FN_ARRAY_REMOVE() -> FUNCTION {
 }
 
 pub(crate) fn array_remove(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    match arguments.as_slice() {
-        [Object::Array(array), item] => {
-            todo!()
+    let mut arguments = arguments.into_iter();
+    match (arguments.next(), arguments.next()) {
+        (Some(Object::Array(array)), Some(positions)) => {
+            let mut indexes = flat_items(positions)
+                .map(|position| index_of(&array, &position, false))
+                .collect::<Result<Vec<usize>, ErrorInfo>>()?;
+
+            // remove from the end, so the rest of indexes stay valid
+            indexes.sort_unstable();
+            indexes.dedup();
+
+            let mut result = array;
+            for index in indexes.into_iter().rev() {
+                result.remove(index);
+            }
+            Ok((env, Object::Array(result)))
         }
 
         _ => panic!("error")
@@ -191,9 +242,12 @@ pub(crate) fn FN_ARRAY_INSERT_BEFORE() -> FUNCTION {
 }
 
 pub(crate) fn array_insert_before(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    match arguments.as_slice() {
-        [Object::Array(array), item] => {
-            todo!()
+    let mut arguments = arguments.into_iter();
+    match (arguments.next(), arguments.next(), arguments.next()) {
+        (Some(Object::Array(mut array)), Some(position), Some(member)) => {
+            let index = index_of(&array, &position, true)?;
+            array.insert(index, member);
+            Ok((env, Object::Array(array)))
         }
 
         _ => panic!("error")
@@ -213,8 +267,11 @@ pub(crate) fn FN_ARRAY_HEAD() -> FUNCTION {
 
 pub(crate) fn array_head(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
     match arguments.as_slice() {
-        [Object::Array(array), item] => {
-            todo!()
+        [Object::Array(array)] => {
+            match array.front() {
+                Some(item) => Ok((env, item.clone())),
+                None => Err((ErrorCode::FOAY0001, String::from("array is empty")))
+            }
         }
 
         _ => panic!("error")
@@ -234,8 +291,12 @@ pub(crate) fn FN_ARRAY_TAIL() -> FUNCTION {
 
 pub(crate) fn array_tail(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
     match arguments.as_slice() {
-        [Object::Array(array), item] => {
-            todo!()
+        [Object::Array(array)] => {
+            if array.is_empty() {
+                Err((ErrorCode::FOAY0001, String::from("array is empty")))
+            } else {
+                Ok((env, Object::Array(array.clone().split_off(1))))
+            }
         }
 
         _ => panic!("error")
@@ -255,8 +316,8 @@ pub(crate) fn FN_ARRAY_REVERSE() -> FUNCTION {
 
 pub(crate) fn array_reverse(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
     match arguments.as_slice() {
-        [Object::Array(array), item] => {
-            todo!()
+        [Object::Array(array)] => {
+            Ok((env, Object::Array(array.iter().rev().cloned().collect())))
         }
 
         _ => panic!("error")
@@ -264,11 +325,10 @@ pub(crate) fn array_reverse(env: Box<Environment>, arguments: Vec<Object>, _cont
 }
 
 // array:join($arrays as array(*)*) as array(*)
-pub(crate) fn FN_ARRAY_JOIN(arity: usize) -> FUNCTION {
-    let params = vec![SequenceType::zero_or_more(ItemType::Array(None)); arity];
+pub(crate) fn FN_ARRAY_JOIN() -> FUNCTION {
     (
         (
-            params,
+            [SequenceType::zero_or_more(ItemType::Array(None))].to_vec(),
             SequenceType::exactly_one(ItemType::Array(None))
         ),
         array_join
@@ -276,13 +336,16 @@ pub(crate) fn FN_ARRAY_JOIN(arity: usize) -> FUNCTION {
 }
 
 pub(crate) fn array_join(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    match arguments.as_slice() {
-        [Object::Array(array), item] => {
-            todo!()
+    let mut result = Vector::new();
+    for argument in arguments {
+        for item in flat_items(argument) {
+            match item {
+                Object::Array(array) => result.append(array),
+                _ => return Err((ErrorCode::XPTY0004, format!("expected array, got {:?}", item)))
+            }
         }
-
-        _ => panic!("error")
     }
+    Ok((env, Object::Array(result)))
 }
 
 // array:for-each($array as array(*), $action as function(item()*) as item()*) as array(*)
@@ -481,14 +544,31 @@ pub(crate) fn FN_ARRAY_SORT_3() -> FUNCTION {
     )
 }
 
-pub(crate) fn array_sort(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    match arguments.as_slice() {
-        [Object::Array(array), item] => {
-            todo!()
-        }
-
+pub(crate) fn array_sort(env: Box<Environment>, arguments: Vec<Object>, context: &DynamicContext) -> EvalResult {
+    let mut arguments = arguments.into_iter();
+    let array = match arguments.next() {
+        Some(Object::Array(array)) => array,
         _ => panic!("error")
+    };
+    let collation = collation_argument(&env, arguments.next().as_ref())?;
+    let key = arguments.next();
+
+    let mut current_env = env;
+    let mut members = vec![];
+    for member in array {
+        let value = if let Some(function) = &key {
+            let (new_env, value) = call_item(current_env, function.clone(), vec![member.clone()], context)?;
+            current_env = new_env;
+            value
+        } else {
+            member.clone()
+        };
+        members.push((sort_key(&current_env, value)?, member));
     }
+
+    sort_by_keys(collation.as_ref(), &mut members)?;
+
+    Ok((current_env, Object::Array(members.into_iter().map(|(_, member)| member).collect())))
 }
 
 // array:flatten($input as item()*) as item()*
@@ -0,0 +1,913 @@
+use std::ops::Range;
+use bigdecimal::num_bigint::{BigInt, BigUint, Sign};
+use ordered_float::OrderedFloat;
+use crate::eval::{Environment, Object, Type, EvalResult, DynamicContext, ErrorInfo, object_to_integer};
+use crate::eval::helpers::{flat_items, relax};
+use crate::eval::sequence_type::*;
+use crate::fns::FUNCTION;
+use crate::parser::errors::ErrorCode;
+
+// EXPath Binary Module 1.0, http://expath.org/spec/binary
+
+fn binary_type() -> ItemType {
+    ItemType::AtomicOrUnionType(XS_BASE64_BINARY.into())
+}
+
+fn integer_type() -> ItemType {
+    ItemType::AtomicOrUnionType(XS_INTEGER.into())
+}
+
+fn string_type() -> ItemType {
+    ItemType::AtomicOrUnionType(XS_STRING.into())
+}
+
+// octets of xs:base64Binary or xs:hexBinary, None for empty sequence
+fn octets(object: &Object) -> Result<Option<Vec<u8>>, ErrorInfo> {
+    match object {
+        Object::Empty => Ok(None),
+        Object::Atomic(Type::Base64Binary(data)) |
+        Object::Atomic(Type::HexBinary(data)) => Ok(Some(data.clone())),
+        _ => Err((ErrorCode::XPTY0004, format!("binary data expected but got {:?}", object)))
+    }
+}
+
+fn binary_result(env: Box<Environment>, data: Option<Vec<u8>>) -> EvalResult {
+    match data {
+        Some(data) => Ok((env, Object::Atomic(Type::Base64Binary(data)))),
+        None => Ok((env, Object::Empty))
+    }
+}
+
+fn string_argument(object: &Object) -> Result<Option<String>, ErrorInfo> {
+    match object {
+        Object::Empty => Ok(None),
+        Object::Atomic(Type::String(str)) |
+        Object::Atomic(Type::Untyped(str)) |
+        Object::Atomic(Type::AnyURI(str)) => Ok(Some(str.clone())),
+        _ => Err((ErrorCode::XPTY0004, format!("string expected but got {:?}", object)))
+    }
+}
+
+// octets from offset and of given size or up to the end
+fn range(data: &[u8], offset: i128, size: Option<i128>) -> Result<Range<usize>, ErrorInfo> {
+    if offset < 0 || offset > data.len() as i128 {
+        return Err((ErrorCode::BinIndexOutOfRange, format!("offset {} is out of range 0..{}", offset, data.len())));
+    }
+    let size = size.unwrap_or(data.len() as i128 - offset);
+    if size < 0 {
+        return Err((ErrorCode::BinNegativeSize, format!("size {} is negative", size)));
+    }
+    if offset + size > data.len() as i128 {
+        return Err((ErrorCode::BinIndexOutOfRange, format!("{} octets at offset {} are out of range 0..{}", size, offset, data.len())));
+    }
+    Ok(offset as usize..(offset + size) as usize)
+}
+
+fn size_argument(env: &Environment, size: i128) -> Result<usize, ErrorInfo> {
+    if size < 0 {
+        return Err((ErrorCode::BinNegativeSize, format!("size {} is negative", size)));
+    }
+    let size = usize::try_from(size).unwrap_or(usize::MAX);
+    env.check_octets(size)?;
+    Ok(size)
+}
+
+// octets of given value, fails instead of aborting when memory can't be allocated
+fn filled(octet: u8, size: usize) -> Result<Vec<u8>, ErrorInfo> {
+    let mut data = Vec::new();
+    if data.try_reserve_exact(size).is_err() {
+        return Err((ErrorCode::XPDY0130, format!("can't allocate {} octets", size)));
+    }
+    data.resize(size, octet);
+    Ok(data)
+}
+
+fn octet_argument(octet: i128) -> Result<u8, ErrorInfo> {
+    if (0..=255).contains(&octet) {
+        Ok(octet as u8)
+    } else {
+        Err((ErrorCode::BinOctetOutOfRange, format!("{} is not an octet", octet)))
+    }
+}
+
+// true when least significant octet goes first, big-endian is default
+fn little_endian(order: Option<&Object>) -> Result<bool, ErrorInfo> {
+    match order {
+        None => Ok(false),
+        Some(order) => {
+            match string_argument(order)?.as_deref() {
+                Some("most-significant-first") | Some("big-endian") | Some("BE") => Ok(false),
+                Some("least-significant-first") | Some("little-endian") | Some("LE") => Ok(true),
+                order => Err((ErrorCode::BinUnknownSignificanceOrder, format!("unknown octet order {:?}", order)))
+            }
+        }
+    }
+}
+
+fn ordered(mut data: Vec<u8>, little_endian: bool) -> Vec<u8> {
+    if little_endian {
+        data.reverse();
+    }
+    data
+}
+
+// digits of radix, each digit gives `bits` bits and result is padded on the left to whole octets
+fn from_digits(env: Box<Environment>, arguments: Vec<Object>, radix: u32, bits: usize) -> EvalResult {
+    let str = match string_argument(&arguments[0])? {
+        Some(str) => str,
+        None => return Ok((env, Object::Empty))
+    };
+    if let Some(ch) = str.chars().find(|ch| !ch.is_digit(radix)) {
+        return Err((ErrorCode::BinNonNumericCharacter, format!("{:?} isn't a digit of radix {}", ch, radix)));
+    }
+    let length = (str.len() * bits).div_ceil(8);
+    let mut data = match BigUint::parse_bytes(str.as_bytes(), radix) {
+        Some(number) => number.to_bytes_be(),
+        None => vec![]
+    };
+    if data.len() < length {
+        let mut padded = vec![0; length - data.len()];
+        padded.append(&mut data);
+        data = padded;
+    }
+    data.drain(..data.len() - length);
+    binary_result(env, Some(data))
+}
+
+// bin:hex($in as xs:string?) as xs:base64Binary?
+pub(crate) fn FN_BIN_HEX() -> FUNCTION {
+    (
+        (
+            [SequenceType::zero_or_one(string_type())].to_vec(),
+            SequenceType::zero_or_one(binary_type())
+        ),
+        bin_hex
+    )
+}
+
+pub(crate) fn bin_hex(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
+    from_digits(env, arguments, 16, 4)
+}
+
+// bin:bin($in as xs:string?) as xs:base64Binary?
+pub(crate) fn FN_BIN_BIN() -> FUNCTION {
+    (
+        (
+            [SequenceType::zero_or_one(string_type())].to_vec(),
+            SequenceType::zero_or_one(binary_type())
+        ),
+        bin_bin
+    )
+}
+
+pub(crate) fn bin_bin(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
+    from_digits(env, arguments, 2, 1)
+}
+
+// bin:octal($in as xs:string?) as xs:base64Binary?
+pub(crate) fn FN_BIN_OCTAL() -> FUNCTION {
+    (
+        (
+            [SequenceType::zero_or_one(string_type())].to_vec(),
+            SequenceType::zero_or_one(binary_type())
+        ),
+        bin_octal
+    )
+}
+
+pub(crate) fn bin_octal(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
+    from_digits(env, arguments, 8, 3)
+}
+
+// bin:to-octets($in as xs:base64Binary) as xs:integer*
+pub(crate) fn FN_BIN_TO_OCTETS() -> FUNCTION {
+    (
+        (
+            [SequenceType::exactly_one(binary_type())].to_vec(),
+            SequenceType::zero_or_more(integer_type())
+        ),
+        bin_to_octets
+    )
+}
+
+pub(crate) fn bin_to_octets(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
+    let data = octets(&arguments[0])?.unwrap_or_default();
+    let items = data.into_iter()
+        .map(|octet| Object::Atomic(Type::Integer(octet as i128)))
+        .collect();
+    relax(env, items)
+}
+
+// bin:from-octets($in as xs:integer*) as xs:base64Binary
+pub(crate) fn FN_BIN_FROM_OCTETS() -> FUNCTION {
+    (
+        (
+            [SequenceType::zero_or_more(integer_type())].to_vec(),
+            SequenceType::exactly_one(binary_type())
+        ),
+        bin_from_octets
+    )
+}
+
+pub(crate) fn bin_from_octets(env: Box<Environment>, mut arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
+    let mut data = vec![];
+    for item in flat_items(arguments.remove(0)) {
+        data.push(octet_argument(object_to_integer(&env, item)?)?);
+    }
+    binary_result(env, Some(data))
+}
+
+// bin:length($in as xs:base64Binary) as xs:integer
+pub(crate) fn FN_BIN_LENGTH() -> FUNCTION {
+    (
+        (
+            [SequenceType::exactly_one(binary_type())].to_vec(),
+            SequenceType::exactly_one(integer_type())
+        ),
+        bin_length
+    )
+}
+
+pub(crate) fn bin_length(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
+    let length = octets(&arguments[0])?.map(|data| data.len()).unwrap_or(0);
+    Ok((env, Object::Atomic(Type::Integer(length as i128))))
+}
+
+// bin:part($in as xs:base64Binary?, $offset as xs:integer) as xs:base64Binary?
+pub(crate) fn FN_BIN_PART_2() -> FUNCTION {
+    (
+        (
+            [
+                SequenceType::zero_or_one(binary_type()),
+                SequenceType::exactly_one(integer_type()),
+            ].to_vec(),
+            SequenceType::zero_or_one(binary_type())
+        ),
+        bin_part
+    )
+}
+
+// bin:part($in as xs:base64Binary?, $offset as xs:integer, $size as xs:integer) as xs:base64Binary?
+pub(crate) fn FN_BIN_PART_3() -> FUNCTION {
+    (
+        (
+            [
+                SequenceType::zero_or_one(binary_type()),
+                SequenceType::exactly_one(integer_type()),
+                SequenceType::exactly_one(integer_type()),
+            ].to_vec(),
+            SequenceType::zero_or_one(binary_type())
+        ),
+        bin_part
+    )
+}
+
+pub(crate) fn bin_part(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
+    let data = match octets(&arguments[0])? {
+        Some(data) => data,
+        None => return Ok((env, Object::Empty))
+    };
+    let offset = object_to_integer(&env, arguments[1].clone())?;
+    let size = match arguments.get(2) {
+        Some(size) => Some(object_to_integer(&env, size.clone())?),
+        None => None
+    };
+    let range = range(&data, offset, size)?;
+    binary_result(env, Some(data[range].to_vec()))
+}
+
+// bin:join($in as xs:base64Binary*) as xs:base64Binary
+pub(crate) fn FN_BIN_JOIN() -> FUNCTION {
+    (
+        (
+            [SequenceType::zero_or_more(binary_type())].to_vec(),
+            SequenceType::exactly_one(binary_type())
+        ),
+        bin_join
+    )
+}
+
+pub(crate) fn bin_join(env: Box<Environment>, mut arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
+    let mut data = vec![];
+    for item in flat_items(arguments.remove(0)) {
+        data.extend(octets(&item)?.unwrap_or_default());
+    }
+    binary_result(env, Some(data))
+}
+
+// bin:insert-before($in as xs:base64Binary?, $offset as xs:integer, $extra as xs:base64Binary?) as xs:base64Binary?
+pub(crate) fn FN_BIN_INSERT_BEFORE() -> FUNCTION {
+    (
+        (
+            [
+                SequenceType::zero_or_one(binary_type()),
+                SequenceType::exactly_one(integer_type()),
+                SequenceType::zero_or_one(binary_type()),
+            ].to_vec(),
+            SequenceType::zero_or_one(binary_type())
+        ),
+        bin_insert_before
+    )
+}
+
+pub(crate) fn bin_insert_before(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
+    let mut data = match octets(&arguments[0])? {
+        Some(data) => data,
+        None => return Ok((env, Object::Empty))
+    };
+    let offset = object_to_integer(&env, arguments[1].clone())?;
+    let position = range(&data, offset, Some(0))?.start;
+    let extra = octets(&arguments[2])?.unwrap_or_default();
+    data.splice(position..position, extra);
+    binary_result(env, Some(data))
+}
+
+// bin:pad-left($in as xs:base64Binary?, $size as xs:integer) as xs:base64Binary?
+pub(crate) fn FN_BIN_PAD_LEFT_2() -> FUNCTION {
+    (
+        (
+            [
+                SequenceType::zero_or_one(binary_type()),
+                SequenceType::exactly_one(integer_type()),
+            ].to_vec(),
+            SequenceType::zero_or_one(binary_type())
+        ),
+        bin_pad_left
+    )
+}
+
+// bin:pad-left($in as xs:base64Binary?, $size as xs:integer, $octet as xs:integer) as xs:base64Binary?
+pub(crate) fn FN_BIN_PAD_LEFT_3() -> FUNCTION {
+    (
+        (
+            [
+                SequenceType::zero_or_one(binary_type()),
+                SequenceType::exactly_one(integer_type()),
+                SequenceType::exactly_one(integer_type()),
+            ].to_vec(),
+            SequenceType::zero_or_one(binary_type())
+        ),
+        bin_pad_left
+    )
+}
+
+pub(crate) fn bin_pad_left(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
+    pad(env, arguments, true)
+}
+
+// bin:pad-right($in as xs:base64Binary?, $size as xs:integer) as xs:base64Binary?
+pub(crate) fn FN_BIN_PAD_RIGHT_2() -> FUNCTION {
+    (
+        (
+            [
+                SequenceType::zero_or_one(binary_type()),
+                SequenceType::exactly_one(integer_type()),
+            ].to_vec(),
+            SequenceType::zero_or_one(binary_type())
+        ),
+        bin_pad_right
+    )
+}
+
+// bin:pad-right($in as xs:base64Binary?, $size as xs:integer, $octet as xs:integer) as xs:base64Binary?
+pub(crate) fn FN_BIN_PAD_RIGHT_3() -> FUNCTION {
+    (
+        (
+            [
+                SequenceType::zero_or_one(binary_type()),
+                SequenceType::exactly_one(integer_type()),
+                SequenceType::exactly_one(integer_type()),
+            ].to_vec(),
+            SequenceType::zero_or_one(binary_type())
+        ),
+        bin_pad_right
+    )
+}
+
+pub(crate) fn bin_pad_right(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
+    pad(env, arguments, false)
+}
+
+fn pad(env: Box<Environment>, arguments: Vec<Object>, left: bool) -> EvalResult {
+    let mut data = match octets(&arguments[0])? {
+        Some(data) => data,
+        None => return Ok((env, Object::Empty))
+    };
+    let size = size_argument(&env, object_to_integer(&env, arguments[1].clone())?)?;
+    let octet = match arguments.get(2) {
+        Some(octet) => octet_argument(object_to_integer(&env, octet.clone())?)?,
+        None => 0
+    };
+    env.check_octets(data.len().saturating_add(size))?;
+    let padding = filled(octet, size)?;
+    if left {
+        data.splice(0..0, padding);
+    } else {
+        data.extend(padding);
+    }
+    binary_result(env, Some(data))
+}
+
+// bin:find($in as xs:base64Binary?, $offset as xs:integer, $search as xs:base64Binary) as xs:integer?
+pub(crate) fn FN_BIN_FIND() -> FUNCTION {
+    (
+        (
+            [
+                SequenceType::zero_or_one(binary_type()),
+                SequenceType::exactly_one(integer_type()),
+                SequenceType::exactly_one(binary_type()),
+            ].to_vec(),
+            SequenceType::zero_or_one(integer_type())
+        ),
+        bin_find
+    )
+}
+
+pub(crate) fn bin_find(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
+    let data = match octets(&arguments[0])? {
+        Some(data) => data,
+        None => return Ok((env, Object::Empty))
+    };
+    let offset = object_to_integer(&env, arguments[1].clone())?;
+    let start = range(&data, offset, None)?.start;
+    let search = octets(&arguments[2])?.unwrap_or_default();
+
+    let found = if search.is_empty() {
+        Some(start)
+    } else {
+        data[start..].windows(search.len())
+            .position(|window| window == search.as_slice())
+            .map(|position| start + position)
+    };
+    match found {
+        Some(position) => Ok((env, Object::Atomic(Type::Integer(position as i128)))),
+        None => Ok((env, Object::Empty))
+    }
+}
+
+// bin:decode-string($in as xs:base64Binary?, $encoding as xs:string, $offset as xs:integer, $size as xs:integer) as xs:string?
+// and variants without trailing arguments
+pub(crate) fn FN_BIN_DECODE_STRING(arity: usize) -> FUNCTION {
+    let parameters = [
+        SequenceType::zero_or_one(binary_type()),
+        SequenceType::exactly_one(string_type()),
+        SequenceType::exactly_one(integer_type()),
+        SequenceType::exactly_one(integer_type()),
+    ];
+    (
+        (
+            parameters[..arity].to_vec(),
+            SequenceType::zero_or_one(string_type())
+        ),
+        bin_decode_string
+    )
+}
+
+pub(crate) fn bin_decode_string(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
+    let data = match octets(&arguments[0])? {
+        Some(data) => data,
+        None => return Ok((env, Object::Empty))
+    };
+    let encoding = match arguments.get(1) {
+        Some(encoding) => string_argument(encoding)?,
+        None => None
+    };
+    let offset = match arguments.get(2) {
+        Some(offset) => object_to_integer(&env, offset.clone())?,
+        None => 0
+    };
+    let size = match arguments.get(3) {
+        Some(size) => Some(object_to_integer(&env, size.clone())?),
+        None => None
+    };
+    let range = range(&data, offset, size)?;
+    let str = decode(&data[range], encoding.as_deref().unwrap_or("utf-8"))?;
+    Ok((env, Object::Atomic(Type::String(str))))
+}
+
+fn conversion_error(encoding: &str) -> ErrorInfo {
+    (ErrorCode::BinConversionError, format!("data can't be converted with encoding {:?}", encoding))
+}
+
+fn decode(data: &[u8], encoding: &str) -> Result<String, ErrorInfo> {
+    match encoding.to_lowercase().as_str() {
+        "utf-8" | "utf8" => {
+            String::from_utf8(data.to_vec()).map_err(|_| conversion_error(encoding))
+        }
+        "us-ascii" | "ascii" => {
+            if data.is_ascii() {
+                Ok(data.iter().map(|octet| *octet as char).collect())
+            } else {
+                Err(conversion_error(encoding))
+            }
+        }
+        "iso-8859-1" | "latin1" => {
+            Ok(data.iter().map(|octet| *octet as char).collect())
+        }
+        "utf-16" | "utf-16be" | "utf-16le" => {
+            if !data.len().is_multiple_of(2) {
+                return Err(conversion_error(encoding));
+            }
+            // byte order mark decides order of "utf-16", without it it's big-endian
+            let (data, little_endian) = match (encoding.to_lowercase().as_str(), data) {
+                ("utf-16", [0xFF, 0xFE, rest @ ..]) => (rest, true),
+                ("utf-16", [0xFE, 0xFF, rest @ ..]) => (rest, false),
+                ("utf-16le", data) => (data, true),
+                (_, data) => (data, false),
+            };
+            let units: Vec<u16> = data.chunks(2)
+                .map(|pair| if little_endian {
+                    u16::from_le_bytes([pair[0], pair[1]])
+                } else {
+                    u16::from_be_bytes([pair[0], pair[1]])
+                })
+                .collect();
+            String::from_utf16(&units).map_err(|_| conversion_error(encoding))
+        }
+        _ => Err((ErrorCode::BinUnknownEncoding, format!("unknown encoding {:?}", encoding)))
+    }
+}
+
+// bin:encode-string($in as xs:string?, $encoding as xs:string) as xs:base64Binary?
+// and variant without encoding
+pub(crate) fn FN_BIN_ENCODE_STRING(arity: usize) -> FUNCTION {
+    let parameters = [
+        SequenceType::zero_or_one(string_type()),
+        SequenceType::exactly_one(string_type()),
+    ];
+    (
+        (
+            parameters[..arity].to_vec(),
+            SequenceType::zero_or_one(binary_type())
+        ),
+        bin_encode_string
+    )
+}
+
+pub(crate) fn bin_encode_string(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
+    let str = match string_argument(&arguments[0])? {
+        Some(str) => str,
+        None => return Ok((env, Object::Empty))
+    };
+    let encoding = match arguments.get(1) {
+        Some(encoding) => string_argument(encoding)?,
+        None => None
+    };
+    let data = encode(&str, encoding.as_deref().unwrap_or("utf-8"))?;
+    binary_result(env, Some(data))
+}
+
+fn encode(str: &str, encoding: &str) -> Result<Vec<u8>, ErrorInfo> {
+    let single_octet = |limit: u32| {
+        str.chars()
+            .map(|ch| if (ch as u32) <= limit { Ok(ch as u8) } else { Err(conversion_error(encoding)) })
+            .collect::<Result<Vec<u8>, ErrorInfo>>()
+    };
+    match encoding.to_lowercase().as_str() {
+        "utf-8" | "utf8" => Ok(str.as_bytes().to_vec()),
+        "us-ascii" | "ascii" => single_octet(0x7F),
+        "iso-8859-1" | "latin1" => single_octet(0xFF),
+        "utf-16" | "utf-16be" => Ok(str.encode_utf16().flat_map(|unit| unit.to_be_bytes()).collect()),
+        "utf-16le" => Ok(str.encode_utf16().flat_map(|unit| unit.to_le_bytes()).collect()),
+        _ => Err((ErrorCode::BinUnknownEncoding, format!("unknown encoding {:?}", encoding)))
+    }
+}
+
+// bin:pack-double($in as xs:double, $octet-order as xs:string) as xs:base64Binary
+// and variant without octet order
+pub(crate) fn FN_BIN_PACK_DOUBLE(arity: usize) -> FUNCTION {
+    let parameters = [
+        SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DOUBLE.into())),
+        SequenceType::exactly_one(string_type()),
+    ];
+    (
+        (
+            parameters[..arity].to_vec(),
+            SequenceType::exactly_one(binary_type())
+        ),
+        bin_pack_double
+    )
+}
+
+pub(crate) fn bin_pack_double(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
+    let number = match &arguments[0] {
+        Object::Atomic(Type::Double(number)) => number.0,
+        other => return Err((ErrorCode::XPTY0004, format!("xs:double expected but got {:?}", other)))
+    };
+    let little_endian = little_endian(arguments.get(1))?;
+    binary_result(env, Some(ordered(number.to_be_bytes().to_vec(), little_endian)))
+}
+
+// bin:pack-float($in as xs:float, $octet-order as xs:string) as xs:base64Binary
+// and variant without octet order
+pub(crate) fn FN_BIN_PACK_FLOAT(arity: usize) -> FUNCTION {
+    let parameters = [
+        SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_FLOAT.into())),
+        SequenceType::exactly_one(string_type()),
+    ];
+    (
+        (
+            parameters[..arity].to_vec(),
+            SequenceType::exactly_one(binary_type())
+        ),
+        bin_pack_float
+    )
+}
+
+pub(crate) fn bin_pack_float(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
+    let number = match &arguments[0] {
+        Object::Atomic(Type::Float(number)) => number.0,
+        other => return Err((ErrorCode::XPTY0004, format!("xs:float expected but got {:?}", other)))
+    };
+    let little_endian = little_endian(arguments.get(1))?;
+    binary_result(env, Some(ordered(number.to_be_bytes().to_vec(), little_endian)))
+}
+
+// bin:pack-integer($in as xs:integer, $size as xs:integer, $octet-order as xs:string) as xs:base64Binary
+// and variant without octet order
+pub(crate) fn FN_BIN_PACK_INTEGER(arity: usize) -> FUNCTION {
+    let parameters = [
+        SequenceType::exactly_one(integer_type()),
+        SequenceType::exactly_one(integer_type()),
+        SequenceType::exactly_one(string_type()),
+    ];
+    (
+        (
+            parameters[..arity].to_vec(),
+            SequenceType::exactly_one(binary_type())
+        ),
+        bin_pack_integer
+    )
+}
+
+pub(crate) fn bin_pack_integer(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
+    let number = match &arguments[0] {
+        Object::Atomic(Type::Integer(number)) => BigInt::from(*number),
+        Object::Atomic(Type::BigInteger(number)) => number.clone(),
+        other => BigInt::from(object_to_integer(&env, other.clone())?)
+    };
+    let size = size_argument(&env, object_to_integer(&env, arguments[1].clone())?)?;
+    let little_endian = little_endian(arguments.get(2))?;
+
+    // two's complement which is sign-extended or truncated to size
+    let mut data = number.to_signed_bytes_be();
+    if data.len() < size {
+        let sign = if number.sign() == Sign::Minus { 0xFF } else { 0x00 };
+        data.splice(0..0, filled(sign, size - data.len())?);
+    } else {
+        data.drain(..data.len() - size);
+    }
+    binary_result(env, Some(ordered(data, little_endian)))
+}
+
+// octets of fixed size at offset in most-significant-first order
+fn unpack(arguments: &[Object], offset: i128, size: usize, order: Option<&Object>) -> Result<Vec<u8>, ErrorInfo> {
+    let data = octets(&arguments[0])?.unwrap_or_default();
+    let range = range(&data, offset, Some(size as i128))?;
+    Ok(ordered(data[range].to_vec(), little_endian(order)?))
+}
+
+// bin:unpack-double($in as xs:base64Binary, $offset as xs:integer, $octet-order as xs:string) as xs:double
+// and variant without octet order
+pub(crate) fn FN_BIN_UNPACK_DOUBLE(arity: usize) -> FUNCTION {
+    let parameters = [
+        SequenceType::exactly_one(binary_type()),
+        SequenceType::exactly_one(integer_type()),
+        SequenceType::exactly_one(string_type()),
+    ];
+    (
+        (
+            parameters[..arity].to_vec(),
+            SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DOUBLE.into()))
+        ),
+        bin_unpack_double
+    )
+}
+
+pub(crate) fn bin_unpack_double(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
+    let offset = object_to_integer(&env, arguments[1].clone())?;
+    let data = unpack(&arguments, offset, 8, arguments.get(2))?;
+    let mut octets = [0; 8];
+    octets.copy_from_slice(&data);
+    let number = f64::from_be_bytes(octets);
+    Ok((env, Object::Atomic(Type::Double(OrderedFloat::from(number)))))
+}
+
+// bin:unpack-float($in as xs:base64Binary, $offset as xs:integer, $octet-order as xs:string) as xs:float
+// and variant without octet order
+pub(crate) fn FN_BIN_UNPACK_FLOAT(arity: usize) -> FUNCTION {
+    let parameters = [
+        SequenceType::exactly_one(binary_type()),
+        SequenceType::exactly_one(integer_type()),
+        SequenceType::exactly_one(string_type()),
+    ];
+    (
+        (
+            parameters[..arity].to_vec(),
+            SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_FLOAT.into()))
+        ),
+        bin_unpack_float
+    )
+}
+
+pub(crate) fn bin_unpack_float(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
+    let offset = object_to_integer(&env, arguments[1].clone())?;
+    let data = unpack(&arguments, offset, 4, arguments.get(2))?;
+    let mut octets = [0; 4];
+    octets.copy_from_slice(&data);
+    let number = f32::from_be_bytes(octets);
+    Ok((env, Object::Atomic(Type::Float(OrderedFloat::from(number)))))
+}
+
+// bin:unpack-integer($in as xs:base64Binary, $offset as xs:integer, $size as xs:integer, $octet-order as xs:string) as xs:integer
+// and variant without octet order
+pub(crate) fn FN_BIN_UNPACK_INTEGER(arity: usize) -> FUNCTION {
+    let parameters = [
+        SequenceType::exactly_one(binary_type()),
+        SequenceType::exactly_one(integer_type()),
+        SequenceType::exactly_one(integer_type()),
+        SequenceType::exactly_one(string_type()),
+    ];
+    (
+        (
+            parameters[..arity].to_vec(),
+            SequenceType::exactly_one(integer_type())
+        ),
+        bin_unpack_integer
+    )
+}
+
+pub(crate) fn bin_unpack_integer(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
+    let size = size_argument(&env, object_to_integer(&env, arguments[2].clone())?)?;
+    let offset = object_to_integer(&env, arguments[1].clone())?;
+    let data = unpack(&arguments, offset, size, arguments.get(3))?;
+    let number = BigInt::from_signed_bytes_be(&data);
+    Ok((env, Object::Atomic(Type::integer(number))))
+}
+
+// bin:unpack-unsigned-integer($in as xs:base64Binary, $offset as xs:integer, $size as xs:integer, $octet-order as xs:string) as xs:integer
+// and variant without octet order
+pub(crate) fn FN_BIN_UNPACK_UNSIGNED_INTEGER(arity: usize) -> FUNCTION {
+    let (parameters, _) = FN_BIN_UNPACK_INTEGER(arity).0;
+    (
+        (
+            parameters,
+            SequenceType::exactly_one(integer_type())
+        ),
+        bin_unpack_unsigned_integer
+    )
+}
+
+pub(crate) fn bin_unpack_unsigned_integer(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
+    let size = size_argument(&env, object_to_integer(&env, arguments[2].clone())?)?;
+    let offset = object_to_integer(&env, arguments[1].clone())?;
+    let data = unpack(&arguments, offset, size, arguments.get(3))?;
+    let number = BigInt::from_bytes_be(Sign::Plus, &data);
+    Ok((env, Object::Atomic(Type::integer(number))))
+}
+
+// bin:or($a as xs:base64Binary?, $b as xs:base64Binary?) as xs:base64Binary?
+// and same signature of bin:xor and bin:and
+pub(crate) fn FN_BIN_BITWISE(function: fn(Box<Environment>, Vec<Object>, &DynamicContext) -> EvalResult) -> FUNCTION {
+    (
+        (
+            [
+                SequenceType::zero_or_one(binary_type()),
+                SequenceType::zero_or_one(binary_type()),
+            ].to_vec(),
+            SequenceType::zero_or_one(binary_type())
+        ),
+        function
+    )
+}
+
+fn bitwise(env: Box<Environment>, arguments: Vec<Object>, operation: fn(u8, u8) -> u8) -> EvalResult {
+    let (a, b) = match (octets(&arguments[0])?, octets(&arguments[1])?) {
+        (Some(a), Some(b)) => (a, b),
+        _ => return Ok((env, Object::Empty))
+    };
+    if a.len() != b.len() {
+        return Err((ErrorCode::BinDifferingLengthArguments, format!("lengths {} and {} differ", a.len(), b.len())));
+    }
+    let data = a.iter().zip(b.iter())
+        .map(|(a, b)| operation(*a, *b))
+        .collect();
+    binary_result(env, Some(data))
+}
+
+pub(crate) fn bin_or(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
+    bitwise(env, arguments, |a, b| a | b)
+}
+
+pub(crate) fn bin_xor(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
+    bitwise(env, arguments, |a, b| a ^ b)
+}
+
+pub(crate) fn bin_and(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
+    bitwise(env, arguments, |a, b| a & b)
+}
+
+// bin:not($in as xs:base64Binary?) as xs:base64Binary?
+pub(crate) fn FN_BIN_NOT() -> FUNCTION {
+    (
+        (
+            [SequenceType::zero_or_one(binary_type())].to_vec(),
+            SequenceType::zero_or_one(binary_type())
+        ),
+        bin_not
+    )
+}
+
+pub(crate) fn bin_not(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
+    let data = octets(&arguments[0])?
+        .map(|data| data.into_iter().map(|octet| !octet).collect());
+    binary_result(env, data)
+}
+
+// bin:shift($in as xs:base64Binary?, $by as xs:integer) as xs:base64Binary?
+pub(crate) fn FN_BIN_SHIFT() -> FUNCTION {
+    (
+        (
+            [
+                SequenceType::zero_or_one(binary_type()),
+                SequenceType::exactly_one(integer_type()),
+            ].to_vec(),
+            SequenceType::zero_or_one(binary_type())
+        ),
+        bin_shift
+    )
+}
+
+pub(crate) fn bin_shift(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
+    let data = match octets(&arguments[0])? {
+        Some(data) => data,
+        None => return Ok((env, Object::Empty))
+    };
+    // shift by integer out of i128 range moves out all bits
+    let by = match &arguments[1] {
+        Object::Atomic(Type::BigInteger(_)) => None,
+        other => Some(object_to_integer(&env, other.clone())?)
+    };
+
+    // positive shifts to the left, bits shifted out are lost and vacated ones are zeros
+    let length = data.len();
+    let bits = length as u128 * 8;
+    let data = match by {
+        Some(by) if by.unsigned_abs() < bits => {
+            let number = BigUint::from_bytes_be(&data);
+            let distance = by.unsigned_abs() as usize;
+            let shifted = if by >= 0 { number << distance } else { number >> distance };
+            let mut shifted = shifted.to_bytes_be();
+            if shifted.len() > length {
+                shifted.drain(..shifted.len() - length);
+            } else {
+                shifted.splice(0..0, vec![0; length - shifted.len()]);
+            }
+            shifted
+        },
+        _ => vec![0; length]
+    };
+    binary_result(env, Some(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::Limits;
+    use crate::parser::errors::ErrorCode;
+    use crate::tests::{test_eval_queries, test_eval_queries_with};
+
+    #[test]
+    fn eval_binary() {
+        test_eval_queries(&[
+            ("bin:length(xs:hexBinary('0102'))", Ok("2")),
+            ("string(bin:bin('1101000111010101'))", Ok("0dU=")),
+            ("string(xs:hexBinary(bin:part(bin:hex('010203'), 1, 1)))", Ok("02")),
+            ("string(xs:hexBinary(bin:join((bin:hex('01'), xs:hexBinary('02')))))", Ok("0102")),
+            ("bin:to-octets(bin:hex('0AFF'))", Ok("10 255")),
+            ("bin:unpack-integer(bin:pack-integer(-2, 2), 0, 2)", Ok("-2")),
+            ("bin:unpack-unsigned-integer(bin:pack-integer(-2, 2, 'LE'), 0, 2, 'LE')", Ok("65534")),
+            ("bin:unpack-double(bin:pack-double(1.5e0, 'little-endian'), 0, 'little-endian')", Ok("1.5")),
+            ("bin:decode-string(bin:encode-string('héllo'), 'utf-8', 1)", Ok("éllo")),
+            ("bin:find(bin:hex('01020304'), 0, bin:hex('0304'))", Ok("2")),
+            ("string(xs:hexBinary(bin:xor(bin:hex('0F'), bin:hex('FF'))))", Ok("F0")),
+            ("string(xs:hexBinary(bin:shift(bin:hex('0102'), -1)))", Ok("0081")),
+            ("string(xs:hexBinary(bin:shift(bin:hex('0102'), -9)))", Ok("0000")),
+            ("string(xs:hexBinary(bin:shift(bin:hex('0102'), -170141183460469231731687303715884105728)))", Ok("0000")),
+            ("string(xs:hexBinary(bin:shift(bin:hex('0102'), 99999999999999999999999999999999999999999999)))", Ok("0000")),
+            ("string(xs:hexBinary(bin:pad-left(bin:hex('01'), 2, 255)))", Ok("FFFF01")),
+            ("string(xs:hexBinary(bin:pack-integer(-1, 3)))", Ok("FFFFFF")),
+            ("bin:pad-left(bin:hex('01'), 99999999999999999999)", Err(ErrorCode::XPDY0130)),
+            ("bin:pack-integer(1, 9223372036854775807)", Err(ErrorCode::XPDY0130)),
+            ("bin:xor(bin:hex('01'), bin:hex('0102'))", Err(ErrorCode::BinDifferingLengthArguments)),
+            ("bin:part(bin:hex('0102'), 3)", Err(ErrorCode::BinIndexOutOfRange)),
+            ("bin:hex('0G')", Err(ErrorCode::BinNonNumericCharacter)),
+            ("bin:pack-integer(1, 2, 'middle')", Err(ErrorCode::BinUnknownSignificanceOrder)),
+        ]);
+
+        test_eval_queries_with(|env| env.set_limits(Limits { max_string_length: Some(10), ..Limits::default() }), &[
+            ("bin:length(bin:pad-right(bin:hex('01'), 9))", Ok("10")),
+            ("bin:pad-right(bin:hex('01'), 10)", Err(ErrorCode::XPDY0130)),
+            ("bin:pack-integer(1, 11)", Err(ErrorCode::XPDY0130)),
+        ]);
+    }
+}
@@ -1,6 +1,6 @@
 use crate::eval::{Environment, Object, Type, EvalResult, comparison, DynamicContext};
 use crate::eval::sequence_type::*;
-use crate::fns::FUNCTION;
+use crate::fns::{FUNCTION, collation_argument};
 
 // fn:deep-equal($parameter1 as item()*, $parameter2 as item()*) as xs:boolean
 pub(crate) fn FN_DEEP_EQUAL_2() -> FUNCTION {
@@ -34,7 +34,10 @@ pub(crate) fn FN_DEEP_EQUAL_3() -> FUNCTION {
 pub(crate) fn fn_deep_equal(env: Box<Environment>, mut arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
     let o1 = arguments.remove(0);
     let o2 = arguments.remove(0);
-    match comparison::deep_eq((&env, &o1), (&env, &o2)) {
+
+    let collation = collation_argument(&env, arguments.get(0))?;
+
+    match comparison::deep_eq_with_collation((&env, &o1), (&env, &o2), collation.as_ref()) {
         Ok(v) => Ok((env, Object::Atomic(Type::Boolean(v)))),
         Err(e) => Err(e)
     }
@@ -15,7 +15,8 @@ pub(crate) fn FN_DEFAULT_COLLATION() -> FUNCTION {
 }
 
 pub(crate) fn fn_default_collation(env: Box<Environment>, arguments: Vec<Object>, context: &DynamicContext) -> EvalResult {
-    todo!()
+    let uri = env.default_collation();
+    Ok((env, Object::Atomic(Type::String(uri))))
 }
 
 // fn:default-language() as xs:language
@@ -30,7 +31,8 @@ pub(crate) fn FN_DEFAULT_LANGUAGE() -> FUNCTION {
 }
 
 pub(crate) fn fn_default_language(env: Box<Environment>, arguments: Vec<Object>, context: &DynamicContext) -> EvalResult {
-    todo!()
+    let language = env.languages().default_language().to_string();
+    Ok((env, Object::Atomic(Type::Language(language))))
 }
 
 // fn:static-base-uri() as xs:anyURI?
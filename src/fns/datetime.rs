@@ -1,7 +1,14 @@
-use chrono::Datelike;
-use crate::eval::{Environment, Object, Type, DynamicContext, EvalResult};
+use bigdecimal::{BigDecimal, Zero};
+use chrono::{Datelike, DateTime, FixedOffset, TimeZone, Timelike};
+use bigdecimal::num_bigint::BigInt;
+use crate::eval::{Environment, Object, Type, DynamicContext, EvalResult, ErrorInfo};
+use crate::eval::arithmetic::day_time_duration;
+use crate::parser::errors::ErrorCode;
+use crate::values::time::Time;
 use crate::eval::sequence_type::*;
 use crate::fns::FUNCTION;
+use crate::fns::format::format_date_time;
+use crate::serialization::object_to_string;
 
 // // op:yearMonthDuration-less-than($arg1 as xs:yearMonthDuration, $arg2 as xs:yearMonthDuration) as xs:boolean
 // pub(crate) static OP_YEAR_MONTH_DURATION_LESS_THAN() -> FUNCTION {
@@ -100,7 +107,19 @@ pub(crate) fn FN_YEARS_FROM_DURATION() -> FUNCTION {
 }
 
 pub(crate) fn fn_years_from_duration(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+    match arguments.as_slice() {
+        [Object::Empty] => Ok((env, Object::Empty)),
+        [Object::Atomic(Type::Duration { positive, years, .. })] => {
+            Ok((env, Object::Atomic(Type::Integer(signed(*positive, *years)))))
+        }
+        [Object::Atomic(Type::YearMonthDuration { positive, years, .. })] => {
+            Ok((env, Object::Atomic(Type::Integer(signed(*positive, *years)))))
+        }
+        [Object::Atomic(Type::DayTimeDuration { .. })] => {
+            Ok((env, Object::Atomic(Type::Integer(0))))
+        }
+        _ => Err((ErrorCode::XPTY0004, String::from("expected xs:duration")))
+    }
 }
 
 // fn:months-from-duration($arg as xs:duration?) as xs:integer?
@@ -115,7 +134,19 @@ pub(crate) fn FN_MONTHS_FROM_DURATION() -> FUNCTION {
 }
 
 pub(crate) fn fn_months_from_duration(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+    match arguments.as_slice() {
+        [Object::Empty] => Ok((env, Object::Empty)),
+        [Object::Atomic(Type::Duration { positive, months, .. })] => {
+            Ok((env, Object::Atomic(Type::Integer(signed(*positive, *months)))))
+        }
+        [Object::Atomic(Type::YearMonthDuration { positive, months, .. })] => {
+            Ok((env, Object::Atomic(Type::Integer(signed(*positive, *months)))))
+        }
+        [Object::Atomic(Type::DayTimeDuration { .. })] => {
+            Ok((env, Object::Atomic(Type::Integer(0))))
+        }
+        _ => Err((ErrorCode::XPTY0004, String::from("expected xs:duration")))
+    }
 }
 
 // fn:days-from-duration($arg as xs:duration?) as xs:integer?
@@ -131,21 +162,17 @@ pub(crate) fn FN_DAYS_FROM_DURATION() -> FUNCTION {
 
 pub(crate) fn fn_days_from_duration(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
     match arguments.as_slice() {
-        [Object::Empty] => {
-            Ok((env, Object::Empty))
-        }
+        [Object::Empty] => Ok((env, Object::Empty)),
         [Object::Atomic(Type::Duration { positive, days, .. })] => {
-            let sign = if *positive { 1 } else { -1 };
-            Ok((env, Object::Atomic(Type::Integer(*days as i128 * sign))))
-        },
+            Ok((env, Object::Atomic(Type::Integer(signed(*positive, *days)))))
+        }
         [Object::Atomic(Type::YearMonthDuration { .. })] => {
             Ok((env, Object::Atomic(Type::Integer(0))))
-        },
+        }
         [Object::Atomic(Type::DayTimeDuration { positive, days, .. })] => {
-            let sign = if *positive { 1 } else { -1 };
-            Ok((env, Object::Atomic(Type::Integer(*days as i128 * sign))))
-        },
-        _ => panic!("error")
+            Ok((env, Object::Atomic(Type::Integer(signed(*positive, *days)))))
+        }
+        _ => Err((ErrorCode::XPTY0004, String::from("expected xs:duration")))
     }
 }
 
@@ -161,7 +188,19 @@ pub(crate) fn FN_HOURS_FROM_DURATION() -> FUNCTION {
 }
 
 pub(crate) fn fn_hours_from_duration(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+    match arguments.as_slice() {
+        [Object::Empty] => Ok((env, Object::Empty)),
+        [Object::Atomic(Type::Duration { positive, hours, .. })] => {
+            Ok((env, Object::Atomic(Type::Integer(signed(*positive, *hours)))))
+        }
+        [Object::Atomic(Type::YearMonthDuration { .. })] => {
+            Ok((env, Object::Atomic(Type::Integer(0))))
+        }
+        [Object::Atomic(Type::DayTimeDuration { positive, hours, .. })] => {
+            Ok((env, Object::Atomic(Type::Integer(signed(*positive, *hours)))))
+        }
+        _ => Err((ErrorCode::XPTY0004, String::from("expected xs:duration")))
+    }
 }
 
 // fn:minutes-from-duration($arg as xs:duration?) as xs:integer?
@@ -176,7 +215,19 @@ pub(crate) fn FN_MINUTES_FROM_DURATION() -> FUNCTION {
 }
 
 pub(crate) fn fn_minutes_from_duration(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+    match arguments.as_slice() {
+        [Object::Empty] => Ok((env, Object::Empty)),
+        [Object::Atomic(Type::Duration { positive, minutes, .. })] => {
+            Ok((env, Object::Atomic(Type::Integer(signed(*positive, *minutes)))))
+        }
+        [Object::Atomic(Type::YearMonthDuration { .. })] => {
+            Ok((env, Object::Atomic(Type::Integer(0))))
+        }
+        [Object::Atomic(Type::DayTimeDuration { positive, minutes, .. })] => {
+            Ok((env, Object::Atomic(Type::Integer(signed(*positive, *minutes)))))
+        }
+        _ => Err((ErrorCode::XPTY0004, String::from("expected xs:duration")))
+    }
 }
 
 // fn:seconds-from-duration($arg as xs:duration?) as xs:decimal?
@@ -191,179 +242,21 @@ pub(crate) fn FN_SECONDS_FROM_DURATION() -> FUNCTION {
 }
 
 pub(crate) fn fn_seconds_from_duration(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+    match arguments.as_slice() {
+        [Object::Empty] => Ok((env, Object::Empty)),
+        [Object::Atomic(Type::Duration { positive, seconds, microseconds, .. })] |
+        [Object::Atomic(Type::DayTimeDuration { positive, seconds, microseconds, .. })] => {
+            let number = seconds_with_fraction(*seconds, *microseconds * 1_000_000);
+            let number = if *positive { number } else { -number };
+            Ok((env, Object::Atomic(Type::Decimal(number))))
+        }
+        [Object::Atomic(Type::YearMonthDuration { .. })] => {
+            Ok((env, Object::Atomic(Type::Decimal(BigDecimal::zero()))))
+        }
+        _ => Err((ErrorCode::XPTY0004, String::from("expected xs:duration")))
+    }
 }
 
-// // op:add-yearMonthDurations($arg1 as xs:yearMonthDuration, $arg2 as xs:yearMonthDuration) as xs:yearMonthDuration
-// pub(crate) static OP_ADD_YEAR_MONTH_DURATIONS() -> FUNCTION {
-//     (
-//     (
-//         [
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_YEAR_MONTH_DURATION)),
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_YEAR_MONTH_DURATION))
-//         ].to_vec(),
-//         SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_YEAR_MONTH_DURATION)),
-//     ),
-//     op_add_year_month_durations
-// );
-//
-// pub(crate) fn op_add_year_month_durations(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-//     todo!()
-// }
-//
-// // op:subtract-yearMonthDurations($arg1 as xs:yearMonthDuration, $arg2 as xs:yearMonthDuration) as xs:yearMonthDuration
-// pub(crate) static OP_SUBTRACT_YEAR_MONTH_DURATIONS() -> FUNCTION {
-//     (
-//     (
-//         [
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_YEAR_MONTH_DURATION)),
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_YEAR_MONTH_DURATION))
-//         ].to_vec(),
-//         SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_YEAR_MONTH_DURATION)),
-//     ),
-//     op_subtract_year_month_durations
-// );
-//
-// pub(crate) fn op_subtract_year_month_durations(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-//     todo!()
-// }
-//
-// // op:multiply-yearMonthDuration($arg1 as xs:yearMonthDuration, $arg2 as xs:double) as xs:yearMonthDuration
-// pub(crate) static OP_MULTIPLY_YEAR_MONTH_DURATIONS() -> FUNCTION {
-//     (
-//     (
-//         [
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_YEAR_MONTH_DURATION)),
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_YEAR_MONTH_DURATION))
-//         ].to_vec(),
-//         SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_YEAR_MONTH_DURATION)),
-//     ),
-//     op_multiply_year_month_durations
-// );
-//
-// pub(crate) fn op_multiply_year_month_durations(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-//     todo!()
-// }
-//
-// // op:divide-yearMonthDuration($arg1 as xs:yearMonthDuration, $arg2 as xs:double) as xs:yearMonthDuration
-// pub(crate) static OP_DIVIDE_YEAR_MONTH_DURATION() -> FUNCTION {
-//     (
-//     (
-//         [
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_YEAR_MONTH_DURATION)),
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DOUBLE))
-//         ].to_vec(),
-//         SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_YEAR_MONTH_DURATION)),
-//     ),
-//     op_divide_year_month_duration
-// );
-//
-// pub(crate) fn op_divide_year_month_duration(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-//     todo!()
-// }
-//
-// // op:divide-yearMonthDuration-by-yearMonthDuration($arg1 as xs:yearMonthDuration, $arg2 as xs:yearMonthDuration) as xs:decimal
-// pub(crate) static OP_DIVIDE_YEAR_MONTH_DURATION_BY_YEAR_MONTH_DURATION() -> FUNCTION {
-//     (
-//     (
-//         [
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_YEAR_MONTH_DURATION)),
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_YEAR_MONTH_DURATION))
-//         ].to_vec(),
-//         SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DECIMAL.into())),
-//     ),
-//     op_divide_year_month_duration_by_year_month_duration
-// );
-//
-// pub(crate) fn op_divide_year_month_duration_by_year_month_duration(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-//     todo!()
-// }
-//
-// // op:add-dayTimeDurations($arg1 as xs:dayTimeDuration, $arg2 as xs:dayTimeDuration) as xs:dayTimeDuration
-// pub(crate) static OP_ADD_DAY_TIME_DURATIONS() -> FUNCTION {
-//     (
-//     (
-//         [
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DAY_TIME_DURATION.into())),
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DAY_TIME_DURATION.into()))
-//         ].to_vec(),
-//         SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DAY_TIME_DURATION.into())),
-//     ),
-//     op_add_day_time_durations
-// );
-//
-// pub(crate) fn op_add_day_time_durations(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-//     todo!()
-// }
-//
-// // op:subtract-dayTimeDurations($arg1 as xs:dayTimeDuration, $arg2 as xs:dayTimeDuration) as xs:dayTimeDuration
-// pub(crate) static OP_SUBTRACT_DAY_TIME_DURATIONS() -> FUNCTION {
-//     (
-//     (
-//         [
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DAY_TIME_DURATION.into())),
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DAY_TIME_DURATION.into()))
-//         ].to_vec(),
-//         SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DAY_TIME_DURATION.into())),
-//     ),
-//     op_subtract_day_time_durations
-// );
-//
-// pub(crate) fn op_subtract_day_time_durations(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-//     todo!()
-// }
-//
-// // op:multiply-dayTimeDuration($arg1 as xs:dayTimeDuration, $arg2 as xs:double) as xs:dayTimeDuration
-// pub(crate) static OP_MULTIPLY_DAY_TIME_DURATIONS() -> FUNCTION {
-//     (
-//     (
-//         [
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DAY_TIME_DURATION.into())),
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DAY_TIME_DURATION.into()))
-//         ].to_vec(),
-//         SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DAY_TIME_DURATION.into())),
-//     ),
-//     op_multiply_day_time_durations
-// );
-//
-// pub(crate) fn op_multiply_day_time_durations(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-//     todo!()
-// }
-//
-// // op:divide-dayTimeDuration($arg1 as xs:dayTimeDuration, $arg2 as xs:double) as xs:dayTimeDuration
-// pub(crate) static OP_DIVIDE_DAY_TIME_DURATION() -> FUNCTION {
-//     (
-//     (
-//         [
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DAY_TIME_DURATION.into())),
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DOUBLE))
-//         ].to_vec(),
-//         SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DAY_TIME_DURATION.into())),
-//     ),
-//     op_divide_day_time_duration
-// );
-//
-// pub(crate) fn op_divide_day_time_duration(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-//     todo!()
-// }
-//
-// // op:divide-dayTimeDuration-by-dayTimeDuration($arg1 as xs:dayTimeDuration, $arg2 as xs:dayTimeDuration) as xs:decimal
-// pub(crate) static OP_DIVIDE_DAY_TIME_DURATION_BY_DAY_TIME_DURATION() -> FUNCTION {
-//     (
-//     (
-//         [
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DAY_TIME_DURATION.into())),
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DAY_TIME_DURATION.into()))
-//         ].to_vec(),
-//         SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DECIMAL.into())),
-//     ),
-//     op_divide_day_time_duration_by_day_time_duration
-// );
-//
-// pub(crate) fn op_divide_day_time_duration_by_day_time_duration(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-//     todo!()
-// }
-
 // fn:year-from-dateTime($arg as xs:dateTime?) as xs:integer?
 pub(crate) fn FN_YEAR_FROM_DATE_TIME() -> FUNCTION {
     (
@@ -376,7 +269,13 @@ pub(crate) fn FN_YEAR_FROM_DATE_TIME() -> FUNCTION {
 }
 
 pub(crate) fn fn_year_from_date_time(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+    match arguments.as_slice() {
+        [Object::Empty] => Ok((env, Object::Empty)),
+        [Object::Atomic(Type::DateTime { dt, .. })] => {
+            Ok((env, Object::Atomic(Type::Integer(dt.year() as i128))))
+        }
+        _ => Err((ErrorCode::XPTY0004, String::from("expected xs:dateTime")))
+    }
 }
 
 // fn:month-from-dateTime($arg as xs:dateTime?) as xs:integer?
@@ -391,7 +290,13 @@ pub(crate) fn FN_MONTH_FROM_DATE_TIME() -> FUNCTION {
 }
 
 pub(crate) fn fn_month_from_date_time(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+    match arguments.as_slice() {
+        [Object::Empty] => Ok((env, Object::Empty)),
+        [Object::Atomic(Type::DateTime { dt, .. })] => {
+            Ok((env, Object::Atomic(Type::Integer(dt.month() as i128))))
+        }
+        _ => Err((ErrorCode::XPTY0004, String::from("expected xs:dateTime")))
+    }
 }
 
 // fn:day-from-dateTime($arg as xs:dateTime?) as xs:integer?
@@ -406,7 +311,13 @@ pub(crate) fn FN_DAY_FROM_DATE_TIME() -> FUNCTION {
 }
 
 pub(crate) fn fn_day_from_date_time(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+    match arguments.as_slice() {
+        [Object::Empty] => Ok((env, Object::Empty)),
+        [Object::Atomic(Type::DateTime { dt, .. })] => {
+            Ok((env, Object::Atomic(Type::Integer(dt.day() as i128))))
+        }
+        _ => Err((ErrorCode::XPTY0004, String::from("expected xs:dateTime")))
+    }
 }
 
 // fn:hours-from-dateTime($arg as xs:dateTime?) as xs:integer?
@@ -421,7 +332,13 @@ pub(crate) fn FN_HOURS_FROM_DATE_TIME() -> FUNCTION {
 }
 
 pub(crate) fn fn_hours_from_date_time(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+    match arguments.as_slice() {
+        [Object::Empty] => Ok((env, Object::Empty)),
+        [Object::Atomic(Type::DateTime { dt, .. })] => {
+            Ok((env, Object::Atomic(Type::Integer(dt.hour() as i128))))
+        }
+        _ => Err((ErrorCode::XPTY0004, String::from("expected xs:dateTime")))
+    }
 }
 
 // fn:minutes-from-dateTime($arg as xs:dateTime?) as xs:integer?
@@ -436,7 +353,13 @@ pub(crate) fn FN_MINUTES_FROM_DATE_TIME() -> FUNCTION {
 }
 
 pub(crate) fn fn_minutes_from_date_time(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+    match arguments.as_slice() {
+        [Object::Empty] => Ok((env, Object::Empty)),
+        [Object::Atomic(Type::DateTime { dt, .. })] => {
+            Ok((env, Object::Atomic(Type::Integer(dt.minute() as i128))))
+        }
+        _ => Err((ErrorCode::XPTY0004, String::from("expected xs:dateTime")))
+    }
 }
 
 // fn:seconds-from-dateTime($arg as xs:dateTime?) as xs:decimal?
@@ -451,7 +374,13 @@ pub(crate) fn FN_SECONDS_FROM_DATE_TIME() -> FUNCTION {
 }
 
 pub(crate) fn fn_seconds_from_date_time(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+    match arguments.as_slice() {
+        [Object::Empty] => Ok((env, Object::Empty)),
+        [Object::Atomic(Type::DateTime { dt, .. })] => {
+            Ok((env, Object::Atomic(Type::Decimal(seconds_with_fraction(dt.second(), dt.nanosecond())))))
+        }
+        _ => Err((ErrorCode::XPTY0004, String::from("expected xs:dateTime")))
+    }
 }
 
 // fn:timezone-from-dateTime($arg as xs:dateTime?) as xs:dayTimeDuration?
@@ -466,7 +395,17 @@ pub(crate) fn FN_TIMEZONE_FROM_DATE_TIME() -> FUNCTION {
 }
 
 pub(crate) fn fn_timezone_from_date_time(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+    match arguments.as_slice() {
+        [Object::Empty] => Ok((env, Object::Empty)),
+        [Object::Atomic(Type::DateTime { dt, offset })] => {
+            if *offset {
+                Ok((env, Object::Atomic(timezone_to_duration(dt.offset())?)))
+            } else {
+                Ok((env, Object::Empty))
+            }
+        }
+        _ => Err((ErrorCode::XPTY0004, String::from("expected xs:dateTime")))
+    }
 }
 
 // fn:year-from-date($arg as xs:date?) as xs:integer?
@@ -550,7 +489,17 @@ pub(crate) fn FN_TIMEZONE_FROM_DATE() -> FUNCTION {
 }
 
 pub(crate) fn fn_timezone_from_date(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+    match arguments.as_slice() {
+        [Object::Empty] => Ok((env, Object::Empty)),
+        [Object::Atomic(Type::Date { date, offset })] => {
+            if *offset {
+                Ok((env, Object::Atomic(timezone_to_duration(date.offset())?)))
+            } else {
+                Ok((env, Object::Empty))
+            }
+        }
+        _ => Err((ErrorCode::XPTY0004, String::from("expected xs:date")))
+    }
 }
 
 // fn:hours-from-time($arg as xs:time?) as xs:integer?
@@ -565,7 +514,13 @@ pub(crate) fn FN_HOURS_FROM_TIME() -> FUNCTION {
 }
 
 pub(crate) fn fn_hours_from_time(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+    match arguments.as_slice() {
+        [Object::Empty] => Ok((env, Object::Empty)),
+        [Object::Atomic(Type::Time { time, .. })] => {
+            Ok((env, Object::Atomic(Type::Integer(time.time.hour() as i128))))
+        }
+        _ => Err((ErrorCode::XPTY0004, String::from("expected xs:time")))
+    }
 }
 
 // fn:minutes-from-time($arg as xs:time?) as xs:integer?
@@ -580,7 +535,13 @@ pub(crate) fn FN_MINUTES_FROM_TIME() -> FUNCTION {
 }
 
 pub(crate) fn fn_minutes_from_time(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+    match arguments.as_slice() {
+        [Object::Empty] => Ok((env, Object::Empty)),
+        [Object::Atomic(Type::Time { time, .. })] => {
+            Ok((env, Object::Atomic(Type::Integer(time.time.minute() as i128))))
+        }
+        _ => Err((ErrorCode::XPTY0004, String::from("expected xs:time")))
+    }
 }
 
 // fn:seconds-from-time($arg as xs:time?) as xs:decimal?
@@ -595,7 +556,13 @@ pub(crate) fn FN_SECONDS_FROM_TIME() -> FUNCTION {
 }
 
 pub(crate) fn fn_seconds_from_time(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+    match arguments.as_slice() {
+        [Object::Empty] => Ok((env, Object::Empty)),
+        [Object::Atomic(Type::Time { time, .. })] => {
+            Ok((env, Object::Atomic(Type::Decimal(seconds_with_fraction(time.time.second(), time.time.nanosecond())))))
+        }
+        _ => Err((ErrorCode::XPTY0004, String::from("expected xs:time")))
+    }
 }
 
 // fn:timezone-from-time($arg as xs:time?) as xs:dayTimeDuration?
@@ -613,21 +580,13 @@ pub(crate) fn fn_timezone_from_time(env: Box<Environment>, arguments: Vec<Object
     match arguments.as_slice() {
         [Object::Empty] => Ok((env, Object::Empty)),
         [Object::Atomic(Type::Time { time, offset })] => {
-            let seconds = time.offset.local_minus_utc();
-
-            let (seconds, positive) = if seconds < 0 {
-                (-seconds as u32, false)
+            if *offset {
+                Ok((env, Object::Atomic(timezone_to_duration(&time.offset)?)))
             } else {
-                (seconds as u32, true)
-            };
-
-            let (seconds, minutes) = norm(seconds, 60);
-            let (minutes, hours) = norm(minutes, 60);
-            let (hours, days) = norm(hours, 24);
-
-            Ok((env, Object::Atomic(Type::DayTimeDuration { positive, days, hours, minutes, seconds, microseconds: 0 })))
-        },
-        _ => panic!("error")
+                Ok((env, Object::Empty))
+            }
+        }
+        _ => Err((ErrorCode::XPTY0004, String::from("expected xs:time")))
     }
 }
 
@@ -657,7 +616,15 @@ pub(crate) fn FN_ADJUST_DATE_TIME_TO_TIMEZONE_2() -> FUNCTION {
 }
 
 pub(crate) fn fn_adjust_date_time_to_timezone(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+    let timezone = timezone_argument(&env, &arguments)?;
+    match arguments.first() {
+        Some(Object::Empty) => Ok((env, Object::Empty)),
+        Some(Object::Atomic(Type::DateTime { dt, offset })) => {
+            let (dt, offset) = adjust_date_time(dt, *offset, timezone)?;
+            Ok((env, Object::Atomic(Type::DateTime { dt, offset })))
+        }
+        _ => Err((ErrorCode::XPTY0004, String::from("expected xs:dateTime")))
+    }
 }
 
 // fn:adjust-date-to-timezone($arg as xs:date?) as xs:date?
@@ -686,7 +653,16 @@ pub(crate) fn FN_ADJUST_DATE_TO_TIMEZONE_2() -> FUNCTION {
 }
 
 pub(crate) fn fn_adjust_date_to_timezone(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+    let timezone = timezone_argument(&env, &arguments)?;
+    match arguments.first() {
+        Some(Object::Empty) => Ok((env, Object::Empty)),
+        Some(Object::Atomic(Type::Date { date, offset })) => {
+            // date is adjusted as dateTime at midnight
+            let (dt, offset) = adjust_date_time(&date.and_hms(0, 0, 0), *offset, timezone)?;
+            Ok((env, Object::Atomic(Type::Date { date: dt.date(), offset })))
+        }
+        _ => Err((ErrorCode::XPTY0004, String::from("expected xs:date")))
+    }
 }
 
 // fn:adjust-time-to-timezone($arg as xs:time?) as xs:time?
@@ -715,230 +691,28 @@ pub(crate) fn FN_ADJUST_TIME_TO_TIMEZONE_2() -> FUNCTION {
 }
 
 pub(crate) fn fn_adjust_time_to_timezone(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+    let timezone = timezone_argument(&env, &arguments)?;
+    match arguments.first() {
+        Some(Object::Empty) => Ok((env, Object::Empty)),
+        Some(Object::Atomic(Type::Time { time, offset })) => {
+            let result = match timezone {
+                None => Type::Time { time: Time::from_utc(time.time), offset: false },
+                Some(timezone) => {
+                    let t = if *offset {
+                        let shift = timezone.local_minus_utc() - time.offset.local_minus_utc();
+                        time.time.overflowing_add_signed(chrono::Duration::seconds(shift as i64)).0
+                    } else {
+                        time.time
+                    };
+                    Type::Time { time: Time::from(t, timezone), offset: true }
+                }
+            };
+            Ok((env, Object::Atomic(result)))
+        }
+        _ => Err((ErrorCode::XPTY0004, String::from("expected xs:time")))
+    }
 }
 
-// // op:subtract-dateTimes($arg1 as xs:dateTime, $arg2 as xs:dateTime) as xs:dayTimeDuration
-// pub(crate) fn OP_SUBTRACT_DATE_TIMES() -> FUNCTION {
-//     (
-//     (
-//         [
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DATE_TIME.into())),
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DATE_TIME.into()))
-//         ].to_vec(),
-//         SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DAY_TIME_DURATION.into())),
-//     ),
-//     op_subtract_date_times
-// );
-//
-// pub(crate) fn op_subtract_date_times(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-//     todo!()
-// }
-//
-// // op:subtract-dates($arg1 as xs:date, $arg2 as xs:date) as xs:dayTimeDuration
-// pub(crate) static OP_SUBTRACT_DATES() -> FUNCTION {
-//     (
-//     (
-//         [
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DATE)),
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DATE))
-//         ].to_vec(),
-//         SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DAY_TIME_DURATION.into())),
-//     ),
-//     op_subtract_dates
-// );
-//
-// pub(crate) fn op_subtract_dates(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-//     todo!()
-// }
-//
-// // op:subtract-times($arg1 as xs:time, $arg2 as xs:time) as xs:dayTimeDuration
-// pub(crate) static OP_SUBTRACT_TIMES() -> FUNCTION {
-//     (
-//     (
-//         [
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_TIME.into())),
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_TIME.into()))
-//         ].to_vec(),
-//         SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DAY_TIME_DURATION.into())),
-//     ),
-//     op_subtract_times
-// );
-//
-// pub(crate) fn op_subtract_times(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-//     todo!()
-// }
-//
-// // op:add-yearMonthDuration-to-dateTime($arg1 as xs:dateTime, $arg2 as xs:yearMonthDuration) as xs:dateTime
-// pub(crate) static OP_ADD_YEAR_MONTH_DURATION_TO_DATE_TIME() -> FUNCTION {
-//     (
-//     (
-//         [
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DATE_TIME.into())),
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_YEAR_MONTH_DURATION))
-//         ].to_vec(),
-//         SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DATE_TIME.into())),
-//     ),
-//     op_add_year_month_duration_to_date_time
-// );
-//
-// pub(crate) fn op_add_year_month_duration_to_date_time(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-//     todo!()
-// }
-//
-// // op:add-dayTimeDuration-to-dateTime($arg1 as xs:dateTime, $arg2 as xs:dayTimeDuration) as xs:dateTime
-// pub(crate) static OP_ADD_DAY_TIME_DURATION_TO_DATE_TIME() -> FUNCTION {
-//     (
-//     (
-//         [
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DATE_TIME.into())),
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DAY_TIME_DURATION.into()))
-//         ].to_vec(),
-//         SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DATE_TIME.into())),
-//     ),
-//     op_add_day_time_duration_to_date_time
-// );
-//
-// pub(crate) fn op_add_day_time_duration_to_date_time(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-//     todo!()
-// }
-//
-// // op:subtract-yearMonthDuration-from-dateTime($arg1 as xs:dateTime, $arg2 as xs:yearMonthDuration) as xs:dateTime
-// pub(crate) static OP_SUBTRACT_YEAR_MONTH_DURATION_FROM_DATE_TIME() -> FUNCTION {
-//     (
-//     (
-//         [
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DATE_TIME.into())),
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_YEAR_MONTH_DURATION))
-//         ].to_vec(),
-//         SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DATE_TIME.into())),
-//     ),
-//     op_subtract_year_month_duration_from_date_time
-// );
-//
-// pub(crate) fn op_subtract_year_month_duration_from_date_time(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-//     todo!()
-// }
-//
-// // op:subtract-dayTimeDuration-from-dateTime($arg1 as xs:dateTime, $arg2 as xs:dayTimeDuration) as xs:dateTime
-// pub(crate) static OP_SUBTRACT_DAY_TIME_DURATION_FROM_DATE_TIME() -> FUNCTION {
-//     (
-//     (
-//         [
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DATE_TIME.into())),
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DAY_TIME_DURATION.into()))
-//         ].to_vec(),
-//         SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DATE_TIME.into())),
-//     ),
-//     op_subtract_day_time_duration_from_date_time
-// );
-//
-// pub(crate) fn op_subtract_day_time_duration_from_date_time(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-//     todo!()
-// }
-//
-// // op:add-yearMonthDuration-to-date($arg1 as xs:date, $arg2 as xs:yearMonthDuration) as xs:date
-// pub(crate) static OP_ADD_YEAR_MONTH_DURATION_TO_DATE() -> FUNCTION {
-//     (
-//     (
-//         [
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DATE)),
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_YEAR_MONTH_DURATION))
-//         ].to_vec(),
-//         SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DATE)),
-//     ),
-//     op_add_year_month_duration_to_date
-// );
-//
-// pub(crate) fn op_add_year_month_duration_to_date(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-//     todo!()
-// }
-//
-// // op:add-dayTimeDuration-to-date($arg1 as xs:date, $arg2 as xs:dayTimeDuration) as xs:date
-// pub(crate) static OP_ADD_DAY_TIME_DURATION_TO_DATE() -> FUNCTION {
-//     (
-//     (
-//         [
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DATE)),
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DAY_TIME_DURATION.into()))
-//         ].to_vec(),
-//         SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DATE)),
-//     ),
-//     op_add_day_time_duration_to_date
-// );
-//
-// pub(crate) fn op_add_day_time_duration_to_date(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-//     todo!()
-// }
-//
-// // op:subtract-yearMonthDuration-from-date($arg1 as xs:date, $arg2 as xs:yearMonthDuration) as xs:date
-// pub(crate) static OP_SUBTRACT_YEAR_MONTH_DURATION_FROM_DATE() -> FUNCTION {
-//     (
-//     (
-//         [
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DATE)),
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_YEAR_MONTH_DURATION))
-//         ].to_vec(),
-//         SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DATE)),
-//     ),
-//     op_subtract_year_month_duration_from_date
-// );
-//
-// pub(crate) fn op_subtract_year_month_duration_from_date(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-//     todo!()
-// }
-//
-// // op:subtract-dayTimeDuration-from-date($arg1 as xs:date, $arg2 as xs:dayTimeDuration) as xs:date
-// pub(crate) static OP_SUBTRACT_DAY_TIME_DURATION_FROM_DATE() -> FUNCTION {
-//     (
-//     (
-//         [
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DATE)),
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DAY_TIME_DURATION.into()))
-//         ].to_vec(),
-//         SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DATE)),
-//     ),
-//     op_subtract_day_time_duration_from_date
-// );
-//
-// pub(crate) fn op_subtract_day_time_duration_from_date(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-//     todo!()
-// }
-//
-// // op:add-dayTimeDuration-to-time($arg1 as xs:time, $arg2 as xs:dayTimeDuration) as xs:time
-// pub(crate) static OP_ADD_DAY_TIME_DURATION_TO_TIME() -> FUNCTION {
-//     (
-//     (
-//         [
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_TIME.into())),
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DAY_TIME_DURATION.into()))
-//         ].to_vec(),
-//         SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_TIME.into())),
-//     ),
-//     op_add_day_time_duration_to_time
-// );
-//
-// pub(crate) fn op_add_day_time_duration_to_time(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-//     todo!()
-// }
-//
-// // op:subtract-dayTimeDuration-from-time($arg1 as xs:time, $arg2 as xs:dayTimeDuration) as xs:time
-// pub(crate) static OP_SUBTRACT_DAY_TIME_DURATION_FROM_TIME() -> FUNCTION {
-//     (
-//     (
-//         [
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_TIME.into())),
-//             SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_DAY_TIME_DURATION.into()))
-//         ].to_vec(),
-//         SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_TIME.into())),
-//     ),
-//     op_subtract_day_time_duration_from_time
-// );
-//
-// pub(crate) fn op_subtract_day_time_duration_from_time(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-//     todo!()
-// }
-
 // fn:format-dateTime($value as xs:dateTime?, $picture as xs:string) as xs:string?
 pub(crate) fn FN_FORMAT_DATE_TIME_2() -> FUNCTION {
     (
@@ -971,7 +745,7 @@ pub(crate) fn FN_FORMAT_DATE_TIME_5() -> FUNCTION {
 }
 
 pub(crate) fn fn_format_date_time(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+    format_by_picture(env, arguments)
 }
 
 // fn:format-date($value as xs:date?, $picture as xs:string) as xs:string?
@@ -1006,7 +780,7 @@ pub(crate) fn FN_FORMAT_DATE_5() -> FUNCTION {
 }
 
 pub(crate) fn fn_format_date(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+    format_by_picture(env, arguments)
 }
 
 // fn:format-time($value as xs:time?, $picture as xs:string) as xs:string?
@@ -1041,7 +815,29 @@ pub(crate) fn FN_FORMAT_TIME_5() -> FUNCTION {
 }
 
 pub(crate) fn fn_format_time(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+    format_by_picture(env, arguments)
+}
+
+// value formatted by picture in given language and calendar, place is ignored
+fn format_by_picture(env: Box<Environment>, arguments: Vec<Object>) -> EvalResult {
+    let string = |i: usize| match arguments.get(i) {
+        None | Some(Object::Empty) => None,
+        Some(object) => Some(object_to_string(&env, object)),
+    };
+    let picture = string(1).unwrap_or_default();
+    let language = string(2);
+    let calendar = string(3);
+
+    let (localization, available) = env.languages().resolve(language.as_deref());
+
+    match arguments.first() {
+        Some(Object::Atomic(value)) => {
+            let result = format_date_time(value, &picture, &*localization, available, calendar.as_deref())?;
+            Ok((env, Object::Atomic(Type::String(result))))
+        }
+        Some(Object::Empty) => Ok((env, Object::Empty)),
+        _ => Err((ErrorCode::XPTY0004, String::from("expected date or time value")))
+    }
 }
 
 // fn:parse-ietf-date($value as xs:string?) as xs:dateTime?
@@ -1072,7 +868,8 @@ pub(crate) fn FN_CURRENT_DATE_TIME() -> FUNCTION {
 
 pub(crate) fn fn_current_date_time(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
     // TODO  deterministic
-    Ok((env, Object::Atomic(Type::date_time_now())))
+    let now = Type::date_time_now(&env.implicit_timezone());
+    Ok((env, Object::Atomic(now)))
 }
 
 // fn:current-date() as xs:date
@@ -1088,7 +885,8 @@ pub(crate) fn FN_CURRENT_DATE() -> FUNCTION {
 
 pub(crate) fn fn_current_date(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
     // TODO  deterministic
-    Ok((env, Object::Atomic(Type::date_now())))
+    let now = Type::date_now(&env.implicit_timezone());
+    Ok((env, Object::Atomic(now)))
 }
 
 // fn:current-time() as xs:time
@@ -1104,7 +902,8 @@ pub(crate) fn FN_CURRENT_TIME() -> FUNCTION {
 
 pub(crate) fn fn_current_time(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
     // TODO  deterministic
-    Ok((env, Object::Atomic(Type::time_now())))
+    let now = Type::time_now(&env.implicit_timezone());
+    Ok((env, Object::Atomic(now)))
 }
 
 // fn:implicit-timezone() as xs:dayTimeDuration
@@ -1119,15 +918,59 @@ pub(crate) fn FN_IMPLICIT_TIMEZONE() -> FUNCTION {
 }
 
 pub(crate) fn fn_implicit_timezone(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+    let timezone = timezone_to_duration(&env.implicit_timezone())?;
+    Ok((env, Object::Atomic(timezone)))
 }
 
-pub(crate) fn norm(value: u32, max: u32) -> (u32, u32) {
-    let mut v = value;
-    let mut count = 0;
-    while v > max {
-        v = v - max;
-        count += 1;
+fn signed(positive: bool, value: u32) -> i128 {
+    if positive { value as i128 } else { -(value as i128) }
+}
+
+fn seconds_with_fraction(seconds: u32, nanoseconds: u32) -> BigDecimal {
+    let number = BigDecimal::new(BigInt::from(seconds as u64 * 1_000_000_000 + nanoseconds as u64), 9);
+    // drop trailing zeros of fraction
+    if number.is_integer() {
+        number.with_scale(0)
+    } else {
+        number.normalized()
+    }
+}
+
+fn timezone_to_duration(offset: &FixedOffset) -> Result<Type, ErrorInfo> {
+    day_time_duration(offset.local_minus_utc() as i64 * 1000)
+}
+
+// timezone of adjust-*-to-timezone functions, implicit one when argument is absent and none when it's empty
+fn timezone_argument(env: &Environment, arguments: &[Object]) -> Result<Option<FixedOffset>, ErrorInfo> {
+    match arguments.get(1) {
+        None => Ok(Some(env.implicit_timezone())),
+        Some(Object::Empty) => Ok(None),
+        Some(Object::Atomic(Type::DayTimeDuration { positive, days, hours, minutes, seconds, microseconds })) => {
+            let minutes = *hours as i32 * 60 + *minutes as i32;
+            if *days != 0 || *seconds != 0 || *microseconds != 0 || minutes > 14 * 60 {
+                return Err((ErrorCode::FODT0003, String::from("invalid timezone value")));
+            }
+            let seconds = if *positive { minutes * 60 } else { -minutes * 60 };
+            Ok(Some(FixedOffset::east(seconds)))
+        }
+        _ => Err((ErrorCode::XPTY0004, String::from("expected xs:dayTimeDuration")))
+    }
+}
+
+// value without timezone keeps local time and gets the timezone, otherwise it's moved to the timezone
+fn adjust_date_time(dt: &DateTime<FixedOffset>, offset: bool, timezone: Option<FixedOffset>) -> Result<(DateTime<FixedOffset>, bool), ErrorInfo> {
+    let result = match timezone {
+        None => FixedOffset::east(0).from_local_datetime(&dt.naive_local()).single(),
+        Some(timezone) => {
+            if offset {
+                Some(dt.with_timezone(&timezone))
+            } else {
+                timezone.from_local_datetime(&dt.naive_local()).single()
+            }
+        }
+    };
+    match result {
+        Some(dt) => Ok((dt, timezone.is_some())),
+        None => Err((ErrorCode::FODT0001, String::from("dateTime overflow")))
     }
-    (v, count)
 }
\ No newline at end of file
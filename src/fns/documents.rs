@@ -0,0 +1,111 @@
+use crate::eval::{Environment, Object, DynamicContext, EvalResult, Type, ErrorInfo};
+use crate::eval::sequence_type::*;
+use crate::fns::FUNCTION;
+use crate::parser::errors::ErrorCode;
+
+// fn:doc($uri as xs:string?) as document-node()?
+pub(crate) fn FN_DOC() -> FUNCTION {
+    (
+        (
+            [SequenceType::zero_or_one(ItemType::AtomicOrUnionType(XS_STRING.into()))].to_vec(),
+            SequenceType::zero_or_one(ItemType::node())
+        ),
+        fn_doc
+    )
+}
+
+pub(crate) fn fn_doc(mut env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
+    let uri = match arguments.get(0) {
+        Some(Object::Atomic(Type::String(uri))) => check_uri(uri)?,
+        _ => return Ok((env, Object::Empty))
+    };
+
+    match env.open_document(uri.as_str())? {
+        Some(rf) => Ok((env, Object::Node(rf))),
+        None => Err((ErrorCode::FODC0002, format!("document {:?} not found", uri)))
+    }
+}
+
+// fn:doc-available($uri as xs:string?) as xs:boolean
+pub(crate) fn FN_DOC_AVAILABLE() -> FUNCTION {
+    (
+        (
+            [SequenceType::zero_or_one(ItemType::AtomicOrUnionType(XS_STRING.into()))].to_vec(),
+            SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_BOOLEAN.into()))
+        ),
+        fn_doc_available
+    )
+}
+
+pub(crate) fn fn_doc_available(mut env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
+    let uri = match arguments.get(0) {
+        Some(Object::Atomic(Type::String(uri))) => uri.clone(),
+        _ => return Ok((env, Object::Atomic(Type::Boolean(false))))
+    };
+
+    let available = match check_uri(&uri) {
+        Ok(uri) => match env.open_document(uri.as_str()) {
+            Ok(rf) => rf.is_some(),
+            Err(_) => false
+        },
+        Err(_) => false
+    };
+
+    Ok((env, Object::Atomic(Type::Boolean(available))))
+}
+
+// fn:uri-collection() as xs:anyURI*
+pub(crate) fn FN_URI_COLLECTION_0() -> FUNCTION {
+    (
+        (
+            [].to_vec(),
+            SequenceType::zero_or_more(ItemType::AtomicOrUnionType(XS_ANY_URI.into()))
+        ),
+        fn_uri_collection
+    )
+}
+
+// fn:uri-collection($arg as xs:string?) as xs:anyURI*
+pub(crate) fn FN_URI_COLLECTION_1() -> FUNCTION {
+    (
+        (
+            [SequenceType::zero_or_one(ItemType::AtomicOrUnionType(XS_STRING.into()))].to_vec(),
+            SequenceType::zero_or_more(ItemType::AtomicOrUnionType(XS_ANY_URI.into()))
+        ),
+        fn_uri_collection
+    )
+}
+
+pub(crate) fn fn_uri_collection(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
+    // collection is folder of storage, default one is whole storage
+    let folder = match arguments.get(0) {
+        Some(Object::Atomic(Type::String(uri))) => {
+            let uri = check_uri(uri)?;
+            let uri = uri.trim_end_matches("/").to_string();
+            if uri.is_empty() { None } else { Some(format!("{}/", uri)) }
+        },
+        _ => None
+    };
+
+    let uris = env.storage.lock().unwrap().list()?;
+
+    let result = uris.into_iter()
+        .filter(|uri| folder.as_ref().map(|folder| uri.starts_with(folder)).unwrap_or(true))
+        .map(|uri| Object::Atomic(Type::AnyURI(uri)))
+        .collect::<Vec<_>>();
+
+    match result.len() {
+        0 => Ok((env, Object::Empty)),
+        1 => Ok((env, result.into_iter().next().unwrap())),
+        _ => Ok((env, Object::Sequence(result)))
+    }
+}
+
+fn check_uri(uri: &String) -> Result<String, ErrorInfo> {
+    let uri = uri.trim();
+    if uri.contains(|c: char| c.is_whitespace() || c == '#') {
+        Err((ErrorCode::FODC0005, format!("invalid uri {:?}", uri)))
+    } else {
+        Ok(uri.to_string())
+    }
+}
@@ -0,0 +1,1112 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use bigdecimal::{BigDecimal, Signed, ToPrimitive, Zero};
+use bigdecimal::num_bigint::BigInt;
+use chrono::{Datelike, Duration, NaiveDate, Timelike};
+use crate::eval::{ErrorInfo, Type};
+use crate::eval::prolog::DecimalFormatPropertyName;
+use crate::parser::errors::ErrorCode;
+use crate::fns::localization::Localization;
+
+// zero digits of decimal digit families which can be used in pictures
+const ZERO_DIGITS: [char; 18] = [
+    '0', '\u{660}', '\u{6F0}', '\u{7C0}', '\u{966}', '\u{9E6}', '\u{A66}', '\u{AE6}', '\u{B66}',
+    '\u{BE6}', '\u{C66}', '\u{CE6}', '\u{D66}', '\u{E50}', '\u{ED0}', '\u{F20}', '\u{1040}', '\u{FF10}',
+];
+
+// zero digit of family and value of digit
+fn digit_value(ch: char) -> Option<(char, u32)> {
+    for zero in ZERO_DIGITS {
+        let value = (ch as u32).wrapping_sub(zero as u32);
+        if value <= 9 {
+            return Some((zero, value));
+        }
+    }
+    None
+}
+
+// ascii digits to digits of family
+fn to_family(digits: &str, zero: char) -> String {
+    digits.chars()
+        .map(|ch| match ch.to_digit(10) {
+            Some(value) => char::from_u32(zero as u32 + value).unwrap_or(ch),
+            None => ch
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Case {
+    Lower,
+    Upper,
+    Title,
+}
+
+impl Case {
+    fn apply(&self, str: &str) -> String {
+        match self {
+            Case::Lower => str.to_lowercase(),
+            Case::Upper => str.to_uppercase(),
+            Case::Title => {
+                // every word is capitalized except conjunction
+                let mut result = String::with_capacity(str.len());
+                let mut start = true;
+                for (i, ch) in str.char_indices() {
+                    if start && !str[i..].starts_with("and ") {
+                        result.extend(ch.to_uppercase());
+                    } else {
+                        result.push(ch);
+                    }
+                    start = ch == ' ' || ch == '-';
+                }
+                result
+            }
+        }
+    }
+}
+
+// decimal digit pattern like "1", "001" or "#,##0"
+#[derive(Clone, Debug)]
+struct DigitPattern {
+    zero: char,
+    mandatory: usize,
+    optional: usize,
+    // grouping separators with number of digits to the right of them
+    separators: Vec<(usize, char)>,
+}
+
+impl DigitPattern {
+    fn default() -> Self {
+        DigitPattern { zero: '0', mandatory: 1, optional: 0, separators: vec![] }
+    }
+
+    fn parse(token: &str, code: ErrorCode) -> Result<Self, ErrorInfo> {
+        let error = || Err((code.clone(), format!("invalid decimal digit pattern {:?}", token)));
+
+        let mut zero = None;
+        let mut mandatory = 0;
+        let mut optional = 0;
+        let mut separators = vec![];
+        let mut after_separator = true;
+        for ch in token.chars() {
+            if ch == '#' {
+                if mandatory > 0 {
+                    return error();
+                }
+                optional += 1;
+                after_separator = false;
+            } else if let Some((family, _)) = digit_value(ch) {
+                if zero.is_some() && zero != Some(family) {
+                    return error();
+                }
+                zero = Some(family);
+                mandatory += 1;
+                after_separator = false;
+            } else if ch.is_alphanumeric() || after_separator {
+                return error();
+            } else {
+                separators.push((mandatory + optional, ch));
+                after_separator = true;
+            }
+        }
+        if after_separator || zero.is_none() {
+            return error();
+        }
+
+        let digits = mandatory + optional;
+        let separators = separators.into_iter()
+            .map(|(position, ch)| (digits - position, ch))
+            .collect();
+
+        Ok(DigitPattern { zero: zero.unwrap(), mandatory, optional, separators })
+    }
+
+    // interval of separators when they are the same and evenly spaced
+    fn regular(&self) -> Option<(usize, char)> {
+        let (interval, separator) = *self.separators.last()?;
+        let mut positions: Vec<usize> = self.separators.iter().map(|(position, _)| *position).collect();
+        positions.sort();
+        let is_regular = self.separators.iter().all(|(_, ch)| *ch == separator)
+            && positions.iter().enumerate().all(|(i, position)| *position == interval * (i + 1));
+        if is_regular { Some((interval, separator)) } else { None }
+    }
+
+    fn format(&self, number: impl Display, min_width: usize) -> String {
+        let digits = format!("{:0>width$}", number, width = self.mandatory.max(min_width));
+        let regular = self.regular();
+
+        let mut result: Vec<char> = Vec::with_capacity(digits.len() * 2);
+        for (i, ch) in digits.chars().rev().enumerate() {
+            if i > 0 {
+                let separator = match regular {
+                    Some((interval, separator)) => if i % interval == 0 { Some(separator) } else { None },
+                    None => self.separators.iter()
+                        .find(|(position, _)| *position == i)
+                        .map(|(_, separator)| *separator)
+                };
+                if let Some(separator) = separator {
+                    result.push(separator);
+                }
+            }
+            result.push(ch);
+        }
+        let digits: String = result.into_iter().rev().collect();
+        to_family(&digits, self.zero)
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Numbering {
+    Digits(DigitPattern),
+    Alphabetic(Case),
+    Roman(Case),
+    Words(Case),
+}
+
+impl Numbering {
+    fn parse(token: &str, code: ErrorCode) -> Result<Numbering, ErrorInfo> {
+        match token {
+            "a" => Ok(Numbering::Alphabetic(Case::Lower)),
+            "A" => Ok(Numbering::Alphabetic(Case::Upper)),
+            "i" => Ok(Numbering::Roman(Case::Lower)),
+            "I" => Ok(Numbering::Roman(Case::Upper)),
+            "w" => Ok(Numbering::Words(Case::Lower)),
+            "W" => Ok(Numbering::Words(Case::Upper)),
+            "Ww" => Ok(Numbering::Words(Case::Title)),
+            _ => {
+                if token.chars().any(|ch| ch == '#' || digit_value(ch).is_some()) {
+                    Ok(Numbering::Digits(DigitPattern::parse(token, code)?))
+                } else {
+                    // unsupported numbering sequences fall back to decimal numbers
+                    Ok(Numbering::Digits(DigitPattern::default()))
+                }
+            }
+        }
+    }
+
+    fn format(&self, number: i128, ordinal: bool, min_width: usize, localization: &dyn Localization) -> String {
+        let sign = if number < 0 { "-" } else { "" };
+        let abs = number.unsigned_abs();
+        match self {
+            Numbering::Digits(pattern) => {
+                let digits = pattern.format(abs, min_width);
+                if ordinal {
+                    format!("{}{}{}", sign, digits, localization.ordinal_suffix(abs))
+                } else {
+                    format!("{}{}", sign, digits)
+                }
+            }
+            Numbering::Alphabetic(case) if number > 0 => case.apply(&alphabetic(abs)),
+            Numbering::Roman(case) if number > 0 && number < 5000 => case.apply(&roman(abs)),
+            Numbering::Words(case) => {
+                let words = if ordinal { localization.ordinal_words(abs) } else { localization.cardinal_words(abs) };
+                if number < 0 {
+                    case.apply(&localization.negative_words(&words))
+                } else {
+                    case.apply(&words)
+                }
+            }
+            _ => Numbering::Digits(DigitPattern::default()).format(number, ordinal, min_width, localization)
+        }
+    }
+
+    // number out of i128 range is formatted by digits only
+    fn format_big(&self, number: &BigInt, ordinal: bool, localization: &dyn Localization) -> Result<String, ErrorInfo> {
+        let pattern = match self {
+            Numbering::Digits(pattern) => pattern.clone(),
+            Numbering::Roman(_) => DigitPattern::default(),
+            _ => return Err((ErrorCode::FOAR0002, format!("{} is out of range of numbering {:?}", number, self)))
+        };
+        let sign = if number.is_negative() { "-" } else { "" };
+        let digits = pattern.format(number.magnitude(), 0);
+        if ordinal {
+            // suffix depends on last digits only
+            let last = (number.magnitude() % 100u32).to_u128().unwrap_or(0) + 100;
+            Ok(format!("{}{}{}", sign, digits, localization.ordinal_suffix(last)))
+        } else {
+            Ok(format!("{}{}", sign, digits))
+        }
+    }
+}
+
+// a, b, ..., z, aa, ab, ...
+fn alphabetic(mut number: u128) -> String {
+    let mut result = vec![];
+    while number > 0 {
+        number -= 1;
+        result.push((b'a' + (number % 26) as u8) as char);
+        number /= 26;
+    }
+    result.into_iter().rev().collect()
+}
+
+fn roman(mut number: u128) -> String {
+    const NUMERALS: [(u128, &str); 13] = [
+        (1000, "m"), (900, "cm"), (500, "d"), (400, "cd"), (100, "c"), (90, "xc"),
+        (50, "l"), (40, "xl"), (10, "x"), (9, "ix"), (5, "v"), (4, "iv"), (1, "i"),
+    ];
+    let mut result = String::new();
+    for (value, numeral) in NUMERALS {
+        while number >= value {
+            result.push_str(numeral);
+            number -= value;
+        }
+    }
+    result
+}
+
+// format modifier of format-integer: ([co](\(.+\))?)?[at]?
+fn parse_format_modifier(modifier: &str) -> Result<bool, ErrorInfo> {
+    let error = || Err((ErrorCode::FODF1310, format!("invalid format modifier {:?}", modifier)));
+
+    let (ordinal, rest) = if let Some(rest) = modifier.strip_prefix('o') {
+        (true, rest)
+    } else if let Some(rest) = modifier.strip_prefix('c') {
+        (false, rest)
+    } else {
+        (false, modifier)
+    };
+    let rest = if rest.starts_with('(') {
+        match rest.find(')') {
+            Some(end) if end > 1 => &rest[end + 1..],
+            _ => return error()
+        }
+    } else {
+        rest
+    };
+    match rest {
+        "" | "a" | "t" => Ok(ordinal),
+        _ => error()
+    }
+}
+
+pub(crate) fn format_integer(number: i128, picture: &str, localization: &dyn Localization) -> Result<String, ErrorInfo> {
+    let (numbering, ordinal) = parse_integer_picture(picture)?;
+    Ok(numbering.format(number, ordinal, 0, localization))
+}
+
+pub(crate) fn format_big_integer(number: &BigInt, picture: &str, localization: &dyn Localization) -> Result<String, ErrorInfo> {
+    let (numbering, ordinal) = parse_integer_picture(picture)?;
+    match number.to_i128() {
+        Some(number) => Ok(numbering.format(number, ordinal, 0, localization)),
+        None => numbering.format_big(number, ordinal, localization)
+    }
+}
+
+// numbering of primary format token and whether it's ordinal
+fn parse_integer_picture(picture: &str) -> Result<(Numbering, bool), ErrorInfo> {
+    let (primary, ordinal) = match picture.rfind(';') {
+        Some(i) => (&picture[..i], parse_format_modifier(&picture[i + 1..])?),
+        None => (picture, false)
+    };
+    if primary.is_empty() {
+        return Err((ErrorCode::FODF1310, String::from("empty primary format token")));
+    }
+
+    Ok((Numbering::parse(primary, ErrorCode::FODF1310)?, ordinal))
+}
+
+// properties of decimal format used by format-number
+#[derive(Clone, Debug)]
+pub(crate) struct DecimalFormat {
+    decimal_separator: char,
+    grouping_separator: char,
+    exponent_separator: char,
+    infinity: String,
+    minus_sign: char,
+    nan: String,
+    percent: char,
+    per_mille: char,
+    zero_digit: char,
+    digit: char,
+    pattern_separator: char,
+}
+
+impl Default for DecimalFormat {
+    fn default() -> Self {
+        DecimalFormat {
+            decimal_separator: '.',
+            grouping_separator: ',',
+            exponent_separator: 'e',
+            infinity: String::from("Infinity"),
+            minus_sign: '-',
+            nan: String::from("NaN"),
+            percent: '%',
+            per_mille: '\u{2030}',
+            zero_digit: '0',
+            digit: '#',
+            pattern_separator: ';',
+        }
+    }
+}
+
+impl DecimalFormat {
+    pub(crate) fn from_properties(properties: &HashMap<DecimalFormatPropertyName, String>) -> Result<Self, ErrorInfo> {
+        let mut format = DecimalFormat::default();
+        for (name, value) in properties {
+            let mut chars = value.chars();
+            let ch = match (chars.next(), chars.next()) {
+                (Some(ch), None) => Some(ch),
+                _ => None
+            };
+            let ch = || ch.ok_or((ErrorCode::XQST0097, format!("{:?} must be a single character", name)));
+            match name {
+                DecimalFormatPropertyName::DecimalSeparator => format.decimal_separator = ch()?,
+                DecimalFormatPropertyName::GroupingSeparator => format.grouping_separator = ch()?,
+                DecimalFormatPropertyName::ExponentSeparator => format.exponent_separator = ch()?,
+                DecimalFormatPropertyName::MinusSign => format.minus_sign = ch()?,
+                DecimalFormatPropertyName::Percent => format.percent = ch()?,
+                DecimalFormatPropertyName::PerMille => format.per_mille = ch()?,
+                DecimalFormatPropertyName::Digit => format.digit = ch()?,
+                DecimalFormatPropertyName::PatternSeparator => format.pattern_separator = ch()?,
+                DecimalFormatPropertyName::ZeroDigit => {
+                    let zero = ch()?;
+                    if digit_value(zero) != Some((zero, 0)) {
+                        return Err((ErrorCode::XQST0097, format!("{:?} isn't a zero digit", value)));
+                    }
+                    format.zero_digit = zero;
+                }
+                DecimalFormatPropertyName::Infinity => format.infinity = value.clone(),
+                DecimalFormatPropertyName::NaN => format.nan = value.clone(),
+            }
+        }
+
+        // characters of pictures must be distinguishable
+        let mut chars = vec![
+            format.decimal_separator, format.grouping_separator, format.exponent_separator,
+            format.percent, format.per_mille, format.digit, format.pattern_separator,
+        ];
+        chars.extend((0..10).filter_map(|i| char::from_u32(format.zero_digit as u32 + i)));
+        let count = chars.len();
+        chars.sort();
+        chars.dedup();
+        if chars.len() != count {
+            return Err((ErrorCode::XQST0098, String::from("decimal format properties must have distinct values")));
+        }
+
+        Ok(format)
+    }
+
+    fn is_digit(&self, ch: char) -> bool {
+        ch == self.digit || self.mandatory_digit(ch).is_some()
+    }
+
+    fn mandatory_digit(&self, ch: char) -> Option<u32> {
+        let value = (ch as u32).wrapping_sub(self.zero_digit as u32);
+        if value <= 9 { Some(value) } else { None }
+    }
+
+    fn is_active(&self, ch: char) -> bool {
+        self.is_digit(ch) || ch == self.decimal_separator || ch == self.grouping_separator
+    }
+}
+
+// analysed sub-picture of format-number
+#[derive(Debug)]
+struct SubPicture {
+    prefix: String,
+    suffix: String,
+    min_integer: usize,
+    // grouping positions counted from decimal separator
+    integer_groups: Vec<usize>,
+    min_fraction: usize,
+    max_fraction: usize,
+    fraction_groups: Vec<usize>,
+    min_exponent: usize,
+    percent: bool,
+    per_mille: bool,
+}
+
+impl SubPicture {
+    fn parse(picture: &[char], format: &DecimalFormat) -> Result<Self, ErrorInfo> {
+        let error = || Err((ErrorCode::FODF1310, format!("invalid picture {:?}", picture.iter().collect::<String>())));
+
+        // exponent separator is active only between digits
+        let is_exponent = |i: usize| {
+            picture[i] == format.exponent_separator
+                && i > 0 && format.is_active(picture[i - 1])
+                && i + 1 < picture.len() && format.mandatory_digit(picture[i + 1]).is_some()
+        };
+        let is_active = |i: usize| format.is_active(picture[i]) || is_exponent(i);
+
+        let first = match (0..picture.len()).find(|i| is_active(*i)) {
+            Some(first) => first,
+            None => return error()
+        };
+        let last = (0..picture.len()).rev().find(|i| is_active(*i)).unwrap();
+
+        let prefix: String = picture[..first].iter().collect();
+        let suffix: String = picture[last + 1..].iter().collect();
+
+        let passive = format!("{}{}", prefix, suffix);
+        let percents = passive.chars().filter(|ch| *ch == format.percent).count();
+        let per_milles = passive.chars().filter(|ch| *ch == format.per_mille).count();
+        if percents + per_milles > 1 {
+            return error();
+        }
+
+        let mut mantissa = &picture[first..=last];
+        let mut min_exponent = 0;
+        if let Some(i) = (first..=last).find(|i| is_exponent(*i)) {
+            let exponent = &picture[i + 1..=last];
+            if percents + per_milles > 0 || !exponent.iter().all(|ch| format.mandatory_digit(*ch).is_some()) {
+                return error();
+            }
+            min_exponent = exponent.len();
+            mantissa = &picture[first..i];
+        }
+        if mantissa.iter().any(|ch| !format.is_active(*ch)) || !mantissa.iter().any(|ch| format.is_digit(*ch)) {
+            return error();
+        }
+
+        let (integer, fraction) = match mantissa.iter().position(|ch| *ch == format.decimal_separator) {
+            Some(i) => (&mantissa[..i], Some(&mantissa[i + 1..])),
+            None => (mantissa, None)
+        };
+        let fraction = fraction.unwrap_or(&[]);
+        if fraction.contains(&format.decimal_separator) {
+            return error();
+        }
+
+        let is_grouping = |ch: &char| *ch == format.grouping_separator;
+        // grouping separator can't be adjacent to another one or to decimal separator
+        if integer.windows(2).any(|pair| is_grouping(&pair[0]) && is_grouping(&pair[1]))
+            || fraction.windows(2).any(|pair| is_grouping(&pair[0]) && is_grouping(&pair[1]))
+            || integer.last().is_some_and(is_grouping)
+            || fraction.first().is_some_and(is_grouping)
+        {
+            return error();
+        }
+
+        let mut min_integer = 0;
+        let mut integer_groups = vec![];
+        let mut digits = 0;
+        for ch in integer.iter().rev() {
+            if is_grouping(ch) {
+                integer_groups.push(digits);
+            } else if *ch == format.digit {
+                digits += 1;
+            } else {
+                if digits > min_integer {
+                    // optional digit before mandatory one
+                    return error();
+                }
+                min_integer += 1;
+                digits += 1;
+            }
+        }
+        // mandatory digits must come first in integer part
+        let mut seen_mandatory = false;
+        for ch in integer {
+            if format.mandatory_digit(*ch).is_some() {
+                seen_mandatory = true;
+            } else if *ch == format.digit && seen_mandatory {
+                return error();
+            }
+        }
+
+        let mut min_fraction = 0;
+        let mut max_fraction = 0;
+        let mut fraction_groups = vec![];
+        for ch in fraction {
+            if is_grouping(ch) {
+                fraction_groups.push(max_fraction);
+            } else if *ch == format.digit {
+                max_fraction += 1;
+            } else {
+                if min_fraction < max_fraction {
+                    // mandatory digit after optional one
+                    return error();
+                }
+                min_fraction += 1;
+                max_fraction += 1;
+            }
+        }
+
+        if min_integer == 0 && max_fraction == 0 {
+            if min_exponent > 0 {
+                min_fraction = 1;
+                max_fraction = 1;
+            } else {
+                min_integer = 1;
+            }
+        }
+
+        Ok(SubPicture {
+            prefix,
+            suffix,
+            min_integer,
+            integer_groups,
+            min_fraction,
+            max_fraction,
+            fraction_groups,
+            min_exponent,
+            percent: percents > 0,
+            per_mille: per_milles > 0,
+        })
+    }
+
+    // grouping of integer part repeats when separators are evenly spaced
+    fn integer_separator_at(&self, position: usize) -> bool {
+        let mut groups = self.integer_groups.clone();
+        groups.sort();
+        if let Some(interval) = groups.first().cloned() {
+            let is_regular = interval > 0 && groups.iter().enumerate().all(|(i, group)| *group == interval * (i + 1));
+            if is_regular {
+                return position % interval == 0;
+            }
+        }
+        groups.contains(&position)
+    }
+}
+
+fn round_half_to_even(value: &BigDecimal, scale: i64) -> BigDecimal {
+    let (number, current) = value.as_bigint_and_exponent();
+    if current <= scale {
+        return value.clone();
+    }
+    let divisor = BigInt::from(10).pow((current - scale) as u32);
+    let negative = number.is_negative();
+    let number = number.abs();
+
+    let mut quotient = &number / &divisor;
+    let remainder: BigInt = &number % &divisor;
+    let twice: BigInt = remainder * 2;
+    if twice > divisor || (twice == divisor && (&quotient % 2) == BigInt::from(1)) {
+        quotient += 1;
+    }
+    BigDecimal::new(if negative { -quotient } else { quotient }, scale)
+}
+
+// integer and fraction digits of non-negative number
+fn split_digits(value: &BigDecimal) -> (String, String) {
+    let (number, scale) = value.as_bigint_and_exponent();
+    let digits = number.to_string();
+    if scale <= 0 {
+        let integer = format!("{}{}", digits, "0".repeat((-scale) as usize));
+        (integer.trim_start_matches('0').to_string(), String::new())
+    } else {
+        let scale = scale as usize;
+        let digits = format!("{:0>width$}", digits, width = scale + 1);
+        let (integer, fraction) = digits.split_at(digits.len() - scale);
+        (integer.trim_start_matches('0').to_string(), fraction.to_string())
+    }
+}
+
+// number of digits before decimal point, may be negative for small fractions
+fn magnitude(value: &BigDecimal) -> i64 {
+    let (number, scale) = value.as_bigint_and_exponent();
+    number.abs().to_string().len() as i64 - scale
+}
+
+pub(crate) fn format_number(value: &Type, picture: &str, format: &DecimalFormat) -> Result<String, ErrorInfo> {
+    let chars: Vec<char> = picture.chars().collect();
+    let sub_pictures: Vec<&[char]> = chars.split(|ch| *ch == format.pattern_separator).collect();
+    if sub_pictures.len() > 2 {
+        return Err((ErrorCode::FODF1310, format!("too many sub-pictures in {:?}", picture)));
+    }
+    let positive = SubPicture::parse(sub_pictures[0], format)?;
+    let negative = match sub_pictures.get(1) {
+        Some(sub_picture) => Some(SubPicture::parse(sub_picture, format)?),
+        None => None
+    };
+
+    let (number, is_negative) = match value {
+        Type::Integer(number) => (BigDecimal::from(BigInt::from(*number)), *number < 0),
+        Type::Decimal(number) => (number.clone(), number.is_negative()),
+        Type::Float(number) => return format_double(number.into_inner() as f64, &positive, negative.as_ref(), format),
+        Type::Double(number) => return format_double(number.into_inner(), &positive, negative.as_ref(), format),
+        _ => return Err((ErrorCode::XPTY0004, String::from("format-number expects number")))
+    };
+    format_decimal(number.abs(), is_negative, &positive, negative.as_ref(), format)
+}
+
+fn format_double(number: f64, positive: &SubPicture, negative: Option<&SubPicture>, format: &DecimalFormat) -> Result<String, ErrorInfo> {
+    if number.is_nan() {
+        return Ok(format.nan.clone());
+    }
+    let is_negative = number.is_sign_negative();
+    if number.is_infinite() {
+        let (prefix, suffix) = affixes(is_negative, positive, negative, format);
+        return Ok(format!("{}{}{}", prefix, format.infinity, suffix));
+    }
+    let number: BigDecimal = number.abs().to_string().parse()
+        .map_err(|_| (ErrorCode::FOCA0002, format!("can't format {}", number)))?;
+    format_decimal(number, is_negative, positive, negative, format)
+}
+
+// negative numbers use second sub-picture or first one with minus sign
+fn affixes(is_negative: bool, positive: &SubPicture, negative: Option<&SubPicture>, format: &DecimalFormat) -> (String, String) {
+    match (is_negative, negative) {
+        (false, _) => (positive.prefix.clone(), positive.suffix.clone()),
+        (true, Some(negative)) => (negative.prefix.clone(), negative.suffix.clone()),
+        (true, None) => (format!("{}{}", format.minus_sign, positive.prefix), positive.suffix.clone()),
+    }
+}
+
+fn format_decimal(number: BigDecimal, is_negative: bool, positive: &SubPicture, negative: Option<&SubPicture>, format: &DecimalFormat) -> Result<String, ErrorInfo> {
+    let picture = match (is_negative, negative) {
+        (true, Some(negative)) => negative,
+        _ => positive
+    };
+    let (prefix, suffix) = affixes(is_negative, positive, negative, format);
+
+    let mut number = number;
+    if picture.percent {
+        number *= BigDecimal::from(100);
+    } else if picture.per_mille {
+        number *= BigDecimal::from(1000);
+    }
+
+    let mut exponent: i64 = 0;
+    let mut mantissa = round_half_to_even(&number, picture.max_fraction as i64);
+    if picture.min_exponent > 0 && !number.is_zero() {
+        // mantissa has as many integer digits as there are mandatory ones
+        let integer_digits = picture.min_integer as i64;
+        exponent = magnitude(&number) - integer_digits;
+        let (digits, scale) = number.as_bigint_and_exponent();
+        mantissa = round_half_to_even(&BigDecimal::new(digits.clone(), scale + exponent), picture.max_fraction as i64);
+        if magnitude(&mantissa) > integer_digits.max(if integer_digits == 0 { 0 } else { 1 }) {
+            exponent += 1;
+            mantissa = round_half_to_even(&BigDecimal::new(digits, scale + exponent), picture.max_fraction as i64);
+        }
+    }
+
+    let (integer, fraction) = split_digits(&mantissa);
+
+    let integer = format!("{:0>width$}", integer, width = picture.min_integer);
+    let mut fraction = fraction.trim_end_matches('0').to_string();
+    while fraction.len() < picture.min_fraction {
+        fraction.push('0');
+    }
+    let integer = if integer.is_empty() && fraction.is_empty() { String::from("0") } else { integer };
+
+    let mut result = String::with_capacity(prefix.len() + integer.len() * 2 + fraction.len() * 2 + suffix.len());
+    result.push_str(&prefix);
+
+    let mut grouped: Vec<char> = vec![];
+    for (i, ch) in integer.chars().rev().enumerate() {
+        if i > 0 && picture.integer_separator_at(i) {
+            grouped.push(format.grouping_separator);
+        }
+        grouped.push(ch);
+    }
+    let integer: String = grouped.into_iter().rev().collect();
+    result.push_str(&to_family(&integer, format.zero_digit));
+
+    if !fraction.is_empty() {
+        result.push(format.decimal_separator);
+        for (i, ch) in fraction.chars().enumerate() {
+            if i > 0 && picture.fraction_groups.contains(&i) {
+                result.push(format.grouping_separator);
+            }
+            result.push_str(&to_family(&ch.to_string(), format.zero_digit));
+        }
+    }
+
+    if picture.min_exponent > 0 {
+        result.push(format.exponent_separator);
+        if exponent < 0 {
+            result.push(format.minus_sign);
+        }
+        let digits = format!("{:0>width$}", exponent.abs(), width = picture.min_exponent);
+        result.push_str(&to_family(&digits, format.zero_digit));
+    }
+
+    result.push_str(&suffix);
+    Ok(result)
+}
+
+// calendars of F&O, only Gregorian one is supported
+const CALENDARS: [&str; 28] = [
+    "AD", "AH", "AME", "AM", "AP", "AS", "BE", "CB", "CE", "CL", "CS", "EE", "FE", "ISO",
+    "JE", "KE", "KY", "ME", "MS", "NS", "OS", "RS", "SE", "SH", "SS", "TE", "VE", "VS",
+];
+
+// components of date, time or dateTime value
+struct DateTimeParts {
+    date: Option<NaiveDate>,
+    time: Option<(u32, u32, u32, u32)>,
+    // offset in minutes
+    timezone: Option<i32>,
+}
+
+impl DateTimeParts {
+    fn from(value: &Type) -> Result<Self, ErrorInfo> {
+        match value {
+            Type::DateTime { dt, offset } => {
+                let local = dt.naive_local();
+                Ok(DateTimeParts {
+                    date: Some(local.date()),
+                    time: Some((local.hour(), local.minute(), local.second(), local.nanosecond())),
+                    timezone: if *offset { Some(dt.offset().local_minus_utc() / 60) } else { None },
+                })
+            }
+            Type::Date { date, offset } => {
+                Ok(DateTimeParts {
+                    date: Some(date.naive_local()),
+                    time: None,
+                    timezone: if *offset { Some(date.offset().local_minus_utc() / 60) } else { None },
+                })
+            }
+            Type::Time { time, offset } => {
+                let t = time.time;
+                Ok(DateTimeParts {
+                    date: None,
+                    time: Some((t.hour(), t.minute(), t.second(), t.nanosecond())),
+                    timezone: if *offset { Some(time.offset.local_minus_utc() / 60) } else { None },
+                })
+            }
+            _ => Err((ErrorCode::XPTY0004, String::from("expected date or time value")))
+        }
+    }
+}
+
+// width modifier of variable marker: min-max where either may be "*"
+fn parse_width(width: &str) -> Result<(usize, Option<usize>), ErrorInfo> {
+    let error = || (ErrorCode::FOFD1340, format!("invalid width modifier {:?}", width));
+    let parse = |value: &str| -> Result<Option<usize>, ErrorInfo> {
+        if value == "*" {
+            Ok(None)
+        } else {
+            match value.parse::<usize>() {
+                Ok(number) if number > 0 => Ok(Some(number)),
+                _ => Err(error())
+            }
+        }
+    };
+    match width.split_once('-') {
+        Some((min, max)) => {
+            let min = parse(min)?.unwrap_or(1);
+            let max = parse(max)?;
+            if max.map_or(false, |max| max < min) {
+                Err(error())
+            } else {
+                Ok((min, max))
+            }
+        }
+        None => Ok((parse(width)?.unwrap_or(1), None))
+    }
+}
+
+fn names_case(modifier: &str) -> Option<Case> {
+    match modifier {
+        "n" => Some(Case::Lower),
+        "N" => Some(Case::Upper),
+        "Nn" => Some(Case::Title),
+        _ => None
+    }
+}
+
+fn format_timezone(minutes: i32, modifier: &str, traditional: bool, gmt: bool) -> Result<String, ErrorInfo> {
+    let sign = if minutes < 0 { '-' } else { '+' };
+    let hours = minutes.abs() / 60;
+    let rest = minutes.abs() % 60;
+
+    if minutes == 0 && traditional {
+        return Ok(String::from("Z"));
+    }
+
+    if modifier == "Z" {
+        // military timezones
+        if minutes == 0 {
+            return Ok(String::from("Z"));
+        } else if rest == 0 && hours <= 12 {
+            let letters = if minutes > 0 { "ABCDEFGHIKLM" } else { "NOPQRSTUVWXY" };
+            return Ok(letters.chars().nth(hours as usize - 1).unwrap().to_string());
+        }
+    }
+
+    let modifier = if modifier.chars().any(|ch| digit_value(ch).is_some()) { modifier } else { "01:01" };
+    let zero = modifier.chars().find_map(digit_value).map(|(zero, _)| zero).unwrap_or('0');
+    let hour_digits = modifier.chars().take_while(|ch| digit_value(*ch).is_some()).count();
+    let separator = modifier.chars().nth(hour_digits);
+
+    let result = match separator {
+        Some(separator) => format!("{}{:0>width$}{}{:02}", sign, hours, separator, rest, width = hour_digits),
+        None if hour_digits <= 2 => {
+            if rest == 0 {
+                format!("{}{:0>width$}", sign, hours, width = hour_digits)
+            } else {
+                format!("{}{:0>width$}:{:02}", sign, hours, rest, width = hour_digits)
+            }
+        }
+        None => format!("{}{:0>width$}{:02}", sign, hours, rest, width = hour_digits - 2),
+    };
+    let result = to_family(&result, zero);
+    if gmt {
+        Ok(format!("GMT{}", result))
+    } else {
+        Ok(result)
+    }
+}
+
+fn format_marker(marker: &str, parts: &DateTimeParts, localization: &dyn Localization) -> Result<String, ErrorInfo> {
+    let mut chars = marker.chars();
+    let component = match chars.next() {
+        Some(component) => component,
+        None => return Err((ErrorCode::FOFD1340, String::from("empty variable marker")))
+    };
+    let rest = chars.as_str();
+
+    let (presentation, width) = match rest.rfind(',') {
+        Some(i) => (&rest[..i], Some(parse_width(&rest[i + 1..])?)),
+        None => (rest, None)
+    };
+
+    // second presentation modifier: ordinal, cardinal, traditional or alphabetic
+    let (modifier, second) = match presentation.chars().last() {
+        Some(ch @ ('o' | 'c' | 't' | 'a')) if presentation.chars().count() > 1 => {
+            (&presentation[..presentation.len() - 1], Some(ch))
+        }
+        _ => (presentation, None)
+    };
+    let ordinal = second == Some('o');
+
+    let not_available = || (ErrorCode::FOFD1350, format!("component {:?} isn't available", component));
+    let date = || parts.date.ok_or_else(not_available);
+    let time = || parts.time.ok_or_else(not_available);
+
+    let (value, names, default): (i128, Option<String>, &str) = match component {
+        'Y' => (date()?.year() as i128, None, "1"),
+        'M' => {
+            let month = date()?.month();
+            (month as i128, Some(localization.month_name(month)), "1")
+        }
+        'D' => (date()?.day() as i128, None, "1"),
+        'd' => (date()?.ordinal() as i128, None, "1"),
+        'F' => {
+            let day = date()?.weekday().number_from_monday();
+            (day as i128, Some(localization.day_name(day)), "n")
+        }
+        'W' => (date()?.iso_week().week() as i128, None, "1"),
+        'w' => {
+            // week of month which contains its Thursday
+            let date = date()?;
+            let thursday = date + Duration::days(3 - date.weekday().num_days_from_monday() as i64);
+            (((thursday.day() - 1) / 7 + 1) as i128, None, "1")
+        }
+        'H' => (time()?.0 as i128, None, "1"),
+        'h' => {
+            let hour = time()?.0 % 12;
+            (if hour == 0 { 12 } else { hour as i128 }, None, "1")
+        }
+        'P' => {
+            let hour = time()?.0;
+            (hour as i128, Some(localization.day_period(hour)), "n")
+        }
+        'm' => (time()?.1 as i128, None, "01"),
+        's' => (time()?.2 as i128, None, "01"),
+        'f' => {
+            let nanoseconds = time()?.3;
+            let pattern = if modifier.is_empty() { "1" } else { modifier };
+            let pattern = match Numbering::parse(pattern, ErrorCode::FOFD1340)? {
+                Numbering::Digits(pattern) => pattern,
+                _ => DigitPattern::default()
+            };
+            let digits = pattern.mandatory + pattern.optional;
+            let (min, max) = match width {
+                Some((min, max)) => (min, max.unwrap_or(9)),
+                None => (pattern.mandatory, if digits > 1 { digits } else { 9 })
+            };
+            let fraction = format!("{:09}", nanoseconds);
+            let mut fraction = fraction[..max.min(9)].trim_end_matches('0').to_string();
+            while fraction.len() < min.max(1) {
+                fraction.push('0');
+            }
+            return Ok(to_family(&fraction, pattern.zero));
+        }
+        'Z' | 'z' => {
+            return match parts.timezone {
+                Some(minutes) => format_timezone(minutes, modifier, second == Some('t'), component == 'z'),
+                None => Ok(String::new())
+            };
+        }
+        'C' => (0, Some(String::from("ISO")), "n"),
+        'E' => {
+            let year = date()?.year();
+            (year as i128, Some(localization.era(year)), "n")
+        }
+        _ => return Err((ErrorCode::FOFD1340, format!("unknown component {:?}", component)))
+    };
+
+    let modifier = if modifier.is_empty() { default } else { modifier };
+
+    if let Some(case) = names_case(modifier) {
+        if let Some(name) = names {
+            let name = case.apply(&name);
+            return Ok(match width {
+                Some((_, Some(max))) => name.chars().take(max).collect(),
+                _ => name
+            });
+        }
+    }
+
+    let numbering = match names_case(modifier) {
+        Some(_) => Numbering::Digits(DigitPattern::default()),
+        None => Numbering::parse(modifier, ErrorCode::FOFD1340)?
+    };
+
+    let min_width = width.map_or(0, |(min, _)| min);
+    let result = numbering.format(value, ordinal, min_width, localization);
+
+    // only year is truncated to maximum width
+    if component == 'Y' {
+        if let Numbering::Digits(pattern) = &numbering {
+            let digits = pattern.mandatory + pattern.optional;
+            let max = match width {
+                Some((_, max)) => max,
+                None => if digits > 1 { Some(digits) } else { None }
+            };
+            if let Some(max) = max {
+                let count = result.chars().count();
+                if count > max {
+                    return Ok(result.chars().skip(count - max).collect());
+                }
+            }
+        }
+    }
+    Ok(result)
+}
+
+// output made with a fallback instead of requested language starts with the language actually used
+pub(crate) fn language_marker(localization: &dyn Localization, available: bool) -> String {
+    if available {
+        String::new()
+    } else {
+        format!("[Language: {}]", localization.language())
+    }
+}
+
+// localization is the one of requested language or a fallback when it isn't available
+pub(crate) fn format_date_time(value: &Type, picture: &str, localization: &dyn Localization, available: bool, calendar: Option<&str>) -> Result<String, ErrorInfo> {
+    let parts = DateTimeParts::from(value)?;
+
+    let mut result = String::new();
+
+    if let Some(calendar) = calendar {
+        let calendar = calendar.trim();
+        if !calendar.starts_with("Q{") && !CALENDARS.contains(&calendar) {
+            return Err((ErrorCode::FOFD1340, format!("unknown calendar {:?}", calendar)));
+        }
+        if calendar != "AD" && calendar != "ISO" && calendar != "CE" {
+            result.push_str("[Calendar: AD]");
+        }
+    }
+    result.push_str(&language_marker(localization, available));
+
+    let mut chars = picture.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '[' if chars.peek() == Some(&'[') => {
+                chars.next();
+                result.push('[');
+            }
+            ']' if chars.peek() == Some(&']') => {
+                chars.next();
+                result.push(']');
+            }
+            '[' => {
+                let mut marker = String::new();
+                loop {
+                    match chars.next() {
+                        Some(']') => break,
+                        Some(ch) if ch.is_whitespace() => {}
+                        Some(ch) => marker.push(ch),
+                        None => return Err((ErrorCode::FOFD1340, format!("unclosed variable marker in {:?}", picture)))
+                    }
+                }
+                result.push_str(&format_marker(&marker, &parts, localization)?);
+            }
+            ']' => return Err((ErrorCode::FOFD1340, format!("unescaped ']' in {:?}", picture))),
+            _ => result.push(ch)
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use crate::fns::{LanguageData, NumberRule};
+    use crate::parser::errors::ErrorCode;
+    use crate::tests::{test_eval_queries, test_eval_queries_with};
+
+    #[test]
+    fn eval_formatting() {
+        test_eval_queries(&[
+            ("format-integer(123, '0000')", Ok("0123")),
+            ("format-integer(1234567, '#,##0')", Ok("1,234,567")),
+            ("format-integer(21, '1;o')", Ok("21st")),
+            ("format-integer(2023, 'Ww')", Ok("Two Thousand and Twenty-Three")),
+            ("format-integer(1999, 'I')", Ok("MCMXCIX")),
+            ("format-integer(1, '#0#')", Err(ErrorCode::FODF1310)),
+            ("format-number(12345.678, '#,##0.00')", Ok("12,345.68")),
+            ("format-number(0.125, '0.00')", Ok("0.12")),
+            ("format-number(0.5, '0%')", Ok("50%")),
+            ("format-number(-1234.5, '#,##0.00;(#,##0.00)')", Ok("(1,234.50)")),
+            ("format-number(12345, '00.000e00')", Ok("12.345e03")),
+            ("format-number(xs:double('NaN'), '0')", Ok("NaN")),
+            ("declare decimal-format local:de decimal-separator = ',' grouping-separator = '.'; \
+                format-number(1234567.891, '#.##0,00', 'local:de')", Ok("1.234.567,89")),
+            ("declare default decimal-format NaN = 'none'; format-number(xs:double('NaN'), '0')", Ok("none")),
+            ("format-number(1, '0', 'local:unknown')", Err(ErrorCode::FODF1280)),
+            ("declare default decimal-format digit = '#' digit = '#'; 1", Err(ErrorCode::XQST0114)),
+            ("declare default decimal-format percent = ','; 1", Err(ErrorCode::XQST0098)),
+            ("format-date(xs:date('2002-12-31'), '[Y0001]-[M01]-[D01]')", Ok("2002-12-31")),
+            ("format-date(xs:date('2002-12-31'), '[MNn] [D1o], [Y]')", Ok("December 31st, 2002")),
+            ("format-date(xs:date('2002-12-31'), '[FNn], [D] [MNn,3-3] [Y01]')", Ok("Tuesday, 31 Dec 02")),
+            ("format-dateTime(xs:dateTime('2002-12-31T15:58:45.762+02:00'), '[h]:[m01]:[s01].[f001] [PN] [Z]')", Ok("3:58:45.762 PM +02:00")),
+            ("format-time(xs:time('09:05:03Z'), '[H01]:[m01] [Z0t]')", Ok("09:05 Z")),
+            ("format-date(xs:date('2002-12-31'), '[Y]', 'de', (), ())", Ok("[Language: en]2002")),
+            ("format-date(xs:date('2002-12-31'), '[H]')", Err(ErrorCode::FOFD1350)),
+            ("format-date(xs:date('2002-12-31'), '[Y')", Err(ErrorCode::FOFD1340)),
+        ]);
+    }
+
+    #[test]
+    fn eval_localized_formatting() {
+        let strings = |words: &[&str]| words.iter().map(|word| word.to_string()).collect::<Vec<_>>();
+        let russian = LanguageData {
+            language: String::from("ru"),
+            months: strings(&["Январь", "Февраль", "Март", "Апрель", "Май", "Июнь",
+                "Июль", "Август", "Сентябрь", "Октябрь", "Ноябрь", "Декабрь"]),
+            numbers: [(1, "один"), (2, "два"), (3, "три"), (20, "двадцать")].iter()
+                .map(|(number, words)| (*number, words.to_string()))
+                .collect(),
+            rules: vec![NumberRule { base: 10, exact: String::new(), compound: String::from("{m} {r}") }],
+            ordinals: [(3, String::from("третий"))].into_iter().collect(),
+            ordinal_suffix: String::from("-й"),
+            minus: String::from("минус "),
+            ..LanguageData::default()
+        };
+        let russian = Rc::new(russian);
+
+        let queries = [
+            ("en", vec![
+                ("default-language()", Ok("en")),
+                ("format-integer(3, 'w', 'ru')", Ok("три")),
+                ("format-integer(-23, 'w', 'ru-RU')", Ok("минус двадцать три")),
+                ("format-integer(23, 'Ww;o', 'ru')", Ok("Двадцать Третий")),
+                ("format-integer(3, '1;o', 'ru')", Ok("3-й")),
+                ("format-integer(3, 'w', 'fr')", Ok("[Language: en]three")),
+                ("format-integer(3, 'w', 'en-GB')", Ok("three")),
+                ("format-date(xs:date('2002-12-31'), '[MNn] [D]', 'ru', (), ())", Ok("Декабрь 31")),
+                ("format-date(xs:date('2002-12-31'), '[MNn]', 'fr', (), ())", Ok("[Language: en]December")),
+            ]),
+            ("ru", vec![
+                ("default-language()", Ok("ru")),
+                ("format-integer(3, 'w')", Ok("три")),
+                ("format-date(xs:date('2002-12-31'), '[MN]')", Ok("ДЕКАБРЬ")),
+            ]),
+        ];
+
+        for (language, queries) in queries {
+            test_eval_queries_with(|env| {
+                env.add_language(russian.clone());
+                env.set_default_language(language);
+            }, &queries);
+        }
+    }
+}
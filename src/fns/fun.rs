@@ -1,11 +1,12 @@
-use crate::eval::{Object, DynamicContext, EvalResult};
+use crate::eval::{Object, Type, DynamicContext, EvalResult};
 use crate::eval::Environment;
 use crate::eval::sequence_type::*;
+use crate::eval::helpers::{flat_items, relax};
 use crate::fns::FUNCTION;
 
-use crate::values::resolve_element_qname;
-use crate::fns::call;
-use crate::fns::strings::object_to_array;
+use crate::values::{resolve_element_qname, QNameResolved};
+use crate::fns::{call, call_item, collation_argument, function_exists, function_signature};
+use crate::eval::comparison::{sort_by_keys, sort_key};
 use crate::parser::errors::ErrorCode;
 
 // fn:function-lookup($name as xs:QName, $arity as xs:integer) as function(*)?
@@ -16,14 +17,32 @@ pub(crate) fn FN_FUNCTION_LOOKUP() -> FUNCTION {
                 SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_QNAME.into())),
                 SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_INTEGER.into()))
             ].to_vec(),
-            SequenceType::exactly_one(ItemType::Function { args: None, st: None })
+            SequenceType::zero_or_one(ItemType::Function { args: None, st: None })
         ),
         fn_function_lookup
     )
 }
 
 pub(crate) fn fn_function_lookup(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+    match arguments.as_slice() {
+        [Object::Atomic(Type::QName { url, prefix, local_part }), arity] => {
+            let url = match (url, prefix) {
+                (Some(url), _) => url.clone(),
+                (None, Some(prefix)) => env.namespaces.by_prefix(prefix)
+                    .map(|ns| ns.uri.clone())
+                    .unwrap_or_default(),
+                (None, None) => String::new()
+            };
+            let name = QNameResolved { url, local_part: local_part.clone() };
+            let arity = arity.to_integer()?;
+            if arity >= 0 && function_exists(&env, &name, arity as usize) {
+                Ok((env, Object::FunctionRef { name, arity: arity as usize }))
+            } else {
+                Ok((env, Object::Empty))
+            }
+        }
+        _ => panic!("error")
+    }
 }
 
 // fn:function-name($func as function(*)) as xs:QName?
@@ -38,7 +57,16 @@ pub(crate) fn FN_FUNCTION_NAME() -> FUNCTION {
 }
 
 pub(crate) fn fn_function_name(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+    match arguments.as_slice() {
+        [Object::FunctionRef { name, .. }] => {
+            let url = if name.url.is_empty() { None } else { Some(name.url.clone()) };
+            let qname = Type::QName { url, prefix: None, local_part: name.local_part.clone() };
+            Ok((env, Object::Atomic(qname)))
+        }
+        // anonymous functions, maps and arrays
+        [_] => Ok((env, Object::Empty)),
+        _ => panic!("error")
+    }
 }
 
 // fn:function-arity($func as function(*)) as xs:integer
@@ -53,7 +81,15 @@ pub(crate) fn FN_FUNCTION_ARITY() -> FUNCTION {
 }
 
 pub(crate) fn fn_function_arity(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+    match arguments.as_slice() {
+        [function] => {
+            match function_signature(&env, function) {
+                Some(params) => Ok((env, Object::Atomic(Type::Integer(params.len() as i128)))),
+                None => Err((ErrorCode::XPTY0004, format!("not a function: {:?}", function)))
+            }
+        }
+        _ => panic!("error")
+    }
 }
 
 // 16.2 Basic higher-order functions
@@ -71,16 +107,25 @@ pub(crate) fn FN_FOR_EACH() -> FUNCTION {
                     st: Some(Box::new(SequenceType::zero_or_more(ItemType::Item)))
                 })
             ].to_vec(),
-            SequenceType::zero_or_one(ItemType::AtomicOrUnionType(XS_INTEGER.into()))
+            SequenceType::zero_or_more(ItemType::Item)
         ),
         fn_for_each
     )
 }
 
-pub(crate) fn fn_for_each(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    match arguments.as_slice() {
-        [Object::Function { parameters, st, body }, Object::Array(arguments)] => {
-            todo!()
+pub(crate) fn fn_for_each(env: Box<Environment>, arguments: Vec<Object>, context: &DynamicContext) -> EvalResult {
+    let mut arguments = arguments.into_iter();
+    match (arguments.next(), arguments.next()) {
+        (Some(seq), Some(action)) => {
+            let mut current_env = env;
+            let mut result = vec![];
+            for item in flat_items(seq) {
+                let (new_env, value) = call_item(current_env, action.clone(), vec![item], context)?;
+                current_env = new_env;
+
+                result.push(value);
+            }
+            relax(current_env, result)
         },
         _ => panic!("error")
     }
@@ -101,14 +146,25 @@ pub(crate) fn FN_FILTER() -> FUNCTION {
             ].to_vec(),
             SequenceType::zero_or_more(ItemType::Item)
         ),
-        fn_for_each
+        fn_filter
     )
 }
 
-pub(crate) fn fn_filter(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    match arguments.as_slice() {
-        [Object::Function { parameters, st, body }, Object::Array(arguments)] => {
-            todo!()
+pub(crate) fn fn_filter(env: Box<Environment>, arguments: Vec<Object>, context: &DynamicContext) -> EvalResult {
+    let mut arguments = arguments.into_iter();
+    match (arguments.next(), arguments.next()) {
+        (Some(seq), Some(predicate)) => {
+            let mut current_env = env;
+            let mut result = vec![];
+            for item in flat_items(seq) {
+                let (new_env, value) = call_item(current_env, predicate.clone(), vec![item.clone()], context)?;
+                current_env = new_env;
+
+                if value.to_bool()? {
+                    result.push(item);
+                }
+            }
+            relax(current_env, result)
         },
         _ => panic!("error")
     }
@@ -136,28 +192,20 @@ pub(crate) fn FN_FOLD_LEFT() -> FUNCTION {
 }
 
 pub(crate) fn fn_fold_left(env: Box<Environment>, arguments: Vec<Object>, context: &DynamicContext) -> EvalResult {
-    match arguments.as_slice() {
-        [seq, Object::Array(array), Object::FunctionRef { name, arity }] => {
-            let mut result = array.clone();
-
+    let mut arguments = arguments.into_iter();
+    match (arguments.next(), arguments.next(), arguments.next()) {
+        (Some(seq), Some(zero), Some(function)) => {
+            let mut result = zero;
             let mut current_env = env;
 
-            for item in seq.clone().into_iter() {
-                let arguments = vec![Object::Array(result), item];
-                if *arity != arguments.len() {
-                    todo!("raise error")
-                }
-                let (new_env, obj) = call(current_env, name.clone(), arguments, context)?;
+            for item in seq.into_iter() {
+                let (new_env, obj) = call_item(current_env, function.clone(), vec![result, item], context)?;
                 current_env = new_env;
 
-                result = object_to_array(obj);
+                result = obj;
             }
 
-            if result.is_empty() {
-                Ok((current_env, Object::Empty))
-            } else {
-                Ok((current_env, Object::Array(result)))
-            }
+            Ok((current_env, result))
         },
         _ => panic!("error")
     }
@@ -184,10 +232,23 @@ pub(crate) fn FN_FOLD_RIGHT() -> FUNCTION {
     )
 }
 
-pub(crate) fn fn_fold_right(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    match arguments.as_slice() {
-        [Object::Function { parameters, st, body }, Object::Array(arguments)] => {
-            todo!()
+pub(crate) fn fn_fold_right(env: Box<Environment>, arguments: Vec<Object>, context: &DynamicContext) -> EvalResult {
+    let mut arguments = arguments.into_iter();
+    match (arguments.next(), arguments.next(), arguments.next()) {
+        (Some(seq), Some(zero), Some(function)) => {
+            let items: Vec<Object> = flat_items(seq).collect();
+
+            let mut result = zero;
+            let mut current_env = env;
+
+            for item in items.into_iter().rev() {
+                let (new_env, obj) = call_item(current_env, function.clone(), vec![item, result], context)?;
+                current_env = new_env;
+
+                result = obj;
+            }
+
+            Ok((current_env, result))
         },
         _ => panic!("error")
     }
@@ -203,7 +264,7 @@ pub(crate) fn FN_FOR_EACH_PAIR() -> FUNCTION {
                 SequenceType::exactly_one(ItemType::Function {
                     args: Some([
                         SequenceType::exactly_one(ItemType::Item),
-                        SequenceType::zero_or_more(ItemType::Item)
+                        SequenceType::exactly_one(ItemType::Item)
                     ].to_vec()),
                     st: Some(Box::new(SequenceType::zero_or_more(ItemType::Item)))
                 })
@@ -214,10 +275,19 @@ pub(crate) fn FN_FOR_EACH_PAIR() -> FUNCTION {
     )
 }
 
-pub(crate) fn fn_for_each_pair(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    match arguments.as_slice() {
-        [Object::Function { parameters, st, body }, Object::Array(arguments)] => {
-            todo!()
+pub(crate) fn fn_for_each_pair(env: Box<Environment>, arguments: Vec<Object>, context: &DynamicContext) -> EvalResult {
+    let mut arguments = arguments.into_iter();
+    match (arguments.next(), arguments.next(), arguments.next()) {
+        (Some(seq1), Some(seq2), Some(action)) => {
+            let mut current_env = env;
+            let mut result = vec![];
+            for (item1, item2) in flat_items(seq1).zip(flat_items(seq2)) {
+                let (new_env, value) = call_item(current_env, action.clone(), vec![item1, item2], context)?;
+                current_env = new_env;
+
+                result.push(value);
+            }
+            relax(current_env, result)
         },
         _ => panic!("error")
     }
@@ -268,13 +338,28 @@ pub(crate) fn FN_SORT_3() -> FUNCTION {
     )
 }
 
-pub(crate) fn fn_sort(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    match arguments.as_slice() {
-        [Object::Function { parameters, st, body }, Object::Array(arguments)] => {
-            todo!()
-        },
-        _ => panic!("error")
+pub(crate) fn fn_sort(env: Box<Environment>, arguments: Vec<Object>, context: &DynamicContext) -> EvalResult {
+    let mut arguments = arguments.into_iter();
+    let input = arguments.next().unwrap();
+    let collation = collation_argument(&env, arguments.next().as_ref())?;
+    let key = arguments.next();
+
+    let mut current_env = env;
+    let mut items = vec![];
+    for item in flat_items(input) {
+        let value = if let Some(function) = &key {
+            let (new_env, value) = call_item(current_env, function.clone(), vec![item.clone()], context)?;
+            current_env = new_env;
+            value
+        } else {
+            item.clone()
+        };
+        items.push((sort_key(&current_env, value)?, item));
     }
+
+    sort_by_keys(collation.as_ref(), &mut items)?;
+
+    relax(current_env, items.into_iter().map(|(_, item)| item).collect())
 }
 
 // fn:apply($function as function(*), $array as array(*)) as item()*
@@ -316,10 +401,10 @@ pub(crate) fn fn_apply(env: Box<Environment>, mut arguments: Vec<Object>, contex
             Ok((current_env, result))
         },
         (Object::FunctionRef { name, arity }, Object::Array( arguments )) => {
-            let fun = current_env.functions.get(&name, arity);
+            let fun = current_env.get_function(&name, arity);
 
             return if let Some(((params, st), fun)) = fun {
-                fun(current_env, arguments, context)
+                fun(current_env, arguments.into_iter().collect(), context)
             } else {
                 panic!("no function {:?}#{:?}", name, arity)
             }
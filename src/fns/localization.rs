@@ -0,0 +1,309 @@
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+pub(crate) const DEFAULT_LANGUAGE: &str = "en";
+
+// names and number words used by format-integer, format-date and friends
+pub trait Localization {
+    // language tag like "en" or "de-AT"
+    fn language(&self) -> &str;
+
+    // month is 1..=12
+    fn month_name(&self, month: u32) -> String;
+
+    // day of week, Monday is 1
+    fn day_name(&self, day: u32) -> String;
+
+    // name of half of day like "am" or "pm"
+    fn day_period(&self, hour: u32) -> String;
+
+    fn era(&self, year: i32) -> String;
+
+    fn cardinal_words(&self, number: u128) -> String;
+
+    fn ordinal_words(&self, number: u128) -> String;
+
+    fn negative_words(&self, words: &str) -> String;
+
+    // suffix of ordinal in digits, like "st" of "1st"
+    fn ordinal_suffix(&self, number: u128) -> String;
+}
+
+const MONTHS: [&str; 12] = [
+    "January", "February", "March", "April", "May", "June",
+    "July", "August", "September", "October", "November", "December",
+];
+
+const DAYS: [&str; 7] = ["Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday", "Sunday"];
+
+const ONES: [&str; 20] = [
+    "zero", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine", "ten",
+    "eleven", "twelve", "thirteen", "fourteen", "fifteen", "sixteen", "seventeen", "eighteen", "nineteen",
+];
+
+const TENS: [&str; 10] = [
+    "", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+];
+
+const SCALES: [&str; 12] = [
+    "thousand", "million", "billion", "trillion", "quadrillion", "quintillion",
+    "sextillion", "septillion", "octillion", "nonillion", "decillion", "undecillion",
+];
+
+// built-in language, it's used when requested one isn't available
+pub(crate) struct English;
+
+impl Localization for English {
+    fn language(&self) -> &str {
+        DEFAULT_LANGUAGE
+    }
+
+    fn month_name(&self, month: u32) -> String {
+        MONTHS[month as usize - 1].to_string()
+    }
+
+    fn day_name(&self, day: u32) -> String {
+        DAYS[day as usize - 1].to_string()
+    }
+
+    fn day_period(&self, hour: u32) -> String {
+        String::from(if hour < 12 { "am" } else { "pm" })
+    }
+
+    fn era(&self, year: i32) -> String {
+        String::from(if year > 0 { "AD" } else { "BC" })
+    }
+
+    fn cardinal_words(&self, number: u128) -> String {
+        if number < 20 {
+            ONES[number as usize].to_string()
+        } else if number < 100 {
+            let tens = TENS[(number / 10) as usize];
+            if number % 10 == 0 {
+                tens.to_string()
+            } else {
+                format!("{}-{}", tens, ONES[(number % 10) as usize])
+            }
+        } else if number < 1000 {
+            let hundreds = format!("{} hundred", ONES[(number / 100) as usize]);
+            if number % 100 == 0 {
+                hundreds
+            } else {
+                format!("{} and {}", hundreds, self.cardinal_words(number % 100))
+            }
+        } else {
+            let mut scale = 0;
+            let mut unit: u128 = 1000;
+            while scale + 1 < SCALES.len() && number / unit >= 1000 {
+                scale += 1;
+                unit *= 1000;
+            }
+            let head = format!("{} {}", self.cardinal_words(number / unit), SCALES[scale]);
+            let rest = number % unit;
+            if rest == 0 {
+                head
+            } else if rest < 100 {
+                format!("{} and {}", head, self.cardinal_words(rest))
+            } else {
+                format!("{} {}", head, self.cardinal_words(rest))
+            }
+        }
+    }
+
+    fn ordinal_words(&self, number: u128) -> String {
+        let cardinal = self.cardinal_words(number);
+        let split = cardinal.rfind([' ', '-']).map(|i| i + 1).unwrap_or(0);
+        let (head, last) = cardinal.split_at(split);
+        let last = match last {
+            "one" => "first".to_string(),
+            "two" => "second".to_string(),
+            "three" => "third".to_string(),
+            "five" => "fifth".to_string(),
+            "eight" => "eighth".to_string(),
+            "nine" => "ninth".to_string(),
+            "twelve" => "twelfth".to_string(),
+            _ => {
+                if let Some(stem) = last.strip_suffix('y') {
+                    format!("{}ieth", stem)
+                } else {
+                    format!("{}th", last)
+                }
+            }
+        };
+        format!("{}{}", head, last)
+    }
+
+    fn negative_words(&self, words: &str) -> String {
+        format!("minus {}", words)
+    }
+
+    fn ordinal_suffix(&self, number: u128) -> String {
+        let suffix = match (number % 10, number % 100) {
+            (_, 11..=13) => "th",
+            (1, _) => "st",
+            (2, _) => "nd",
+            (3, _) => "rd",
+            _ => "th"
+        };
+        suffix.to_string()
+    }
+}
+
+// spelling of numbers from the largest base which isn't greater than number,
+// "{q}" is replaced by words of quotient, "{r}" by remainder and "{m}" by number without remainder
+#[derive(Clone, Debug)]
+pub struct NumberRule {
+    pub base: u128,
+    // template for numbers without remainder, it can't use "{m}" and isn't used for ones in `numbers`
+    pub exact: String,
+    pub compound: String,
+}
+
+// language given by tables, so it can be loaded from configuration
+#[derive(Clone, Debug, Default)]
+pub struct LanguageData {
+    pub language: String,
+    pub months: Vec<String>,
+    pub days: Vec<String>,
+    // before and after noon
+    pub day_periods: Vec<String>,
+    // before and after start of era
+    pub eras: Vec<String>,
+    // words of numbers which aren't composed, like 0..19 and tens in English
+    pub numbers: BTreeMap<u128, String>,
+    pub rules: Vec<NumberRule>,
+    pub ordinals: BTreeMap<u128, String>,
+    // appended to words of number when there is no ordinal for it
+    pub ordinal_ending: String,
+    pub ordinal_suffix: String,
+    pub minus: String,
+}
+
+impl LanguageData {
+    fn name(names: &[String], index: usize, fallback: String) -> String {
+        names.get(index).cloned().unwrap_or(fallback)
+    }
+
+    fn rule(&self, number: u128) -> Option<(&NumberRule, u128, u128)> {
+        self.rules.iter()
+            .filter(|rule| rule.base > 0 && rule.base <= number)
+            .max_by_key(|rule| rule.base)
+            .map(|rule| (rule, number / rule.base, number % rule.base))
+    }
+
+    fn expand(&self, template: &str, number: u128, quotient: u128, rest: u128, ordinal: bool) -> String {
+        let mut result = template.to_string();
+        if result.contains("{q}") {
+            result = result.replace("{q}", &self.cardinal_words(quotient));
+        }
+        if result.contains("{m}") && rest > 0 {
+            result = result.replace("{m}", &self.cardinal_words(number - rest));
+        }
+        if result.contains("{r}") {
+            let words = if ordinal { self.ordinal_words(rest) } else { self.cardinal_words(rest) };
+            result = result.replace("{r}", &words);
+        }
+        result
+    }
+}
+
+impl Localization for LanguageData {
+    fn language(&self) -> &str {
+        &self.language
+    }
+
+    fn month_name(&self, month: u32) -> String {
+        LanguageData::name(&self.months, month as usize - 1, English.month_name(month))
+    }
+
+    fn day_name(&self, day: u32) -> String {
+        LanguageData::name(&self.days, day as usize - 1, English.day_name(day))
+    }
+
+    fn day_period(&self, hour: u32) -> String {
+        LanguageData::name(&self.day_periods, if hour < 12 { 0 } else { 1 }, English.day_period(hour))
+    }
+
+    fn era(&self, year: i32) -> String {
+        LanguageData::name(&self.eras, if year > 0 { 1 } else { 0 }, English.era(year))
+    }
+
+    fn cardinal_words(&self, number: u128) -> String {
+        if let Some(words) = self.numbers.get(&number) {
+            return words.clone();
+        }
+        match self.rule(number) {
+            Some((rule, quotient, rest)) => {
+                let template = if rest == 0 { &rule.exact } else { &rule.compound };
+                self.expand(template, number, quotient, rest, false)
+            }
+            None => number.to_string()
+        }
+    }
+
+    fn ordinal_words(&self, number: u128) -> String {
+        if let Some(words) = self.ordinals.get(&number) {
+            return words.clone();
+        }
+        // ordinal of compound number ending with remainder is ordinal of remainder
+        if let Some((rule, quotient, rest)) = self.rule(number) {
+            if rest > 0 && rule.compound.ends_with("{r}") {
+                return self.expand(&rule.compound, number, quotient, rest, true);
+            }
+        }
+        format!("{}{}", self.cardinal_words(number), self.ordinal_ending)
+    }
+
+    fn negative_words(&self, words: &str) -> String {
+        format!("{}{}", self.minus, words)
+    }
+
+    fn ordinal_suffix(&self, _number: u128) -> String {
+        self.ordinal_suffix.clone()
+    }
+}
+
+// languages available to format functions and the default one
+#[derive(Clone)]
+pub(crate) struct Languages {
+    default: String,
+    languages: HashMap<String, Rc<dyn Localization>>,
+}
+
+impl Languages {
+    pub(crate) fn new() -> Self {
+        let mut languages = Languages { default: DEFAULT_LANGUAGE.to_string(), languages: HashMap::new() };
+        languages.add(Rc::new(English));
+        languages
+    }
+
+    pub(crate) fn add(&mut self, localization: Rc<dyn Localization>) {
+        self.languages.insert(localization.language().to_lowercase(), localization);
+    }
+
+    pub(crate) fn set_default(&mut self, language: &str) {
+        self.default = language.to_string();
+    }
+
+    pub(crate) fn default_language(&self) -> &str {
+        &self.default
+    }
+
+    // by tag or its primary subtag, so "de-AT" falls back to "de"
+    fn find(&self, language: &str) -> Option<Rc<dyn Localization>> {
+        let language = language.trim().to_lowercase();
+        self.languages.get(&language)
+            .or_else(|| language.split('-').next().and_then(|primary| self.languages.get(primary)))
+            .cloned()
+    }
+
+    // localization of language, default one when it's absent, and whether requested language is available
+    pub(crate) fn resolve(&self, language: Option<&str>) -> (Rc<dyn Localization>, bool) {
+        let language = language.filter(|language| !language.trim().is_empty());
+        if let Some(localization) = self.find(language.unwrap_or(&self.default)) {
+            return (localization, true);
+        }
+        let fallback = self.find(&self.default).unwrap_or_else(|| Rc::new(English));
+        (fallback, false)
+    }
+}
@@ -1,11 +1,12 @@
-use crate::eval::{Object, EvalResult, DynamicContext};
+use crate::eval::{Object, Type, EvalResult, DynamicContext};
 use crate::eval::Environment;
 
-use std::collections::HashMap;
+use im_rc::Vector;
+use crate::eval::helpers::{flat_items, relax};
 use crate::eval::sequence_type::*;
-use crate::fns::FUNCTION;
-
-// op:same-key($k1 as xs:anyAtomicType, $k2 as xs:anyAtomicType) as xs:boolean
+use crate::fns::{call_item, FUNCTION};
+use crate::parser::errors::ErrorCode;
+use crate::values::{OrderedMap, same_key};
 
 // map:merge($maps as map(*)*) as map(*)
 pub(crate) fn FN_MAP_MERGE_1() -> FUNCTION {
@@ -33,7 +34,59 @@ pub(crate) fn FN_MAP_MERGE_2() -> FUNCTION {
 }
 
 pub(crate) fn fn_map_merge(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+    let mut arguments = arguments.into_iter();
+    let maps = arguments.next().unwrap_or(Object::Empty);
+
+    let duplicates = match arguments.next() {
+        Some(Object::Map(options)) => {
+            match options.get(&Type::String(String::from("duplicates"))) {
+                Some(Object::Atomic(Type::String(str))) => str.clone(),
+                Some(value) => return Err((ErrorCode::XPTY0004, format!("invalid duplicates option: {:?}", value))),
+                None => String::from("use-first")
+            }
+        }
+        _ => String::from("use-first")
+    };
+
+    let mut result: Option<OrderedMap> = None;
+    for item in flat_items(maps) {
+        let map = match item {
+            Object::Map(map) => map,
+            _ => return Err((ErrorCode::XPTY0004, format!("expected map, got {:?}", item)))
+        };
+
+        // first map is taken as is, so its structure is shared with result
+        let mut merged = match result {
+            Some(merged) => merged,
+            None => {
+                result = Some(map);
+                continue;
+            }
+        };
+
+        for (key, value) in map.iter() {
+            match merged.get(key) {
+                None => merged.insert(key.clone(), value.clone()),
+                Some(existing) => {
+                    match duplicates.as_str() {
+                        "use-first" | "use-any" => {},
+                        "use-last" => merged.insert(key.clone(), value.clone()),
+                        "combine" => {
+                            let combined = Object::Sequence(
+                                flat_items(existing.clone()).chain(flat_items(value.clone())).collect()
+                            );
+                            merged.insert(key.clone(), combined)
+                        },
+                        "reject" => return Err((ErrorCode::FOJS0003, format!("duplicate key: {:?}", key))),
+                        _ => return Err((ErrorCode::FOJS0005, format!("invalid duplicates option: {:?}", duplicates)))
+                    }
+                }
+            }
+        }
+        result = Some(merged);
+    }
+
+    Ok((env, Object::Map(result.unwrap_or_default())))
 }
 
 // map:size($map as map(*)) as xs:integer
@@ -48,7 +101,11 @@ pub(crate) fn FN_MAP_SIZE() -> FUNCTION {
 }
 
 pub(crate) fn map_size(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+    match arguments.as_slice() {
+        [Object::Map(map)] => Ok((env, Object::Atomic(Type::Integer(map.len() as i128)))),
+
+        _ => panic!("error")
+    }
 }
 
 // map:keys($map as map(*)) as xs:anyAtomicType*
@@ -63,7 +120,16 @@ pub(crate) fn FN_MAP_KEYS() -> FUNCTION {
 }
 
 pub(crate) fn map_keys(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+    match arguments.as_slice() {
+        [Object::Map(map)] => {
+            let keys: Vec<Object> = map.keys()
+                .map(|key| Object::Atomic(key.clone()))
+                .collect();
+            relax(env, keys)
+        }
+
+        _ => panic!("error")
+    }
 }
 
 // map:contains($map as map(*), $key as xs:anyAtomicType) as xs:boolean
@@ -81,7 +147,13 @@ pub(crate) fn FN_MAP_CONTAINS() -> FUNCTION {
 }
 
 pub(crate) fn map_contains(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+    match arguments.as_slice() {
+        [Object::Map(map), Object::Atomic(k)] => {
+            Ok((env, Object::Atomic(Type::Boolean(map.contains_key(k)))))
+        }
+
+        _ => panic!("error")
+    }
 }
 
 // map:get($map as map(*), $key as xs:anyAtomicType) as item()*
@@ -130,7 +202,37 @@ pub(crate) fn FN_MAP_FIND() -> FUNCTION {
 }
 
 pub(crate) fn map_find(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+    let mut arguments = arguments.into_iter();
+    match (arguments.next(), arguments.next()) {
+        (Some(input), Some(Object::Atomic(k))) => {
+            let mut result = Vector::new();
+            find(input, &k, &mut result);
+            Ok((env, Object::Array(result)))
+        }
+
+        _ => panic!("error")
+    }
+}
+
+fn find(input: Object, key: &Type, result: &mut Vector<Object>) {
+    for item in flat_items(input) {
+        match item {
+            Object::Map(map) => {
+                for (k, value) in map.iter() {
+                    if same_key(k, key) {
+                        result.push_back(value.clone());
+                    }
+                    find(value.clone(), key, result);
+                }
+            }
+            Object::Array(items) => {
+                for item in items {
+                    find(item, key, result);
+                }
+            }
+            _ => {}
+        }
+    }
 }
 
 // map:put($map as map(*), $key as xs:anyAtomicType, $value as item()*) as map(*)
@@ -149,7 +251,14 @@ pub(crate) fn FN_MAP_PUT() -> FUNCTION {
 }
 
 pub(crate) fn map_put(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+    let mut arguments = arguments.into_iter();
+    match (arguments.next(), arguments.next(), arguments.next()) {
+        (Some(Object::Map(map)), Some(Object::Atomic(k)), Some(value)) => {
+            Ok((env, Object::Map(map.update(k, value))))
+        }
+
+        _ => panic!("error")
+    }
 }
 
 // map:entry($key as xs:anyAtomicType, $value as item()*) as map(*)
@@ -168,11 +277,9 @@ pub(crate) fn FN_MAP_ENTRY() -> FUNCTION {
 
 pub(crate) fn map_entry(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
     match arguments.as_slice() {
-        [Object::Atomic(k), Object::Atomic(v)] => {
-
-            let mut map = HashMap::new();
-
-            map.insert(k.clone(), Object::Atomic(v.clone())); //TODO: understand, is it possible to avoid clone?
+        [Object::Atomic(k), value] => {
+            let mut map = OrderedMap::new();
+            map.insert(k.clone(), value.clone());
 
             Ok((env, Object::Map(map)))
         }
@@ -196,7 +303,19 @@ pub(crate) fn FN_MAP_REMOVE() -> FUNCTION {
 }
 
 pub(crate) fn map_remove(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+    let mut arguments = arguments.into_iter();
+    match (arguments.next(), arguments.next()) {
+        (Some(Object::Map(mut map)), Some(keys)) => {
+            for key in flat_items(keys) {
+                if let Object::Atomic(key) = key {
+                    map.remove(&key);
+                }
+            }
+            Ok((env, Object::Map(map)))
+        }
+
+        _ => panic!("error")
+    }
 }
 
 // map:for-each($map as map(*), $action as function(xs:anyAtomicType, item()*) as item()*) as item()*
@@ -212,14 +331,27 @@ pub(crate) fn FN_MAP_FOR_EACH() -> FUNCTION {
                     ].to_vec()),
                     st: Some(Box::new(SequenceType::zero_or_more(ItemType::Item)))
                 }),
-                SequenceType::zero_or_more(ItemType::AnyAtomicType),
             ].to_vec(),
-            SequenceType::exactly_one(ItemType::Map(None))
+            SequenceType::zero_or_more(ItemType::Item)
         ),
         map_for_each
     )
 }
 
-pub(crate) fn map_for_each(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+pub(crate) fn map_for_each(env: Box<Environment>, arguments: Vec<Object>, context: &DynamicContext) -> EvalResult {
+    let mut arguments = arguments.into_iter();
+    match (arguments.next(), arguments.next()) {
+        (Some(Object::Map(map)), Some(action)) => {
+            let mut current_env = env;
+            let mut result = vec![];
+            for (key, value) in map.iter() {
+                let (new_env, item) = call_item(current_env, action.clone(), vec![Object::Atomic(key.clone()), value.clone()], context)?;
+                current_env = new_env;
+                result.push(item);
+            }
+            relax(current_env, result)
+        }
+
+        _ => panic!("error")
+    }
 }
\ No newline at end of file
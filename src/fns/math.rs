@@ -1,12 +1,18 @@
-use crate::eval::{Environment, Object, Type, EvalResult, DynamicContext};
+use crate::eval::{Environment, Object, Type, EvalResult, DynamicContext, ErrorInfo};
 use crate::eval::sequence_type::*;
 use crate::fns::FUNCTION;
+use crate::fns::format::{format_big_integer, format_integer, format_number, language_marker};
+use crate::eval::object_to_integer;
+use crate::parser::parse_names::parse_eqname;
+use crate::serialization::object_to_string;
+use crate::values::resolve_element_qname;
+use nom::combinator::all_consuming;
 
 use crate::parser::errors::ErrorCode;
 use crate::values::Types;
 use math::round::half_to_even;
 use bigdecimal::num_traits::float::FloatCore;
-use bigdecimal::{BigDecimal, FromPrimitive, Signed, ToPrimitive};
+use bigdecimal::{BigDecimal, FromPrimitive, Signed, ToPrimitive, Zero};
 use bigdecimal::num_bigint::BigInt;
 use ordered_float::OrderedFloat;
 
@@ -25,7 +31,14 @@ pub(crate) fn fn_abs(env: Box<Environment>, arguments: Vec<Object>, _context: &D
 
     match arguments.as_slice() {
         [Object::Atomic(Type::Integer(number))] => {
-            Ok((env, Object::Atomic(Type::Integer(number.abs()))))
+            let number = match number.checked_abs() {
+                Some(number) => Type::Integer(number),
+                None => Type::integer(BigInt::from(*number).abs())
+            };
+            Ok((env, Object::Atomic(number)))
+        },
+        [Object::Atomic(Type::BigInteger(number))] => {
+            Ok((env, Object::Atomic(Type::BigInteger(number.abs()))))
         },
         [Object::Atomic(Type::Decimal(number))] => {
             Ok((env, Object::Atomic(Type::Decimal(number.abs()))))
@@ -71,7 +84,8 @@ pub(crate) fn fn_ceiling(env: Box<Environment>, mut arguments: Vec<Object>, _con
                 Type::NonPositiveInteger(_) |
                 Type::NegativeInteger(_) |
 
-                Type::Integer(_) => t,
+                Type::Integer(_) |
+                Type::BigInteger(_) => t,
 
                 Type::Decimal(number) => {
                     if let Some(number) = number.to_f64() {
@@ -126,7 +140,8 @@ pub(crate) fn fn_floor(env: Box<Environment>, mut arguments: Vec<Object>, _conte
                 Type::NonPositiveInteger(_) |
                 Type::NegativeInteger(_) |
 
-                Type::Integer(_) => t,
+                Type::Integer(_) |
+                Type::BigInteger(_) => t,
 
                 Type::Decimal(number) => Type::Decimal(number.round(0)), // TODO: fix it
                 Type::Float(number) => Type::Float(number.floor()),
@@ -170,6 +185,9 @@ pub(crate) fn fn_round(env: Box<Environment>, arguments: Vec<Object>, _context:
         [Object::Atomic(Type::Integer(number))] => {
             Ok((env, Object::Atomic(Type::Integer(*number))))
         },
+        [Object::Atomic(Type::BigInteger(number))] => {
+            Ok((env, Object::Atomic(Type::BigInteger(number.clone()))))
+        },
         [Object::Atomic(Type::Decimal(number))] => {
             Ok((env, Object::Atomic(Type::Decimal(number.round(0)))))
         },
@@ -234,6 +252,9 @@ pub(crate) fn fn_round_half_to_even(env: Box<Environment>, arguments: Vec<Object
         [Object::Atomic(Type::Integer(number))] => {
             Ok((env, Object::Atomic(Type::Integer(*number))))
         },
+        [Object::Atomic(Type::BigInteger(number))] => {
+            Ok((env, Object::Atomic(Type::BigInteger(number.clone()))))
+        },
         [Object::Atomic(Type::Decimal(number))] => {
             Ok((env, Object::Atomic(Type::Decimal(number.round(0)))))
         },
@@ -327,7 +348,7 @@ pub(crate) fn fn_number(env: Box<Environment>, arguments: Vec<Object>, context:
             }
         }
         Object::Node(rf) => {
-            match rf.to_typed_value() {
+            match rf.to_string_value() {
                 Ok(str) => {
                     let value = crate::values::string_to::double(&str, false)?;
                     Ok((env, Object::Atomic(value)))
@@ -369,7 +390,27 @@ pub(crate) fn FN_MATH_FORMAT_INTEGER_3() -> FUNCTION {
 }
 
 pub(crate) fn fn_format_integer(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+    let picture = object_to_string(&env, &arguments[1]);
+    let language = match arguments.get(2) {
+        None | Some(Object::Empty) => None,
+        Some(language) => Some(object_to_string(&env, language)),
+    };
+    // unavailable language falls back to the default one, marked as by format-date
+    let (localization, available) = env.languages().resolve(language.as_deref());
+    let marker = language_marker(&*localization, available);
+
+    match arguments.into_iter().next() {
+        Some(Object::Empty) | None => Ok((env, Object::Atomic(Type::String(String::new())))),
+        Some(Object::Atomic(Type::BigInteger(number))) => {
+            let result = format_big_integer(&number, &picture, &*localization)?;
+            Ok((env, Object::Atomic(Type::String(marker + &result))))
+        }
+        Some(value) => {
+            let number = object_to_integer(&env, value)?;
+            let result = format_integer(number, &picture, &*localization)?;
+            Ok((env, Object::Atomic(Type::String(marker + &result))))
+        }
+    }
 }
 
 // fn:format-number($value as xs:numeric?, $picture as xs:string) as xs:string
@@ -402,7 +443,30 @@ pub(crate) fn FN_MATH_FORMAT_NUMBER_3() -> FUNCTION {
 }
 
 pub(crate) fn fn_format_number(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+    let picture = object_to_string(&env, &arguments[1]);
+
+    let name = match arguments.get(2) {
+        None | Some(Object::Empty) => None,
+        Some(name) => {
+            let name = object_to_string(&env, name);
+            let qname = all_consuming(parse_eqname)(name.trim()).map(|(_, qname)| qname);
+            match qname {
+                Ok(qname) => Some(resolve_element_qname(&qname, &env)),
+                Err(_) => return Err((ErrorCode::FODF1280, format!("invalid decimal format name {:?}", name)))
+            }
+        }
+    };
+    let format = match env.decimal_format(&name) {
+        Some(format) => format,
+        None => return Err((ErrorCode::FODF1280, String::from("unknown decimal format")))
+    };
+
+    let result = match &arguments[0] {
+        Object::Empty => format_number(&Type::Double(OrderedFloat::from(f64::NAN)), &picture, &format)?,
+        Object::Atomic(value) => format_number(value, &picture, &format)?,
+        _ => return Err((ErrorCode::XPTY0004, String::from("format-number expects number")))
+    };
+    Ok((env, Object::Atomic(Type::String(result))))
 }
 
 // math:pi() as xs:double
@@ -432,7 +496,7 @@ pub(crate) fn FN_MATH_EXP() -> FUNCTION {
 }
 
 pub(crate) fn fn_exp(env: Box<Environment>, arguments: Vec<Object>, context: &DynamicContext) -> EvalResult {
-    todo!()
+    math_unary(env, &arguments, f64::exp)
 }
 
 // math:exp10($arg as xs:double?) as xs:double?
@@ -447,7 +511,7 @@ pub(crate) fn FN_MATH_EXP10() -> FUNCTION {
 }
 
 pub(crate) fn fn_exp10(env: Box<Environment>, arguments: Vec<Object>, context: &DynamicContext) -> EvalResult {
-    todo!()
+    math_unary(env, &arguments, |x| 10f64.powf(x))
 }
 
 // math:log($arg as xs:double?) as xs:double?
@@ -462,7 +526,7 @@ pub(crate) fn FN_MATH_LOG() -> FUNCTION {
 }
 
 pub(crate) fn fn_log(env: Box<Environment>, arguments: Vec<Object>, context: &DynamicContext) -> EvalResult {
-    todo!()
+    math_unary(env, &arguments, f64::ln)
 }
 
 // math:log10($arg as xs:double?) as xs:double?
@@ -477,7 +541,7 @@ pub(crate) fn FN_MATH_LOG10() -> FUNCTION {
 }
 
 pub(crate) fn fn_log10(env: Box<Environment>, arguments: Vec<Object>, context: &DynamicContext) -> EvalResult {
-    todo!()
+    math_unary(env, &arguments, f64::log10)
 }
 
 // math:pow($x as xs:double?, $y as xs:numeric) as xs:double?
@@ -495,7 +559,11 @@ pub(crate) fn FN_MATH_POW() -> FUNCTION {
 }
 
 pub(crate) fn fn_pow(env: Box<Environment>, arguments: Vec<Object>, context: &DynamicContext) -> EvalResult {
-    todo!()
+    // special cases of the spec, like pow(NaN, 0) = 1 and pow(-1, INF) = 1, are the ones of IEEE pow
+    match (double_argument(&arguments[0])?, double_argument(&arguments[1])?) {
+        (Some(x), Some(y)) => Ok((env, Object::Atomic(Type::Double(OrderedFloat::from(x.powf(y)))))),
+        _ => Ok((env, Object::Empty))
+    }
 }
 
 // math:sqrt($arg as xs:double?) as xs:double?
@@ -510,7 +578,7 @@ pub(crate) fn FN_MATH_SQRT() -> FUNCTION {
 }
 
 pub(crate) fn fn_sqrt(env: Box<Environment>, arguments: Vec<Object>, context: &DynamicContext) -> EvalResult {
-    todo!()
+    math_unary(env, &arguments, f64::sqrt)
 }
 
 // math:sin($θ as xs:double?) as xs:double?
@@ -525,7 +593,7 @@ pub(crate) fn FN_MATH_SIN() -> FUNCTION {
 }
 
 pub(crate) fn fn_sin(env: Box<Environment>, arguments: Vec<Object>, context: &DynamicContext) -> EvalResult {
-    todo!()
+    math_unary(env, &arguments, f64::sin)
 }
 
 // math:cos($θ as xs:double?) as xs:double?
@@ -540,7 +608,7 @@ pub(crate) fn FN_MATH_COS() -> FUNCTION {
 }
 
 pub(crate) fn fn_cos(env: Box<Environment>, arguments: Vec<Object>, context: &DynamicContext) -> EvalResult {
-    todo!()
+    math_unary(env, &arguments, f64::cos)
 }
 
 // math:tan($θ as xs:double?) as xs:double?
@@ -555,7 +623,7 @@ pub(crate) fn FN_MATH_TAN() -> FUNCTION {
 }
 
 pub(crate) fn fn_tan(env: Box<Environment>, arguments: Vec<Object>, context: &DynamicContext) -> EvalResult {
-    todo!()
+    math_unary(env, &arguments, f64::tan)
 }
 
 // math:asin($arg as xs:double?) as xs:double?
@@ -570,7 +638,7 @@ pub(crate) fn FN_MATH_ASIN() -> FUNCTION {
 }
 
 pub(crate) fn fn_asin(env: Box<Environment>, arguments: Vec<Object>, context: &DynamicContext) -> EvalResult {
-    todo!()
+    math_unary(env, &arguments, f64::asin)
 }
 
 // math:acos($arg as xs:double?) as xs:double?
@@ -585,7 +653,7 @@ pub(crate) fn FN_MATH_ACOS() -> FUNCTION {
 }
 
 pub(crate) fn fn_acos(env: Box<Environment>, arguments: Vec<Object>, context: &DynamicContext) -> EvalResult {
-    todo!()
+    math_unary(env, &arguments, f64::acos)
 }
 
 // math:atan($arg as xs:double?) as xs:double?
@@ -600,7 +668,7 @@ pub(crate) fn FN_MATH_ATAN() -> FUNCTION {
 }
 
 pub(crate) fn fn_atan(env: Box<Environment>, arguments: Vec<Object>, context: &DynamicContext) -> EvalResult {
-    todo!()
+    math_unary(env, &arguments, f64::atan)
 }
 
 // math:atan2($y as xs:double, $x as xs:double) as xs:double
@@ -618,5 +686,132 @@ pub(crate) fn FN_MATH_ATAN2() -> FUNCTION {
 }
 
 pub(crate) fn fn_atan2(env: Box<Environment>, arguments: Vec<Object>, context: &DynamicContext) -> EvalResult {
-    todo!()
+    match (double_argument(&arguments[0])?, double_argument(&arguments[1])?) {
+        (Some(y), Some(x)) => Ok((env, Object::Atomic(Type::Double(OrderedFloat::from(y.atan2(x)))))),
+        _ => Err((ErrorCode::XPTY0004, String::from("math:atan2 expects two numbers")))
+    }
+}
+
+// argument of math function as double, none for empty sequence
+fn double_argument(argument: &Object) -> Result<Option<f64>, ErrorInfo> {
+    match argument {
+        Object::Empty => Ok(None),
+        Object::Atomic(Type::Double(number)) => Ok(Some(number.into_inner())),
+        Object::Atomic(value) => {
+            match value.convert(Types::Double)? {
+                Type::Double(number) => Ok(Some(number.into_inner())),
+                _ => Err((ErrorCode::XPTY0004, format!("can't convert to double {:?}", value)))
+            }
+        }
+        _ => Err((ErrorCode::XPTY0004, String::from("math functions expect number")))
+    }
+}
+
+fn math_unary(env: Box<Environment>, arguments: &[Object], op: fn(f64) -> f64) -> EvalResult {
+    match double_argument(&arguments[0])? {
+        Some(number) => Ok((env, Object::Atomic(Type::Double(OrderedFloat::from(op(number)))))),
+        None => Ok((env, Object::Empty))
+    }
+}
+
+// argument of decimal math function, none for empty sequence
+fn decimal_argument(argument: &Object) -> Result<Option<BigDecimal>, ErrorInfo> {
+    match argument {
+        Object::Empty => Ok(None),
+        Object::Atomic(Type::Decimal(number)) => Ok(Some(number.clone())),
+        Object::Atomic(Type::Integer(number)) => Ok(Some(BigDecimal::from(BigInt::from(*number)))),
+        Object::Atomic(value) => {
+            match value.convert(Types::Decimal)? {
+                Type::Decimal(number) => Ok(Some(number)),
+                _ => Err((ErrorCode::XPTY0004, format!("can't convert to decimal {:?}", value)))
+            }
+        }
+        _ => Err((ErrorCode::XPTY0004, String::from("decimal math functions expect number")))
+    }
+}
+
+fn decimal_result(env: Box<Environment>, number: Option<BigDecimal>) -> EvalResult {
+    match number {
+        Some(number) => Ok((env, Object::Atomic(Type::Decimal(number.normalized())))),
+        None => Ok((env, Object::Empty))
+    }
+}
+
+// dmath:sqrt($arg as xs:decimal?) as xs:decimal?
+pub(crate) fn FN_DECIMAL_SQRT() -> FUNCTION {
+    (
+        (
+            [SequenceType::zero_or_one(ItemType::AtomicOrUnionType(XS_DECIMAL.into()))].to_vec(),
+            SequenceType::zero_or_one(ItemType::AtomicOrUnionType(XS_DECIMAL.into()))
+        ),
+        fn_decimal_sqrt
+    )
+}
+
+pub(crate) fn fn_decimal_sqrt(env: Box<Environment>, arguments: Vec<Object>, context: &DynamicContext) -> EvalResult {
+    let number = match decimal_argument(&arguments[0])? {
+        Some(number) => match number.sqrt() {
+            Some(root) => Some(root),
+            None => return Err((ErrorCode::FOAR0002, String::from("square root of negative decimal")))
+        },
+        None => None
+    };
+    decimal_result(env, number)
+}
+
+// dmath:exp($arg as xs:decimal?) as xs:decimal?
+pub(crate) fn FN_DECIMAL_EXP() -> FUNCTION {
+    (
+        (
+            [SequenceType::zero_or_one(ItemType::AtomicOrUnionType(XS_DECIMAL.into()))].to_vec(),
+            SequenceType::zero_or_one(ItemType::AtomicOrUnionType(XS_DECIMAL.into()))
+        ),
+        fn_decimal_exp
+    )
+}
+
+pub(crate) fn fn_decimal_exp(env: Box<Environment>, arguments: Vec<Object>, context: &DynamicContext) -> EvalResult {
+    let number = decimal_argument(&arguments[0])?.map(|number| number.exp());
+    decimal_result(env, number)
+}
+
+// dmath:pow($x as xs:decimal?, $y as xs:integer) as xs:decimal?
+pub(crate) fn FN_DECIMAL_POW() -> FUNCTION {
+    (
+        (
+            [
+                SequenceType::zero_or_one(ItemType::AtomicOrUnionType(XS_DECIMAL.into())),
+                SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_INTEGER.into())),
+            ].to_vec(),
+            SequenceType::zero_or_one(ItemType::AtomicOrUnionType(XS_DECIMAL.into()))
+        ),
+        fn_decimal_pow
+    )
+}
+
+pub(crate) fn fn_decimal_pow(env: Box<Environment>, arguments: Vec<Object>, context: &DynamicContext) -> EvalResult {
+    let exponent = object_to_integer(&env, arguments[1].clone())?;
+    let number = match decimal_argument(&arguments[0])? {
+        Some(number) => {
+            if number.is_zero() && exponent < 0 {
+                return Err((ErrorCode::FOAR0001, String::from("zero to negative power")));
+            }
+            // exact for positive exponents, negative ones divide with precision of decimal division
+            let mut result = BigDecimal::from(1);
+            let mut base = number;
+            let mut power = exponent.unsigned_abs();
+            while power > 0 {
+                if power & 1 == 1 {
+                    result = &result * &base;
+                }
+                power >>= 1;
+                if power > 0 {
+                    base = &base * &base;
+                }
+            }
+            Some(if exponent < 0 { result.inverse() } else { result })
+        }
+        None => None
+    };
+    decimal_result(env, number)
 }
\ No newline at end of file
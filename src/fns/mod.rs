@@ -2,7 +2,8 @@ use std::collections::HashMap;
 use crate::eval::{Object, DynamicContext, EvalResult, ErrorInfo, Type};
 use crate::eval::Environment;
 use crate::namespaces::*;
-use crate::values::{QName, QNameResolved, resolve_element_qname, Types};
+use crate::values::{QName, QNameResolved, resolve_element_qname, Types, Collation};
+use crate::serialization::object_to_string;
 
 mod fun;
 mod sequences;
@@ -18,22 +19,46 @@ mod math;
 mod map;
 mod array;
 mod aggregates;
+mod documents;
+mod format;
+mod localization;
+mod random;
+mod binary;
+
+pub(crate) use format::DecimalFormat;
+pub(crate) use localization::Languages;
+pub use localization::{LanguageData, Localization, NumberRule};
 
 use crate::parser::errors::ErrorCode;
-use crate::eval::expression::Expression;
-use crate::eval::sequence_type::{ItemType, SequenceType, XS_NOTATION, XS_QNAME};
+use crate::eval::expression::{Expression, Tail, TailResult};
+use crate::eval::prolog::PartialApplication;
+use crate::eval::sequence_type::{ItemType, SequenceType, XS_INTEGER, XS_NOTATION, XS_QNAME};
 use crate::fns::types::*;
 
 pub type FUNCTION = ((Vec<SequenceType>, SequenceType), fn(Box<Environment>, Vec<Object>, &DynamicContext) -> EvalResult);
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct Function {
     name: QNameResolved,
     parameters: Vec<Param>,
     st: Option<SequenceType>,
+    updating: bool,
     body: Box<dyn Expression>,
 }
 
+impl Function {
+    // declared types of parameters and result, `item()*` when omitted
+    pub(crate) fn signature(&self) -> (Vec<SequenceType>, SequenceType) {
+        let any = || SequenceType::zero_or_more(ItemType::Item);
+        (
+            self.parameters.iter()
+                .map(|param| param.sequence_type.clone().unwrap_or_else(any))
+                .collect(),
+            self.st.clone().unwrap_or_else(any)
+        )
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Param {
     pub name: QName,
@@ -54,11 +79,16 @@ pub struct FunctionsRegister {
 }
 
 impl FunctionsRegister {
-    pub(crate) fn new() -> Self {
-        let mut instance = FunctionsRegister {
+    // register of nested environment, builtin functions are looked up at root one
+    pub(crate) fn empty() -> Self {
+        FunctionsRegister {
             functions: HashMap::new(),
             declared: HashMap::new(),
-        };
+        }
+    }
+
+    pub(crate) fn new() -> Self {
+        let mut instance = FunctionsRegister::empty();
 
         instance.register(&*SCHEMA.uri, "untypedAtomic", 1, types::FN_XS_UNTYPED_ATOMIC());
         instance.register(&*SCHEMA.uri, "numeric", 1, types::FN_XS_NUMERIC());
@@ -129,18 +159,17 @@ impl FunctionsRegister {
         instance.register(&*XPATH_FUNCTIONS.uri, "node-name", 0, qname::FN_NODE_NAME_0());
         instance.register(&*XPATH_FUNCTIONS.uri, "node-name", 1, qname::FN_NODE_NAME_1());
 
-//        instance.register("op", "same-key", 2, map::map_merge);
         instance.register(&*XPATH_MAP.uri, "merge", 1, map::FN_MAP_MERGE_1());
         instance.register(&*XPATH_MAP.uri, "merge", 2, map::FN_MAP_MERGE_2());
         instance.register(&*XPATH_MAP.uri, "size", 1, map::FN_MAP_SIZE());
+        instance.register(&*XPATH_MAP.uri, "keys", 1, map::FN_MAP_KEYS());
         instance.register(&*XPATH_MAP.uri, "contains", 2, map::FN_MAP_CONTAINS());
         instance.register(&*XPATH_MAP.uri, "get", 2, map::FN_MAP_GET());
         instance.register(&*XPATH_MAP.uri, "find", 2, map::FN_MAP_FIND());
         instance.register(&*XPATH_MAP.uri, "put", 3, map::FN_MAP_PUT());
-        instance.register(&*XPATH_MAP.uri, "find", 2, map::FN_MAP_FIND());
         instance.register(&*XPATH_MAP.uri, "entry", 2, map::FN_MAP_ENTRY());
         instance.register(&*XPATH_MAP.uri, "remove", 2, map::FN_MAP_REMOVE());
-        instance.register(&*XPATH_MAP.uri, "for-each", 3, map::FN_MAP_FOR_EACH());
+        instance.register(&*XPATH_MAP.uri, "for-each", 2, map::FN_MAP_FOR_EACH());
 
         instance.register(&*XPATH_ARRAY.uri, "size", 1, array::FN_ARRAY_SIZE());
         instance.register(&*XPATH_ARRAY.uri, "get", 2, array::FN_ARRAY_GET());
@@ -148,11 +177,12 @@ impl FunctionsRegister {
         instance.register(&*XPATH_ARRAY.uri, "append", 2, array::FN_ARRAY_APPEND());
         instance.register(&*XPATH_ARRAY.uri, "subarray", 2, array::FN_ARRAY_SUBARRAY_2());
         instance.register(&*XPATH_ARRAY.uri, "subarray", 3, array::FN_ARRAY_SUBARRAY_3());
+        instance.register(&*XPATH_ARRAY.uri, "remove", 2, array::FN_ARRAY_REMOVE());
         instance.register(&*XPATH_ARRAY.uri, "insert-before", 3, array::FN_ARRAY_INSERT_BEFORE());
         instance.register(&*XPATH_ARRAY.uri, "head", 1, array::FN_ARRAY_HEAD());
         instance.register(&*XPATH_ARRAY.uri, "tail", 1, array::FN_ARRAY_TAIL());
         instance.register(&*XPATH_ARRAY.uri, "reverse", 1, array::FN_ARRAY_REVERSE());
-        // instance.register(&*XPATH_ARRAY.uri, "join", usize::MAX, array::FN_ARRAY_JOIN());
+        instance.register(&*XPATH_ARRAY.uri, "join", 1, array::FN_ARRAY_JOIN());
         instance.register(&*XPATH_ARRAY.uri, "for-each", 2, array::FN_ARRAY_FOR_EACH());
         instance.register(&*XPATH_ARRAY.uri, "filter", 2, array::FN_ARRAY_FILTER());
         instance.register(&*XPATH_ARRAY.uri, "fold-left", 3, array::FN_ARRAY_FOLD_LEFT());
@@ -166,15 +196,47 @@ impl FunctionsRegister {
         instance.register(&*XPATH_FUNCTIONS.uri, "current-dateTime", 0, datetime::FN_CURRENT_DATE_TIME());
         instance.register(&*XPATH_FUNCTIONS.uri, "current-date", 0, datetime::FN_CURRENT_DATE());
         instance.register(&*XPATH_FUNCTIONS.uri, "current-time", 0, datetime::FN_CURRENT_TIME());
+
+        instance.register(&*XPATH_FUNCTIONS.uri, "years-from-duration", 1, datetime::FN_YEARS_FROM_DURATION());
+        instance.register(&*XPATH_FUNCTIONS.uri, "months-from-duration", 1, datetime::FN_MONTHS_FROM_DURATION());
+        instance.register(&*XPATH_FUNCTIONS.uri, "days-from-duration", 1, datetime::FN_DAYS_FROM_DURATION());
+        instance.register(&*XPATH_FUNCTIONS.uri, "hours-from-duration", 1, datetime::FN_HOURS_FROM_DURATION());
+        instance.register(&*XPATH_FUNCTIONS.uri, "minutes-from-duration", 1, datetime::FN_MINUTES_FROM_DURATION());
+        instance.register(&*XPATH_FUNCTIONS.uri, "seconds-from-duration", 1, datetime::FN_SECONDS_FROM_DURATION());
+
+        instance.register(&*XPATH_FUNCTIONS.uri, "year-from-dateTime", 1, datetime::FN_YEAR_FROM_DATE_TIME());
+        instance.register(&*XPATH_FUNCTIONS.uri, "month-from-dateTime", 1, datetime::FN_MONTH_FROM_DATE_TIME());
+        instance.register(&*XPATH_FUNCTIONS.uri, "day-from-dateTime", 1, datetime::FN_DAY_FROM_DATE_TIME());
+        instance.register(&*XPATH_FUNCTIONS.uri, "hours-from-dateTime", 1, datetime::FN_HOURS_FROM_DATE_TIME());
+        instance.register(&*XPATH_FUNCTIONS.uri, "minutes-from-dateTime", 1, datetime::FN_MINUTES_FROM_DATE_TIME());
+        instance.register(&*XPATH_FUNCTIONS.uri, "seconds-from-dateTime", 1, datetime::FN_SECONDS_FROM_DATE_TIME());
+        instance.register(&*XPATH_FUNCTIONS.uri, "timezone-from-dateTime", 1, datetime::FN_TIMEZONE_FROM_DATE_TIME());
+
         instance.register(&*XPATH_FUNCTIONS.uri, "year-from-date", 1, datetime::FN_YEAR_FROM_DATE());
         instance.register(&*XPATH_FUNCTIONS.uri, "month-from-date", 1, datetime::FN_MONTH_FROM_DATE());
         instance.register(&*XPATH_FUNCTIONS.uri, "day-from-date", 1, datetime::FN_DAY_FROM_DATE());
-        instance.register(&*XPATH_FUNCTIONS.uri, "days-from-duration", 1, datetime::FN_DAYS_FROM_DURATION());
+        instance.register(&*XPATH_FUNCTIONS.uri, "timezone-from-date", 1, datetime::FN_TIMEZONE_FROM_DATE());
 
-        instance.register(&*XPATH_FUNCTIONS.uri, "timezone-from-time", 1, datetime::FN_TIMEZONE_FROM_DATE_TIME());
-        instance.register(&*XPATH_FUNCTIONS.uri, "timezone-from-time", 1, datetime::FN_TIMEZONE_FROM_DATE());
+        instance.register(&*XPATH_FUNCTIONS.uri, "hours-from-time", 1, datetime::FN_HOURS_FROM_TIME());
+        instance.register(&*XPATH_FUNCTIONS.uri, "minutes-from-time", 1, datetime::FN_MINUTES_FROM_TIME());
+        instance.register(&*XPATH_FUNCTIONS.uri, "seconds-from-time", 1, datetime::FN_SECONDS_FROM_TIME());
         instance.register(&*XPATH_FUNCTIONS.uri, "timezone-from-time", 1, datetime::FN_TIMEZONE_FROM_TIME());
 
+        instance.register(&*XPATH_FUNCTIONS.uri, "adjust-dateTime-to-timezone", 1, datetime::FN_ADJUST_DATE_TIME_TO_TIMEZONE_1());
+        instance.register(&*XPATH_FUNCTIONS.uri, "adjust-dateTime-to-timezone", 2, datetime::FN_ADJUST_DATE_TIME_TO_TIMEZONE_2());
+        instance.register(&*XPATH_FUNCTIONS.uri, "adjust-date-to-timezone", 1, datetime::FN_ADJUST_DATE_TO_TIMEZONE_1());
+        instance.register(&*XPATH_FUNCTIONS.uri, "adjust-date-to-timezone", 2, datetime::FN_ADJUST_DATE_TO_TIMEZONE_2());
+        instance.register(&*XPATH_FUNCTIONS.uri, "adjust-time-to-timezone", 1, datetime::FN_ADJUST_TIME_TO_TIMEZONE_1());
+        instance.register(&*XPATH_FUNCTIONS.uri, "adjust-time-to-timezone", 2, datetime::FN_ADJUST_TIME_TO_TIMEZONE_2());
+        instance.register(&*XPATH_FUNCTIONS.uri, "implicit-timezone", 0, datetime::FN_IMPLICIT_TIMEZONE());
+
+        instance.register(&*XPATH_FUNCTIONS.uri, "format-dateTime", 2, datetime::FN_FORMAT_DATE_TIME_2());
+        instance.register(&*XPATH_FUNCTIONS.uri, "format-dateTime", 5, datetime::FN_FORMAT_DATE_TIME_5());
+        instance.register(&*XPATH_FUNCTIONS.uri, "format-date", 2, datetime::FN_FORMAT_DATE_2());
+        instance.register(&*XPATH_FUNCTIONS.uri, "format-date", 5, datetime::FN_FORMAT_DATE_5());
+        instance.register(&*XPATH_FUNCTIONS.uri, "format-time", 2, datetime::FN_FORMAT_TIME_2());
+        instance.register(&*XPATH_FUNCTIONS.uri, "format-time", 5, datetime::FN_FORMAT_TIME_5());
+
         instance.register(&*XPATH_FUNCTIONS.uri, "for-each", 2, fun::FN_FOR_EACH());
         instance.register(&*XPATH_FUNCTIONS.uri, "filter", 2, fun::FN_FILTER());
         instance.register(&*XPATH_FUNCTIONS.uri, "fold-left", 3, fun::FN_FOLD_LEFT());
@@ -190,9 +252,14 @@ impl FunctionsRegister {
         instance.register(&*XPATH_FUNCTIONS.uri, "error", 2, fun::FN_ERROR_2());
         instance.register(&*XPATH_FUNCTIONS.uri, "error", 3, fun::FN_ERROR_3());
 
+        instance.register(&*XPATH_FUNCTIONS.uri, "format-integer", 2, math::FN_MATH_FORMAT_INTEGER_2());
+        instance.register(&*XPATH_FUNCTIONS.uri, "format-integer", 3, math::FN_MATH_FORMAT_INTEGER_3());
         instance.register(&*XPATH_FUNCTIONS.uri, "format-number", 2, math::FN_MATH_FORMAT_NUMBER_2());
         instance.register(&*XPATH_FUNCTIONS.uri, "format-number", 3, math::FN_MATH_FORMAT_NUMBER_3());
 
+        instance.register(&*XPATH_FUNCTIONS.uri, "random-number-generator", 0, random::FN_RANDOM_NUMBER_GENERATOR_0());
+        instance.register(&*XPATH_FUNCTIONS.uri, "random-number-generator", 1, random::FN_RANDOM_NUMBER_GENERATOR_1());
+
         instance.register(&*XPATH_FUNCTIONS.uri, "number", 0, math::FN_MATH_NUMBER_0());
         instance.register(&*XPATH_FUNCTIONS.uri, "number", 1, math::FN_MATH_NUMBER_1());
 
@@ -206,6 +273,61 @@ impl FunctionsRegister {
         instance.register(&*XPATH_FUNCTIONS.uri, "sum", 2, aggregates::FN_SUM_2());
 
         instance.register(&*XPATH_MATH.uri, "pi", 0, math::FN_MATH_PI());
+        instance.register(&*XPATH_MATH.uri, "exp", 1, math::FN_MATH_EXP());
+        instance.register(&*XPATH_MATH.uri, "exp10", 1, math::FN_MATH_EXP10());
+        instance.register(&*XPATH_MATH.uri, "log", 1, math::FN_MATH_LOG());
+        instance.register(&*XPATH_MATH.uri, "log10", 1, math::FN_MATH_LOG10());
+        instance.register(&*XPATH_MATH.uri, "pow", 2, math::FN_MATH_POW());
+        instance.register(&*XPATH_MATH.uri, "sqrt", 1, math::FN_MATH_SQRT());
+        instance.register(&*XPATH_MATH.uri, "sin", 1, math::FN_MATH_SIN());
+        instance.register(&*XPATH_MATH.uri, "cos", 1, math::FN_MATH_COS());
+        instance.register(&*XPATH_MATH.uri, "tan", 1, math::FN_MATH_TAN());
+        instance.register(&*XPATH_MATH.uri, "asin", 1, math::FN_MATH_ASIN());
+        instance.register(&*XPATH_MATH.uri, "acos", 1, math::FN_MATH_ACOS());
+        instance.register(&*XPATH_MATH.uri, "atan", 1, math::FN_MATH_ATAN());
+        instance.register(&*XPATH_MATH.uri, "atan2", 2, math::FN_MATH_ATAN2());
+
+        instance.register(&*DECIMAL_MATH.uri, "sqrt", 1, math::FN_DECIMAL_SQRT());
+        instance.register(&*DECIMAL_MATH.uri, "exp", 1, math::FN_DECIMAL_EXP());
+        instance.register(&*DECIMAL_MATH.uri, "pow", 2, math::FN_DECIMAL_POW());
+
+        instance.register(&*EXPATH_BINARY.uri, "hex", 1, binary::FN_BIN_HEX());
+        instance.register(&*EXPATH_BINARY.uri, "bin", 1, binary::FN_BIN_BIN());
+        instance.register(&*EXPATH_BINARY.uri, "octal", 1, binary::FN_BIN_OCTAL());
+        instance.register(&*EXPATH_BINARY.uri, "to-octets", 1, binary::FN_BIN_TO_OCTETS());
+        instance.register(&*EXPATH_BINARY.uri, "from-octets", 1, binary::FN_BIN_FROM_OCTETS());
+        instance.register(&*EXPATH_BINARY.uri, "length", 1, binary::FN_BIN_LENGTH());
+        instance.register(&*EXPATH_BINARY.uri, "part", 2, binary::FN_BIN_PART_2());
+        instance.register(&*EXPATH_BINARY.uri, "part", 3, binary::FN_BIN_PART_3());
+        instance.register(&*EXPATH_BINARY.uri, "join", 1, binary::FN_BIN_JOIN());
+        instance.register(&*EXPATH_BINARY.uri, "insert-before", 3, binary::FN_BIN_INSERT_BEFORE());
+        instance.register(&*EXPATH_BINARY.uri, "pad-left", 2, binary::FN_BIN_PAD_LEFT_2());
+        instance.register(&*EXPATH_BINARY.uri, "pad-left", 3, binary::FN_BIN_PAD_LEFT_3());
+        instance.register(&*EXPATH_BINARY.uri, "pad-right", 2, binary::FN_BIN_PAD_RIGHT_2());
+        instance.register(&*EXPATH_BINARY.uri, "pad-right", 3, binary::FN_BIN_PAD_RIGHT_3());
+        instance.register(&*EXPATH_BINARY.uri, "find", 3, binary::FN_BIN_FIND());
+        for arity in 1..=4 {
+            instance.register(&*EXPATH_BINARY.uri, "decode-string", arity, binary::FN_BIN_DECODE_STRING(arity));
+        }
+        for arity in 1..=2 {
+            instance.register(&*EXPATH_BINARY.uri, "encode-string", arity, binary::FN_BIN_ENCODE_STRING(arity));
+            instance.register(&*EXPATH_BINARY.uri, "pack-double", arity, binary::FN_BIN_PACK_DOUBLE(arity));
+            instance.register(&*EXPATH_BINARY.uri, "pack-float", arity, binary::FN_BIN_PACK_FLOAT(arity));
+        }
+        for arity in 2..=3 {
+            instance.register(&*EXPATH_BINARY.uri, "pack-integer", arity, binary::FN_BIN_PACK_INTEGER(arity));
+            instance.register(&*EXPATH_BINARY.uri, "unpack-double", arity, binary::FN_BIN_UNPACK_DOUBLE(arity));
+            instance.register(&*EXPATH_BINARY.uri, "unpack-float", arity, binary::FN_BIN_UNPACK_FLOAT(arity));
+        }
+        for arity in 3..=4 {
+            instance.register(&*EXPATH_BINARY.uri, "unpack-integer", arity, binary::FN_BIN_UNPACK_INTEGER(arity));
+            instance.register(&*EXPATH_BINARY.uri, "unpack-unsigned-integer", arity, binary::FN_BIN_UNPACK_UNSIGNED_INTEGER(arity));
+        }
+        instance.register(&*EXPATH_BINARY.uri, "or", 2, binary::FN_BIN_BITWISE(binary::bin_or));
+        instance.register(&*EXPATH_BINARY.uri, "xor", 2, binary::FN_BIN_BITWISE(binary::bin_xor));
+        instance.register(&*EXPATH_BINARY.uri, "and", 2, binary::FN_BIN_BITWISE(binary::bin_and));
+        instance.register(&*EXPATH_BINARY.uri, "not", 1, binary::FN_BIN_NOT());
+        instance.register(&*EXPATH_BINARY.uri, "shift", 2, binary::FN_BIN_SHIFT());
 
         instance.register(&*XPATH_FUNCTIONS.uri, "abs", 1, math::FN_MATH_ABS());
         instance.register(&*XPATH_FUNCTIONS.uri, "ceiling", 1, math::FN_MATH_CEILING());
@@ -237,6 +359,10 @@ impl FunctionsRegister {
         instance.register(&*XPATH_FUNCTIONS.uri, "upper-case", 1, strings::FN_UPPER_CASE());
         instance.register(&*XPATH_FUNCTIONS.uri, "lower-case", 1, strings::FN_LOWER_CASE());
         instance.register(&*XPATH_FUNCTIONS.uri, "translate", 3, strings::FN_TRANSLATE());
+        instance.register(&*XPATH_FUNCTIONS.uri, "compare", 2, strings::FN_COMPARE_2());
+        instance.register(&*XPATH_FUNCTIONS.uri, "compare", 3, strings::FN_COMPARE_3());
+        instance.register(&*XPATH_FUNCTIONS.uri, "collation-key", 1, strings::FN_COLLATION_KEY_1());
+        instance.register(&*XPATH_FUNCTIONS.uri, "collation-key", 2, strings::FN_COLLATION_KEY_2());
         instance.register(&*XPATH_FUNCTIONS.uri, "contains", 2, strings::FN_CONTAINS_2());
         instance.register(&*XPATH_FUNCTIONS.uri, "contains", 3, strings::FN_CONTAINS_3());
         instance.register(&*XPATH_FUNCTIONS.uri, "starts-with", 2, strings::FN_STARTS_WITH_2());
@@ -263,6 +389,11 @@ impl FunctionsRegister {
         instance.register(&*XPATH_FUNCTIONS.uri, "default-language", 0, context::FN_DEFAULT_LANGUAGE());
         instance.register(&*XPATH_FUNCTIONS.uri, "static-base-uri", 0, context::FN_STATIC_BASE_URI());
 
+        instance.register(&*XPATH_FUNCTIONS.uri, "doc", 1, documents::FN_DOC());
+        instance.register(&*XPATH_FUNCTIONS.uri, "doc-available", 1, documents::FN_DOC_AVAILABLE());
+        instance.register(&*XPATH_FUNCTIONS.uri, "uri-collection", 0, documents::FN_URI_COLLECTION_0());
+        instance.register(&*XPATH_FUNCTIONS.uri, "uri-collection", 1, documents::FN_URI_COLLECTION_1());
+
         instance.register(&*XPATH_FUNCTIONS.uri, "function-lookup", 2, fun::FN_FUNCTION_LOOKUP());
         instance.register(&*XPATH_FUNCTIONS.uri, "function-name", 1, fun::FN_FUNCTION_NAME());
         instance.register(&*XPATH_FUNCTIONS.uri, "function-arity", 1, fun::FN_FUNCTION_ARITY());
@@ -307,6 +438,8 @@ impl FunctionsRegister {
         instance.register(&*XPATH_FUNCTIONS.uri, "innermost", 1, nodes::FN_INNERMOST());
         instance.register(&*XPATH_FUNCTIONS.uri, "outermost", 1, nodes::FN_OUTERMOST());
 
+        instance.register(&*XPATH_FUNCTIONS.uri, "put", 2, nodes::FN_PUT());
+
         instance
     }
 
@@ -320,10 +453,10 @@ impl FunctionsRegister {
             .insert(arity,fun);
     }
 
-    pub(crate) fn put(&mut self, name: QNameResolved, parameters: Vec<Param>, st: Option<SequenceType>, body: Box<dyn Expression>) {
+    pub(crate) fn put(&mut self, name: QNameResolved, parameters: Vec<Param>, st: Option<SequenceType>, updating: bool, body: Box<dyn Expression>) {
         self.declared.entry(name.clone())
             .or_insert_with(HashMap::new)
-            .insert(parameters.len(), Function { name, parameters, st, body });
+            .insert(parameters.len(), Function { name, parameters, st, updating, body });
     }
 
     pub(crate) fn get(&self, qname: &QNameResolved, arity: usize) -> Option<FUNCTION> {
@@ -381,34 +514,37 @@ fn function_conversion_rules(env: &Box<Environment>, sequence_type: Option<Seque
     }
 }
 
+// functions which need only first items of the first argument, the rest of arguments is evaluated first
+pub(crate) fn is_partial_on_first(name: &QNameResolved, arity: usize) -> bool {
+    name.url == *XPATH_FUNCTIONS.uri && match name.local_part.as_str() {
+        "head" | "exists" | "empty" => arity == 1,
+        "subsequence" => arity == 3,
+        _ => false
+    }
+}
+
+// number of first items needed by function, None when all are needed
+pub(crate) fn first_items_needed(name: &QNameResolved, rest: &[Object]) -> Option<usize> {
+    match (name.local_part.as_str(), rest) {
+        ("head" | "exists" | "empty", []) => Some(1),
+        ("subsequence", [Object::Atomic(Type::Integer(start)), Object::Atomic(Type::Integer(length))]) => {
+            usize::try_from(start.saturating_add(*length).saturating_sub(1).max(0)).ok()
+        },
+        _ => None
+    }
+}
+
 pub(crate) fn call(env: Box<Environment>, name: QNameResolved, arguments: Vec<Object>, context: &DynamicContext) -> EvalResult {
     // println!("call: {:?} {:?}", name, arguments);
 
-    let mut fn_env = env.next();
-
-    let fun = fn_env.declared_functions(&name, arguments.len());
+    let fun = env.declared_functions(&name, arguments.len());
     if fun.is_some() {
         let fun = fun.unwrap().clone();
 
-        for (parameter, mut argument) in (&fun.parameters).into_iter()
-            .zip(arguments.into_iter())
-            .into_iter()
-        {
-            argument = function_conversion_rules(&fn_env, parameter.sequence_type.clone(), argument)?;
-
-            fn_env.set_variable(resolve_element_qname(&parameter.name, &fn_env), argument.clone())
-        }
-
-        let (new_env, mut result) = fun.body.eval(fn_env, context)?;
-        let env = new_env.prev();
-
-        if let Some(st) = fun.st {
-            result = st.cascade(&env, result)?;
-        }
-
-        Ok((env, result))
-
+        call_declared(env, &fun, arguments, context)
     } else {
+        let fn_env = env.next();
+
         let fun: Option<FUNCTION> =
             // workaround for "concat" function
             if name.local_part == "concat" {
@@ -417,13 +553,6 @@ pub(crate) fn call(env: Box<Environment>, name: QNameResolved, arguments: Vec<Ob
                 } else {
                     None
                 }
-            // workaround for "join" function
-            } else if name.local_part == "join" {
-                if name.url == *XPATH_FUNCTIONS.uri && arguments.len() >= 2 {
-                    Some(array::FN_ARRAY_JOIN(arguments.len()))
-                } else {
-                    None
-                }
             } else {
                 fn_env.get_function(&name, arguments.len())
             };
@@ -450,4 +579,197 @@ pub(crate) fn call(env: Box<Environment>, name: QNameResolved, arguments: Vec<Ob
             Err((ErrorCode::XPST0017, format!("no function {:?}#{:?}", name, arguments.len())))
         }
     }
-}
\ No newline at end of file
+}
+
+// stack left when function body is moved to a new stack segment, and size of the segment
+const STACK_RED_ZONE: usize = 256 * 1024;
+const STACK_SEGMENT: usize = 4 * 1024 * 1024;
+
+// call of function declared in prolog
+pub(crate) fn call_declared(env: Box<Environment>, fun: &Function, arguments: Vec<Object>, context: &DynamicContext) -> EvalResult {
+    let (mut env, mut result) = eval_declared(env, fun, arguments, context)?;
+
+    // result types of callers, checked from the innermost one; type of function which tail calls
+    // itself is kept once, so recursion doesn't grow the list
+    let mut result_types: Vec<(QNameResolved, usize, SequenceType)> = vec![];
+    if let Some(st) = &fun.st {
+        result_types.push((fun.name.clone(), fun.parameters.len(), st.clone()));
+    }
+
+    // tail calls are made in a loop, so stack doesn't grow with them
+    let mut result = loop {
+        match result {
+            Tail::Value(value) => break value,
+            Tail::Call(fun, arguments) => {
+                (env, result) = eval_declared(env, &fun, arguments, context)?;
+
+                if let Some(st) = fun.st {
+                    let is_repeated = matches!(result_types.last(), Some((name, arity, _)) if *name == fun.name && *arity == fun.parameters.len());
+                    if !is_repeated {
+                        result_types.push((fun.name, fun.parameters.len(), st));
+                    }
+                }
+            }
+        }
+    };
+
+    for (_, _, st) in result_types.iter().rev() {
+        result = st.cascade(&env, result)?;
+    }
+
+    Ok((env, result))
+}
+
+fn eval_declared(env: Box<Environment>, fun: &Function, arguments: Vec<Object>, context: &DynamicContext) -> TailResult {
+    let mut fn_env = env.call_frame()?;
+
+    for (parameter, mut argument) in (&fun.parameters).into_iter()
+        .zip(arguments.into_iter())
+        .into_iter()
+    {
+        argument = function_conversion_rules(&fn_env, parameter.sequence_type.clone(), argument)?;
+
+        fn_env.set_variable(resolve_element_qname(&parameter.name, &fn_env), argument)
+    }
+
+    let updates = fn_env.pending_updates().len();
+
+    let (mut new_env, result) = stacker::maybe_grow(STACK_RED_ZONE, STACK_SEGMENT, || {
+        fun.body.eval_tail(fn_env, context)
+    })?;
+    if fun.updating {
+        // body of updating function must be updating or vacuous expression
+        if matches!(&result, Tail::Value(value) if !matches!(value, Object::Empty)) {
+            return Err((ErrorCode::XUST0002, format!("updating function {:?} returned value", fun.name)));
+        }
+    } else if new_env.pending_updates().len() != updates {
+        return Err((ErrorCode::XUST0001, format!("updating expression in non-updating function {:?}", fun.name)));
+    }
+
+    Ok((new_env.prev(), result))
+}
+
+// dynamic call of function item: inline function, named function reference, map or array
+pub(crate) fn call_item(env: Box<Environment>, function: Object, mut arguments: Vec<Object>, context: &DynamicContext) -> EvalResult {
+    match function {
+        Object::Function { parameters, st, body } => {
+            if parameters.len() != arguments.len() {
+                return Err((ErrorCode::XPTY0004, format!("function expects {} arguments, got {}", parameters.len(), arguments.len())));
+            }
+
+            let mut fn_env = env.call_frame()?;
+            for (parameter, mut argument) in parameters.iter().zip(arguments.into_iter()) {
+                argument = function_conversion_rules(&fn_env, parameter.sequence_type.clone(), argument)?;
+
+                fn_env.set_variable(resolve_element_qname(&parameter.name, &fn_env), argument)
+            }
+
+            let (new_env, mut result) = stacker::maybe_grow(STACK_RED_ZONE, STACK_SEGMENT, || {
+                body.eval(fn_env, context)
+            })?;
+            let env = new_env.prev();
+
+            if let Some(st) = st {
+                result = st.cascade(&env, result)?;
+            }
+
+            Ok((env, result))
+        }
+        Object::FunctionRef { name, arity } => {
+            if arity != arguments.len() {
+                return Err((ErrorCode::XPTY0004, format!("function {:?} expects {} arguments, got {}", name, arity, arguments.len())));
+            }
+            call(env, name, arguments, context)
+        }
+        Object::Map(map) => {
+            if arguments.len() != 1 {
+                return Err((ErrorCode::XPTY0004, format!("map expects 1 argument, got {}", arguments.len())));
+            }
+            let st = SequenceType::exactly_one(ItemType::AnyAtomicType);
+            match function_conversion_rules(&env, Some(st), arguments.remove(0))? {
+                Object::Atomic(key) => {
+                    let value = map.get(&key).cloned().unwrap_or(Object::Empty);
+                    Ok((env, value))
+                }
+                key => Err((ErrorCode::XPTY0004, format!("map key must be atomic value: {:?}", key)))
+            }
+        }
+        Object::Array(items) => {
+            if arguments.len() != 1 {
+                return Err((ErrorCode::XPTY0004, format!("array expects 1 argument, got {}", arguments.len())));
+            }
+            let st = SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_INTEGER.into()));
+            match function_conversion_rules(&env, Some(st), arguments.remove(0))? {
+                Object::Atomic(Type::Integer(index)) if index >= 1 && index as usize <= items.len() => {
+                    let item = items[(index - 1) as usize].clone();
+                    Ok((env, item))
+                }
+                index => Err((ErrorCode::FOAY0001, format!("index outside of bounds: {:?}", index)))
+            }
+        }
+        _ => Err((ErrorCode::XPTY0004, format!("not a function: {:?}", function)))
+    }
+}
+
+// types of parameters of function item, None for items which aren't functions or unknown functions
+pub(crate) fn function_signature(env: &Environment, function: &Object) -> Option<Vec<SequenceType>> {
+    match function {
+        Object::Function { parameters, .. } => {
+            Some(parameters.iter()
+                .map(|param| param.sequence_type.clone().unwrap_or_else(|| SequenceType::zero_or_more(ItemType::Item)))
+                .collect())
+        }
+        Object::FunctionRef { name, arity } => {
+            if let Some(fun) = env.declared_functions(name, *arity) {
+                Some(fun.signature().0)
+            } else if let Some(((params, _), _)) = env.get_function(name, *arity) {
+                Some(params)
+            } else if name.url == *XPATH_FUNCTIONS.uri && name.local_part == "concat" && *arity >= 2 {
+                Some(strings::FN_CONCAT(*arity).0.0)
+            } else {
+                None
+            }
+        }
+        Object::Map(_) => Some(vec![SequenceType::exactly_one(ItemType::AnyAtomicType)]),
+        Object::Array(_) => Some(vec![SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_INTEGER.into()))]),
+        _ => None
+    }
+}
+
+pub(crate) fn function_exists(env: &Environment, name: &QNameResolved, arity: usize) -> bool {
+    function_signature(env, &Object::FunctionRef { name: name.clone(), arity }).is_some()
+}
+
+// function item which calls function with fixed arguments and its parameters in place of placeholders
+pub(crate) fn partial_application(env: &Environment, function: Object, arguments: Vec<Object>) -> Result<Object, ErrorInfo> {
+    let params = match function_signature(env, &function) {
+        Some(params) => params,
+        None => return Err((ErrorCode::XPTY0004, format!("not a function: {:?}", function)))
+    };
+    if params.len() != arguments.len() {
+        return Err((ErrorCode::XPTY0004, format!("function expects {} arguments, got {}", params.len(), arguments.len())));
+    }
+
+    let mut parameters = vec![];
+    for (argument, st) in arguments.iter().zip(params.into_iter()) {
+        if let Object::Placeholder = argument {
+            let name = QName { prefix: None, url: None, local_part: format!("#{}", parameters.len() + 1) };
+            parameters.push(Param { name, sequence_type: Some(st) });
+        }
+    }
+    let names = parameters.iter().map(|param| param.name.clone()).collect();
+
+    Ok(Object::Function {
+        parameters,
+        st: None,
+        body: Box::new(PartialApplication { function: Box::new(function), arguments, parameters: names })
+    })
+}
+
+// collation named by optional argument, the default collation when it's absent or empty
+pub(crate) fn collation_argument(env: &Box<Environment>, argument: Option<&Object>) -> Result<Box<dyn Collation>, ErrorInfo> {
+    match argument {
+        None | Some(Object::Empty) => env.collation(None),
+        Some(uri) => env.collation(Some(&object_to_string(env, uri)))
+    }
+}
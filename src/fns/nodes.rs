@@ -1,5 +1,6 @@
 use crate::eval::{Environment, Object, DynamicContext, EvalResult, Type};
 use crate::eval::sequence_type::*;
+use crate::eval::update;
 use crate::fns::FUNCTION;
 use crate::parser::errors::ErrorCode;
 
@@ -286,4 +287,34 @@ pub(crate) fn FN_OUTERMOST() -> FUNCTION {
 pub(crate) fn fn_outermost(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
     println!("arguments {:?}", arguments);
     todo!()
-}
\ No newline at end of file
+}
+// fn:put($node as node(), $uri as xs:string?) as empty-sequence()
+pub(crate) fn FN_PUT() -> FUNCTION {
+    (
+        (
+            [
+                SequenceType::exactly_one(ItemType::node()),
+                SequenceType::zero_or_one(ItemType::AtomicOrUnionType(XS_STRING.into()))
+            ].to_vec(),
+            SequenceType::exactly_one(ItemType::SequenceEmpty)
+        ),
+        fn_put
+    )
+}
+
+pub(crate) fn fn_put(mut env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
+    let node = match arguments.get(0) {
+        Some(Object::Node(rf)) => rf.clone(),
+        _ => return Err((ErrorCode::FOUP0001, String::from("first operand of fn:put must be a node")))
+    };
+
+    let uri = match arguments.get(1) {
+        Some(Object::Atomic(Type::String(uri))) => uri.clone(),
+        // base uri of nodes is not tracked
+        _ => return Err((ErrorCode::FOUP0002, String::from("uri is not specified")))
+    };
+
+    update::put(&mut env, node, uri)?;
+
+    Ok((env, Object::Empty))
+}
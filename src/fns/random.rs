@@ -0,0 +1,115 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use ordered_float::OrderedFloat;
+use crate::eval::{Environment, Object, Type, EvalResult, DynamicContext};
+use crate::eval::expression::Expression;
+use crate::eval::helpers::{flat_items, relax};
+use crate::eval::prolog::PartialApplication;
+use crate::eval::sequence_type::*;
+use crate::fns::{FUNCTION, Param};
+use crate::namespaces::XPATH_FUNCTIONS;
+use crate::parser::errors::ErrorCode;
+use crate::values::{OrderedMap, QName, QNameResolved, resolve_element_qname};
+
+// fn:random-number-generator() as map(xs:string, item())
+pub(crate) fn FN_RANDOM_NUMBER_GENERATOR_0() -> FUNCTION {
+    (
+        (
+            [].to_vec(),
+            SequenceType::exactly_one(ItemType::Map(None))
+        ),
+        fn_random_number_generator
+    )
+}
+
+// fn:random-number-generator($seed as xs:anyAtomicType?) as map(xs:string, item())
+pub(crate) fn FN_RANDOM_NUMBER_GENERATOR_1() -> FUNCTION {
+    (
+        (
+            [SequenceType::zero_or_one(ItemType::AnyAtomicType)].to_vec(),
+            SequenceType::exactly_one(ItemType::Map(None))
+        ),
+        fn_random_number_generator
+    )
+}
+
+pub(crate) fn fn_random_number_generator(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
+    // without seed every call of one execution returns the same sequence
+    let seed = match arguments.first() {
+        None | Some(Object::Empty) => env.random_seed(),
+        Some(Object::Atomic(value)) => {
+            let mut hasher = DefaultHasher::new();
+            value.hash(&mut hasher);
+            hasher.finish()
+        }
+        Some(seed) => return Err((ErrorCode::XPTY0004, format!("seed must be atomic value: {:?}", seed)))
+    };
+    Ok((env, generator(seed)))
+}
+
+fn generator(seed: u64) -> Object {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let number: f64 = rng.gen();
+    let next_seed: u64 = rng.gen();
+    let permute_seed: u64 = rng.gen();
+
+    // next generator is the one seeded by next value of this one
+    let function = Object::FunctionRef {
+        name: QNameResolved { url: XPATH_FUNCTIONS.uri.to_string(), local_part: String::from("random-number-generator") },
+        arity: 1
+    };
+    let next = Object::Function {
+        parameters: vec![],
+        st: Some(SequenceType::exactly_one(ItemType::Map(None))),
+        body: Box::new(PartialApplication {
+            function: Box::new(function),
+            arguments: vec![Object::Atomic(Type::Integer(next_seed as i128))],
+            parameters: vec![]
+        })
+    };
+
+    let permute = Object::Function {
+        parameters: vec![Param { name: Permute::parameter(), sequence_type: Some(SequenceType::zero_or_more(ItemType::Item)) }],
+        st: Some(SequenceType::zero_or_more(ItemType::Item)),
+        body: Box::new(Permute { seed: permute_seed })
+    };
+
+    let mut map = OrderedMap::new();
+    map.insert(Type::String(String::from("number")), Object::Atomic(Type::Double(OrderedFloat::from(number))));
+    map.insert(Type::String(String::from("next")), next);
+    map.insert(Type::String(String::from("permute")), permute);
+    Object::Map(map)
+}
+
+// body of permute function, it shuffles items of its argument in order given by seed
+#[derive(Clone, Debug)]
+struct Permute {
+    seed: u64,
+}
+
+impl Permute {
+    fn parameter() -> QName {
+        QName { prefix: None, url: None, local_part: String::from("#1") }
+    }
+}
+
+impl Expression for Permute {
+    fn eval(&self, env: Box<Environment>, _context: &DynamicContext) -> EvalResult {
+        let name = resolve_element_qname(&Permute::parameter(), &env);
+        let value = match env.get_variable(&name) {
+            Some(value) => value,
+            None => return Err((ErrorCode::XPST0008, format!("unknown variable {:?}", name)))
+        };
+
+        let mut items: Vec<Object> = flat_items(value).collect();
+        items.shuffle(&mut StdRng::seed_from_u64(self.seed));
+        relax(env, items)
+    }
+
+    fn predicate(&self, _env: Box<Environment>, _context: &DynamicContext, _value: Object) -> EvalResult {
+        Err((ErrorCode::XPTY0004, String::from("permute isn't a predicate")))
+    }
+}
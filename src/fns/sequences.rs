@@ -1,7 +1,9 @@
 use std::collections::HashSet;
-use crate::eval::{Environment, Object, Type, DynamicContext, EvalResult, object_to_integer};
+use bigdecimal::Signed;
+use crate::eval::{Environment, Object, Type, DynamicContext, EvalResult, ErrorInfo, object_to_integer};
 use crate::eval::sequence_type::*;
-use crate::fns::FUNCTION;
+use crate::fns::{FUNCTION, collation_argument};
+use crate::eval::comparison::string_value;
 
 use crate::eval::helpers::{relax, sort_and_dedup};
 use crate::parser::errors::ErrorCode;
@@ -34,7 +36,7 @@ pub(crate) fn FN_DISTINCT_VALUES_2() -> FUNCTION {
 pub(crate) fn fn_distinct_values(env: Box<Environment>, mut arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
     let item = arguments.remove(0);
 
-    // TODO handle $collation
+    let collation = collation_argument(&env, arguments.get(0))?;
 
     match item {
         Object::Empty |
@@ -53,6 +55,16 @@ pub(crate) fn fn_distinct_values(env: Box<Environment>, mut arguments: Vec<Objec
 
             sort_and_dedup(&mut items);
 
+            // strings equal by collation are duplicates too
+            let mut keys = HashSet::new();
+            items.retain(|item| match item {
+                Object::Atomic(t) => match string_value(t) {
+                    Some(str) => keys.insert(collation.key(str)),
+                    None => true
+                },
+                _ => true
+            });
+
             relax(env, items)
         }
         _ => panic!("raise error? {:?}", item)
@@ -90,29 +102,25 @@ pub(crate) fn fn_data(env: Box<Environment>, arguments: Vec<Object>, context: &D
     };
 
     let mut result = vec![];
-    match data(env, item.clone(), &mut result) {
-        Ok(env) => relax(env, result),
-        Err(msg) => Err((ErrorCode::TODO, msg))
-    }
+    let env = data(env, item.clone(), &mut result)?;
+    relax(env, result)
 }
 
-fn data(env: Box<Environment>, obj: Object, result: &mut Vec<Object>) -> Result<Box<Environment>, String> {
+fn data(env: Box<Environment>, obj: Object, result: &mut Vec<Object>) -> Result<Box<Environment>, ErrorInfo> {
     match obj {
         Object::Atomic(..) => {
             result.push(obj);
             Ok(env)
         },
         Object::Node(rf) => {
-            match rf.to_typed_value() {
-                Ok(data) => {
-                    let item = Object::Atomic(Type::Untyped(data));
-                    result.push(item);
-                },
-                Err(msg) => return Err(msg)
+            for t in rf.to_typed_value()? {
+                result.push(Object::Atomic(t));
             }
             Ok(env)
         },
-        Object::Array(items) |
+        Object::Array(items) => {
+            data_of_vec(env, items.into_iter().collect(), result)
+        },
         Object::Sequence(items) => {
             data_of_vec(env, items, result)
         },
@@ -120,13 +128,10 @@ fn data(env: Box<Environment>, obj: Object, result: &mut Vec<Object>) -> Result<
     }
 }
 
-fn data_of_vec(env: Box<Environment>, items: Vec<Object>, result: &mut Vec<Object>) -> Result<Box<Environment>, String> {
+fn data_of_vec(env: Box<Environment>, items: Vec<Object>, result: &mut Vec<Object>) -> Result<Box<Environment>, ErrorInfo> {
     let mut current_env = env;
     for item in items {
-        match data(current_env, item, result) {
-            Ok(env) => current_env = env,
-            Err(msg) => return Err(msg),
-        }
+        current_env = data(current_env, item, result)?;
     }
     Ok(current_env)
 }
@@ -338,12 +343,28 @@ pub(crate) fn fn_subsequence(env: Box<Environment>, mut arguments: Vec<Object>,
     println!("arguments {:?}", arguments);
 
     let source = arguments.remove(0);
-    let start = arguments.remove(0).to_integer()?;
-    let mut length_opt = if arguments.len() == 0 {
+    let start = match position_argument(arguments.remove(0))? {
+        Some(start) => start,
+        None => return Ok((env, Object::Empty))
+    };
+    let length_opt = if arguments.len() == 0 {
         None
     } else {
-        Some(arguments.remove(0).to_integer()?)
+        match position_argument(arguments.remove(0))? {
+            Some(length) => Some(length),
+            None => return Ok((env, Object::Empty))
+        }
+    };
+
+    // positions from `from` till `till` (exclusive) are selected
+    let from = start.max(1);
+    let till = match length_opt {
+        Some(length) => start.saturating_add(length),
+        None => i128::MAX
     };
+    if till <= from {
+        return Ok((env, Object::Empty));
+    }
 
     match source {
         Object::Empty => Ok((env, Object::Empty)),
@@ -351,55 +372,54 @@ pub(crate) fn fn_subsequence(env: Box<Environment>, mut arguments: Vec<Object>,
             let min = mi.min(ma);
             let max = mi.max(ma);
 
-            let length = if let Some(length) = length_opt { length } else { min - max + 1 };
-
-            if start <= 0 || length <= 0 {
+            let new_min = min.saturating_add(from - 1);
+            if new_min > max {
                 Ok((env, Object::Empty))
             } else {
-                let new_min = min + (start.max(1) - 1);
-                if new_min > max {
-                    Ok((env, Object::Empty))
-                } else {
-                    let new_max = (new_min + (length - 1)).min(max);
+                let new_max = min.saturating_add(till - 2).min(max);
 
-                    if new_min == new_max {
-                        Ok((env, Object::Atomic(Type::Integer(new_min))))
-                    } else {
-                        Ok((env, Object::Range { min: new_min, max: new_max }))
-                    }
+                if new_min == new_max {
+                    Ok((env, Object::Atomic(Type::Integer(new_min))))
+                } else {
+                    Ok((env, Object::Range { min: new_min, max: new_max }))
                 }
             }
         },
         Object::Atomic(t) => {
-            let length = if let Some(length) = length_opt { length } else { 1 };
-
-            if start == 1 && length >= 1 {
+            if from == 1 {
                 Ok((env, Object::Atomic(t.clone())))
             } else {
                 Ok((env, Object::Empty))
             }
         },
         Object::Sequence(items) => {
-            let length = if let Some(length) = length_opt { length } else { items.len() as i128 };
-
-            let mut result = Vec::with_capacity(length as usize);
+            let skip = usize::try_from(from - 1).unwrap_or(usize::MAX);
+            let take = usize::try_from(till - from).unwrap_or(usize::MAX);
 
-            let from = start as usize;
-            let till = (start + length) as usize;
-
-            for position in from..till as usize {
-                if let Some(item) = items.get((position - 1) as usize) {
-                    result.push(item.clone());
-                } else {
-                    break
-                }
-            }
+            let result = items.into_iter().skip(skip).take(take).collect();
             relax(env, result)
         },
         _ => panic!("error {:?}", arguments)
     }
 }
 
+// position rounded as by fn:round, values out of i128 range are saturated; None for NaN which selects nothing
+fn position_argument(argument: Object) -> Result<Option<i128>, ErrorInfo> {
+    let number = match &argument {
+        Object::Atomic(Type::Integer(number)) => return Ok(Some(*number)),
+        Object::Atomic(Type::BigInteger(number)) => return Ok(Some(if number.is_negative() { i128::MIN } else { i128::MAX })),
+        Object::Atomic(Type::Double(number)) => number.into_inner(),
+        Object::Atomic(Type::Float(number)) => number.into_inner() as f64,
+        _ => return argument.to_integer().map(Some)
+    };
+    if number.is_nan() {
+        Ok(None)
+    } else {
+        // conversion of float to integer saturates
+        Ok(Some((number + 0.5).floor() as i128))
+    }
+}
+
 // fn:unordered($sourceSeq as item()*) as item()*
 pub(crate) fn FN_UNORDERED() -> FUNCTION {
     (
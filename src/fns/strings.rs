@@ -1,16 +1,17 @@
+use std::cmp::Ordering;
 use std::convert::TryFrom;
 use std::iter::FromIterator;
 use unicode_normalization::UnicodeNormalization;
 use crate::eval::{Environment, Object, Type, DynamicContext, EvalResult};
 use crate::eval::helpers::relax;
 use crate::eval::sequence_type::*;
-use crate::fns::FUNCTION;
+use crate::fns::{FUNCTION, collation_argument};
 use crate::namespaces::*;
 
 use crate::serialization::object_to_string;
 use crate::serialization::to_string::_object_to_string;
 use crate::parser::errors::ErrorCode;
-use crate::values::Types;
+use crate::values::{Types, collation_find, collation_starts_with, collation_ends_with};
 
 // fn:string() as xs:string
 pub(crate) fn FN_STRING_0() -> FUNCTION {
@@ -119,7 +120,7 @@ pub(crate) fn fn_string_to_codepoints(env: Box<Environment>, arguments: Vec<Obje
             }
         }
         [Object::Node(rf)] => {
-            match rf.to_typed_value() {
+            match rf.to_string_value() {
                 Ok(str) => processing(env, str),
                 Err(msg) => Err((ErrorCode::FORG0001, msg))
             }
@@ -141,9 +142,10 @@ pub(crate) fn FN_CONCAT(arity: usize) -> FUNCTION {
 }
 
 pub(crate) fn fn_concat(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    let str = arguments.iter()
+    let str: String = arguments.iter()
         .map(|item| object_to_string(&env, item))
         .collect();
+    env.check_string(&str)?;
 
     Ok((env, Object::Atomic(Type::String(str))))
 }
@@ -184,6 +186,7 @@ pub(crate) fn fn_string_join(env: Box<Environment>, arguments: Vec<Object>, _con
     } else {
         return Err((ErrorCode::TODO, format!("got {:?} arguments, but expected 1 or 2", arguments.len())));
     };
+    env.check_string(&str)?;
 
     Ok((env, Object::Atomic(Type::String(str))))
 }
@@ -391,6 +394,89 @@ pub(crate) fn fn_translate(env: Box<Environment>, arguments: Vec<Object>, _conte
     todo!()
 }
 
+// fn:compare($comparand1 as xs:string?, $comparand2 as xs:string?) as xs:integer?
+pub(crate) fn FN_COMPARE_2() -> FUNCTION {
+    (
+        (
+            [
+                SequenceType::zero_or_one(ItemType::AtomicOrUnionType(XS_STRING.into())),
+                SequenceType::zero_or_one(ItemType::AtomicOrUnionType(XS_STRING.into()))
+            ].to_vec(),
+            SequenceType::zero_or_one(ItemType::AtomicOrUnionType(XS_INTEGER.into()))
+        ),
+        fn_compare
+    )
+}
+
+// fn:compare($comparand1 as xs:string?, $comparand2 as xs:string?, $collation as xs:string) as xs:integer?
+pub(crate) fn FN_COMPARE_3() -> FUNCTION {
+    (
+        (
+            [
+                SequenceType::zero_or_one(ItemType::AtomicOrUnionType(XS_STRING.into())),
+                SequenceType::zero_or_one(ItemType::AtomicOrUnionType(XS_STRING.into())),
+                SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_STRING.into()))
+            ].to_vec(),
+            SequenceType::zero_or_one(ItemType::AtomicOrUnionType(XS_INTEGER.into()))
+        ),
+        fn_compare
+    )
+}
+
+pub(crate) fn fn_compare(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
+    let collation = collation_argument(&env, arguments.get(2))?;
+
+    match (arguments.get(0), arguments.get(1)) {
+        (Some(Object::Empty), _) | (_, Some(Object::Empty)) => Ok((env, Object::Empty)),
+        (Some(left), Some(right)) => {
+            let left = object_to_string(&env, left);
+            let right = object_to_string(&env, right);
+
+            let result = match collation.compare(&left, &right) {
+                Ordering::Less => -1,
+                Ordering::Equal => 0,
+                Ordering::Greater => 1,
+            };
+            Ok((env, Object::Atomic(Type::Integer(result))))
+        }
+        _ => panic!("error")
+    }
+}
+
+// fn:collation-key($key as xs:string) as xs:base64Binary
+pub(crate) fn FN_COLLATION_KEY_1() -> FUNCTION {
+    (
+        (
+            [SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_STRING.into()))].to_vec(),
+            SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_BASE64_BINARY.into()))
+        ),
+        fn_collation_key
+    )
+}
+
+// fn:collation-key($key as xs:string, $collation as xs:string) as xs:base64Binary
+pub(crate) fn FN_COLLATION_KEY_2() -> FUNCTION {
+    (
+        (
+            [
+                SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_STRING.into())),
+                SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_STRING.into()))
+            ].to_vec(),
+            SequenceType::exactly_one(ItemType::AtomicOrUnionType(XS_BASE64_BINARY.into()))
+        ),
+        fn_collation_key
+    )
+}
+
+pub(crate) fn fn_collation_key(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
+    let collation = collation_argument(&env, arguments.get(1))?;
+
+    let key = object_to_string(&env, arguments.get(0).unwrap());
+    let key = collation.key(&key);
+
+    Ok((env, Object::Atomic(Type::Base64Binary(key))))
+}
+
 // fn:contains($arg1 as xs:string?, $arg2 as xs:string?) as xs:boolean
 pub(crate) fn FN_CONTAINS_2() -> FUNCTION {
     (
@@ -424,12 +510,12 @@ pub(crate) fn fn_contains(env: Box<Environment>, arguments: Vec<Object>, _contex
     let string = arguments.get(0).unwrap();
     let pattern = arguments.get(1).unwrap();
 
-    // TODO handle $collation
+    let collation = collation_argument(&env, arguments.get(2))?;
 
     let string = object_to_string(&env, string);
     let pattern = object_to_string(&env, pattern);
 
-    let result = string.contains(&pattern);
+    let result = collation_find(collation.as_ref(), &string, &pattern).is_some();
 
     Ok((env, Object::Atomic(Type::Boolean(result))))
 }
@@ -468,10 +554,12 @@ pub(crate) fn fn_starts_with(env: Box<Environment>, arguments: Vec<Object>, cont
     let string = arguments.get(0).unwrap();
     let pattern = arguments.get(1).unwrap();
 
+    let collation = collation_argument(&env, arguments.get(2))?;
+
     let string = object_to_string(&env, string);
     let pattern = object_to_string(&env, pattern);
 
-    let result = string.starts_with(&pattern);
+    let result = collation_starts_with(collation.as_ref(), &string, &pattern);
 
     Ok((env, Object::Atomic(Type::Boolean(result))))
 }
@@ -510,10 +598,12 @@ pub(crate) fn fn_ends_with(env: Box<Environment>, arguments: Vec<Object>, contex
     let string = arguments.get(0).unwrap();
     let pattern = arguments.get(1).unwrap();
 
+    let collation = collation_argument(&env, arguments.get(2))?;
+
     let string = object_to_string(&env, string);
     let pattern = object_to_string(&env, pattern);
 
-    let result = string.ends_with(&pattern);
+    let result = collation_ends_with(collation.as_ref(), &string, &pattern);
 
     Ok((env, Object::Atomic(Type::Boolean(result))))
 }
@@ -548,7 +638,20 @@ pub(crate) fn FN_SUBSTRING_BEFORE_3() -> FUNCTION {
 }
 
 pub(crate) fn fn_substring_before(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+    let string = arguments.get(0).unwrap();
+    let pattern = arguments.get(1).unwrap();
+
+    let collation = collation_argument(&env, arguments.get(2))?;
+
+    let string = object_to_string(&env, string);
+    let pattern = object_to_string(&env, pattern);
+
+    let result = match collation_find(collation.as_ref(), &string, &pattern) {
+        Some((start, end)) => String::from(&string[..start]),
+        None => String::new()
+    };
+
+    Ok((env, Object::Atomic(Type::String(result))))
 }
 
 // fn:substring-after($arg1 as xs:string?, $arg2 as xs:string?) as xs:string
@@ -581,7 +684,20 @@ pub(crate) fn FN_SUBSTRING_AFTER_3() -> FUNCTION {
 }
 
 pub(crate) fn fn_substring_after(env: Box<Environment>, arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    todo!()
+    let string = arguments.get(0).unwrap();
+    let pattern = arguments.get(1).unwrap();
+
+    let collation = collation_argument(&env, arguments.get(2))?;
+
+    let string = object_to_string(&env, string);
+    let pattern = object_to_string(&env, pattern);
+
+    let result = match collation_find(collation.as_ref(), &string, &pattern) {
+        Some((start, end)) => String::from(&string[end..]),
+        None => String::new()
+    };
+
+    Ok((env, Object::Atomic(Type::String(result))))
 }
 
 // fn:matches($input as xs:string?, $pattern as xs:string) as xs:boolean
@@ -744,9 +860,3 @@ pub(crate) fn fn_analyze_string(env: Box<Environment>, arguments: Vec<Object>, _
     todo!()
 }
 
-pub(crate) fn object_to_array(object: Object) -> Vec<Object> {
-    match object {
-        Object::Array(array) => array,
-        _ => panic!("TODO object_to_array {:?}", object)
-    }
-}
@@ -1,4 +1,4 @@
-use crate::eval::{Environment, Object, Type, DynamicContext, EvalResult, ErrorInfo};
+use crate::eval::{Environment, Object, Type, DynamicContext, EvalResult, ErrorInfo, atomization};
 use crate::parser::parse_duration::*;
 use crate::parser::errors::ErrorCode;
 use ordered_float::OrderedFloat;
@@ -22,7 +22,7 @@ fn convert(to_type: Types, env: Box<Environment>, arguments: Vec<Object>) -> Eva
             Ok((env, Object::Atomic(n)))
         }
         [Object::Node(rf)] => {
-            match rf.to_typed_value() {
+            match rf.to_string_value() {
                 Ok(str) => {
                     let t = Type::Untyped(str);
                     let n = t.convert(to_type)?;
@@ -31,7 +31,17 @@ fn convert(to_type: Types, env: Box<Environment>, arguments: Vec<Object>) -> Eva
                 Err(msg) => Err((ErrorCode::FORG0001, msg))
             }
         }
-        _ => todo!("{:?}", arguments)
+        [item] => {
+            match atomization(&env, item.clone())? {
+                Object::Empty => Ok((env, Object::Empty)),
+                Object::Atomic(t) => {
+                    let n = t.convert(to_type)?;
+                    Ok((env, Object::Atomic(n)))
+                }
+                value => Err((ErrorCode::XPTY0004, format!("can't cast {:?} to {:?}", value, to_type)))
+            }
+        }
+        _ => Err((ErrorCode::XPTY0004, format!("can't cast more than one item to {:?}", to_type)))
     }
 }
 
@@ -711,7 +721,7 @@ pub(crate) fn FN_XS_NUMERIC() -> FUNCTION {
 }
 
 pub(crate) fn xs_numeric_eval(env: Box<Environment>, mut arguments: Vec<Object>, _context: &DynamicContext) -> EvalResult {
-    let arg = arguments.remove(0);
+    let arg = atomization(&env, arguments.remove(0))?;
     match arg {
         Object::Empty => Ok((env, Object::Empty)),
         Object::Atomic(t) => {
@@ -725,6 +735,7 @@ pub(crate) fn xs_numeric_eval(env: Box<Environment>, mut arguments: Vec<Object>,
                     Type::Double(OrderedFloat::from(number))
                 },
                 Type::Integer(_) |
+                Type::BigInteger(_) |
                 Type::Decimal(_) |
                 Type::Float(_) |
                 Type::Double(_) => t,
@@ -732,7 +743,7 @@ pub(crate) fn xs_numeric_eval(env: Box<Environment>, mut arguments: Vec<Object>,
             };
             Ok((env, Object::Atomic(t)))
         }
-        _ => todo!("{:?}", arguments)
+        _ => Err((ErrorCode::XPTY0004, String::from("xs:numeric expects single atomic value")))
     }
 }
 
@@ -66,6 +66,9 @@ pub const XPATH_FUNCTIONS: NS = NS::new("fn", "http://www.w3.org/2005/xpath-func
 pub const XPATH_MAP: NS = NS::new("map", "http://www.w3.org/2005/xpath-functions/map");
 pub const XPATH_ARRAY: NS = NS::new("array", "http://www.w3.org/2005/xpath-functions/array");
 pub const XPATH_MATH: NS = NS::new("math", "http://www.w3.org/2005/xpath-functions/math");
+// arbitrary-precision variants of math functions for xs:decimal
+pub const DECIMAL_MATH: NS = NS::new("dmath", "urn:rustle:decimal-math");
+pub const EXPATH_BINARY: NS = NS::new("bin", "http://expath.org/ns/binary");
 pub const XQUERY_LOCAL: NS = NS::new("local", "http://www.w3.org/2005/xquery-local-functions");
 pub const XQT_ERROR: NS = NS::new("err", "http://www.w3.org/2005/xqt-errors");
 // http://www.w3.org/2012/xquery
@@ -82,6 +85,8 @@ lazy_static! {
             &XPATH_MAP,
             &XPATH_ARRAY,
             &XPATH_MATH,
+            &DECIMAL_MATH,
+            &EXPATH_BINARY,
             &XQUERY_LOCAL,
             &XQT_ERROR
         ] {
@@ -114,6 +119,8 @@ impl Namespaces {
         instance.add(&XPATH_MAP);
         instance.add(&XPATH_ARRAY);
         instance.add(&XPATH_MATH);
+        instance.add(&DECIMAL_MATH);
+        instance.add(&EXPATH_BINARY);
         instance.add(&XQUERY_LOCAL);
 
         instance
@@ -106,6 +106,34 @@ pub enum ErrorCode {
     XQST0134,
     XQDY0137,
 
+    XUST0001,
+    XUST0002,
+    XUTY0004,
+    XUTY0005,
+    XUTY0006,
+    XUTY0007,
+    XUTY0008,
+    XUDY0009,
+    XUTY0010,
+    XUTY0011,
+    XUTY0012,
+    XUTY0013,
+    XUDY0014,
+    XUDY0015,
+    XUDY0016,
+    XUDY0017,
+    XUDY0021,
+    XUTY0022,
+    XUDY0023,
+    XUDY0024,
+    XUDY0027,
+    XUST0028,
+    XUDY0029,
+    XUDY0030,
+    XUDY0031,
+    XUST0032,
+    XUST0033,
+
     FOAP0001,
     FOAR0001,
     FOAR0002,
@@ -165,6 +193,8 @@ pub enum ErrorCode {
     FOTY0013,
     FOTY0014,
     FOTY0015,
+    FOUP0001,
+    FOUP0002,
     FOUT1170,
     FOUT1190,
     FOUT1200,
@@ -172,7 +202,25 @@ pub enum ErrorCode {
     FOXT0002,
     FOXT0003,
     FOXT0004,
-    FOXT0006
+    FOXT0006,
+
+    // EXPath Binary Module
+    #[strum(serialize = "bin:differing-length-arguments")]
+    BinDifferingLengthArguments,
+    #[strum(serialize = "bin:index-out-of-range")]
+    BinIndexOutOfRange,
+    #[strum(serialize = "bin:negative-size")]
+    BinNegativeSize,
+    #[strum(serialize = "bin:octet-out-of-range")]
+    BinOctetOutOfRange,
+    #[strum(serialize = "bin:non-numeric-character")]
+    BinNonNumericCharacter,
+    #[strum(serialize = "bin:unknown-encoding")]
+    BinUnknownEncoding,
+    #[strum(serialize = "bin:conversion-error")]
+    BinConversionError,
+    #[strum(serialize = "bin:unknown-significance-order")]
+    BinUnknownSignificanceOrder,
 }
 
 impl ErrorCode {
@@ -63,6 +63,27 @@ pub enum OperatorComparison {
 }
 
 impl OperatorComparison {
+    // operator for swapped operands, `a < b` is `b > a`
+    pub(crate) fn swap(&self) -> OperatorComparison {
+        match self {
+            OperatorComparison::GeneralEquals => OperatorComparison::GeneralEquals,
+            OperatorComparison::GeneralNotEquals => OperatorComparison::GeneralNotEquals,
+            OperatorComparison::GeneralLessThan => OperatorComparison::GeneralGreaterThan,
+            OperatorComparison::GeneralLessOrEquals => OperatorComparison::GeneralGreaterOrEquals,
+            OperatorComparison::GeneralGreaterThan => OperatorComparison::GeneralLessThan,
+            OperatorComparison::GeneralGreaterOrEquals => OperatorComparison::GeneralLessOrEquals,
+            OperatorComparison::ValueEquals => OperatorComparison::ValueEquals,
+            OperatorComparison::ValueNotEquals => OperatorComparison::ValueNotEquals,
+            OperatorComparison::ValueLessThan => OperatorComparison::ValueGreaterThan,
+            OperatorComparison::ValueLessOrEquals => OperatorComparison::ValueGreaterOrEquals,
+            OperatorComparison::ValueGreaterThan => OperatorComparison::ValueLessThan,
+            OperatorComparison::ValueGreaterOrEquals => OperatorComparison::ValueLessOrEquals,
+            OperatorComparison::NodeIs => OperatorComparison::NodeIs,
+            OperatorComparison::NodePrecedes => OperatorComparison::NodeFollows,
+            OperatorComparison::NodeFollows => OperatorComparison::NodePrecedes
+        }
+    }
+
     pub(crate) fn to_comparison(&self) -> Comparison {
         match self {
             OperatorComparison::GeneralEquals => Comparison::Equals,
@@ -154,24 +154,24 @@ pub fn parse_g_day(input: &str) -> IResult<&str, Type> {
 
 pub fn string_to_month(input: &str) -> Result<Type, String> {
     match all_consuming(parse_g_month)(input.trim()) {
-        Ok((_, dt)) => Ok(dt),
+        Ok((_, result)) => result,
         Err(e) => Err(format!("can't convert to GMonth: {:?}", input))
     }
 }
 
-pub fn parse_g_month_complete(input: &str) -> IResult<&str, Type> {
+pub fn parse_g_month_complete(input: &str) -> IResult<&str, Result<Type, String>> {
     all_consuming(parse_g_month)(input.trim())
 }
 
-pub fn parse_g_month(input: &str) -> IResult<&str, Type> {
+pub fn parse_g_month(input: &str) -> IResult<&str, Result<Type, String>> {
     map(
         tuple((
             tag("--"),
-            take_digits, // parse_day,
+            take_digits, // parse_month,
             opt(alt((timezone_hour, timezone_utc))),
         )),
-        |(sign, month, tz_m)| {
-            Type::GMonth { month, tz_m }
+        |(_, month, tz_m)| {
+            new_g_month(month, tz_m)
         }
     )(input)
 }
@@ -569,9 +569,8 @@ fn parse_second(input: &str) -> IResult<&str, u32> {
 
 fn parse_second_and_ms(input: &str) -> IResult<&str, (u32, Option<u32>)> {
     tuple((
-        // take_digits,
         parse_second,
-        opt(preceded(tag("."), take_digits))
+        opt(preceded(tag("."), duration_ms))
     ))(input)
 }
 
@@ -588,7 +587,7 @@ fn duration_minute(input: &str) -> IResult<&str, u32> {
 }
 
 fn duration_second_and_ms(input: &str) -> IResult<&str, (u32, u32)> {
-    let (input, sec) = digit_in_range(input, (1, 2), 0..=60)?;
+    let (input, sec) = take_digits(input)?;
 
     let (input, ms) = opt(
         preceded(
@@ -603,8 +602,12 @@ fn duration_second_and_ms(input: &str) -> IResult<&str, (u32, u32)> {
 fn duration_ms(input: &str) -> IResult<&str, u32> {
     let (input, digits) = take_while(is_digits)(input)?;
 
-    let num: f32 = format!("0.{}", digits).parse().unwrap();
-    let num = (num * 1000.0) as u32;
+    if digits.is_empty() {
+        return Err(nom::Err::Error(Error::new(input, nom::error::ErrorKind::Digit)));
+    }
+
+    // fraction of second truncated to milliseconds
+    let num = format!("{:0<3}", &digits[..digits.len().min(3)]).parse().unwrap();
 
     Ok((input, num))
 }
@@ -679,6 +682,18 @@ pub(crate) fn new_g_year_month(year: i32, month: u32, tz_m: Option<i32>) -> Resu
     }
 }
 
+pub(crate) fn new_g_month(month: u32, tz_m: Option<i32>) -> Result<Type, String> {
+    if !(1..=12).contains(&month) {
+        return Err(format!("month {} is out of range", month))
+    }
+
+    if check_tz(tz_m) {
+        return Err(String::from("TODO"))
+    }
+
+    Ok(Type::GMonth { month, tz_m })
+}
+
 pub(crate) fn new_g_month_day(month: u32, day: u32, tz_m: Option<i32>) -> Result<Type, String> {
     match month {
         1 | 3 | 5 | 7 | 8 | 10 | 12 => {
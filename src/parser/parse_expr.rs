@@ -21,6 +21,7 @@ use crate::parser::op::{found_expr, Statement, OperatorComparison, OperatorArith
 use crate::eval::expression::{Expression, NodeTest};
 use crate::eval::sequence_type::*;
 use crate::eval::navigation::NodeParent;
+use crate::tree::InsertPosition;
 use crate::parser::errors::ErrorCode::*;
 
 // [2]    	VersionDecl 	   ::=    	"xquery" (("encoding" StringLiteral) | ("version" StringLiteral ("encoding" StringLiteral)?)) Separator
@@ -85,13 +86,14 @@ pub(crate) fn parse_prolog(input: &str) -> IResult<&str, Vec<Box<dyn Expression>
             )),
             tag(";")
         )(current_input);
-        if check.is_ok() {
-            let (input, expr) = check?;
-            current_input = input;
+        match check {
+            Ok((input, expr)) => {
+                current_input = input;
 
-            prolog.push(expr);
-        } else {
-            break
+                prolog.push(expr);
+            }
+            Err(nom::Err::Failure(code)) => return Err(nom::Err::Failure(code)),
+            Err(_) => break
         }
     }
 
@@ -100,13 +102,14 @@ pub(crate) fn parse_prolog(input: &str) -> IResult<&str, Vec<Box<dyn Expression>
             alt((parse_annotated_decl, parse_option_decl)),
             tuple((ws, tag(";")))
         )(current_input);
-        if check.is_ok() {
-            let (input, expr) = check?;
-            current_input = input;
+        match check {
+            Ok((input, expr)) => {
+                current_input = input;
 
-            prolog.push(expr);
-        } else {
-            break
+                prolog.push(expr);
+            }
+            Err(nom::Err::Failure(code)) => return Err(nom::Err::Failure(code)),
+            Err(_) => break
         }
     }
 
@@ -251,27 +254,23 @@ pub(crate) fn parse_copy_namespaces_decl(input: &str) -> IResult<&str, Box<dyn E
 // [18]    	DecimalFormatDecl 	   ::=    	"declare" (("decimal-format" EQName) | ("default" "decimal-format")) (DFPropertyName "=" StringLiteral)*
 // [19]    	DFPropertyName 	   ::=    	"decimal-separator" | "grouping-separator" | "infinity" | "minus-sign" | "NaN" | "percent" | "per-mille" | "zero-digit" | "digit" | "pattern-separator" | "exponent-separator"
 pub(crate) fn parse_decimal_format_decl(input: &str) -> IResult<&str, Box<dyn Expression>, CustomError<&str>> {
-    map(
-        tuple((
-            preceded(
-                tuple((ws, tag("declare"), ws1, )),
-                alt((
-                    map(preceded(tag("decimal-format"), parse_eqname), |n| Some(n)),
-                    map(preceded(tag("default"), tag("decimal-format")), |_| None)
-                ))
-            ),
-            many0(parse_df_property)
-        )),
-        |(name, properties)| {
-            let mut map = HashMap::with_capacity(properties.len());
-            for (name, value) in properties {
-                if map.insert(name, value) != None {
-                    todo!()
-                }
-            }
-            DeclareDecimalFormat::boxed(name, map)
+    let (input, name) = preceded(
+        tuple((ws, tag("declare"), ws1, )),
+        alt((
+            map(preceded(tuple((tag("decimal-format"), ws1)), parse_eqname), |n| Some(n)),
+            map(tuple((tag("default"), ws1, tag("decimal-format"))), |_| None)
+        ))
+    )(input)?;
+
+    let (input, properties) = many0(parse_df_property)(input)?;
+
+    let mut map = HashMap::with_capacity(properties.len());
+    for (name, value) in properties {
+        if map.insert(name, value).is_some() {
+            return Err(CustomError::failed(input, ErrorCode::XQST0114));
         }
-    )(input)
+    }
+    Ok((input, DeclareDecimalFormat::boxed(name, map)))
 }
 
 pub(crate) fn parse_df_property(input: &str) -> IResult<&str, (DecimalFormatPropertyName, String), CustomError<&str>> {
@@ -354,9 +353,8 @@ pub(crate) fn parse_annotated_decl(input: &str) -> IResult<&str, Box<dyn Express
     let mut current_input = input;
 
     let mut annotations = vec![];
-    let mut check;
     loop {
-        check = parse_annotation(current_input);
+        let check = parse_annotation(current_input);
         if check.is_ok() {
             let (input, annotation) = check?;
             current_input = input;
@@ -367,18 +365,24 @@ pub(crate) fn parse_annotated_decl(input: &str) -> IResult<&str, Box<dyn Express
         }
     }
 
-    check = parse_var_decl(current_input);
+    let updating = annotations.iter()
+        .any(|annotation| annotation.name.prefix.is_none() && annotation.name.local_part == "updating");
+
+    let check = parse_var_decl(current_input);
     let (input, decl) = if check.is_ok() {
+        if updating {
+            return Err(CustomError::failed(current_input, ErrorCode::XUST0032));
+        }
         check?
     } else {
-        parse_function_decl(current_input)?
+        parse_function_decl(current_input, updating)?
     };
 
     found_expr(input, Box::new(AnnotatedDecl { annotations, decl } ))
 }
 
 // [27]    	Annotation 	   ::=    	"%" EQName ("(" Literal ("," Literal)* ")")?
-pub(crate) fn parse_annotation(input: &str) -> IResult<&str, Box<dyn Expression>, CustomError<&str>> {
+pub(crate) fn parse_annotation(input: &str) -> IResult<&str, Annotation, CustomError<&str>> {
 
     let (input, _) = ws_tag("%", input)?;
 
@@ -389,7 +393,7 @@ pub(crate) fn parse_annotation(input: &str) -> IResult<&str, Box<dyn Expression>
         let (input, list) = check?;
         todo!()
     } else {
-        found_expr(input, Box::new(Annotation { name, value: None } ))
+        Ok((input, Annotation { name, value: None }))
     }
 }
 
@@ -504,7 +508,7 @@ fn parse_param(input: &str) -> IResult<&str, Param, CustomError<&str>> {
 
 // [32]    	FunctionDecl 	   ::=    	"function" EQName "(" ParamList? ")" ("as" SequenceType)? (FunctionBody | "external")
 // [35]    	FunctionBody 	   ::=    	EnclosedExpr
-fn parse_function_decl(input: &str) -> IResult<&str, Box<dyn Expression>, CustomError<&str>> {
+fn parse_function_decl(input: &str, updating: bool) -> IResult<&str, Box<dyn Expression>, CustomError<&str>> {
     let (input, _) = ws1_tag_ws1("function", input)?;
 
     let (input, name) = parse_function_name(input)?;
@@ -534,6 +538,11 @@ fn parse_function_decl(input: &str) -> IResult<&str, Box<dyn Expression>, Custom
         None
     };
 
+    // updating function can't declare return type
+    if updating && type_declaration.is_some() {
+        return Err(CustomError::failed(current_input, ErrorCode::XUST0028));
+    }
+
     let check = ws1_tag_ws1("external", current_input);
     let (input, external, body) = if check.is_ok() {
         let (input, _) = check?;
@@ -544,7 +553,7 @@ fn parse_function_decl(input: &str) -> IResult<&str, Box<dyn Expression>, Custom
         (input, false, Some(body))
     };
 
-    found_expr(input, Box::new(FunctionDecl { name, params, external, st: type_declaration, body } ))
+    found_expr(input, Box::new(FunctionDecl { name, params, external, updating, st: type_declaration, body } ))
 }
 
 // [36]    	EnclosedExpr 	   ::=    	"{" Expr? "}"
@@ -600,6 +609,11 @@ pub(crate) fn parse_expr(input: &str) -> IResult<&str, Box<dyn Expression>, Cust
 //  | TypeswitchExpr
 //  | IfExpr
 //  TODO: | TryCatchExpr
+//  | InsertExpr
+//  | DeleteExpr
+//  | RenameExpr
+//  | ReplaceExpr
+//  | CopyModifyExpr
 //  | OrExpr
 parse_one_of!(parse_expr_single,
     parse_flwor_expr,
@@ -607,6 +621,11 @@ parse_one_of!(parse_expr_single,
     parse_switch_expr,
     parse_typeswitch_expr,
     parse_if_expr,
+    parse_insert_expr,
+    parse_delete_expr,
+    parse_rename_expr,
+    parse_replace_expr,
+    parse_copy_modify_expr,
     parse_or_expr,
 );
 // fn parse_expr_single(input: &str) -> IResult<&str, Box<dyn Expression>, CustomError<&str>> {
@@ -659,11 +678,12 @@ fn parse_initial_clause(input: &str) -> IResult<&str, Clause, CustomError<&str>>
     alt((parse_for_clause, parse_let_clause))(input)
 }
 
-// [43]    	IntermediateClause 	   ::=    	InitialClause | TODO WhereClause | GroupByClause | OrderByClause | CountClause
+// [43]    	IntermediateClause 	   ::=    	InitialClause | WhereClause | TODO GroupByClause | OrderByClause | TODO CountClause
 fn parse_intermediate_clause(input: &str) -> IResult<&str, Clause, CustomError<&str>> {
     alt((
         parse_initial_clause,
-        parse_where_clause
+        parse_where_clause,
+        parse_order_by_clause
     ))(input)
 }
 
@@ -781,6 +801,54 @@ fn parse_where_clause(input: &str) -> IResult<&str, Clause, CustomError<&str>> {
     )(input)
 }
 
+// [64]    	OrderByClause 	   ::=    	(("order" "by") | ("stable" "order" "by")) OrderSpecList
+// [65]    	OrderSpecList 	   ::=    	OrderSpec ("," OrderSpec)*
+fn parse_order_by_clause(input: &str) -> IResult<&str, Clause, CustomError<&str>> {
+    // sorting is always stable
+    let (input, _) = opt(tuple((ws1, tag("stable"))))(input)?;
+    let (input, _) = tuple((ws1, tag("order"), ws1, tag("by")))(input)?;
+
+    let mut current_input = input;
+
+    let mut specs = vec![];
+    loop {
+        let (input, spec) = parse_order_spec(current_input)?;
+
+        specs.push(spec);
+
+        let tmp = ws_tag(",", input);
+        if tmp.is_err() {
+            return Ok((input, Clause::OrderBy(specs)))
+        }
+        current_input = tmp?.0;
+    }
+}
+
+// [66]    	OrderSpec 	   ::=    	ExprSingle OrderModifier
+// [67]    	OrderModifier 	   ::=    	("ascending" | "descending")? ("empty" ("greatest" | "least"))? ("collation" URILiteral)?
+fn parse_order_spec(input: &str) -> IResult<&str, OrderSpec, CustomError<&str>> {
+    let (input, expr) = parse_expr_single(input)?;
+
+    let (input, direction) = opt(preceded(ws1, alt((tag("ascending"), tag("descending")))))(input)?;
+
+    let (input, empty) = opt(preceded(
+        tuple((ws1, tag("empty"), ws1)),
+        alt((tag("greatest"), tag("least")))
+    ))(input)?;
+
+    let (input, collation) = opt(preceded(
+        tuple((ws1, tag("collation"))),
+        parse_uri_literal_as_string
+    ))(input)?;
+
+    Ok((input, OrderSpec {
+        expr,
+        descending: direction == Some("descending"),
+        empty_greatest: empty.map(|empty| empty == "greatest"),
+        collation
+    }))
+}
+
 // [70]    	QuantifiedExpr 	   ::=    	("some" | "every")
 // "$" VarName TypeDeclaration?
 // "in" ExprSingle ("," "$" VarName TypeDeclaration? "in" ExprSingle)*
@@ -914,6 +982,87 @@ fn parse_if_expr(input: &str) -> IResult<&str, Box<dyn Expression>, CustomError<
     found_expr(input, Box::new(If { condition, consequence, alternative }))
 }
 
+// [XQUF]    	InsertExpr 	   ::=    	"insert" ("node" | "nodes") SourceExpr InsertExprTargetChoice TargetExpr
+// [XQUF]    	InsertExprTargetChoice 	   ::=    	(("as" ("first" | "last"))? "into") | "after" | "before"
+// [XQUF]    	SourceExpr 	   ::=    	ExprSingle
+// [XQUF]    	TargetExpr 	   ::=    	ExprSingle
+fn parse_insert_expr(input: &str) -> IResult<&str, Box<dyn Expression>, CustomError<&str>> {
+    map(
+        tuple((
+            preceded(
+                tuple((ws, tag("insert"), ws1, alt((tag("nodes"), tag("node"))), ws1)),
+                parse_expr_single
+            ),
+            delimited(
+                ws1,
+                alt((
+                    map(tuple((tag("as"), ws1, tag("first"), ws1, tag("into"))), |_| InsertPosition::AsFirstInto),
+                    map(tuple((tag("as"), ws1, tag("last"), ws1, tag("into"))), |_| InsertPosition::AsLastInto),
+                    map(tag("into"), |_| InsertPosition::Into),
+                    map(tag("after"), |_| InsertPosition::After),
+                    map(tag("before"), |_| InsertPosition::Before),
+                )),
+                ws1
+            ),
+            parse_expr_single
+        )),
+        |(source, position, target)| InsertExpr::boxed(source, position, target)
+    )(input)
+}
+
+// [XQUF]    	DeleteExpr 	   ::=    	"delete" ("node" | "nodes") TargetExpr
+fn parse_delete_expr(input: &str) -> IResult<&str, Box<dyn Expression>, CustomError<&str>> {
+    map(
+        preceded(
+            tuple((ws, tag("delete"), ws1, alt((tag("nodes"), tag("node"))), ws1)),
+            parse_expr_single
+        ),
+        |target| DeleteExpr::boxed(target)
+    )(input)
+}
+
+// [XQUF]    	ReplaceExpr 	   ::=    	"replace" ("value" "of")? "node" TargetExpr "with" ExprSingle
+fn parse_replace_expr(input: &str) -> IResult<&str, Box<dyn Expression>, CustomError<&str>> {
+    map(
+        tuple((
+            preceded(tuple((ws, tag("replace"), ws1)), opt(tuple((tag("value"), ws1, tag("of"), ws1)))),
+            preceded(tuple((tag("node"), ws1)), parse_expr_single),
+            preceded(tuple((ws1, tag("with"), ws1)), parse_expr_single)
+        )),
+        |(value_of, target, with)| ReplaceExpr::boxed(value_of.is_some(), target, with)
+    )(input)
+}
+
+// [XQUF]    	RenameExpr 	   ::=    	"rename" "node" TargetExpr "as" NewNameExpr
+// [XQUF]    	NewNameExpr 	   ::=    	ExprSingle
+fn parse_rename_expr(input: &str) -> IResult<&str, Box<dyn Expression>, CustomError<&str>> {
+    map(
+        tuple((
+            preceded(tuple((ws, tag("rename"), ws1, tag("node"), ws1)), parse_expr_single),
+            preceded(tuple((ws1, tag("as"), ws1)), parse_expr_single)
+        )),
+        |(target, name)| RenameExpr::boxed(target, name)
+    )(input)
+}
+
+// [XQUF]    	CopyModifyExpr 	   ::=    	"copy" "$" VarName ":=" ExprSingle ("," "$" VarName ":=" ExprSingle)* "modify" ExprSingle "return" ExprSingle
+fn parse_copy_modify_expr(input: &str) -> IResult<&str, Box<dyn Expression>, CustomError<&str>> {
+    map(
+        tuple((
+            preceded(
+                tuple((ws, tag("copy"), ws1, tag("$"))),
+                separated_list1(
+                    tuple((ws, tag(","), ws, tag("$"))),
+                    separated_pair(parse_var_name, tuple((ws, tag(":="), ws)), parse_expr_single)
+                )
+            ),
+            preceded(tuple((ws1, tag("modify"), ws1)), parse_expr_single),
+            preceded(tuple((ws1, tag("return"), ws1)), parse_expr_single)
+        )),
+        |(bindings, modify, return_expr)| CopyModifyExpr::boxed(bindings, modify, return_expr)
+    )(input)
+}
+
 // [83]    	OrExpr 	   ::=    	AndExpr ( "or" AndExpr )*
 fn parse_or_expr(input: &str) -> IResult<&str, Box<dyn Expression>, CustomError<&str>> {
     let (input, mut exprs) = separated_list1(
@@ -1639,7 +1788,7 @@ fn parse_name_test(input: &str) -> IResult<&str, Box<dyn NodeTest>, CustomError<
     Ok((input, NameTest::boxed(qname)))
 }
 
-// [121]    	PostfixExpr 	   ::=    	PrimaryExpr (Predicate | ArgumentList | TODO: Lookup)*
+// [121]    	PostfixExpr 	   ::=    	PrimaryExpr (Predicate | ArgumentList | Lookup)*
 fn parse_postfix_expr(input: &str) -> IResult<&str, Box<dyn Expression>, CustomError<&str>> {
     let (input, _) = ws(input)?;
     let (input, primary) = parse_primary_expr(input)?;
@@ -1731,17 +1880,43 @@ fn parse_predicate(input: &str) -> IResult<&str, PrimaryExprSuffix, CustomError<
 }
 
 // [125]    	Lookup 	   ::=    	"?" KeySpecifier
-// [126]    	KeySpecifier 	   ::=    	NCName | IntegerLiteral | ParenthesizedExpr | "*"
 fn parse_lookup(input: &str) -> IResult<&str, PrimaryExprSuffix, CustomError<&str>> {
     let input = ws_tag("?", input)?.0;
 
-    let (input, lookup) = alt((
-        parse_ncname_expr,
+    let (input, lookup) = parse_key_specifier(input)?;
+
+    Ok((input, PrimaryExprSuffix { predicate: None, argument_list: None, lookup: Some(lookup) }))
+}
+
+// [126]    	KeySpecifier 	   ::=    	NCName | IntegerLiteral | ParenthesizedExpr | "*"
+fn parse_key_specifier(input: &str) -> IResult<&str, KeySpecifier, CustomError<&str>> {
+    let check = parse_ncname(input);
+    if check.is_ok() {
+        let (input, name) = check?;
+        return Ok((input, KeySpecifier::Key(StringExpr::new(name))));
+    }
+
+    let check = tag("*")(input);
+    if check.is_ok() {
+        let (input, _) = check?;
+        return Ok((input, KeySpecifier::Wildcard));
+    }
+
+    let (input, key) = alt((
         parse_integer_literal,
         parse_parenthesized_expr
     ))(input)?;
 
-    Ok((input, PrimaryExprSuffix { predicate: None, argument_list: None, lookup: Some(lookup) }))
+    Ok((input, KeySpecifier::Key(key)))
+}
+
+// [127]    	UnaryLookup 	   ::=    	"?" KeySpecifier
+fn parse_unary_lookup(input: &str) -> IResult<&str, Box<dyn Expression>, CustomError<&str>> {
+    let input = ws_tag("?", input)?.0;
+
+    let (input, key) = parse_key_specifier(input)?;
+
+    found_expr(input, UnaryLookup::boxed(key))
 }
 
 // [128]    	PrimaryExpr 	   ::=    	Literal
@@ -1756,7 +1931,7 @@ fn parse_lookup(input: &str) -> IResult<&str, PrimaryExprSuffix, CustomError<&st
 //  | MapConstructor
 //  | ArrayConstructor
 //  | StringConstructor
-//  TODO: | UnaryLookup
+//  | UnaryLookup
 parse_one_of!(parse_primary_expr,
     parse_literal,
     parse_var_ref,
@@ -1768,6 +1943,7 @@ parse_one_of!(parse_primary_expr,
     parse_map_constructor,
     parse_array_constructor,
     parse_string_constructor,
+    parse_unary_lookup,
 );
 
 // [131]    	VarRef 	   ::=    	"$" VarName
@@ -1867,7 +2043,8 @@ fn parse_function_name(input: &str) -> IResult<&str, QName, CustomError<&str>> {
 // [138]    	Argument 	   ::=    	ExprSingle | ArgumentPlaceholder
 // [139]    	ArgumentPlaceholder 	   ::=    	"?"
 fn parse_argument(input: &str) -> IResult<&str, Box<dyn Expression>, CustomError<&str>> {
-    let check = tag("?")(input);
+    // "?" followed by key specifier is unary lookup
+    let check: IResult<&str, &str, CustomError<&str>> = preceded(ws, terminated(tag("?"), peek(tuple((ws, alt((tag(","), tag(")"))))))))(input);
     if check.is_ok() {
         let (input, _) = check?;
         Ok((input, ArgumentPlaceholder::boxed()))
@@ -1918,7 +2095,7 @@ fn parse_inline_function_expr(input: &str) -> IResult<&str, Box<dyn Expression>,
 
     let (input, body) = parse_enclosed_expr(input)?;
 
-    found_expr(input, Box::new(Function { arguments, st, body }))
+    found_expr(input, Box::new(Function { arguments, st, body, captures: None }))
 }
 
 // [170]    	MapConstructor 	   ::=    	"map" "{" (MapConstructorEntry ("," MapConstructorEntry)*)? "}"
@@ -2233,10 +2410,10 @@ fn parse_pi_test(input: &str) -> IResult<&str, Box<dyn NodeTest>, CustomError<&s
     map(
         delimited(
             tuple((ws, tag("processing-instruction"), ws, tag("("), ws)),
-            opt(alt((parse_ncname_expr, parse_string_literal))),
+            opt(alt((parse_ncname, parse_string_literal_as_string))),
             tuple((ws, tag(")")))
         ),
-        |name| PITest::boxed(name)
+        |target| PITest::boxed(target.map(|target| target.trim().to_string()))
     )(input)
 }
 
@@ -2348,21 +2525,25 @@ fn parse_function_test(input: &str) -> IResult<&str, ItemType, CustomError<&str>
                 tuple((ws, tag("function"), ws, tag("("))),
                 alt((
                     map(tag("*"), |_| None),
-                    opt(
-                        map(
-                            tuple((
-                                parse_sequence_type,
-                                many0(preceded(tag(","), parse_sequence_type))
-                            )),
-                            |(fst, rest)| {
-                                let mut args = Vec::with_capacity(1 + rest.len());
-                                args.push(fst);
-                                for arg in rest {
-                                    args.push(arg);
+                    // "function()" is function without arguments, not any function
+                    map(
+                        opt(
+                            map(
+                                tuple((
+                                    parse_sequence_type,
+                                    many0(preceded(tag(","), parse_sequence_type))
+                                )),
+                                |(fst, rest)| {
+                                    let mut args = Vec::with_capacity(1 + rest.len());
+                                    args.push(fst);
+                                    for arg in rest {
+                                        args.push(arg);
+                                    }
+                                    args
                                 }
-                                args
-                            }
-                        )
+                            )
+                        ),
+                        |args| Some(args.unwrap_or_default())
                     ),
                 )),
                 tuple((ws, tag(")")))
@@ -14,6 +14,7 @@ use crate::parser::parse_xml::{parse_refs, parse_refs_as_char};
 use crate::parser::helper::ws;
 use ordered_float::OrderedFloat;
 use bigdecimal::BigDecimal;
+use bigdecimal::num_bigint::BigInt;
 use nom::combinator::map;
 use nom::sequence::{preceded, terminated, tuple};
 use crate::eval::expression::Expression;
@@ -88,12 +89,7 @@ pub(crate) fn parse_numeric_literal(input: &str) -> IResult<&str, Box<dyn Expres
         if a == "0" {
             let number = format!("{}", b);
 
-            match number.parse::<i128>() {
-                Ok(number) => {
-                    found_expr(input, Box::new(Integer { number }))
-                },
-                Err(_) => Err(CustomError::failed(input, FOAR0002))
-            }
+            integer_literal(input, &number)
         } else {
             let number = format!("{}.{}", b, a);
 
@@ -113,7 +109,18 @@ pub(crate) fn parse_numeric_literal(input: &str) -> IResult<&str, Box<dyn Expres
 pub(crate) fn parse_integer_literal(input: &str) -> IResult<&str, Box<dyn Expression>, CustomError<&str>> {
     let (input, number) = take_while1(is_digits)(input)?;
 
-    found_expr(input, Box::new(Integer { number: number.parse::<i128>().unwrap() }))
+    integer_literal(input, number)
+}
+
+fn integer_literal<'a>(input: &'a str, number: &str) -> IResult<&'a str, Box<dyn Expression>, CustomError<&'a str>> {
+    if let Ok(number) = number.parse::<i128>() {
+        found_expr(input, Box::new(Integer { number }))
+    } else {
+        match number.parse::<BigInt>() {
+            Ok(number) => found_expr(input, Box::new(BigInteger { number })),
+            Err(_) => Err(CustomError::failed(input, FOAR0002))
+        }
+    }
 }
 
 // [222]    	StringLiteral 	   ::=    	('"' (PredefinedEntityRef | CharRef | EscapeQuot | [^"&])* '"') | ("'" (PredefinedEntityRef | CharRef | EscapeApos | [^'&])* "'")
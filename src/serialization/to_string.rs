@@ -1,4 +1,5 @@
-use bigdecimal::{BigDecimal, Zero};
+use bigdecimal::BigDecimal;
+use bigdecimal::num_traits::{Float, FromPrimitive};
 use chrono::{Date, DateTime, FixedOffset, SecondsFormat, Timelike};
 use ordered_float::OrderedFloat;
 use crate::eval::{Object, Type, RangeIterator, Environment};
@@ -6,6 +7,7 @@ use crate::parser::op::Representation;
 use crate::values::{binary_base64_to_string, binary_hex_to_string};
 use crate::values::time::Time;
 use std::num;
+use std::fmt::{Display, UpperExp};
 use crate::values::string_to::decimal;
 
 pub fn object_to_string_xml(env: &Box<Environment>, object: &Object) -> String {
@@ -90,9 +92,10 @@ pub fn _object_to_string(env: &Box<Environment>, object: &Object, ref_resolving:
                 Type::NonPositiveInteger(number) |
                 Type::NegativeInteger(number) |
                 Type::Integer(number) => number.to_string(),
+                Type::BigInteger(number) => number.to_string(),
                 Type::Decimal(number) => decimal_to_string(number),
-                Type::Float(number) => float_to_string(number, true),
-                Type::Double(number) => double_to_string(number, true),
+                Type::Float(number) => float_to_string(number),
+                Type::Double(number) => double_to_string(number),
 
                 Type::DateTimeStamp() => todo!(),
                 Type::DateTime { dt, offset } => {
@@ -144,7 +147,12 @@ pub fn _object_to_string(env: &Box<Environment>, object: &Object, ref_resolving:
                 Type::NOTATION() => todo!()
             }
         }
-        Object::Array(items) |
+        Object::Array(items) => {
+            items.iter()
+                .map(|item| _object_to_string(env, item, ref_resolving, " "))
+                .collect::<Vec<_>>()
+                .join(sep)
+        },
         Object::Sequence(items) => {
             let mut buf = Vec::with_capacity(items.len());
             for item in items {
@@ -155,7 +163,7 @@ pub fn _object_to_string(env: &Box<Environment>, object: &Object, ref_resolving:
             data
         },
         Object::Node(rf) => {
-            match rf.to_typed_value() {
+            match rf.to_string_value() {
                 Ok(data) => data,
                 Err(msg) => panic!("{}", msg)
             }
@@ -164,104 +172,46 @@ pub fn _object_to_string(env: &Box<Environment>, object: &Object, ref_resolving:
     }
 }
 
+// canonical form: no trailing zeros of fraction and no decimal point for integral values
 pub(crate) fn decimal_to_string(number: &BigDecimal) -> String {
-    let str = number.to_string();
-    if str.ends_with(".0") {
-        str[..str.len()-2].to_string()
+    let str = number.normalized().to_string();
+    if str.contains('.') {
+        str.trim_end_matches('0').trim_end_matches('.').to_string()
     } else {
         str
     }
 }
 
-pub(crate) fn float_to_string(number: &OrderedFloat<f32>, rules: bool) -> String {
-    if number.is_nan() {
-        String::from("NaN")
-    } else if number.is_infinite() {
-        if number.is_sign_positive() {
-            String::from("INF")
-        } else {
-            String::from("-INF")
-        }
-    } else if number.is_zero() {
-        number.to_string()
-    } else if rules {
-        // If SV has an absolute value that is greater than or equal to 0.000001 (one millionth)
-        // and less than 1000000 (one million), then the value is converted to an xs:decimal
-        // and the resulting xs:decimal is converted to an xs:string
-        let abs = number.0.abs();
-        if abs < 1e6 {
-            if abs >= 1e-3 {
-                number.to_string()
-            } else if abs >= 1e-6 {
-                number.to_string()
-            } else {
-                // ???
-                format!("{:+E}", number.0)
-            }
-        } else if abs < 1e7 {
-            //
-            format!("{:+E}", number.0)
-        } else {
-            // science notation
-            // format!("{:.precision$E}", number.0, precision = 1)
-            let mut str = format!("{:E}", number.0);
-            // workarounds
-            str = if str.contains(".") {
-                str
-            } else {
-                str.replace("E", ".0E")
-            };
+pub(crate) fn float_to_string(number: &OrderedFloat<f32>) -> String {
+    floating_to_string(number.0)
+}
 
-            str
-        }
-    } else {
-        number.to_string()
-    }
+pub(crate) fn double_to_string(number: &OrderedFloat<f64>) -> String {
+    floating_to_string(number.0)
 }
 
-pub(crate) fn double_to_string(number: &OrderedFloat<f64>, rules: bool) -> String {
+// canonical form of xs:float and xs:double with shortest digits which round-trip,
+// values from 0.000001 (inclusive) to 1000000 (exclusive) are written as xs:decimal
+// and others in scientific notation like "1.0E7"
+fn floating_to_string<F: Float + FromPrimitive + Display + UpperExp>(number: F) -> String {
     if number.is_nan() {
         String::from("NaN")
     } else if number.is_infinite() {
-        if number.is_sign_positive() {
-            String::from("INF")
-        } else {
-            String::from("-INF")
-        }
+        String::from(if number.is_sign_positive() { "INF" } else { "-INF" })
     } else if number.is_zero() {
-        number.to_string()
-    } else if rules {
-        // If SV has an absolute value that is greater than or equal to 0.000001 (one millionth)
-        // and less than 1000000 (one million), then the value is converted to an xs:decimal
-        // and the resulting xs:decimal is converted to an xs:string
-        let abs = number.0.abs();
-        if abs < 1e6 {
-            if abs >= 1e-3 {
-                number.to_string()
-            } else if abs >= 1e-6 {
-                number.to_string()
-            } else {
-                // ???
-                format!("{:+E}", number.0)
-            }
-        } else if abs < 1e7 {
-            //
-            format!("{:+E}", number.0)
+        String::from(if number.is_sign_negative() { "-0" } else { "0" })
+    } else {
+        let abs = number.abs();
+        if abs >= F::from_f64(1e-6).unwrap() && abs < F::from_f64(1e6).unwrap() {
+            number.to_string()
         } else {
-            // science notation
-            // format!("{:.precision$E}", number.0, precision = 1)
-            let mut str = format!("{:E}", number.0);
-            // workarounds
-            str = if str.contains(".") {
+            let str = format!("{:E}", number);
+            if str.contains('.') {
                 str
             } else {
-                str.replace("E", ".0E")
-            };
-
-            str
+                str.replacen('E', ".0E", 1)
+            }
         }
-    } else {
-        number.to_string()
     }
 }
 
@@ -412,7 +362,8 @@ pub(crate) fn duration_to_string(positive: bool, years: u32, months: u32, days:
             buf.push_str(seconds.to_string().as_str());
             if microseconds != 0 {
                 buf.push_str(".");
-                buf.push_str(microseconds.to_string().as_str());
+                // milliseconds without trailing zeros
+                buf.push_str(format!("{:03}", microseconds).trim_end_matches('0'));
             }
             buf.push_str("S");
         }
@@ -476,7 +427,8 @@ pub(crate) fn day_time_duration_to_string(positive: bool, days: u32, hours: u32,
                 buf.push_str(seconds.to_string().as_str());
                 if microseconds != 0 {
                     buf.push_str(".");
-                    buf.push_str(microseconds.to_string().as_str());
+                    // milliseconds without trailing zeros
+                    buf.push_str(format!("{:03}", microseconds).trim_end_matches('0'));
                 }
                 buf.push_str("S");
             }
@@ -30,6 +30,14 @@ pub(crate) fn eval_on_spec(
 pub(crate) fn eval(
     sources_namespaces: Option<(Vec<(&str, &str)>, Vec<(&str, &str)>)>,
     input: &str
+) -> EvalResult {
+    eval_in(Environment::create(), sources_namespaces, input)
+}
+
+pub(crate) fn eval_in(
+    mut env: Box<Environment>,
+    sources_namespaces: Option<(Vec<(&str, &str)>, Vec<(&str, &str)>)>,
+    input: &str
 ) -> EvalResult {
     // println!("script: {:?}", input);
 
@@ -39,8 +47,6 @@ pub(crate) fn eval(
 
         // println!("{:#?}", program);
 
-        let mut env = Environment::create();
-
         let mut context = DynamicContext::nothing();
 
         if let Some(sources_namespaces) = sources_namespaces {
@@ -85,6 +91,30 @@ pub(crate) fn eval(
     }
 }
 
+// results of queries as strings or error codes
+pub(crate) fn test_eval_queries(queries: &[(&str, Result<&str, ErrorCode>)]) {
+    test_eval_queries_in(Environment::create, queries)
+}
+
+// same with settings of environment
+pub(crate) fn test_eval_queries_with<F: Fn(&mut Environment)>(configure: F, queries: &[(&str, Result<&str, ErrorCode>)]) {
+    test_eval_queries_in(|| {
+        let mut env = Environment::create();
+        configure(&mut env);
+        env
+    }, queries)
+}
+
+pub(crate) fn test_eval_queries_in<F: Fn() -> Box<Environment>>(create: F, queries: &[(&str, Result<&str, ErrorCode>)]) {
+    for (query, expected) in queries {
+        let result = eval_in(create(), None, query)
+            .map(|(_, result)| result.to_string().unwrap())
+            .map_err(|(code, _)| code);
+
+        assert_eq!(result, expected.clone().map(String::from), "{}", query);
+    }
+}
+
 pub(crate) fn check_assert(result: &EvalResult, check: &str) {
     let (_, check_result) = eval_assert(result, check).unwrap();
     match check_result.to_bool() {
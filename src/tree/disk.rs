@@ -0,0 +1,1072 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Mutex;
+use crate::eval::{Axis, ErrorInfo, INS};
+use crate::eval::sequence_type::QNameToTypes;
+use crate::namespaces::SCHEMA;
+use crate::parser::errors::ErrorCode;
+use crate::parser::op::Comparison;
+use crate::tree::{copy_node, shared, InMemoryXMLTree, IndexConfig, IndexKey, InsertPosition, NodeRecord, NodeType, Reference, TypeAnnotation, XMLNode, XMLStorage, XMLTreeReader, XMLTreeWriter, DLN};
+use crate::values::{QName, QNameResolved, Type};
+
+// single file store of nodes keyed by DLN
+//
+// file       := MAGIC block* index footer
+// block      := header_len:u32 header records
+// header     := count:u32 (id start:u32 end:u32)*      ranges of element subtrees in records, in document order
+// record     := kind:u8 id content                      in document order, element content is followed by its children
+// element    := name annotation count:u32 (name value annotation)*
+// annotation := UNTYPED | ATOMIC type | LIST type     type is local name of schema type
+// index      := count:u32 (uri offset:u64 length:u64)*
+// footer     := index_offset:u64 MAGIC
+// id         := number_of_bits:u32 bits
+//
+// saved blocks are kept in memory until flush (and drop), it writes live blocks
+// to temporary file and renames it over the store, so a crash leaves previous state;
+// opened document is loaded lazily, children of element are decoded on first access to them
+const MAGIC: &[u8; 4] = b"RXS3";
+const FOOTER_LEN: u64 = 12;
+
+const DOCUMENT: u8 = 0;
+const ELEMENT: u8 = 1;
+const TEXT: u8 = 2;
+const COMMENT: u8 = 3;
+const PI: u8 = 4;
+
+const UNTYPED: u8 = 0;
+const ATOMIC: u8 = 1;
+const LIST: u8 = 2;
+
+// blocks of documents as (uri, (offset, length))
+type Blocks = Vec<(String, (u64, u64))>;
+
+pub struct DiskStorage {
+    path: PathBuf,
+    file: Mutex<File>,
+    // blocks in file as (offset, length)
+    index: BTreeMap<String, (u64, u64)>,
+    // blocks saved after last flush
+    pending: BTreeMap<String, Vec<u8>>,
+}
+
+impl DiskStorage {
+    // only index is read, documents are loaded on open
+    pub fn open_file<P: AsRef<Path>>(path: P) -> Result<Self, ErrorInfo> {
+        let path = path.as_ref().to_path_buf();
+        if !path.exists() {
+            DiskStorage::write_file(&path, |_| Ok(vec![]))?;
+        }
+
+        let mut file = File::open(&path).map_err(|e| io_error(&path, e))?;
+        let len = file.metadata().map_err(|e| io_error(&path, e))?.len();
+
+        let start = MAGIC.len() as u64;
+        let footer_offset = len.checked_sub(FOOTER_LEN)
+            .filter(|offset| *offset >= start)
+            .ok_or_else(|| corrupted(&path))?;
+
+        let mut magic = vec![0; MAGIC.len()];
+        file.read_exact(&mut magic).map_err(|e| io_error(&path, e))?;
+        if magic != MAGIC {
+            return Err(corrupted(&path));
+        }
+
+        let mut data = vec![0; FOOTER_LEN as usize];
+        file.seek(SeekFrom::Start(footer_offset)).map_err(|e| io_error(&path, e))?;
+        file.read_exact(&mut data).map_err(|e| io_error(&path, e))?;
+        if &data[8..] != MAGIC {
+            return Err(corrupted(&path));
+        }
+        let index_offset = Decoder::new(&data).u64()
+            .filter(|offset| *offset >= start)
+            .ok_or_else(|| corrupted(&path))?;
+        let index_len = footer_offset.checked_sub(index_offset).ok_or_else(|| corrupted(&path))?;
+
+        let mut data = vec![0; index_len as usize];
+        file.seek(SeekFrom::Start(index_offset)).map_err(|e| io_error(&path, e))?;
+        file.read_exact(&mut data).map_err(|e| io_error(&path, e))?;
+
+        let mut index = BTreeMap::new();
+        let mut decoder = Decoder::new(&data);
+        let count = decoder.u32().ok_or_else(|| corrupted(&path))?;
+        for _ in 0..count {
+            let uri = decoder.string().ok_or_else(|| corrupted(&path))?;
+            let offset = decoder.u64().ok_or_else(|| corrupted(&path))?;
+            let length = decoder.u64().ok_or_else(|| corrupted(&path))?;
+            // block must be between magic and index
+            match offset.checked_add(length) {
+                Some(end) if offset >= start && end <= index_offset => {}
+                _ => return Err(corrupted(&path))
+            }
+            index.insert(uri, (offset, length));
+        }
+
+        Ok(DiskStorage { path, file: Mutex::new(file), index, pending: BTreeMap::new() })
+    }
+
+    // whole store is written to temporary file which replaces original one,
+    // blocks of replaced documents aren't copied
+    pub fn flush(&mut self) -> Result<(), ErrorInfo> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        let mut uris: Vec<String> = self.index.keys().cloned().collect();
+        uris.extend(self.pending.keys().filter(|uri| !self.index.contains_key(*uri)).cloned());
+        uris.sort();
+
+        let index = DiskStorage::write_file(&self.path, |file| {
+            let mut index = Vec::with_capacity(uris.len());
+            let mut offset = MAGIC.len() as u64;
+            for uri in &uris {
+                let block = match self.pending.get(uri) {
+                    Some(block) => block.clone(),
+                    None => {
+                        let (offset, length) = self.index[uri];
+                        self.read(offset, length).map_err(|(_, msg)| std::io::Error::other(msg))?
+                    }
+                };
+                file.write_all(&block)?;
+                index.push((uri.clone(), (offset, block.len() as u64)));
+                offset += block.len() as u64;
+            }
+            Ok(index)
+        })?;
+
+        let file = File::open(&self.path).map_err(|e| io_error(&self.path, e))?;
+        self.file = Mutex::new(file);
+        self.index = index.into_iter().collect();
+        self.pending.clear();
+
+        Ok(())
+    }
+
+    // writes MAGIC, blocks by given function, index and footer to temporary file, then renames it to path
+    fn write_file<F>(path: &PathBuf, blocks: F) -> Result<Blocks, ErrorInfo>
+        where F: FnOnce(&mut File) -> std::io::Result<Blocks>
+    {
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let temporary = PathBuf::from(temporary);
+
+        let result = File::create(&temporary).and_then(|mut file| {
+            file.write_all(MAGIC)?;
+            let index = blocks(&mut file)?;
+            let index_offset = MAGIC.len() as u64 + index.iter().map(|(_, (_, length))| length).sum::<u64>();
+
+            let too_large = |(_, msg): ErrorInfo| std::io::Error::other(msg);
+            let mut encoder = Encoder::new();
+            encoder.length(index.len()).map_err(too_large)?;
+            for (uri, (offset, length)) in &index {
+                encoder.string(uri).map_err(too_large)?;
+                encoder.u64(*offset);
+                encoder.u64(*length);
+            }
+            encoder.u64(index_offset);
+            encoder.bytes(MAGIC);
+
+            file.write_all(&encoder.data)?;
+            file.sync_all()?;
+            Ok(index)
+        });
+
+        match result.and_then(|index| std::fs::rename(&temporary, path).map(|_| index)) {
+            Ok(index) => Ok(index),
+            Err(e) => {
+                let _ = std::fs::remove_file(&temporary);
+                Err(io_error(path, e))
+            }
+        }
+    }
+
+    // subtree of element with given id as new tree or None if there is no such element,
+    // ids are relative to root of new tree
+    pub fn open_subtree(&self, uri: &str, id: &DLN, tree_id: usize) -> Result<Option<Rc<Mutex<Box<dyn XMLTreeWriter>>>>, ErrorInfo> {
+        let block = match self.block(uri)? {
+            Some(block) => block,
+            None => return Ok(None)
+        };
+        let (start, end) = match block.starts.get(id) {
+            Some(start) => (*start, block.elements[start].1),
+            None => return Ok(None)
+        };
+        let records = block.records(start, end).ok_or_else(|| corrupted(&self.path))?;
+
+        let root = DLN::level_id(1);
+        let mut tree = InMemoryXMLTree::empty(tree_id);
+        for (stored, record) in records {
+            tree.load_node(stored.rebase(id, &root), record);
+        }
+        Ok(Some(shared(tree.as_writer())))
+    }
+
+    // header and records of document, records are decoded when nodes are loaded
+    fn block(&self, uri: &str) -> Result<Option<Block>, ErrorInfo> {
+        if !self.contains(uri) {
+            return Ok(None);
+        }
+
+        let data = self.read_block(uri, 0, 4)?;
+        let header_len = Decoder::new(&data).u32().ok_or_else(|| corrupted(&self.path))? as u64;
+        let header = self.read_block(uri, 4, header_len)?;
+
+        let length = self.block_len(uri).checked_sub(4 + header_len).ok_or_else(|| corrupted(&self.path))?;
+        let records = self.read_block(uri, 4 + header_len, length)?;
+
+        let mut block = Block { records, elements: BTreeMap::new(), starts: BTreeMap::new() };
+        let mut decoder = Decoder::new(&header);
+        let count = decoder.u32().ok_or_else(|| corrupted(&self.path))?;
+        for _ in 0..count {
+            let id = decoder.dln().ok_or_else(|| corrupted(&self.path))?;
+            let start = decoder.u32().ok_or_else(|| corrupted(&self.path))?;
+            let end = decoder.u32().ok_or_else(|| corrupted(&self.path))?;
+            if start > end || end as u64 > length {
+                return Err(corrupted(&self.path));
+            }
+            block.starts.insert(id.clone(), start);
+            block.elements.insert(start, (id, end));
+        }
+        Ok(Some(block))
+    }
+
+    fn contains(&self, uri: &str) -> bool {
+        self.pending.contains_key(uri) || self.index.contains_key(uri)
+    }
+
+    fn block_len(&self, uri: &str) -> u64 {
+        match self.pending.get(uri) {
+            Some(block) => block.len() as u64,
+            None => self.index.get(uri).map(|(_, length)| *length).unwrap_or(0)
+        }
+    }
+
+    // part of block of document, it must be inside of block
+    fn read_block(&self, uri: &str, offset: u64, length: u64) -> Result<Vec<u8>, ErrorInfo> {
+        match offset.checked_add(length) {
+            Some(end) if end <= self.block_len(uri) => {}
+            _ => return Err(corrupted(&self.path))
+        }
+        match self.pending.get(uri) {
+            Some(block) => Ok(block[offset as usize..(offset + length) as usize].to_vec()),
+            None => {
+                let (start, _) = self.index[uri];
+                self.read(start + offset, length)
+            }
+        }
+    }
+
+    fn read(&self, offset: u64, length: u64) -> Result<Vec<u8>, ErrorInfo> {
+        let mut data = vec![0; length as usize];
+        let mut file = self.file.lock().unwrap();
+        file.seek(SeekFrom::Start(offset))
+            .and_then(|_| file.read_exact(&mut data))
+            .map_err(|e| io_error(&self.path, e))?;
+        Ok(data)
+    }
+}
+
+impl Drop for DiskStorage {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+impl XMLStorage for DiskStorage {
+    fn open(&self, uri: &str, id: usize) -> Result<Option<Rc<Mutex<Box<dyn XMLTreeWriter>>>>, ErrorInfo> {
+        let block = match self.block(uri)? {
+            Some(block) => block,
+            None => return Ok(None)
+        };
+        let tree = LazyXMLTree::new(id, block).ok_or_else(|| corrupted(&self.path))?;
+        Ok(Some(shared(Box::new(tree))))
+    }
+
+    fn save(&mut self, uri: &str, rf: &Reference) -> Result<(), ErrorInfo> {
+        // copy resolves linked nodes, so whole document is in one tree
+        let tree = InMemoryXMLTree::create(0);
+        let copy = {
+            let mut writer = tree.lock().unwrap();
+            copy_node(&mut writer, rf)
+        };
+        let copy = match copy {
+            Some(copy) => copy,
+            None => return Err((ErrorCode::FOUP0001, String::from("only document or element can be saved")))
+        };
+
+        let mut records = Encoder::new();
+        let mut elements = vec![];
+        encode(&copy, &mut records, &mut elements)?;
+
+        let mut header = Encoder::new();
+        header.length(elements.len())?;
+        for (id, start, end) in elements {
+            header.dln(&id)?;
+            header.u32(start);
+            header.u32(end);
+        }
+
+        let mut block = Encoder::new();
+        block.length(header.data.len())?;
+        block.bytes(&header.data);
+        block.bytes(&records.data);
+
+        self.pending.insert(uri.to_string(), block.data);
+
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<String>, ErrorInfo> {
+        let mut uris: Vec<String> = self.index.keys().chain(self.pending.keys()).cloned().collect();
+        uris.sort();
+        uris.dedup();
+        Ok(uris)
+    }
+}
+
+fn encode(rf: &Reference, records: &mut Encoder, elements: &mut Vec<(DLN, u32, u32)>) -> Result<(), ErrorInfo> {
+    match rf.get_type() {
+        Some(NodeType::Document) => {
+            records.u8(DOCUMENT);
+            records.dln(&rf.id)?;
+            for child in rf.forward(&None, &Axis::ForwardChild) {
+                encode(&child, records, elements)?;
+            }
+        }
+        Some(NodeType::Element) => {
+            let start = to_u32(records.data.len())?;
+            let position = elements.len();
+            elements.push((rf.id.clone(), start, start));
+
+            records.u8(ELEMENT);
+            records.dln(&rf.id)?;
+            records.qname(&rf.name().unwrap_or_else(|| QName::local_part("")))?;
+            records.annotation(&rf.type_annotation())?;
+
+            let attributes = rf.attributes().unwrap_or_default();
+            records.length(attributes.len())?;
+            for attribute in attributes {
+                if let Some(name) = &attribute.attr_name {
+                    records.qname(name)?;
+                    records.string(&attribute.to_string_value().unwrap_or_default())?;
+                    records.annotation(&attribute.type_annotation())?;
+                }
+            }
+
+            for child in rf.forward(&None, &Axis::ForwardChild) {
+                encode(&child, records, elements)?;
+            }
+            elements[position].2 = to_u32(records.data.len())?;
+        }
+        Some(NodeType::Text) => {
+            records.u8(TEXT);
+            records.dln(&rf.id)?;
+            records.string(&rf.content().unwrap_or_default())?;
+        }
+        Some(NodeType::Comment) => {
+            records.u8(COMMENT);
+            records.dln(&rf.id)?;
+            records.string(&rf.content().unwrap_or_default())?;
+        }
+        Some(NodeType::PI) => {
+            records.u8(PI);
+            records.dln(&rf.id)?;
+            records.qname(&rf.target().unwrap_or_else(|| QName::local_part("")))?;
+            records.string(&rf.content().unwrap_or_default())?;
+        }
+        Some(NodeType::Attribute) | None => {}
+    }
+    Ok(())
+}
+
+// records of stored document with ranges of element subtrees
+struct Block {
+    records: Vec<u8>,
+    // element subtrees in document order as start -> (id, end)
+    elements: BTreeMap<u32, (DLN, u32)>,
+    starts: BTreeMap<DLN, u32>,
+}
+
+impl Block {
+    fn record(&self, position: usize) -> Option<(DLN, NodeRecord, usize)> {
+        let mut decoder = Decoder::new(&self.records);
+        decoder.pos = position;
+
+        let kind = decoder.u8()?;
+        let id = decoder.dln()?;
+        let record = match kind {
+            DOCUMENT => NodeRecord::Document,
+            ELEMENT => {
+                let name = decoder.qname()?;
+                let annotation = decoder.annotation()?;
+                let count = decoder.u32()?;
+                let mut attributes = vec![];
+                for _ in 0..count {
+                    let name = decoder.qname()?;
+                    let value = decoder.string()?;
+                    let annotation = decoder.annotation()?;
+                    attributes.push((name, value, annotation));
+                }
+                NodeRecord::Element { name, annotation, attributes }
+            }
+            TEXT => NodeRecord::Text(decoder.string()?),
+            COMMENT => NodeRecord::Comment(decoder.string()?),
+            PI => {
+                let target = decoder.qname()?;
+                NodeRecord::PI { target, content: decoder.string()? }
+            }
+            _ => return None
+        };
+        Some((id, record, decoder.pos))
+    }
+
+    // range of records of node with its descendants, None if node has no children
+    fn range(&self, id: &DLN) -> Option<(u32, u32)> {
+        match self.starts.get(id) {
+            Some(start) => Some((*start, self.elements[start].1)),
+            None => match self.record(0) {
+                Some((root, NodeRecord::Document, _)) if &root == id => Some((0, self.records.len() as u32)),
+                _ => None
+            }
+        }
+    }
+
+    // all records in range
+    fn records(&self, start: u32, end: u32) -> Option<Vec<(DLN, NodeRecord)>> {
+        let mut result = vec![];
+        let mut position = start as usize;
+        while position < end as usize {
+            let (id, record, next) = self.record(position)?;
+            result.push((id, record));
+            position = next;
+        }
+        Some(result)
+    }
+
+    // records of children of node in range, descendants of child elements are skipped
+    fn children(&self, start: u32, end: u32) -> Option<Vec<(DLN, NodeRecord)>> {
+        let (_, _, mut position) = self.record(start as usize)?;
+        let mut result = vec![];
+        while position < end as usize {
+            let (id, record, next) = self.record(position)?;
+            position = match self.elements.get(&(position as u32)) {
+                Some((_, end)) => *end as usize,
+                None => next
+            };
+            result.push((id, record));
+        }
+        Some(result)
+    }
+}
+
+// stored document loaded on demand, nodes are loaded with their ancestors and children
+// of element are decoded on first access to them; updates load whole document first
+#[derive(Clone)]
+struct LazyXMLTree {
+    inner: RefCell<InMemoryXMLTree>,
+    block: Rc<Block>,
+    root: DLN,
+    // nodes with loaded children
+    expanded: RefCell<BTreeSet<DLN>>,
+    complete: Cell<bool>,
+}
+
+impl LazyXMLTree {
+    fn new(id: usize, block: Block) -> Option<Self> {
+        let (root, record, _) = block.record(0)?;
+        let mut inner = InMemoryXMLTree::empty(id);
+        inner.load_node(root.clone(), record);
+
+        Some(LazyXMLTree {
+            inner: RefCell::new(inner),
+            block: Rc::new(block),
+            root,
+            expanded: RefCell::new(BTreeSet::new()),
+            complete: Cell::new(false),
+        })
+    }
+
+    fn load(&self, records: Vec<(DLN, NodeRecord)>) {
+        let mut inner = self.inner.borrow_mut();
+        for (id, record) in records {
+            inner.load_node(id, record);
+        }
+    }
+
+    fn expand(&self, rf: &Reference) {
+        if self.complete.get() || rf.attr_name.is_some() || self.expanded.borrow().contains(&rf.id) {
+            return;
+        }
+        if let Some((start, end)) = self.block.range(&rf.id) {
+            self.load(self.block.children(start, end).unwrap_or_default());
+        }
+        self.expanded.borrow_mut().insert(rf.id.clone());
+    }
+
+    fn expand_subtree(&self, id: &DLN) {
+        if self.complete.get() {
+            return;
+        }
+        if let Some((start, end)) = self.block.range(id) {
+            self.load(self.block.records(start, end).unwrap_or_default());
+
+            let mut expanded = self.expanded.borrow_mut();
+            expanded.insert(id.clone());
+            for (_, (element, _)) in self.block.elements.range(start..end) {
+                expanded.insert(element.clone());
+            }
+        }
+        if id == &self.root {
+            self.complete.set(true);
+        }
+    }
+
+    fn expand_all(&self) {
+        self.expand_subtree(&self.root.clone());
+    }
+}
+
+impl XMLTreeReader for LazyXMLTree {
+    fn name(&self, rf: &Reference) -> Option<QName> {
+        self.inner.borrow().name(rf)
+    }
+
+    fn target(&self, rf: &Reference) -> Option<QName> {
+        self.inner.borrow().target(rf)
+    }
+
+    fn content(&self, rf: &Reference) -> Option<String> {
+        self.inner.borrow().content(rf)
+    }
+
+    fn to_string(&self, rf: &Reference) -> Result<String, String> {
+        self.expand_subtree(&rf.id);
+        XMLTreeReader::to_string(&*self.inner.borrow(), rf)
+    }
+
+    fn to_xml(&self, rf: &Reference) -> Result<String, String> {
+        self.expand_subtree(&rf.id);
+        self.inner.borrow().to_xml(rf)
+    }
+
+    fn string_value_of_node(&self, rf: &Reference) -> Result<String, String> {
+        self.expand_subtree(&rf.id);
+        self.inner.borrow().string_value_of_node(rf)
+    }
+
+    fn typed_value_of_node(&self, rf: &Reference) -> Result<Vec<Type>, ErrorInfo> {
+        self.expand_subtree(&rf.id);
+        self.inner.borrow().typed_value_of_node(rf)
+    }
+
+    fn type_annotation(&self, rf: &Reference) -> TypeAnnotation {
+        self.inner.borrow().type_annotation(rf)
+    }
+
+    fn first(&self) -> Option<Reference> {
+        self.inner.borrow().first()
+    }
+
+    fn attribute_value(&self, rf: &Reference, name: &QName) -> Option<String> {
+        self.inner.borrow().attribute_value(rf, name)
+    }
+
+    fn root(&self, rf: &Reference) -> Option<Reference> {
+        self.inner.borrow().root(rf)
+    }
+
+    fn parent(&self, rf: &Reference) -> Option<Reference> {
+        self.inner.borrow().parent(rf)
+    }
+
+    fn forward(&self, rf: &Reference, initial_node_sequence: &Option<INS>, axis: &Axis) -> Vec<Reference> {
+        match (initial_node_sequence, axis) {
+            (None, Axis::ForwardSelf) |
+            (None, Axis::ForwardAttribute) => {}
+            (None, Axis::ForwardChild) => self.expand(rf),
+            (None, Axis::ForwardDescendant) |
+            (None, Axis::ForwardDescendantOrSelf) => self.expand_subtree(&rf.id),
+            _ => self.expand_all()
+        }
+        self.inner.borrow().forward(rf, initial_node_sequence, axis)
+    }
+
+    fn attributes(&self, rf: &Reference) -> Option<Vec<Reference>> {
+        self.inner.borrow().attributes(rf)
+    }
+
+    fn forward_by_name(&self, rf: &Reference, initial_node_sequence: &Option<INS>, axis: &Axis, name: &QName) -> Option<Vec<Reference>> {
+        self.expand_all();
+        self.inner.borrow().forward_by_name(rf, initial_node_sequence, axis, name)
+    }
+
+    fn forward_by_value(&self, rf: &Reference, initial_node_sequence: &Option<INS>, axis: &Axis, name: &QName, key: &IndexKey, comparison: &Comparison, value: &Type) -> Option<Vec<Reference>> {
+        self.expand_all();
+        self.inner.borrow().forward_by_value(rf, initial_node_sequence, axis, name, key, comparison, value)
+    }
+
+    fn get_node(&self, rf: &Reference) -> Option<Box<dyn XMLNode>> {
+        self.inner.borrow().get_node(rf)
+    }
+
+    fn get_type(&self, rf: &Reference) -> Option<NodeType> {
+        self.inner.borrow().get_type(rf)
+    }
+
+    fn is_namespace(&self, rf: &Reference) -> bool {
+        self.inner.borrow().is_namespace(rf)
+    }
+
+    fn is_text(&self, rf: &Reference) -> bool {
+        self.inner.borrow().is_text(rf)
+    }
+
+    fn is_comment(&self, rf: &Reference) -> bool {
+        self.inner.borrow().is_comment(rf)
+    }
+
+    fn dump(&self, rf: &Reference) -> String {
+        XMLTreeReader::dump(&*self.inner.borrow(), rf)
+    }
+}
+
+impl XMLTreeWriter for LazyXMLTree {
+    fn init(&mut self, rf: Rc<Mutex<Box<dyn XMLTreeWriter>>>) {
+        self.inner.get_mut().init(rf)
+    }
+
+    fn id(&self) -> usize {
+        self.inner.borrow().id()
+    }
+
+    fn as_reader(&self) -> Box<&dyn XMLTreeReader> {
+        Box::new(self)
+    }
+
+    fn index(&mut self, config: IndexConfig) {
+        self.inner.get_mut().index(config)
+    }
+
+    fn link_node(&mut self, rf: &Reference) -> Reference {
+        self.expand_all();
+        self.inner.get_mut().link_node(rf)
+    }
+
+    fn start_document(&mut self) -> Reference {
+        self.expand_all();
+        self.inner.get_mut().start_document()
+    }
+
+    fn end_document(&mut self) -> Option<Reference> {
+        self.inner.get_mut().end_document()
+    }
+
+    fn start_element(&mut self, name: QName) -> Reference {
+        self.expand_all();
+        self.inner.get_mut().start_element(name)
+    }
+
+    fn attribute(&mut self, name: QName, value: String) -> Reference {
+        self.expand_all();
+        self.inner.get_mut().attribute(name, value)
+    }
+
+    fn end_element(&mut self) -> Option<Reference> {
+        self.inner.get_mut().end_element()
+    }
+
+    fn pi(&mut self, target: QName, content: String) -> Reference {
+        self.expand_all();
+        self.inner.get_mut().pi(target, content)
+    }
+
+    fn ns(&mut self, prefix: String, url: String) -> Reference {
+        self.expand_all();
+        self.inner.get_mut().ns(prefix, url)
+    }
+
+    fn text(&mut self, content: String) -> Reference {
+        self.expand_all();
+        self.inner.get_mut().text(content)
+    }
+
+    fn comment(&mut self, content: String) -> Reference {
+        self.expand_all();
+        self.inner.get_mut().comment(content)
+    }
+
+    fn annotate(&mut self, rf: &Reference, annotation: TypeAnnotation) -> bool {
+        self.inner.get_mut().annotate(rf, annotation)
+    }
+
+    fn insert(&mut self, target: &Reference, position: InsertPosition, nodes: &Vec<Reference>) -> Result<Vec<(DLN, DLN)>, ErrorInfo> {
+        self.expand_all();
+        self.inner.get_mut().insert(target, position, nodes)
+    }
+
+    fn insert_attributes(&mut self, target: &Reference, attributes: &Vec<Reference>) -> Result<(), ErrorInfo> {
+        self.expand_all();
+        self.inner.get_mut().insert_attributes(target, attributes)
+    }
+
+    fn delete(&mut self, target: &Reference) -> Result<Vec<(DLN, DLN)>, ErrorInfo> {
+        self.expand_all();
+        self.inner.get_mut().delete(target)
+    }
+
+    fn replace_value(&mut self, target: &Reference, value: String) -> Result<(), ErrorInfo> {
+        self.expand_all();
+        self.inner.get_mut().replace_value(target, value)
+    }
+
+    fn rename(&mut self, target: &Reference, name: QName) -> Result<(), ErrorInfo> {
+        self.expand_all();
+        self.inner.get_mut().rename(target, name)
+    }
+
+    fn restore_ids(&mut self, ids: Vec<(DLN, DLN)>) {
+        self.expand_all();
+        self.inner.get_mut().restore_ids(ids)
+    }
+}
+
+fn io_error(path: &PathBuf, e: std::io::Error) -> ErrorInfo {
+    (ErrorCode::FODC0002, format!("{:?}: {}", path, e))
+}
+
+fn corrupted(path: &PathBuf) -> ErrorInfo {
+    (ErrorCode::FODC0002, format!("{:?}: corrupted storage", path))
+}
+
+// lengths and offsets are stored as u32
+fn to_u32(value: usize) -> Result<u32, ErrorInfo> {
+    u32::try_from(value)
+        .map_err(|_| (ErrorCode::FOUP0002, format!("{} exceeds size limit of storage", value)))
+}
+
+struct Encoder {
+    data: Vec<u8>,
+}
+
+impl Encoder {
+    fn new() -> Self {
+        Encoder { data: vec![] }
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn bytes(&mut self, value: &[u8]) {
+        self.data.extend_from_slice(value);
+    }
+
+    fn length(&mut self, value: usize) -> Result<(), ErrorInfo> {
+        self.u32(to_u32(value)?);
+        Ok(())
+    }
+
+    fn string(&mut self, value: &str) -> Result<(), ErrorInfo> {
+        self.length(value.len())?;
+        self.bytes(value.as_bytes());
+        Ok(())
+    }
+
+    fn optional_string(&mut self, value: &Option<String>) -> Result<(), ErrorInfo> {
+        match value {
+            Some(value) => {
+                self.u8(1);
+                self.string(value)
+            }
+            None => {
+                self.u8(0);
+                Ok(())
+            }
+        }
+    }
+
+    fn qname(&mut self, name: &QName) -> Result<(), ErrorInfo> {
+        self.optional_string(&name.prefix)?;
+        self.optional_string(&name.url)?;
+        self.string(&name.local_part)
+    }
+
+    fn dln(&mut self, id: &DLN) -> Result<(), ErrorInfo> {
+        let (bits, number_of_bits) = id.to_bits();
+        self.length(number_of_bits)?;
+        self.bytes(&bits);
+        Ok(())
+    }
+
+    fn annotation(&mut self, annotation: &TypeAnnotation) -> Result<(), ErrorInfo> {
+        let (kind, types) = match annotation {
+            TypeAnnotation::Untyped => {
+                self.u8(UNTYPED);
+                return Ok(());
+            }
+            TypeAnnotation::Atomic(types) => (ATOMIC, types),
+            TypeAnnotation::List(types) => (LIST, types),
+        };
+        let name = QNameToTypes.iter()
+            .find(|(_, t)| *t == types)
+            .map(|(name, _)| name.local_part.clone())
+            .ok_or_else(|| (ErrorCode::FOUP0002, format!("can't store annotation {:?}", annotation)))?;
+        self.u8(kind);
+        self.string(&name)
+    }
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Decoder { data, pos: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.pos + len <= self.data.len() {
+            let bytes = &self.data[self.pos..self.pos + len];
+            self.pos += len;
+            Some(bytes)
+        } else {
+            None
+        }
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|bytes| bytes[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Some(u32::from_le_bytes(bytes))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Some(u64::from_le_bytes(bytes))
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).ok()
+    }
+
+    fn optional_string(&mut self) -> Option<Option<String>> {
+        match self.u8()? {
+            0 => Some(None),
+            _ => Some(Some(self.string()?))
+        }
+    }
+
+    fn qname(&mut self) -> Option<QName> {
+        let prefix = self.optional_string()?;
+        let url = self.optional_string()?;
+        let local_part = self.string()?;
+        Some(QName { prefix, url, local_part })
+    }
+
+    fn dln(&mut self) -> Option<DLN> {
+        let number_of_bits = self.u32()? as usize;
+        if number_of_bits == 0 {
+            return None;
+        }
+        let bits = self.take(number_of_bits.div_ceil(8))?;
+        Some(DLN::new(bits.to_vec(), number_of_bits))
+    }
+
+    fn annotation(&mut self) -> Option<TypeAnnotation> {
+        let kind = self.u8()?;
+        if kind == UNTYPED {
+            return Some(TypeAnnotation::Untyped);
+        }
+        let name = QNameResolved { url: SCHEMA.uri.to_string(), local_part: self.string()? };
+        let types = QNameToTypes.get(&name)?.clone();
+        match kind {
+            ATOMIC => Some(TypeAnnotation::Atomic(types)),
+            LIST => Some(TypeAnnotation::List(types)),
+            _ => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::eval::{Axis, Environment};
+    use crate::parser::errors::ErrorCode;
+    use crate::tests::test_eval_queries_in;
+    use crate::tree::{InMemoryXMLTree, TypeAnnotation, XMLStorage};
+    use crate::values::Types;
+    use super::DiskStorage;
+
+    #[test]
+    fn save_reopen_and_load_subtree() {
+        let path = std::env::temp_dir().join(format!("rustle-disk-{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let source = InMemoryXMLTree::from_str(1, "<a x='1'><b>one</b><!--c--><b>two<!--d--></b></a>");
+        let document = source.lock().unwrap().as_reader().first().unwrap();
+        let expected = document.to_xml().unwrap();
+
+        let second = document.forward(&None, &Axis::ForwardChild).remove(0)
+            .forward(&None, &Axis::ForwardChild).remove(2);
+
+        {
+            let mut storage = DiskStorage::open_file(&path).unwrap();
+            storage.save("a.xml", &document).unwrap();
+        }
+
+        let storage = DiskStorage::open_file(&path).unwrap();
+        assert_eq!(storage.list().unwrap(), vec!["a.xml".to_string()]);
+
+        let tree = storage.open("a.xml", 2).unwrap().unwrap();
+        let loaded = tree.lock().unwrap().as_reader().first().unwrap();
+        assert_eq!(loaded.to_xml().unwrap(), expected);
+
+        let ids = |rf: &crate::tree::Reference| rf.forward(&None, &Axis::ForwardDescendantOrSelf)
+            .into_iter().map(|rf| rf.id.to_string()).collect::<Vec<_>>();
+        assert_eq!(ids(&loaded), ids(&document));
+
+        let tree = storage.open_subtree("a.xml", &second.id, 3).unwrap().unwrap();
+        let subtree = tree.lock().unwrap().as_reader().first().unwrap();
+        assert_eq!(subtree.to_xml().unwrap(), "<b>two<!--d--></b>");
+
+        assert!(storage.open("b.xml", 4).unwrap().is_none());
+
+        drop(storage);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn flush_replaces_file() {
+        let path = std::env::temp_dir().join(format!("rustle-disk-flush-{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let first = InMemoryXMLTree::from_str(1, "<a>first</a>");
+        let first = first.lock().unwrap().as_reader().first().unwrap();
+        let second = InMemoryXMLTree::from_str(2, "<a>second</a>");
+        let second = second.lock().unwrap().as_reader().first().unwrap();
+
+        let mut storage = DiskStorage::open_file(&path).unwrap();
+        storage.save("a.xml", &first).unwrap();
+        storage.flush().unwrap();
+        let size = fs::metadata(&path).unwrap().len();
+
+        // file isn't touched before flush, so it's readable with previous content
+        storage.save("a.xml", &second).unwrap();
+        let other = DiskStorage::open_file(&path).unwrap();
+        let tree = other.open("a.xml", 3).unwrap().unwrap();
+        let loaded = tree.lock().unwrap().as_reader().first().unwrap();
+        assert_eq!(loaded.to_xml().unwrap(), first.to_xml().unwrap());
+        drop(other);
+
+        // replaced block is dropped
+        storage.flush().unwrap();
+        storage.save("a.xml", &first).unwrap();
+        storage.flush().unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), size);
+        drop(storage);
+
+        // corrupted footer is reported as error
+        let mut data = fs::read(&path).unwrap();
+        let len = data.len();
+        data[len - 12..len - 4].copy_from_slice(&u64::MAX.to_le_bytes());
+        fs::write(&path, &data).unwrap();
+        assert!(DiskStorage::open_file(&path).is_err());
+
+        fs::write(&path, &data[..6]).unwrap();
+        assert!(DiskStorage::open_file(&path).is_err());
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn children_are_loaded_on_access() {
+        let path = std::env::temp_dir().join(format!("rustle-disk-lazy-{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let source = InMemoryXMLTree::from_str(1, "<a><b><c>one</c></b><b><c>two</c></b></a>");
+        let document = source.lock().unwrap().as_reader().first().unwrap();
+
+        let mut storage = DiskStorage::open_file(&path).unwrap();
+        storage.save("a.xml", &document).unwrap();
+
+        let tree = storage.open("a.xml", 2).unwrap().unwrap();
+        let loaded = tree.lock().unwrap().as_reader().first().unwrap();
+        assert_eq!(loaded.forward(&None, &Axis::ForwardDescendant).len(), 7);
+        assert_eq!(loaded.to_xml().unwrap(), document.to_xml().unwrap());
+
+        // records which aren't accessed are never decoded, so damage of them is unnoticed
+        let block = storage.pending.get_mut("a.xml").unwrap();
+        let position = block.windows(3).position(|bytes| bytes == b"one").unwrap();
+        block[position] = 0xFF;
+
+        let tree = storage.open("a.xml", 3).unwrap().unwrap();
+        let loaded = tree.lock().unwrap().as_reader().first().unwrap();
+        let b = loaded.forward(&None, &Axis::ForwardChild).remove(0).forward(&None, &Axis::ForwardChild);
+        assert_eq!(b.len(), 2);
+        assert_eq!(b[1].to_string_value().unwrap(), "two");
+        assert_eq!(b[0].forward(&None, &Axis::ForwardChild).len(), 1);
+        assert_eq!(b[0].to_string_value().unwrap(), "");
+
+        drop(storage);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn type_annotations_are_stored() {
+        let path = std::env::temp_dir().join(format!("rustle-disk-typed-{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let source = InMemoryXMLTree::from_str(1, "<a n='1 2'><b>3</b></a>");
+        let document = source.lock().unwrap().as_reader().first().unwrap();
+        let a = document.forward(&None, &Axis::ForwardChild).remove(0);
+        a.attributes().unwrap().remove(0).annotate(TypeAnnotation::List(Types::Integer));
+        a.forward(&None, &Axis::ForwardChild).remove(0).annotate(TypeAnnotation::Atomic(Types::Decimal));
+
+        {
+            let mut storage = DiskStorage::open_file(&path).unwrap();
+            storage.save("a.xml", &document).unwrap();
+        }
+
+        let storage = DiskStorage::open_file(&path).unwrap();
+        let tree = storage.open("a.xml", 2).unwrap().unwrap();
+        let loaded = tree.lock().unwrap().as_reader().first().unwrap();
+        let a = loaded.forward(&None, &Axis::ForwardChild).remove(0);
+        assert_eq!(a.type_annotation(), TypeAnnotation::Untyped);
+        assert_eq!(a.attributes().unwrap()[0].type_annotation(), TypeAnnotation::List(Types::Integer));
+        assert_eq!(a.forward(&None, &Axis::ForwardChild)[0].type_annotation(), TypeAnnotation::Atomic(Types::Decimal));
+
+        test_eval_queries_in(|| Environment::create_with_storage(Box::new(DiskStorage::open_file(&path).unwrap())), &[
+            ("sum(doc('a.xml')/a/@n)", Ok("3")),
+            ("doc('a.xml')/a/b = 3", Ok("true")),
+            ("string-join(doc('a.xml')//b/string(), ' ')", Ok("3")),
+            ("count(doc('a.xml')/a/descendant-or-self::node())", Ok("3")),
+            ("doc('a.xml')/a/b = '3'", Err(ErrorCode::XPTY0004)),
+        ]);
+
+        drop(storage);
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -185,6 +185,14 @@ impl DLN {
         id
     }
 
+    pub fn from_level_ids(level_ids: &[usize]) -> Self {
+        let mut id = DLN::default();
+        for level_id in level_ids {
+            id.add_level_id(*level_id, false);
+        }
+        id
+    }
+
     // generative
     pub fn parent(&self) -> Option<Self> {
         // is document?
@@ -317,6 +325,31 @@ impl DLN {
         id
     }
 
+    pub(crate) fn level_ids(&self) -> Vec<usize> {
+        let mut ids = Vec::with_capacity(7);
+        let mut offset = 0;
+        while offset <= self.pos {
+            if offset > 0 {
+                offset += 1;
+            }
+            let id = self.get_level_id(offset);
+            ids.push(id);
+            match units_required(id) {
+                Ok(units) => offset += units as usize * BITS_PER_UNIT as usize,
+                Err(_) => break
+            }
+        }
+        ids
+    }
+
+    // same relative position under new ancestor
+    pub(crate) fn rebase(&self, ancestor: &DLN, new_ancestor: &DLN) -> DLN {
+        let mut ids = new_ancestor.level_ids();
+        let skip = ancestor.level_ids().len();
+        ids.extend_from_slice(&self.level_ids()[skip..]);
+        DLN::from_level_ids(&ids)
+    }
+
     pub(crate) fn count_levels(&self) -> usize {
         let mut count = 0;
         let mut pos = 0;
@@ -414,6 +447,11 @@ impl DLN {
         units
     }
 
+    // raw form of id, DLN::new restores it
+    pub(crate) fn to_bits(&self) -> (Vec<u8>, usize) {
+        (self.bits.clone(), self.pos + 1)
+    }
+
     pub(crate) fn start_with(&self, other: &DLN) -> bool {
         if self.pos >= other.pos {
             let number_of_bytes = other.pos / 8;
@@ -1,4 +1,5 @@
 use std::borrow::Cow;
+use std::cell::RefCell;
 use std::collections::BTreeMap;
 use std::fs;
 use std::rc::Rc;
@@ -8,8 +9,11 @@ use xmlparser::{ElementEnd, Token};
 use crate::eval::{Axis, ErrorInfo, INS};
 use crate::namespaces::XML;
 use crate::tree::dln::DLN;
-use crate::tree::{NodeType, Reference, XMLNode, XMLTreeReader, XMLTreeWriter};
-use crate::values::QName;
+use crate::parser::op::Comparison;
+use crate::tree::index::{filter_by_axis, IndexConfig, IndexKey, Indexes};
+use crate::tree::{shared, InsertPosition, NodeRecord, NodeType, Reference, TypeAnnotation, XMLNode, XMLTreeReader, XMLTreeWriter};
+use crate::values::{QName, Type, Types};
+use crate::parser::errors::ErrorCode;
 
 #[derive(Clone)]
 pub struct InMemoryXMLTree {
@@ -22,33 +26,55 @@ pub struct InMemoryXMLTree {
 
     // state
     items: BTreeMap<DLN, Box<dyn XMLNode>>,
+
+    // built on first lookup, dropped on updates
+    index_config: Option<IndexConfig>,
+    indexes: RefCell<Option<Indexes>>,
 }
 
 impl InMemoryXMLTree {
-    fn instance(id: usize) -> Box<dyn XMLTreeWriter> {
+    pub(crate) fn empty(id: usize) -> InMemoryXMLTree {
         let mut namespaces = LinkedHashMap::with_capacity(21);
         namespaces.insert("xml".to_string(), "http://www.w3.org/XML/1998/namespace".to_string());
 
-        Box::new(InMemoryXMLTree {
+        InMemoryXMLTree {
             id,
             storage: None,
             stack: Vec::with_capacity(21),
             namespaces,
-            items: BTreeMap::new()
-        })
+            items: BTreeMap::new(),
+            index_config: None,
+            indexes: RefCell::new(None),
+        }
     }
 
     pub fn create(id: usize) -> Rc<Mutex<Box<dyn XMLTreeWriter>>> {
-        let tree = InMemoryXMLTree::instance(id);
+        shared(InMemoryXMLTree::empty(id).as_writer())
+    }
 
-        let rf = Rc::new(Mutex::new(tree));
-        let clone = rf.clone();
-        {
-            let mut instance = rf.lock().unwrap();
-            instance.init(clone);
+    // node of stored document with its stored id, already present node is kept
+    pub(crate) fn load_node(&mut self, id: DLN, record: NodeRecord) {
+        if self.items.contains_key(&id) {
+            return;
         }
-
-        rf
+        self.changed();
+
+        let node: Box<dyn XMLNode> = match record {
+            NodeRecord::Document => Box::new(Document { id: id.clone() }),
+            NodeRecord::Element { name, annotation, attributes } => {
+                let mut element = Element::new(id.clone(), name);
+                element.annotation = annotation;
+                for (name, value, annotation) in attributes {
+                    element.add_attribute(name.clone(), value);
+                    element.annotate(Some(&name), annotation);
+                }
+                element
+            }
+            NodeRecord::Text(content) => Box::new(Text { id: id.clone(), content }),
+            NodeRecord::Comment(content) => Box::new(Comment { id: id.clone(), content }),
+            NodeRecord::PI { target, content } => Box::new(PI { id: id.clone(), target, content }),
+        };
+        self.items.insert(id, node);
     }
 
     fn resolve(&self, mut name: QName) -> QName {
@@ -68,12 +94,23 @@ impl InMemoryXMLTree {
     }
 
     pub fn from_str(id: usize, data: &str) -> Rc<Mutex<Box<dyn XMLTreeWriter>>> {
+        match InMemoryXMLTree::parse(id, data) {
+            Ok(rf) => rf,
+            Err((code, msg)) => panic!("{:?}: {}", code, msg)
+        }
+    }
+
+    pub fn parse(id: usize, data: &str) -> Result<Rc<Mutex<Box<dyn XMLTreeWriter>>>, ErrorInfo> {
         let rf = InMemoryXMLTree::create(id);
         {
             let mut tree = rf.lock().unwrap();
             tree.start_document();
             for token in xmlparser::Tokenizer::from(data) {
-                match token.unwrap() {
+                let token = match token {
+                    Ok(token) => token,
+                    Err(e) => return Err((ErrorCode::FODC0002, e.to_string()))
+                };
+                match token {
                     Token::Declaration { .. } => {
                         // TODO
                     }
@@ -89,10 +126,12 @@ impl InMemoryXMLTree {
                     Token::Comment { text, .. } => {
                         tree.comment(text.as_str().to_string());
                     },
-                    Token::DtdStart { .. } => panic!(),
-                    Token::EmptyDtd { .. } => panic!(),
-                    Token::EntityDeclaration { .. } => panic!(),
-                    Token::DtdEnd { .. } => panic!(),
+                    Token::DtdStart { .. } |
+                    Token::EmptyDtd { .. } |
+                    Token::EntityDeclaration { .. } |
+                    Token::DtdEnd { .. } => {
+                        return Err((ErrorCode::FODC0002, String::from("DTD is not supported")));
+                    }
                     Token::ElementStart { prefix, local, .. } => {
                         let name = QName::new(prefix.as_str().to_string(), local.as_str().to_string());
                         tree.start_element(name);
@@ -115,19 +154,56 @@ impl InMemoryXMLTree {
                     Token::Text { text } => {
                         tree.text(text.as_str().to_string());
                     },
-                    Token::Cdata { text, .. } => panic!()
+                    Token::Cdata { text, .. } => {
+                        tree.text(text.as_str().to_string());
+                    }
                 }
             }
             tree.end_document();
         }
 
-        rf
+        Ok(rf)
     }
 
     pub(crate) fn as_writer(self) -> Box<dyn XMLTreeWriter> {
         Box::new(self)
     }
 
+    // context node of forward step and whatever step applies to all its descendants
+    fn context_of(&self, rf: &Reference, initial_node_sequence: &Option<INS>) -> (Reference, bool) {
+        if let Some(initial_node) = initial_node_sequence {
+            match initial_node {
+                INS::Root |
+                INS::RootDescendantOrSelf => {
+                    if let Some(doc) = self.root(rf) {
+                        (doc, initial_node == &INS::RootDescendantOrSelf)
+                    } else {
+                        panic!("XPDY0050")
+                    }
+                },
+                INS::DescendantOrSelf => {
+                    (rf.clone(), true)
+                }
+            }
+        } else {
+            (rf.clone(), false)
+        }
+    }
+
+    // lookup in indexes, they are built on first use; None if tree isn't indexed
+    fn with_indexes<T, F>(&self, lookup: F) -> Option<T> where F: FnOnce(&Indexes) -> Option<T> {
+        let config = self.index_config.as_ref()?;
+        let mut indexes = self.indexes.borrow_mut();
+        if indexes.is_none() {
+            *indexes = Some(Indexes::build(config, &self.items));
+        }
+        indexes.as_ref().and_then(lookup)
+    }
+
+    fn changed(&mut self) {
+        *self.indexes.get_mut() = None;
+    }
+
     fn pop(&mut self) -> Option<Reference> {
         match self.stack.pop() {
             Some((id, ns_len)) => {
@@ -213,6 +289,75 @@ impl InMemoryXMLTree {
         result
     }
 
+    // child nodes with their descendants in document order
+    fn subtrees(&self, parent: &DLN) -> Vec<Subtree> {
+        let mut nodes = vec![];
+        for (k, v) in self.items.range(parent..) {
+            if k == parent {
+                continue;
+            } else if k.start_with(parent) {
+                nodes.push(v.clone());
+            } else {
+                break;
+            }
+        }
+        group_by_child(parent.count_levels() + 1, nodes, true)
+    }
+
+    // replace children of parent by subtrees with new ids, adjacent text nodes are merged;
+    // returns moved roots of existing subtrees as (old id, new id)
+    fn rebuild_children(&mut self, parent: &DLN, subtrees: Vec<Subtree>) -> Vec<(DLN, DLN)> {
+        let mut merged: Vec<(Subtree, Vec<DLN>)> = Vec::with_capacity(subtrees.len());
+        for subtree in subtrees {
+            if subtree.nodes.len() == 1 && subtree.nodes[0].is_text() {
+                let content = subtree.nodes[0].content().unwrap_or_default();
+                if content.is_empty() {
+                    continue;
+                }
+                if let Some((last, joined)) = merged.last_mut() {
+                    if last.nodes.len() == 1 && last.nodes[0].is_text() {
+                        let mut current_content = last.nodes[0].content().unwrap_or_default();
+                        current_content.push_str(content.as_str());
+                        last.nodes[0] = Box::new(Text { id: last.nodes[0].id(), content: current_content });
+                        if subtree.existing {
+                            joined.push(subtree.root);
+                        }
+                        continue;
+                    }
+                }
+            }
+            merged.push((subtree, vec![]));
+        }
+
+        let keys: Vec<DLN> = self.items.range(parent..)
+            .map(|(k, _)| k)
+            .take_while(|k| k.start_with(parent))
+            .filter(|k| *k != parent)
+            .cloned()
+            .collect();
+        for key in keys {
+            self.items.remove(&key);
+        }
+
+        let mut moves = vec![];
+        let mut next = parent.first_child();
+        for (subtree, joined) in merged {
+            if subtree.existing && subtree.root != next {
+                moves.push((subtree.root.clone(), next.clone()));
+            }
+            for id in joined {
+                moves.push((id, next.clone()));
+            }
+            for mut node in subtree.nodes {
+                let id = node.id().rebase(&subtree.root, &next);
+                node.set_id(id.clone());
+                self.items.insert(id, node);
+            }
+            next = next.next_sibling();
+        }
+        moves
+    }
+
     fn dump(&self) -> String {
         let mut buf = String::with_capacity(100_100);
         buf.push_str(format!("storage: {}\n", self.id).as_str());
@@ -334,7 +479,7 @@ impl XMLTreeReader for InMemoryXMLTree {
         Ok(buf)
     }
 
-    fn typed_value_of_node(&self, rf: &Reference) -> Result<String, String> {
+    fn string_value_of_node(&self, rf: &Reference) -> Result<String, String> {
         if let Some(name) = &rf.attr_name {
             if let Some(node) = self.items.get(&rf.id) {
                 if let Some(value) = node.attribute_value(name) {
@@ -349,7 +494,7 @@ impl XMLTreeReader for InMemoryXMLTree {
             let mut result = vec![];
             for (k, v) in self.items.range(&rf.id..) {
                 if k.start_with(&rf.id) {
-                    result.push(v.typed_value());
+                    result.push(v.string_value());
                 } else {
                     break;
                 }
@@ -359,6 +504,26 @@ impl XMLTreeReader for InMemoryXMLTree {
         }
     }
 
+    fn typed_value_of_node(&self, rf: &Reference) -> Result<Vec<Type>, ErrorInfo> {
+        let value = match self.string_value_of_node(rf) {
+            Ok(value) => value,
+            Err(msg) => return Err((ErrorCode::TODO, msg))
+        };
+        self.type_annotation(rf).typed_value(value)
+    }
+
+    fn type_annotation(&self, rf: &Reference) -> TypeAnnotation {
+        if let Some(node) = self.items.get(&rf.id) {
+            if let Some(name) = &rf.attr_name {
+                node.attribute_annotation(name).unwrap_or(TypeAnnotation::Untyped)
+            } else {
+                node.type_annotation()
+            }
+        } else {
+            TypeAnnotation::Untyped
+        }
+    }
+
     fn first(&self) -> Option<Reference> {
         if let Some((k, v)) = self.items.first_key_value() {
             Some(self.reference(k.clone(), None))
@@ -407,23 +572,7 @@ impl XMLTreeReader for InMemoryXMLTree {
         // println!("forward {:?} {:?} {}", initial_node_sequence, axis, rf.id);
         // println!("{}", self.dump());
 
-        let (rf, all) = if let Some(initial_node) = initial_node_sequence {
-            match initial_node {
-                INS::Root |
-                INS::RootDescendantOrSelf => {
-                    if let Some(doc) = self.root(rf) {
-                        (doc, initial_node == &INS::RootDescendantOrSelf)
-                    } else {
-                        panic!("XPDY0050")
-                    }
-                },
-                INS::DescendantOrSelf => {
-                    (rf.clone(), true)
-                }
-            }
-        } else {
-            (rf.clone(), false)
-        };
+        let (rf, all) = self.context_of(rf, initial_node_sequence);
 
         if rf.attr_name.is_some() {
             let mut result = Vec::with_capacity(1);
@@ -501,6 +650,44 @@ impl XMLTreeReader for InMemoryXMLTree {
         }
     }
 
+    fn forward_by_name(&self, rf: &Reference, initial_node_sequence: &Option<INS>, axis: &Axis, name: &QName) -> Option<Vec<Reference>> {
+        if rf.attr_name.is_some() {
+            return None;
+        }
+        let (rf, all) = self.context_of(rf, initial_node_sequence);
+
+        self.with_indexes(|indexes| {
+            if axis == &Axis::ForwardAttribute {
+                let attributes = indexes.attributes(name)?;
+                let found = filter_by_axis(attributes, |(id, _)| id, &rf.id, all, &Axis::ForwardSelf)?;
+                Some(found.into_iter()
+                    .map(|(id, name)| self.reference(id.clone(), Some(name.clone())))
+                    .collect())
+            } else {
+                let ids = indexes.elements(name)?;
+                let found = filter_by_axis(ids, |id| id, &rf.id, all, axis)?;
+                Some(found.into_iter()
+                    .map(|id| self.reference(id.clone(), None))
+                    .collect())
+            }
+        })
+    }
+
+    fn forward_by_value(&self, rf: &Reference, initial_node_sequence: &Option<INS>, axis: &Axis, name: &QName, key: &IndexKey, comparison: &Comparison, value: &Type) -> Option<Vec<Reference>> {
+        if rf.attr_name.is_some() {
+            return None;
+        }
+        let (rf, all) = self.context_of(rf, initial_node_sequence);
+
+        self.with_indexes(|indexes| {
+            let ids = indexes.by_value(name, key, comparison, value)?;
+            let found = filter_by_axis(&ids, |id| id, &rf.id, all, axis)?;
+            Some(found.into_iter()
+                .map(|id| self.reference(id.clone(), None))
+                .collect())
+        })
+    }
+
     fn attributes(&self, rf: &Reference) -> Option<Vec<Reference>> {
         if let Some(node) = self.items.get(&rf.id) {
             if let Some(names) = node.get_attributes() {
@@ -586,11 +773,16 @@ impl XMLTreeWriter for InMemoryXMLTree {
         Box::new(self)
     }
 
+    fn index(&mut self, config: IndexConfig) {
+        self.index_config = Some(config);
+        self.changed();
+    }
+
     fn link_node(&mut self, rf: &Reference) -> Reference {
         let other = rf.storage.lock().unwrap();
         let other = other.as_reader();
         if let Some(name) = &rf.attr_name {
-            let value = other.typed_value_of_node(rf).unwrap();
+            let value = other.string_value_of_node(rf).unwrap();
             self.attribute(name.clone(), value)
         } else {
             let id = self.next_sibling();
@@ -655,18 +847,7 @@ impl XMLTreeWriter for InMemoryXMLTree {
         name = self.resolve(name);
 
         let size = self.stack.len();
-        if size == 0 {
-            let id = DLN::level_id(0);
-            self.stack.push((id.clone(), self.namespaces.len()));
-
-            let mut node: Box<dyn XMLNode> = Element::empty(id.clone());
-            if node.add_attribute(name.clone(), value) {
-                self.items.insert(id.clone(), node);
-                return self.reference(id, Some(name));
-            } else {
-                panic!("internal error")
-            }
-        } else if size >= 2 {
+        if size >= 2 {
             if let Some((id, ns_len)) = self.stack.get(size - 2) {
                 if let Some(node) = self.items.get_mut(id) {
                     if node.add_attribute(name.clone(), value) {
@@ -677,7 +858,23 @@ impl XMLTreeWriter for InMemoryXMLTree {
                 }
             }
         }
-        todo!()
+
+        // standalone attribute, kept by unnamed holder at top level
+        let id = if size == 0 {
+            let id = DLN::level_id(0);
+            self.stack.push((id.clone(), self.namespaces.len()));
+            id
+        } else {
+            self.next_sibling()
+        };
+
+        let mut node: Box<dyn XMLNode> = Element::empty(id.clone());
+        if node.add_attribute(name.clone(), value) {
+            self.items.insert(id.clone(), node);
+            self.reference(id, Some(name))
+        } else {
+            panic!("internal error")
+        }
     }
 
     fn end_element(&mut self) -> Option<Reference> {
@@ -728,6 +925,178 @@ impl XMLTreeWriter for InMemoryXMLTree {
 
         self.reference(id, None)
     }
+
+    fn annotate(&mut self, rf: &Reference, annotation: TypeAnnotation) -> bool {
+        self.changed();
+
+        if let Some(node) = self.items.get_mut(&rf.id) {
+            node.annotate(rf.attr_name.as_ref(), annotation)
+        } else {
+            false
+        }
+    }
+
+    fn insert(&mut self, target: &Reference, position: InsertPosition, nodes: &Vec<Reference>) -> Result<Vec<(DLN, DLN)>, ErrorInfo> {
+        self.changed();
+
+        let mut inserting = vec![];
+        for rf in nodes {
+            let items: Vec<Box<dyn XMLNode>> = rf.forward(&None, &Axis::ForwardDescendantOrSelf)
+                .iter()
+                .filter_map(|rf| rf.get_node())
+                .collect();
+
+            if let Some(NodeType::Document) = items.first().map(|node| node.get_type()) {
+                // document node is replaced by its children
+                let level = rf.id.count_levels() + 1;
+                inserting.append(&mut group_by_child(level, items, false));
+            } else {
+                inserting.push(Subtree { root: rf.id.clone(), nodes: items, existing: false });
+            }
+        }
+
+        let (parent, index) = match position {
+            InsertPosition::Into |
+            InsertPosition::AsLastInto => {
+                let children = self.subtrees(&target.id);
+                (target.id.clone(), children.len())
+            }
+            InsertPosition::AsFirstInto => (target.id.clone(), 0),
+            InsertPosition::Before |
+            InsertPosition::After => {
+                let parent = match target.id.parent() {
+                    Some(id) if self.items.contains_key(&id) => id,
+                    _ => return Err((ErrorCode::XUDY0029, String::from("target node has no parent")))
+                };
+                let children = self.subtrees(&parent);
+                let index = match children.iter().position(|subtree| subtree.root == target.id) {
+                    Some(index) => index,
+                    None => return Err((ErrorCode::XUDY0029, String::from("target node has no parent")))
+                };
+                if position == InsertPosition::After {
+                    (parent, index + 1)
+                } else {
+                    (parent, index)
+                }
+            }
+        };
+
+        let mut children = self.subtrees(&parent);
+        let tail = children.split_off(index);
+        children.append(&mut inserting);
+        children.extend(tail);
+
+        Ok(self.rebuild_children(&parent, children))
+    }
+
+    fn insert_attributes(&mut self, target: &Reference, attributes: &Vec<Reference>) -> Result<(), ErrorInfo> {
+        self.changed();
+
+        let node = match self.items.get_mut(&target.id) {
+            Some(node) => node,
+            None => return Err((ErrorCode::XUTY0022, String::from("TODO")))
+        };
+        for attribute in attributes {
+            if let Some(name) = &attribute.attr_name {
+                if node.attribute_value(name).is_some() {
+                    return Err((ErrorCode::XUDY0021, format!("duplicate attribute {:?}", name)));
+                }
+                let value = match attribute.to_string_value() {
+                    Ok(value) => value,
+                    Err(msg) => return Err((ErrorCode::TODO, msg))
+                };
+                if !node.add_attribute(name.clone(), value) {
+                    return Err((ErrorCode::XUTY0022, String::from("TODO")));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn delete(&mut self, target: &Reference) -> Result<Vec<(DLN, DLN)>, ErrorInfo> {
+        self.changed();
+
+        if let Some(name) = &target.attr_name {
+            if let Some(node) = self.items.get_mut(&target.id) {
+                node.remove_attribute(name);
+            }
+            Ok(vec![])
+        } else {
+            let keys: Vec<DLN> = self.items.range(&target.id..)
+                .map(|(k, _)| k)
+                .take_while(|k| k.start_with(&target.id))
+                .cloned()
+                .collect();
+
+            for key in keys {
+                self.items.remove(&key);
+            }
+
+            if let Some(parent) = target.id.parent() {
+                if self.items.contains_key(&parent) {
+                    let children = self.subtrees(&parent);
+                    return Ok(self.rebuild_children(&parent, children));
+                }
+            }
+            Ok(vec![])
+        }
+    }
+
+    fn replace_value(&mut self, target: &Reference, value: String) -> Result<(), ErrorInfo> {
+        self.changed();
+
+        if let Some(node) = self.items.get_mut(&target.id) {
+            if node.set_value(target.attr_name.as_ref(), value) {
+                return Ok(());
+            }
+        }
+        Err((ErrorCode::XUTY0008, String::from("value of node can't be replaced")))
+    }
+
+    fn rename(&mut self, target: &Reference, name: QName) -> Result<(), ErrorInfo> {
+        self.changed();
+
+        if let Some(node) = self.items.get_mut(&target.id) {
+            if node.rename(target.attr_name.as_ref(), name) {
+                return Ok(());
+            }
+        }
+        Err((ErrorCode::XUTY0012, String::from("node can't be renamed")))
+    }
+
+    fn restore_ids(&mut self, ids: Vec<(DLN, DLN)>) {
+        self.changed();
+
+        // all nodes are taken out first, so new ids may reuse old ones
+        let mut nodes = Vec::with_capacity(ids.len());
+        for (old, new) in ids {
+            if let Some(mut node) = self.items.remove(&old) {
+                node.set_id(new.clone());
+                nodes.push((new, node));
+            }
+        }
+        self.items.extend(nodes);
+    }
+}
+
+// child node with its descendants
+struct Subtree {
+    root: DLN,
+    nodes: Vec<Box<dyn XMLNode>>,
+    existing: bool,
+}
+
+fn group_by_child(level: usize, nodes: Vec<Box<dyn XMLNode>>, existing: bool) -> Vec<Subtree> {
+    let mut groups: Vec<Subtree> = vec![];
+    for node in nodes {
+        let id = node.id();
+        if id.count_levels() == level {
+            groups.push(Subtree { root: id, nodes: vec![node], existing });
+        } else if let Some(subtree) = groups.last_mut() {
+            subtree.nodes.push(node);
+        }
+    }
+    groups
 }
 
 #[derive(Debug, Clone)]
@@ -752,22 +1121,54 @@ impl XMLNode for Document {
         None
     }
 
-    fn typed_value(&self) -> String {
+    fn string_value(&self) -> String {
         String::new()
     }
 
+    fn type_annotation(&self) -> TypeAnnotation {
+        TypeAnnotation::Untyped
+    }
+
     fn attribute_value(&self, name: &QName) -> Option<String> {
         None
     }
 
+    fn attribute_annotation(&self, name: &QName) -> Option<TypeAnnotation> {
+        None
+    }
+
     fn add_attribute(&mut self, name: QName, value: String) -> bool {
         false
     }
 
+    fn annotate(&mut self, attr_name: Option<&QName>, annotation: TypeAnnotation) -> bool {
+        false
+    }
+
     fn get_attributes(&self) -> Option<Vec<QName>> {
         None
     }
 
+    fn set_id(&mut self, id: DLN) {
+        self.id = id;
+    }
+
+    fn rename(&mut self, attr_name: Option<&QName>, name: QName) -> bool {
+        false
+    }
+
+    fn set_value(&mut self, attr_name: Option<&QName>, value: String) -> bool {
+        false
+    }
+
+    fn remove_attribute(&mut self, name: &QName) -> bool {
+        false
+    }
+
+    fn linked(&self) -> Option<Reference> {
+        None
+    }
+
     fn get_type(&self) -> NodeType {
         NodeType::Document
     }
@@ -806,15 +1207,16 @@ struct Element {
     id: DLN,
     name: Option<QName>,
     attributes: Option<LinkedHashMap<QName, Attribute>>,
+    annotation: TypeAnnotation,
 }
 
 impl Element {
     pub(crate) fn empty(id: DLN) -> Box<Self> {
-        Box::new(Element { id, name: None, attributes: None })
+        Box::new(Element { id, name: None, attributes: None, annotation: TypeAnnotation::Untyped })
     }
 
     pub(crate) fn new(id: DLN, name: QName) -> Box<Self> {
-        Box::new(Element { id, name: Some(name), attributes: None })
+        Box::new(Element { id, name: Some(name), attributes: None, annotation: TypeAnnotation::Untyped })
     }
 }
 
@@ -835,10 +1237,14 @@ impl XMLNode for Element {
         None
     }
 
-    fn typed_value(&self) -> String {
+    fn string_value(&self) -> String {
         String::new()
     }
 
+    fn type_annotation(&self) -> TypeAnnotation {
+        self.annotation.clone()
+    }
+
     fn attribute_value(&self, name: &QName) -> Option<String> {
         if let Some(attributes) = &self.attributes {
             if let Some(attribute) = attributes.get(&name) {
@@ -848,6 +1254,15 @@ impl XMLNode for Element {
         None
     }
 
+    fn attribute_annotation(&self, name: &QName) -> Option<TypeAnnotation> {
+        if let Some(attributes) = &self.attributes {
+            if let Some(attribute) = attributes.get(&name) {
+                return Some(attribute.annotation.clone());
+            }
+        }
+        None
+    }
+
     fn add_attribute(&mut self, name: QName, value: String) -> bool {
         if self.attributes.is_none() {
             self.attributes = Some(LinkedHashMap::new());
@@ -875,12 +1290,27 @@ impl XMLNode for Element {
         }
 
         if let Some(attributes) = &mut self.attributes {
-            attributes.insert(name.clone(), Attribute { name, value } );
+            attributes.insert(name.clone(), Attribute { name, value, annotation: TypeAnnotation::Untyped } );
         }
 
         true
     }
 
+    fn annotate(&mut self, attr_name: Option<&QName>, annotation: TypeAnnotation) -> bool {
+        if let Some(name) = attr_name {
+            if let Some(attributes) = &mut self.attributes {
+                if let Some(attribute) = attributes.get_mut(name) {
+                    attribute.annotation = annotation;
+                    return true;
+                }
+            }
+            false
+        } else {
+            self.annotation = annotation;
+            true
+        }
+    }
+
     fn get_attributes(&self) -> Option<Vec<QName>> {
         if let Some(attributes) = &self.attributes {
             let mut result = Vec::with_capacity(attributes.len());
@@ -893,6 +1323,60 @@ impl XMLNode for Element {
         }
     }
 
+    fn set_id(&mut self, id: DLN) {
+        self.id = id;
+    }
+
+    fn rename(&mut self, attr_name: Option<&QName>, name: QName) -> bool {
+        if let Some(attr_name) = attr_name {
+            if let Some(attributes) = &mut self.attributes {
+                if attributes.contains_key(&name) {
+                    return false;
+                }
+                let mut renamed = LinkedHashMap::with_capacity(attributes.len());
+                for (key, mut attribute) in attributes.drain() {
+                    if &key == attr_name {
+                        attribute.name = name.clone();
+                        renamed.insert(name.clone(), attribute);
+                    } else {
+                        renamed.insert(key, attribute);
+                    }
+                }
+                *attributes = renamed;
+                return true;
+            }
+            false
+        } else {
+            self.name = Some(name);
+            true
+        }
+    }
+
+    fn set_value(&mut self, attr_name: Option<&QName>, value: String) -> bool {
+        if let Some(name) = attr_name {
+            if let Some(attributes) = &mut self.attributes {
+                if let Some(attribute) = attributes.get_mut(name) {
+                    attribute.value = value;
+                    attribute.annotation = TypeAnnotation::Untyped;
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    fn remove_attribute(&mut self, name: &QName) -> bool {
+        if let Some(attributes) = &mut self.attributes {
+            attributes.remove(name).is_some()
+        } else {
+            false
+        }
+    }
+
+    fn linked(&self) -> Option<Reference> {
+        None
+    }
+
     fn get_type(&self) -> NodeType {
         NodeType::Element
     }
@@ -988,7 +1472,8 @@ impl XMLNode for Element {
 #[derive(Debug, Clone)]
 struct Attribute {
     name: QName,
-    value: String
+    value: String,
+    annotation: TypeAnnotation,
 }
 
 impl XMLNode for Attribute {
@@ -1008,10 +1493,14 @@ impl XMLNode for Attribute {
         None
     }
 
-    fn typed_value(&self) -> String {
+    fn string_value(&self) -> String {
         self.value.clone()
     }
 
+    fn type_annotation(&self) -> TypeAnnotation {
+        self.annotation.clone()
+    }
+
     fn attribute_value(&self, name: &QName) -> Option<String> {
         if &self.name == name {
             Some(escape_str_attribute(self.value.as_str()).to_string())
@@ -1020,14 +1509,62 @@ impl XMLNode for Attribute {
         }
     }
 
+    fn attribute_annotation(&self, name: &QName) -> Option<TypeAnnotation> {
+        if &self.name == name {
+            Some(self.annotation.clone())
+        } else {
+            None
+        }
+    }
+
     fn add_attribute(&mut self, name: QName, value: String) -> bool {
         false
     }
 
+    fn annotate(&mut self, attr_name: Option<&QName>, annotation: TypeAnnotation) -> bool {
+        if attr_name.is_none() || attr_name == Some(&self.name) {
+            self.annotation = annotation;
+            true
+        } else {
+            false
+        }
+    }
+
     fn get_attributes(&self) -> Option<Vec<QName>> {
         None
     }
 
+    fn set_id(&mut self, id: DLN) {
+        panic!()
+    }
+
+    fn rename(&mut self, attr_name: Option<&QName>, name: QName) -> bool {
+        if attr_name.is_none() || attr_name == Some(&self.name) {
+            self.name = name;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn set_value(&mut self, attr_name: Option<&QName>, value: String) -> bool {
+        if attr_name.is_none() || attr_name == Some(&self.name) {
+            self.value = value;
+            self.annotation = TypeAnnotation::Untyped;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn remove_attribute(&mut self, name: &QName) -> bool {
+        false
+    }
+
+    fn linked(&self) -> Option<Reference> {
+        None
+    }
+
     fn get_type(&self) -> NodeType {
         NodeType::Attribute
     }
@@ -1085,22 +1622,64 @@ impl XMLNode for PI {
         Some(self.content.clone())
     }
 
-    fn typed_value(&self) -> String {
+    fn string_value(&self) -> String {
         self.content.clone()
     }
 
+    fn type_annotation(&self) -> TypeAnnotation {
+        TypeAnnotation::Atomic(Types::String)
+    }
+
     fn attribute_value(&self, name: &QName) -> Option<String> {
         None
     }
 
+    fn attribute_annotation(&self, name: &QName) -> Option<TypeAnnotation> {
+        None
+    }
+
     fn add_attribute(&mut self, name: QName, value: String) -> bool {
         false
     }
 
+    fn annotate(&mut self, attr_name: Option<&QName>, annotation: TypeAnnotation) -> bool {
+        false
+    }
+
     fn get_attributes(&self) -> Option<Vec<QName>> {
         None
     }
 
+    fn set_id(&mut self, id: DLN) {
+        self.id = id;
+    }
+
+    fn rename(&mut self, attr_name: Option<&QName>, name: QName) -> bool {
+        if attr_name.is_none() {
+            self.target = name;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn set_value(&mut self, attr_name: Option<&QName>, value: String) -> bool {
+        if attr_name.is_none() {
+            self.content = value;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn remove_attribute(&mut self, name: &QName) -> bool {
+        false
+    }
+
+    fn linked(&self) -> Option<Reference> {
+        None
+    }
+
     fn get_type(&self) -> NodeType {
         NodeType::PI
     }
@@ -1157,22 +1736,59 @@ impl XMLNode for Text {
         Some(self.content.clone())
     }
 
-    fn typed_value(&self) -> String {
+    fn string_value(&self) -> String {
         self.content.clone()
     }
 
+    fn type_annotation(&self) -> TypeAnnotation {
+        TypeAnnotation::Untyped
+    }
+
     fn attribute_value(&self, name: &QName) -> Option<String> {
         None
     }
 
+    fn attribute_annotation(&self, name: &QName) -> Option<TypeAnnotation> {
+        None
+    }
+
     fn add_attribute(&mut self, name: QName, value: String) -> bool {
         false
     }
 
+    fn annotate(&mut self, attr_name: Option<&QName>, annotation: TypeAnnotation) -> bool {
+        false
+    }
+
     fn get_attributes(&self) -> Option<Vec<QName>> {
         None
     }
 
+    fn set_id(&mut self, id: DLN) {
+        self.id = id;
+    }
+
+    fn rename(&mut self, attr_name: Option<&QName>, name: QName) -> bool {
+        false
+    }
+
+    fn set_value(&mut self, attr_name: Option<&QName>, value: String) -> bool {
+        if attr_name.is_none() {
+            self.content = value;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn remove_attribute(&mut self, name: &QName) -> bool {
+        false
+    }
+
+    fn linked(&self) -> Option<Reference> {
+        None
+    }
+
     fn get_type(&self) -> NodeType {
         NodeType::Text
     }
@@ -1229,22 +1845,59 @@ impl XMLNode for Comment {
         Some(self.content.clone())
     }
 
-    fn typed_value(&self) -> String {
+    fn string_value(&self) -> String {
         self.content.clone()
     }
 
+    fn type_annotation(&self) -> TypeAnnotation {
+        TypeAnnotation::Atomic(Types::String)
+    }
+
     fn attribute_value(&self, name: &QName) -> Option<String> {
         None
     }
 
+    fn attribute_annotation(&self, name: &QName) -> Option<TypeAnnotation> {
+        None
+    }
+
     fn add_attribute(&mut self, name: QName, value: String) -> bool {
         false
     }
 
+    fn annotate(&mut self, attr_name: Option<&QName>, annotation: TypeAnnotation) -> bool {
+        false
+    }
+
     fn get_attributes(&self) -> Option<Vec<QName>> {
         None
     }
 
+    fn set_id(&mut self, id: DLN) {
+        self.id = id;
+    }
+
+    fn rename(&mut self, attr_name: Option<&QName>, name: QName) -> bool {
+        false
+    }
+
+    fn set_value(&mut self, attr_name: Option<&QName>, value: String) -> bool {
+        if attr_name.is_none() {
+            self.content = value;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn remove_attribute(&mut self, name: &QName) -> bool {
+        false
+    }
+
+    fn linked(&self) -> Option<Reference> {
+        None
+    }
+
     fn get_type(&self) -> NodeType {
         NodeType::Comment
     }
@@ -1258,7 +1911,7 @@ impl XMLNode for Comment {
     }
 
     fn to_xml_open(&self, namespaces: &mut LinkedHashMap<String, String>) -> String {
-        "<!--".to_string()
+        format!("<!--{}", self.content)
     }
 
     fn to_xml_start_children(&self) -> String {
@@ -1301,22 +1954,58 @@ impl XMLNode for LinkedNode {
         self.rf.content()
     }
 
-    fn typed_value(&self) -> String {
-        self.rf.to_typed_value().unwrap()
+    fn string_value(&self) -> String {
+        self.rf.to_string_value().unwrap()
+    }
+
+    fn type_annotation(&self) -> TypeAnnotation {
+        self.rf.type_annotation()
     }
 
     fn attribute_value(&self, name: &QName) -> Option<String> {
         self.rf.attribute_value(name)
     }
 
+    fn attribute_annotation(&self, name: &QName) -> Option<TypeAnnotation> {
+        let mut rf = self.rf.clone();
+        rf.attr_name = Some(name.clone());
+        Some(rf.type_annotation())
+    }
+
     fn add_attribute(&mut self, name: QName, value: String) -> bool {
         todo!()
     }
 
+    fn annotate(&mut self, attr_name: Option<&QName>, annotation: TypeAnnotation) -> bool {
+        let mut rf = self.rf.clone();
+        rf.attr_name = attr_name.cloned();
+        rf.annotate(annotation)
+    }
+
     fn get_attributes(&self) -> Option<Vec<QName>> {
         todo!()
     }
 
+    fn set_id(&mut self, id: DLN) {
+        self.id = id;
+    }
+
+    fn rename(&mut self, attr_name: Option<&QName>, name: QName) -> bool {
+        false
+    }
+
+    fn set_value(&mut self, attr_name: Option<&QName>, value: String) -> bool {
+        false
+    }
+
+    fn remove_attribute(&mut self, name: &QName) -> bool {
+        false
+    }
+
+    fn linked(&self) -> Option<Reference> {
+        Some(self.rf.clone())
+    }
+
     fn get_type(&self) -> NodeType {
         if let Some(node_type) = self.rf.get_type() {
             node_type
@@ -1453,4 +2142,29 @@ pub fn escape_str_content(s: &str) -> Cow<str> {
 #[inline]
 pub fn escape_str_pcdata(s: &str) -> Cow<str> {
     escape_str(s, Value::dispatch_for_pcdata)
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use crate::eval::Axis;
+    use crate::values::{Type, Types};
+    use crate::tree::TypeAnnotation;
+    use super::InMemoryXMLTree;
+
+    #[test]
+    fn typed_value_of_annotated_attribute() {
+        let storage = InMemoryXMLTree::from_str(0, "<a n='1 2'>3</a>");
+
+        let document = storage.lock().unwrap().as_reader().first().unwrap();
+        let element = document.forward(&None, &Axis::ForwardChild).remove(0);
+        let attribute = element.attributes().unwrap().remove(0);
+
+        assert_eq!(attribute.type_annotation(), TypeAnnotation::Untyped);
+        assert_eq!(attribute.to_typed_value().unwrap(), vec![Type::Untyped(String::from("1 2"))]);
+
+        assert!(attribute.annotate(TypeAnnotation::List(Types::Integer)));
+        assert_eq!(attribute.to_typed_value().unwrap(), vec![Type::Integer(1), Type::Integer(2)]);
+
+        assert!(element.annotate(TypeAnnotation::Atomic(Types::Integer)));
+        assert_eq!(element.to_typed_value().unwrap(), vec![Type::Integer(3)]);
+    }
+}
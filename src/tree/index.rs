@@ -0,0 +1,317 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+use bigdecimal::ToPrimitive;
+use ordered_float::OrderedFloat;
+use crate::eval::Axis;
+use crate::parser::op::Comparison;
+use crate::tree::{NodeType, TypeAnnotation, XMLNode, DLN};
+use crate::values::{string_to, QName, Type};
+
+// value of range index entry: child element or attribute of indexed element
+#[derive(Clone, Debug, PartialEq)]
+pub enum IndexKey {
+    Child(QName),
+    Attribute(QName),
+}
+
+impl IndexKey {
+    fn same(&self, other: &IndexKey) -> bool {
+        match (self, other) {
+            (IndexKey::Child(a), IndexKey::Child(b)) |
+            (IndexKey::Attribute(a), IndexKey::Attribute(b)) => same_name(a, b),
+            _ => false
+        }
+    }
+}
+
+// index on `element[key]` values, answers predicates like `book[@id = $x]` or `book[price > 10]`
+#[derive(Clone, Debug, PartialEq)]
+pub struct RangeIndexConfig {
+    pub element: QName,
+    pub key: IndexKey,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IndexConfig {
+    // element and attribute names
+    pub names: bool,
+    pub ranges: Vec<RangeIndexConfig>,
+}
+
+type NameKey = (Option<String>, String);
+
+fn name_key(name: &QName) -> NameKey {
+    (name.url.clone(), name.local_part.clone())
+}
+
+fn same_name(a: &QName, b: &QName) -> bool {
+    a.url == b.url && a.local_part == b.local_part
+}
+
+fn is_wildcard(name: &QName) -> bool {
+    name.local_part == "*" || name.url == Some(String::from("*"))
+}
+
+// indexes of one tree, lists of ids are sorted in document order
+#[derive(Clone, Default)]
+pub(crate) struct Indexes {
+    names: bool,
+    elements: HashMap<NameKey, Vec<DLN>>,
+    attributes: HashMap<NameKey, Vec<(DLN, QName)>>,
+    ranges: Vec<(RangeIndexConfig, RangeIndex)>,
+}
+
+#[derive(Clone, Default)]
+struct RangeIndex {
+    strings: BTreeMap<String, Vec<DLN>>,
+    numbers: BTreeMap<OrderedFloat<f64>, Vec<DLN>>,
+    // NaN is not equal to anything, so only `!=` matches it
+    nan: Vec<DLN>,
+    // numeric comparison with such value must raise FORG0001, so it can't be answered by index
+    not_numeric: bool,
+    // typed values compare differently than string values
+    typed: bool,
+}
+
+impl RangeIndex {
+    fn add(&mut self, value: String, id: &DLN) {
+        match string_to::double(&value, false) {
+            Ok(Type::Double(number)) => {
+                if number.is_nan() {
+                    self.nan.push(id.clone());
+                } else {
+                    self.numbers.entry(number).or_default().push(id.clone());
+                }
+            }
+            _ => self.not_numeric = true
+        }
+        self.strings.entry(value).or_default().push(id.clone());
+    }
+}
+
+impl Indexes {
+    pub(crate) fn build(config: &IndexConfig, items: &BTreeMap<DLN, Box<dyn XMLNode>>) -> Self {
+        let mut indexes = Indexes {
+            names: config.names,
+            ranges: config.ranges.iter()
+                .map(|range| (range.clone(), RangeIndex::default()))
+                .collect(),
+            ..Indexes::default()
+        };
+
+        for (id, node) in items {
+            let name = match node.name() {
+                Some(name) => name,
+                None => continue
+            };
+            let attributes = node.get_attributes().unwrap_or_default();
+
+            if config.names {
+                indexes.elements.entry(name_key(&name)).or_default().push(id.clone());
+                for attribute in &attributes {
+                    indexes.attributes.entry(name_key(attribute)).or_default().push((id.clone(), attribute.clone()));
+                }
+            }
+
+            if node.get_type() != NodeType::Element {
+                continue;
+            }
+
+            for (range, index) in &mut indexes.ranges {
+                match &range.key {
+                    IndexKey::Attribute(key) => {
+                        if !same_name(&range.element, &name) {
+                            continue;
+                        }
+                        for attribute in &attributes {
+                            if same_name(key, attribute) {
+                                if !matches!(node.attribute_annotation(attribute), None | Some(TypeAnnotation::Untyped)) {
+                                    index.typed = true;
+                                }
+                                if let Some(value) = node.attribute_value(attribute) {
+                                    index.add(value, id);
+                                }
+                            }
+                        }
+                    }
+                    IndexKey::Child(key) => {
+                        if !same_name(key, &name) {
+                            continue;
+                        }
+                        let parent = match id.parent() {
+                            Some(parent) => parent,
+                            None => continue
+                        };
+                        let is_indexed = items.get(&parent)
+                            .and_then(|node| node.name())
+                            .map(|parent_name| same_name(&range.element, &parent_name))
+                            .unwrap_or(false);
+
+                        if is_indexed {
+                            if node.type_annotation() != TypeAnnotation::Untyped {
+                                index.typed = true;
+                            }
+                            let value: String = items.range(id..)
+                                .take_while(|(k, _)| k.start_with(id))
+                                .map(|(_, node)| node.string_value())
+                                .collect();
+
+                            index.add(value, &parent);
+                        }
+                    }
+                }
+            }
+        }
+
+        // element with several keys may be added more than once
+        for (_, index) in &mut indexes.ranges {
+            for ids in index.strings.values_mut().chain(index.numbers.values_mut()) {
+                ids.dedup();
+            }
+            index.nan.dedup();
+        }
+
+        indexes
+    }
+
+    pub(crate) fn elements(&self, name: &QName) -> Option<&[DLN]> {
+        if !self.names || is_wildcard(name) {
+            return None;
+        }
+        Some(self.elements.get(&name_key(name)).map(|ids| ids.as_slice()).unwrap_or(&[]))
+    }
+
+    pub(crate) fn attributes(&self, name: &QName) -> Option<&[(DLN, QName)]> {
+        if !self.names || is_wildcard(name) {
+            return None;
+        }
+        Some(self.attributes.get(&name_key(name)).map(|ids| ids.as_slice()).unwrap_or(&[]))
+    }
+
+    // elements which `key` compares with value as true, general comparison semantics
+    pub(crate) fn by_value(&self, element: &QName, key: &IndexKey, comparison: &Comparison, value: &Type) -> Option<Vec<DLN>> {
+        let (_, index) = self.ranges.iter()
+            .find(|(range, _)| same_name(&range.element, element) && range.key.same(key))?;
+
+        if index.typed {
+            return None;
+        }
+
+        let mut ids = match value {
+            Type::Untyped(str) |
+            Type::String(str) |
+            Type::NormalizedString(str) |
+            Type::AnyURI(str) |
+            Type::Token(str) |
+            Type::Language(str) |
+            Type::NMTOKEN(str) |
+            Type::Name(str) |
+            Type::NCName(str) |
+            Type::ID(str) |
+            Type::IDREF(str) |
+            Type::ENTITY(str) => select(&index.strings, comparison, str),
+            _ => {
+                let number = match to_double(value) {
+                    Some(number) => number,
+                    None => return None
+                };
+                if index.not_numeric {
+                    return None;
+                }
+                if number.is_nan() {
+                    match comparison {
+                        Comparison::NotEquals => {
+                            let mut ids: Vec<DLN> = index.numbers.values().flatten().cloned().collect();
+                            ids.extend(index.nan.iter().cloned());
+                            ids
+                        }
+                        _ => vec![]
+                    }
+                } else {
+                    let mut ids = select(&index.numbers, comparison, &OrderedFloat(number));
+                    if let Comparison::NotEquals = comparison {
+                        ids.extend(index.nan.iter().cloned());
+                    }
+                    ids
+                }
+            }
+        };
+
+        ids.sort();
+        ids.dedup();
+        Some(ids)
+    }
+}
+
+fn to_double(value: &Type) -> Option<f64> {
+    match value {
+        Type::UnsignedByte(num) => Some(*num as f64),
+        Type::UnsignedShort(num) => Some(*num as f64),
+        Type::UnsignedInt(num) => Some(*num as f64),
+        Type::UnsignedLong(num) => Some(*num as f64),
+        Type::Byte(num) => Some(*num as f64),
+        Type::Short(num) => Some(*num as f64),
+        Type::Int(num) => Some(*num as f64),
+        Type::Long(num) => Some(*num as f64),
+        Type::PositiveInteger(num) |
+        Type::NonNegativeInteger(num) |
+        Type::NonPositiveInteger(num) |
+        Type::NegativeInteger(num) |
+        Type::Integer(num) => Some(*num as f64),
+        Type::Decimal(num) => num.to_f64(),
+        Type::Float(num) => Some(num.into_inner() as f64),
+        Type::Double(num) => Some(num.into_inner()),
+        _ => None
+    }
+}
+
+fn select<K: Ord>(map: &BTreeMap<K, Vec<DLN>>, comparison: &Comparison, value: &K) -> Vec<DLN> {
+    let ranges: Vec<(Bound<&K>, Bound<&K>)> = match comparison {
+        Comparison::Equals => vec![(Bound::Included(value), Bound::Included(value))],
+        Comparison::NotEquals => vec![
+            (Bound::Unbounded, Bound::Excluded(value)),
+            (Bound::Excluded(value), Bound::Unbounded)
+        ],
+        Comparison::LessThan => vec![(Bound::Unbounded, Bound::Excluded(value))],
+        Comparison::LessOrEquals => vec![(Bound::Unbounded, Bound::Included(value))],
+        Comparison::GreaterThan => vec![(Bound::Excluded(value), Bound::Unbounded)],
+        Comparison::GreaterOrEquals => vec![(Bound::Included(value), Bound::Unbounded)],
+    };
+
+    ranges.into_iter()
+        .flat_map(|range| map.range::<K, _>(range))
+        .flat_map(|(_, ids)| ids.iter().cloned())
+        .collect()
+}
+
+// ids from sorted list which are reachable from context node by axis,
+// `all` is set when axis is applied to all descendants (initial "//")
+pub(crate) fn filter_by_axis<'a, T, F>(ids: &'a [T], id_of: F, context: &DLN, all: bool, axis: &Axis) -> Option<Vec<&'a T>>
+    where F: Fn(&T) -> &DLN
+{
+    let (include_self, children, descendants) = match axis {
+        Axis::ForwardSelf => (true, all, all),
+        Axis::ForwardChild => (false, true, all),
+        Axis::ForwardDescendant => (false, true, true),
+        Axis::ForwardDescendantOrSelf => (true, true, true),
+        _ => return None
+    };
+    let level = context.count_levels() + 1;
+
+    let start = ids.partition_point(|item| id_of(item) < context);
+    let result = ids[start..].iter()
+        .take_while(|item| id_of(item).start_with(context))
+        .filter(|item| {
+            let id = id_of(item);
+            if id == context {
+                include_self
+            } else if id.count_levels() == level {
+                children
+            } else {
+                descendants
+            }
+        })
+        .collect();
+
+    Some(result)
+}
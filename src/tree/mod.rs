@@ -4,14 +4,21 @@ use std::rc::Rc;
 use std::sync::Mutex;
 use dyn_clone::DynClone;
 use linked_hash_map::LinkedHashMap;
-use crate::values::QName;
+use crate::values::{QName, Type, Types};
 
+mod disk;
 mod dln;
 mod in_memory;
+mod index;
+mod storage;
 
+pub use disk::DiskStorage;
 pub use dln::DLN;
 pub use in_memory::InMemoryXMLTree;
-use crate::eval::{Axis, Environment, INS};
+pub use index::{IndexConfig, IndexKey, RangeIndexConfig};
+pub use storage::{FileSystemStorage, InMemoryStorage};
+use crate::eval::{Axis, Environment, ErrorInfo, INS};
+use crate::parser::op::Comparison;
 
 #[derive(Clone)]
 pub struct Reference {
@@ -46,11 +53,23 @@ impl Reference {
         storage.id()
     }
 
-    fn get_type(&self) -> Option<NodeType> {
+    pub(crate) fn get_type(&self) -> Option<NodeType> {
         let storage = self.storage.lock().unwrap();
         storage.as_reader().get_type(self)
     }
 
+    // follow linked nodes to the original one
+    pub(crate) fn resolve(&self) -> Reference {
+        if self.attr_name.is_none() {
+            if let Some(node) = self.get_node() {
+                if let Some(rf) = node.linked() {
+                    return rf.resolve();
+                }
+            }
+        }
+        self.clone()
+    }
+
     pub fn is_namespace(&self) -> bool {
         let storage = self.storage.lock().unwrap();
         storage.as_reader().is_namespace(self)
@@ -75,11 +94,26 @@ impl Reference {
         storage.as_reader().to_xml(self)
     }
 
-    pub fn to_typed_value(&self) -> Result<String, String> {
+    pub fn to_string_value(&self) -> Result<String, String> {
+        let storage = self.storage.lock().unwrap();
+        storage.as_reader().string_value_of_node(self)
+    }
+
+    pub fn to_typed_value(&self) -> Result<Vec<Type>, ErrorInfo> {
         let storage = self.storage.lock().unwrap();
         storage.as_reader().typed_value_of_node(self)
     }
 
+    pub fn type_annotation(&self) -> TypeAnnotation {
+        let storage = self.storage.lock().unwrap();
+        storage.as_reader().type_annotation(self)
+    }
+
+    pub fn annotate(&self, annotation: TypeAnnotation) -> bool {
+        let mut storage = self.storage.lock().unwrap();
+        storage.annotate(self, annotation)
+    }
+
     pub fn deep_eq(&self, other: &Reference) -> bool {
         if Rc::ptr_eq(&self.storage, &other.storage) {
             self.id == other.id
@@ -194,6 +228,16 @@ impl Reference {
         storage.as_reader().forward(&self, initial_node_sequence, axis)
     }
 
+    pub(crate) fn forward_by_name(&self, initial_node_sequence: &Option<INS>, axis: &Axis, name: &QName) -> Option<Vec<Reference>> {
+        let storage = self.storage.lock().unwrap();
+        storage.as_reader().forward_by_name(&self, initial_node_sequence, axis, name)
+    }
+
+    pub(crate) fn forward_by_value(&self, initial_node_sequence: &Option<INS>, axis: &Axis, name: &QName, key: &IndexKey, comparison: &Comparison, value: &Type) -> Option<Vec<Reference>> {
+        let storage = self.storage.lock().unwrap();
+        storage.as_reader().forward_by_value(&self, initial_node_sequence, axis, name, key, comparison, value)
+    }
+
     pub(crate) fn dump(&self) -> String {
         match self.storage.try_lock() {
             Ok(storage) => storage.as_reader().dump(&self),
@@ -216,6 +260,57 @@ impl PartialEq for Reference {
     }
 }
 
+// type annotation of element or attribute node, without schema everything is untyped
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeAnnotation {
+    // xs:untyped for elements and xs:untypedAtomic for attributes
+    Untyped,
+    // simple type with atomic values
+    Atomic(Types),
+    // list type, string value is whitespace separated sequence of items
+    List(Types),
+}
+
+impl TypeAnnotation {
+    // https://www.w3.org/TR/xpath-datamodel-31/#TypedValueDetermination
+    pub fn typed_value(&self, value: String) -> Result<Vec<Type>, ErrorInfo> {
+        match self {
+            TypeAnnotation::Untyped => Ok(vec![Type::Untyped(value)]),
+            TypeAnnotation::Atomic(types) => {
+                let t = Type::Untyped(value).convert(types.clone())?;
+                Ok(vec![t])
+            }
+            TypeAnnotation::List(types) => {
+                let mut result = vec![];
+                for item in value.split_whitespace() {
+                    let t = Type::Untyped(item.to_string()).convert(types.clone())?;
+                    result.push(t);
+                }
+                Ok(result)
+            }
+        }
+    }
+}
+
+// content of node as it's kept by storage, without its id and children
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum NodeRecord {
+    Document,
+    Element { name: QName, annotation: TypeAnnotation, attributes: Vec<(QName, String, TypeAnnotation)> },
+    Text(String),
+    Comment(String),
+    PI { target: QName, content: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InsertPosition {
+    Into,
+    AsFirstInto,
+    AsLastInto,
+    Before,
+    After,
+}
+
 #[derive(Debug, PartialEq)]
 pub enum NodeType {
     Document,
@@ -233,12 +328,24 @@ pub trait XMLNode: DynClone + Debug {
     fn target(&self) -> Option<QName>;
     fn content(&self) -> Option<String>;
 
-    fn typed_value(&self) -> String;
+    fn string_value(&self) -> String;
+    fn type_annotation(&self) -> TypeAnnotation;
 
     fn attribute_value(&self, name: &QName) -> Option<String>;
+    fn attribute_annotation(&self, name: &QName) -> Option<TypeAnnotation>;
     fn add_attribute(&mut self, name: QName, value: String) -> bool;
+
+    fn annotate(&mut self, attr_name: Option<&QName>, annotation: TypeAnnotation) -> bool;
     fn get_attributes(&self) -> Option<Vec<QName>>;
 
+    // updates
+    fn set_id(&mut self, id: DLN);
+    fn rename(&mut self, attr_name: Option<&QName>, name: QName) -> bool;
+    fn set_value(&mut self, attr_name: Option<&QName>, value: String) -> bool;
+    fn remove_attribute(&mut self, name: &QName) -> bool;
+
+    fn linked(&self) -> Option<Reference>;
+
     // tests
     fn get_type(&self) -> NodeType;
     fn is_text(&self) -> bool;
@@ -263,9 +370,14 @@ pub trait XMLNode: DynClone + Debug {
 
 dyn_clone::clone_trait_object!(XMLNode);
 
+// persistence of documents by uri
 pub trait XMLStorage {
-    fn reader(&self) -> Option<Box<dyn XMLTreeReader>>;
-    fn writer(&self) -> Option<Box<dyn XMLTreeWriter>>;
+    // new tree with given id or None if there is no such document
+    fn open(&self, uri: &str, id: usize) -> Result<Option<Rc<Mutex<Box<dyn XMLTreeWriter>>>>, ErrorInfo>;
+
+    fn save(&mut self, uri: &str, rf: &Reference) -> Result<(), ErrorInfo>;
+
+    fn list(&self) -> Result<Vec<String>, ErrorInfo>;
 }
 
 pub trait XMLTreeWriter: DynClone {
@@ -275,6 +387,9 @@ pub trait XMLTreeWriter: DynClone {
 
     fn as_reader(&self) -> Box<&dyn XMLTreeReader>;
 
+    // enable indexes for lookups by name and value
+    fn index(&mut self, config: IndexConfig);
+
     fn link_node(&mut self, rf: &Reference) -> Reference;
 
     fn start_document(&mut self) -> Reference;
@@ -294,10 +409,35 @@ pub trait XMLTreeWriter: DynClone {
     fn text(&mut self, content: String) -> Reference;
 
     fn comment(&mut self, content: String) -> Reference;
+
+    fn annotate(&mut self, rf: &Reference, annotation: TypeAnnotation) -> bool;
+
+    // updates of existing nodes, inserted nodes must be from other storage;
+    // insert and delete may renumber siblings and return moved nodes as (old id, new id)
+    fn insert(&mut self, target: &Reference, position: InsertPosition, nodes: &Vec<Reference>) -> Result<Vec<(DLN, DLN)>, ErrorInfo>;
+
+    fn insert_attributes(&mut self, target: &Reference, attributes: &Vec<Reference>) -> Result<(), ErrorInfo>;
+
+    fn delete(&mut self, target: &Reference) -> Result<Vec<(DLN, DLN)>, ErrorInfo>;
+
+    fn replace_value(&mut self, target: &Reference, value: String) -> Result<(), ErrorInfo>;
+
+    fn rename(&mut self, target: &Reference, name: QName) -> Result<(), ErrorInfo>;
+
+    // move nodes to given ids as (current id, new id), used when ids are loaded from storage
+    fn restore_ids(&mut self, ids: Vec<(DLN, DLN)>);
 }
 
 dyn_clone::clone_trait_object!(XMLTreeWriter);
 
+// tree shared by references to its nodes
+pub(crate) fn shared(tree: Box<dyn XMLTreeWriter>) -> Rc<Mutex<Box<dyn XMLTreeWriter>>> {
+    let rf = Rc::new(Mutex::new(tree));
+    let clone = rf.clone();
+    rf.lock().unwrap().init(clone);
+    rf
+}
+
 pub trait XMLTreeReader: DynClone {
     fn name(&self, pointer: &Reference) -> Option<QName>;
 
@@ -309,7 +449,11 @@ pub trait XMLTreeReader: DynClone {
 
     fn to_xml(&self, rf: &Reference) -> Result<String, String>;
 
-    fn typed_value_of_node(&self, rf: &Reference) -> Result<String, String>;
+    fn string_value_of_node(&self, rf: &Reference) -> Result<String, String>;
+
+    fn typed_value_of_node(&self, rf: &Reference) -> Result<Vec<Type>, ErrorInfo>;
+
+    fn type_annotation(&self, rf: &Reference) -> TypeAnnotation;
 
     fn first(&self) -> Option<Reference>;
 
@@ -321,6 +465,10 @@ pub trait XMLTreeReader: DynClone {
     fn forward(&self, rf: &Reference, initial_node_sequence: &Option<INS>, axis: &Axis) -> Vec<Reference>;
     fn attributes(&self, rf: &Reference) -> Option<Vec<Reference>>;
 
+    // same as forward with name test (and predicate on key of range index), None if tree isn't indexed for it
+    fn forward_by_name(&self, rf: &Reference, initial_node_sequence: &Option<INS>, axis: &Axis, name: &QName) -> Option<Vec<Reference>>;
+    fn forward_by_value(&self, rf: &Reference, initial_node_sequence: &Option<INS>, axis: &Axis, name: &QName, key: &IndexKey, comparison: &Comparison, value: &Type) -> Option<Vec<Reference>>;
+
     // tests
     fn get_node(&self, rf: &Reference) -> Option<Box<dyn XMLNode>>;
     fn get_type(&self, rf: &Reference) -> Option<NodeType>;
@@ -337,4 +485,45 @@ pub trait XMLTreeReader: DynClone {
     fn dump(&self, rf: &Reference) -> String;
 }
 
-dyn_clone::clone_trait_object!(XMLTreeReader);
\ No newline at end of file
+dyn_clone::clone_trait_object!(XMLTreeReader);
+
+// deep copy of node (linked nodes are resolved) with type annotations
+pub(crate) fn copy_node(writer: &mut Box<dyn XMLTreeWriter>, rf: &Reference) -> Option<Reference> {
+    let rf = rf.resolve();
+    if let Some(name) = &rf.attr_name {
+        let value = rf.to_string_value().ok()?;
+        let copy = writer.attribute(name.clone(), value);
+        writer.annotate(&copy, rf.type_annotation());
+        return Some(copy);
+    }
+
+    let copy = match rf.get_type()? {
+        NodeType::Document => {
+            let copy = writer.start_document();
+            for child in rf.forward(&None, &Axis::ForwardChild) {
+                copy_node(writer, &child);
+            }
+            writer.end_document();
+            copy
+        }
+        NodeType::Element => {
+            let copy = writer.start_element(rf.name()?);
+            writer.annotate(&copy, rf.type_annotation());
+            if let Some(attributes) = rf.attributes() {
+                for attribute in attributes {
+                    copy_node(writer, &attribute);
+                }
+            }
+            for child in rf.forward(&None, &Axis::ForwardChild) {
+                copy_node(writer, &child);
+            }
+            writer.end_element();
+            copy
+        }
+        NodeType::Text => writer.text(rf.content()?),
+        NodeType::Comment => writer.comment(rf.content()?),
+        NodeType::PI => writer.pi(rf.target()?, rf.content()?),
+        NodeType::Attribute => return None,
+    };
+    Some(copy)
+}
@@ -0,0 +1,213 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Mutex;
+use crate::eval::ErrorInfo;
+use crate::parser::errors::ErrorCode;
+use crate::tree::{InMemoryXMLTree, Reference, XMLStorage, XMLTreeWriter};
+
+// documents as *.xml files under root folder
+#[derive(Clone)]
+pub struct FileSystemStorage {
+    root: PathBuf,
+}
+
+impl FileSystemStorage {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        FileSystemStorage { root: root.into() }
+    }
+
+    // file of uri, it must stay under root, so absolute paths, ".." and symlinks out of root are refused
+    fn path(&self, uri: &str) -> Result<PathBuf, String> {
+        let path = uri.strip_prefix("file://")
+            .or_else(|| uri.strip_prefix("file:"))
+            .unwrap_or(uri);
+
+        let path = Path::new(path);
+        if !path.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir)) {
+            return Err(format!("{:?} is outside of storage root", uri));
+        }
+
+        let path = self.root.join(path);
+        if self.inside_root(&path) {
+            Ok(path)
+        } else {
+            Err(format!("{:?} is outside of storage root", uri))
+        }
+    }
+
+    // canonical form of the deepest existing ancestor must be under canonical root
+    fn inside_root(&self, path: &Path) -> bool {
+        let root = match self.root.canonicalize() {
+            Ok(root) => root,
+            Err(_) => return false
+        };
+        path.ancestors()
+            .find_map(|ancestor| ancestor.canonicalize().ok())
+            .map(|existing| existing.starts_with(&root))
+            .unwrap_or(false)
+    }
+
+    fn collect(&self, folder: &Path, uris: &mut Vec<String>) -> Result<(), ErrorInfo> {
+        let entries = match fs::read_dir(folder) {
+            Ok(entries) => entries,
+            Err(e) => return Err((ErrorCode::FODC0002, format!("can't list {:?}: {}", folder, e)))
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if !self.inside_root(&path) {
+                continue;
+            }
+            if path.is_dir() {
+                self.collect(&path, uris)?;
+            } else if path.extension().map(|ext| ext == "xml").unwrap_or(false) {
+                if let Ok(relative) = path.strip_prefix(&self.root) {
+                    let uri = relative.components()
+                        .map(|c| c.as_os_str().to_string_lossy().to_string())
+                        .collect::<Vec<_>>()
+                        .join("/");
+                    uris.push(uri);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl XMLStorage for FileSystemStorage {
+    fn open(&self, uri: &str, id: usize) -> Result<Option<Rc<Mutex<Box<dyn XMLTreeWriter>>>>, ErrorInfo> {
+        let path = match self.path(uri) {
+            Ok(path) => path,
+            Err(msg) => return Err((ErrorCode::FODC0002, msg))
+        };
+        if !path.is_file() {
+            return Ok(None);
+        }
+        match fs::read_to_string(&path) {
+            Ok(data) => InMemoryXMLTree::parse(id, data.as_str()).map(Some),
+            Err(e) => Err((ErrorCode::FODC0002, format!("can't read {:?}: {}", uri, e)))
+        }
+    }
+
+    fn save(&mut self, uri: &str, rf: &Reference) -> Result<(), ErrorInfo> {
+        let xml = match rf.to_xml() {
+            Ok(xml) => xml,
+            Err(msg) => return Err((ErrorCode::FOUP0002, msg))
+        };
+
+        let path = match self.path(uri) {
+            Ok(path) => path,
+            Err(msg) => return Err((ErrorCode::FOUP0002, msg))
+        };
+        if let Some(folder) = path.parent() {
+            if let Err(e) = fs::create_dir_all(folder) {
+                return Err((ErrorCode::FOUP0002, format!("can't write {:?}: {}", uri, e)));
+            }
+        }
+        match fs::write(&path, xml) {
+            Ok(_) => Ok(()),
+            Err(e) => Err((ErrorCode::FOUP0002, format!("can't write {:?}: {}", uri, e)))
+        }
+    }
+
+    fn list(&self) -> Result<Vec<String>, ErrorInfo> {
+        let mut uris = vec![];
+        self.collect(&self.root, &mut uris)?;
+        uris.sort();
+        Ok(uris)
+    }
+}
+
+// serialized documents kept in memory
+#[derive(Clone, Default)]
+pub struct InMemoryStorage {
+    documents: BTreeMap<String, String>,
+}
+
+impl InMemoryStorage {
+    pub fn new() -> Self {
+        InMemoryStorage { documents: BTreeMap::new() }
+    }
+
+    pub fn insert<S: Into<String>>(&mut self, uri: S, data: S) {
+        self.documents.insert(uri.into(), data.into());
+    }
+
+    pub fn get(&self, uri: &str) -> Option<&String> {
+        self.documents.get(uri)
+    }
+}
+
+impl XMLStorage for InMemoryStorage {
+    fn open(&self, uri: &str, id: usize) -> Result<Option<Rc<Mutex<Box<dyn XMLTreeWriter>>>>, ErrorInfo> {
+        match self.documents.get(uri) {
+            Some(data) => InMemoryXMLTree::parse(id, data.as_str()).map(Some),
+            None => Ok(None)
+        }
+    }
+
+    fn save(&mut self, uri: &str, rf: &Reference) -> Result<(), ErrorInfo> {
+        match rf.to_xml() {
+            Ok(xml) => {
+                self.documents.insert(uri.to_string(), xml);
+                Ok(())
+            }
+            Err(msg) => Err((ErrorCode::FOUP0002, msg))
+        }
+    }
+
+    fn list(&self) -> Result<Vec<String>, ErrorInfo> {
+        Ok(self.documents.keys().cloned().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::parser::errors::ErrorCode;
+    use crate::tree::XMLStorage;
+    use super::{FileSystemStorage, InMemoryStorage};
+
+    #[test]
+    fn save_and_open() {
+        let mut storage = InMemoryStorage::new();
+        storage.insert("a.xml", "<a><b/></a>");
+
+        let tree = storage.open("a.xml", 1).unwrap().unwrap();
+        let document = tree.lock().unwrap().as_reader().first().unwrap();
+        storage.save("copy.xml", &document).unwrap();
+
+        assert_eq!(storage.list().unwrap(), vec!["a.xml".to_string(), "copy.xml".to_string()]);
+        assert_eq!(storage.get("copy.xml"), Some(&document.to_xml().unwrap()));
+        assert!(storage.open("b.xml", 2).unwrap().is_none());
+    }
+
+    #[test]
+    fn file_system_stays_under_root() {
+        let folder = std::env::temp_dir().join(format!("rustle-storage-{}", std::process::id()));
+        let root = folder.join("root");
+        fs::create_dir_all(&root).unwrap();
+        fs::write(folder.join("outside.xml"), "<outside/>").unwrap();
+        fs::write(root.join("inside.xml"), "<inside/>").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&folder, root.join("link")).unwrap();
+
+        let mut storage = FileSystemStorage::new(&root);
+        assert!(storage.open("inside.xml", 1).unwrap().is_some());
+
+        let outside = folder.join("outside.xml").to_string_lossy().to_string();
+        for uri in [outside.as_str(), "../outside.xml", "link/outside.xml", "file:///etc/hosts"] {
+            assert_eq!(storage.open(uri, 2).err().map(|(code, _)| code), Some(ErrorCode::FODC0002), "{}", uri);
+        }
+
+        let document = storage.open("inside.xml", 3).unwrap().unwrap().lock().unwrap().as_reader().first().unwrap();
+        for uri in ["../written.xml", "link/written.xml"] {
+            assert_eq!(storage.save(uri, &document).err().map(|(code, _)| code), Some(ErrorCode::FOUP0002), "{}", uri);
+        }
+        assert!(!folder.join("written.xml").exists());
+        assert_eq!(storage.list().unwrap(), vec!["inside.xml".to_string()]);
+
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
@@ -0,0 +1,347 @@
+use std::cmp::Ordering;
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::is_combining_mark;
+use crate::eval::ErrorInfo;
+use crate::parser::errors::ErrorCode;
+
+pub(crate) const CODEPOINT_COLLATION: &str = "http://www.w3.org/2005/xpath-functions/collation/codepoint";
+pub(crate) const HTML_ASCII_CASE_INSENSITIVE_COLLATION: &str = "http://www.w3.org/2005/xpath-functions/collation/html-ascii-case-insensitive";
+pub(crate) const UCA_COLLATION: &str = "http://www.w3.org/2013/collation/UCA";
+
+pub(crate) trait Collation {
+    // keys of strings order the same way as strings do, equal strings have equal keys
+    fn key(&self, value: &str) -> Vec<u8>;
+
+    // form of character used for substring matching
+    fn fold(&self, ch: char) -> String;
+
+    fn compare(&self, left: &str, right: &str) -> Ordering {
+        self.key(left).cmp(&self.key(right))
+    }
+
+    fn equals(&self, left: &str, right: &str) -> bool {
+        self.compare(left, right) == Ordering::Equal
+    }
+}
+
+pub(crate) fn resolve_collation(uri: &str) -> Result<Box<dyn Collation>, ErrorInfo> {
+    match uri {
+        CODEPOINT_COLLATION => Ok(Box::new(Codepoint)),
+        HTML_ASCII_CASE_INSENSITIVE_COLLATION => Ok(Box::new(HtmlAsciiCaseInsensitive)),
+        _ => {
+            if let Some(params) = uri.strip_prefix(UCA_COLLATION) {
+                if params.is_empty() {
+                    return Uca::parse("");
+                } else if let Some(params) = params.strip_prefix('?') {
+                    return Uca::parse(params);
+                }
+            }
+            Err((ErrorCode::FOCH0002, format!("unsupported collation {}", uri)))
+        }
+    }
+}
+
+// byte range of first occurrence of pattern in value, characters are matched by folded forms
+pub(crate) fn collation_find(collation: &dyn Collation, value: &str, pattern: &str) -> Option<(usize, usize)> {
+    let pattern: String = pattern.chars().map(|ch| collation.fold(ch)).collect();
+    if pattern.is_empty() {
+        return Some((0, 0));
+    }
+
+    // folded value with offsets of characters in folded and original strings
+    let mut folded = String::with_capacity(value.len());
+    let mut offsets = Vec::with_capacity(value.len() + 1);
+    for (offset, ch) in value.char_indices() {
+        offsets.push((folded.len(), offset));
+        folded.push_str(&collation.fold(ch));
+    }
+    offsets.push((folded.len(), value.len()));
+
+    for (i, (start, original_start)) in offsets.iter().enumerate() {
+        if folded[*start..].starts_with(&pattern) {
+            let end = start + pattern.len();
+            if let Some((_, original_end)) = offsets[i..].iter().find(|(offset, _)| *offset == end) {
+                return Some((*original_start, *original_end));
+            }
+        }
+    }
+    None
+}
+
+pub(crate) fn collation_starts_with(collation: &dyn Collation, value: &str, pattern: &str) -> bool {
+    let value: String = value.chars().map(|ch| collation.fold(ch)).collect();
+    let pattern: String = pattern.chars().map(|ch| collation.fold(ch)).collect();
+    value.starts_with(&pattern)
+}
+
+pub(crate) fn collation_ends_with(collation: &dyn Collation, value: &str, pattern: &str) -> bool {
+    let value: String = value.chars().map(|ch| collation.fold(ch)).collect();
+    let pattern: String = pattern.chars().map(|ch| collation.fold(ch)).collect();
+    value.ends_with(&pattern)
+}
+
+struct Codepoint;
+
+impl Collation for Codepoint {
+    fn key(&self, value: &str) -> Vec<u8> {
+        // UTF-8 keeps order of codepoints
+        value.as_bytes().to_vec()
+    }
+
+    fn fold(&self, ch: char) -> String {
+        ch.to_string()
+    }
+
+    fn compare(&self, left: &str, right: &str) -> Ordering {
+        left.cmp(right)
+    }
+}
+
+struct HtmlAsciiCaseInsensitive;
+
+impl Collation for HtmlAsciiCaseInsensitive {
+    fn key(&self, value: &str) -> Vec<u8> {
+        value.to_ascii_lowercase().into_bytes()
+    }
+
+    fn fold(&self, ch: char) -> String {
+        ch.to_ascii_lowercase().to_string()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+enum Strength {
+    Primary,
+    Secondary,
+    Tertiary,
+    Quaternary,
+    Identical,
+}
+
+// approximation of root collation without tailoring: base characters, then accents, then case;
+// at primary level whitespace and punctuation go before digits and digits before letters,
+// inside of those groups characters are ordered by code point
+struct Uca {
+    strength: Strength,
+    upper_first: bool,
+    numeric: bool,
+}
+
+impl Uca {
+    fn parse(params: &str) -> Result<Box<dyn Collation>, ErrorInfo> {
+        let mut collation = Uca { strength: Strength::Tertiary, upper_first: false, numeric: false };
+
+        let mut fallback = true;
+        let mut unsupported = vec![];
+        for param in params.split(';').filter(|param| !param.is_empty()) {
+            let (name, value) = match param.split_once('=') {
+                Some(pair) => pair,
+                None => {
+                    unsupported.push(param);
+                    continue;
+                }
+            };
+            let supported = match (name, value) {
+                ("fallback", "yes") => true,
+                ("fallback", "no") => {
+                    fallback = false;
+                    true
+                }
+                ("strength", "primary") | ("strength", "1") => {
+                    collation.strength = Strength::Primary;
+                    true
+                }
+                ("strength", "secondary") | ("strength", "2") => {
+                    collation.strength = Strength::Secondary;
+                    true
+                }
+                ("strength", "tertiary") | ("strength", "3") => {
+                    collation.strength = Strength::Tertiary;
+                    true
+                }
+                ("strength", "quaternary") | ("strength", "4") => {
+                    collation.strength = Strength::Quaternary;
+                    true
+                }
+                ("strength", "identical") | ("strength", "5") => {
+                    collation.strength = Strength::Identical;
+                    true
+                }
+                ("caseFirst", "upper") => {
+                    collation.upper_first = true;
+                    true
+                }
+                ("caseFirst", "lower") | ("caseFirst", "off") => {
+                    collation.upper_first = false;
+                    true
+                }
+                ("numeric", "yes") => {
+                    collation.numeric = true;
+                    true
+                }
+                ("numeric", "no") => {
+                    collation.numeric = false;
+                    true
+                }
+                // no tailoring, only languages which are sorted by root collation are honoured
+                ("lang", lang) => is_root_language(lang),
+                ("alternate", "non-ignorable") |
+                ("backwards", "no") |
+                ("caseLevel", "no") |
+                ("normalization", "yes") | ("normalization", "no") => true,
+                _ => false
+            };
+            if !supported {
+                unsupported.push(param);
+            }
+        }
+
+        if !fallback && !unsupported.is_empty() {
+            Err((ErrorCode::FOCH0002, format!("unsupported collation parameters {}", unsupported.join(";"))))
+        } else {
+            Ok(Box::new(collation))
+        }
+    }
+}
+
+// languages without tailoring of root collation
+fn is_root_language(lang: &str) -> bool {
+    let language = lang.split(['-', '_']).next().unwrap_or(lang).to_ascii_lowercase();
+    matches!(language.as_str(), "en" | "root" | "und")
+}
+
+// primary weight of base character, case is ignored
+fn primary(ch: char) -> u32 {
+    if ch.is_numeric() {
+        0x110000 + ch as u32
+    } else if ch.is_alphanumeric() {
+        0x220000 + ch.to_lowercase().next().unwrap_or(ch) as u32
+    } else {
+        ch as u32
+    }
+}
+
+// three bytes weight, zero is reserved for separators
+fn push_weight(key: &mut Vec<u8>, weight: u32) {
+    key.extend_from_slice(&(weight + 1).to_be_bytes()[1..]);
+}
+
+impl Collation for Uca {
+    fn key(&self, value: &str) -> Vec<u8> {
+        let chars: Vec<char> = value.nfd().collect();
+
+        // base characters with their combining marks
+        let mut units: Vec<(char, Vec<char>)> = vec![];
+        for ch in &chars {
+            if is_combining_mark(*ch) {
+                if let Some((_, marks)) = units.last_mut() {
+                    marks.push(*ch);
+                    continue;
+                }
+            }
+            units.push((*ch, vec![]));
+        }
+
+        let mut key = Vec::with_capacity(chars.len() * 8);
+
+        // primary level: base characters regardless of case
+        let mut i = 0;
+        while i < units.len() {
+            let ch = units[i].0;
+            if self.numeric && ch.is_ascii_digit() {
+                let mut digits = vec![];
+                while i < units.len() && units[i].0.is_ascii_digit() {
+                    digits.push(units[i].0);
+                    i += 1;
+                }
+                let zeros = digits.iter().take_while(|d| **d == '0').count().min(digits.len() - 1);
+                let digits = &digits[zeros..];
+
+                // longer numbers are greater
+                push_weight(&mut key, primary('0'));
+                push_weight(&mut key, digits.len() as u32);
+                for digit in digits {
+                    push_weight(&mut key, primary(*digit));
+                }
+                continue;
+            }
+            for lower in ch.to_lowercase() {
+                push_weight(&mut key, primary(lower));
+            }
+            i += 1;
+        }
+
+        // secondary level: accents
+        if self.strength >= Strength::Secondary {
+            key.extend_from_slice(&[0, 0, 0]);
+            for (_, marks) in &units {
+                for mark in marks {
+                    push_weight(&mut key, *mark as u32);
+                }
+                push_weight(&mut key, 0);
+            }
+        }
+
+        // tertiary level: case
+        if self.strength >= Strength::Tertiary {
+            key.push(0);
+            for (ch, _) in &units {
+                // lower case first unless caseFirst=upper
+                key.push(if ch.is_uppercase() != self.upper_first { 2 } else { 1 });
+            }
+        }
+
+        if self.strength >= Strength::Identical {
+            key.push(0);
+            for ch in &chars {
+                push_weight(&mut key, *ch as u32);
+            }
+        }
+
+        key
+    }
+
+    fn fold(&self, ch: char) -> String {
+        match self.strength {
+            Strength::Primary => ch.nfd()
+                .filter(|ch| !is_combining_mark(*ch))
+                .flat_map(|ch| ch.to_lowercase())
+                .collect(),
+            Strength::Secondary => ch.nfd()
+                .flat_map(|ch| ch.to_lowercase())
+                .collect(),
+            _ => ch.nfd().collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::errors::ErrorCode;
+    use crate::tests::test_eval_queries;
+
+    #[test]
+    fn eval_collations() {
+        test_eval_queries(&[
+            ("for $x in ('b', 'a', 'C') order by $x return $x", Ok("C a b")),
+            ("for $x in ('b', 'a', 'C') order by $x descending collation 'http://www.w3.org/2013/collation/UCA' return $x", Ok("C b a")),
+            ("for $x in (2, 1), $y in ('b', 'a') order by $y, $x return $x || $y", Ok("1a 2a 1b 2b")),
+            ("compare('résumé', 'Resume', 'http://www.w3.org/2013/collation/UCA?lang=fr;strength=primary')", Ok("0")),
+            ("compare('file10', 'file9', 'http://www.w3.org/2013/collation/UCA?numeric=yes')", Ok("1")),
+            ("contains('Résumé', 'SUM', 'http://www.w3.org/2013/collation/UCA?strength=primary')", Ok("true")),
+            ("substring-after('Résumé', 'SU', 'http://www.w3.org/2013/collation/UCA?strength=primary')", Ok("mé")),
+            ("count(distinct-values(('a', 'A', 'b'), 'http://www.w3.org/2005/xpath-functions/collation/html-ascii-case-insensitive'))", Ok("2")),
+            ("deep-equal(('a', 'b'), ('A', 'B'), 'http://www.w3.org/2005/xpath-functions/collation/html-ascii-case-insensitive')", Ok("true")),
+            ("sort(('b', 'a', 'C'), 'http://www.w3.org/2013/collation/UCA')", Ok("a b C")),
+            ("declare default collation 'http://www.w3.org/2013/collation/UCA?strength=secondary'; 'ABC' eq 'abc'", Ok("true")),
+            ("compare('a', 'b', 'http://www.w3.org/2013/collation/UCA?reorder=Grek;fallback=no')", Err(ErrorCode::FOCH0002)),
+            // tailorings which aren't implemented are rejected without fallback
+            ("compare('a', 'b', 'http://www.w3.org/2013/collation/UCA?lang=sv;fallback=no')", Err(ErrorCode::FOCH0002)),
+            ("compare('a', 'b', 'http://www.w3.org/2013/collation/UCA?version=6.2.0;fallback=no')", Err(ErrorCode::FOCH0002)),
+            ("compare('a', 'b', 'http://www.w3.org/2013/collation/UCA?lang=sv')", Ok("-1")),
+            ("compare('a', 'B', 'http://www.w3.org/2013/collation/UCA?lang=en-US;fallback=no')", Ok("-1")),
+            ("sort(('a', '_', '1', ' '), 'http://www.w3.org/2013/collation/UCA') => string-join('|')", Ok(" |_|1|a")),
+            ("declare default collation 'http://example.com/unknown'; 1", Err(ErrorCode::XQST0038)),
+        ]);
+    }
+}
@@ -6,3 +6,10 @@ pub use qname::*;
 
 mod value;
 pub use value::*;
+
+mod ordered_map;
+pub use ordered_map::OrderedMap;
+pub(crate) use ordered_map::same_key;
+
+mod collation;
+pub(crate) use collation::*;
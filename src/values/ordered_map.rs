@@ -0,0 +1,254 @@
+use std::fmt;
+use std::fmt::{Debug, Formatter};
+use std::mem::{discriminant, Discriminant};
+use bigdecimal::BigDecimal;
+use bigdecimal::num_bigint::BigInt;
+use chrono::{Duration, FixedOffset, NaiveDate, NaiveDateTime};
+use im_rc::{HashMap, OrdMap};
+use ordered_float::OrderedFloat;
+use crate::values::{Object, Type};
+use crate::values::time::Time;
+
+// persistent map which keeps insertion order of keys, updates share structure with original map.
+// entries are found by same key form of key (op:same-key), original key is kept for map:keys
+#[derive(Clone, Default)]
+pub struct OrderedMap {
+    entries: HashMap<KeyForm, (u64, Type, Object)>,
+    order: OrdMap<u64, KeyForm>,
+    sequence: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum KeyForm {
+    Value(Type),
+    // date and g* values with timezone by starting instant in UTC, values of different types are never same key
+    Instant(Discriminant<Type>, NaiveDateTime),
+    // durations of any type by months and seconds
+    Duration(i64, i128),
+}
+
+// op:same-key($k1 as xs:anyAtomicType, $k2 as xs:anyAtomicType) as xs:boolean
+pub(crate) fn same_key(k1: &Type, k2: &Type) -> bool {
+    key_form(k1) == key_form(k2)
+}
+
+// keys are same key when their forms are equal: strings compare by codepoints whatever their type,
+// numerics by mathematical value with NaN equal to itself, date/time values with timezone by instant
+fn key_form(key: &Type) -> KeyForm {
+    match key {
+        Type::Date { date, offset: true } => match date.and_hms_opt(0, 0, 0) {
+            Some(dt) => KeyForm::Instant(discriminant(key), dt.naive_utc()),
+            None => KeyForm::Value(key.clone())
+        },
+        Type::GYearMonth { year, month, tz_m: Some(tz_m) } => g_form(key, *year, *month, 1, *tz_m),
+        Type::GYear { year, tz_m: Some(tz_m) } => g_form(key, *year, 1, 1, *tz_m),
+        // reference year and month as used for comparison
+        Type::GMonthDay { month, day, tz_m: Some(tz_m) } => g_form(key, 1972, *month, *day, *tz_m),
+        Type::GDay { day, tz_m: Some(tz_m) } => g_form(key, 1972, 12, *day, *tz_m),
+        Type::GMonth { month, tz_m: Some(tz_m) } => g_form(key, 1972, *month, 1, *tz_m),
+
+        Type::Duration { positive, years, months, days, hours, minutes, seconds, microseconds } => {
+            duration_form(*positive, *years, *months, (*days, *hours, *minutes, *seconds, *microseconds))
+        }
+        Type::YearMonthDuration { positive, years, months } => {
+            duration_form(*positive, *years, *months, (0, 0, 0, 0, 0))
+        }
+        Type::DayTimeDuration { positive, days, hours, minutes, seconds, microseconds } => {
+            duration_form(*positive, 0, 0, (*days, *hours, *minutes, *seconds, *microseconds))
+        }
+
+        _ => KeyForm::Value(value_form(key))
+    }
+}
+
+fn value_form(key: &Type) -> Type {
+    match key {
+        Type::Untyped(str) |
+        Type::String(str) |
+        Type::NormalizedString(str) |
+        Type::AnyURI(str) |
+        Type::Token(str) |
+        Type::Language(str) |
+        Type::NMTOKEN(str) |
+        Type::Name(str) |
+        Type::NCName(str) |
+        Type::ID(str) |
+        Type::IDREF(str) |
+        Type::ENTITY(str) => Type::String(str.clone()),
+
+        Type::UnsignedByte(num) => Type::Integer(*num as i128),
+        Type::UnsignedShort(num) => Type::Integer(*num as i128),
+        Type::UnsignedInt(num) => Type::Integer(*num as i128),
+        Type::UnsignedLong(num) => Type::Integer(*num as i128),
+        Type::Byte(num) => Type::Integer(*num as i128),
+        Type::Short(num) => Type::Integer(*num as i128),
+        Type::Int(num) => Type::Integer(*num as i128),
+        Type::Long(num) => Type::Integer(*num as i128),
+        Type::PositiveInteger(num) |
+        Type::NonNegativeInteger(num) |
+        Type::NonPositiveInteger(num) |
+        Type::NegativeInteger(num) => Type::Integer(*num),
+
+        Type::Decimal(num) => decimal_form(num),
+        Type::Float(num) => double_form(num.0 as f64),
+        Type::Double(num) => double_form(num.0),
+
+        Type::DateTime { dt, offset: true } => {
+            Type::DateTime { dt: dt.with_timezone(&FixedOffset::east(0)), offset: true }
+        }
+        Type::Time { time, offset: true } => {
+            let utc = time.time.overflowing_sub_signed(
+                chrono::Duration::seconds(time.offset.local_minus_utc() as i64)
+            ).0;
+            Type::Time { time: Time::from_utc(utc), offset: true }
+        }
+
+        _ => key.clone()
+    }
+}
+
+fn g_form(key: &Type, year: i32, month: u32, day: u32, tz_m: i32) -> KeyForm {
+    match NaiveDate::from_ymd_opt(year, month, day).and_then(|date| date.and_hms_opt(0, 0, 0)) {
+        Some(dt) => KeyForm::Instant(discriminant(key), dt - Duration::minutes(tz_m as i64)),
+        None => KeyForm::Value(key.clone())
+    }
+}
+
+// fraction of second is kept in the same unit as in duration arithmetic
+fn duration_form(positive: bool, years: u32, months: u32, (days, hours, minutes, seconds, fraction): (u32, u32, u32, u32, u32)) -> KeyForm {
+    let months = years as i64 * 12 + months as i64;
+    let ms = fraction as i128 + 1000 * (seconds as i128 + 60 * (minutes as i128 + 60 * (hours as i128 + 24 * days as i128)));
+    if positive {
+        KeyForm::Duration(months, ms)
+    } else {
+        KeyForm::Duration(-months, -ms)
+    }
+}
+
+fn decimal_form(num: &BigDecimal) -> Type {
+    if num.is_integer() {
+        let (digits, _) = num.with_scale(0).as_bigint_and_exponent();
+        return Type::integer(digits);
+    }
+    Type::Decimal(num.normalized())
+}
+
+fn double_form(num: f64) -> Type {
+    if num.is_finite() {
+        decimal_form(&exact_decimal(num))
+    } else {
+        Type::Double(OrderedFloat(num))
+    }
+}
+
+// exact value of finite double, so 0.1e0 is not same key as 0.1
+fn exact_decimal(num: f64) -> BigDecimal {
+    let bits = num.to_bits();
+    let exponent = ((bits >> 52) & 0x7ff) as i64;
+    let mantissa = if exponent == 0 {
+        (bits & 0xfffffffffffff) << 1
+    } else {
+        (bits & 0xfffffffffffff) | 0x10000000000000
+    };
+    let exponent = exponent - 1075;
+
+    let mut int_val = BigInt::from(mantissa);
+    if bits >> 63 == 1 {
+        int_val = -int_val;
+    }
+
+    if exponent >= 0 {
+        BigDecimal::new(int_val << exponent as usize, 0)
+    } else {
+        let scale = -exponent;
+        BigDecimal::new(int_val * BigInt::from(5).pow(scale as u32), scale)
+    }
+}
+
+impl OrderedMap {
+    pub fn new() -> Self {
+        OrderedMap::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &Type) -> Option<&Object> {
+        self.entries.get(&key_form(key)).map(|(_, _, value)| value)
+    }
+
+    pub fn contains_key(&self, key: &Type) -> bool {
+        self.entries.contains_key(&key_form(key))
+    }
+
+    // replaced entry keeps its position
+    pub fn insert(&mut self, key: Type, value: Object) {
+        let form = key_form(&key);
+        let position = match self.entries.get(&form) {
+            Some((position, _, _)) => *position,
+            None => {
+                self.sequence += 1;
+                self.order.insert(self.sequence, form.clone());
+                self.sequence
+            }
+        };
+        self.entries.insert(form, (position, key, value));
+    }
+
+    pub fn update(&self, key: Type, value: Object) -> Self {
+        let mut map = self.clone();
+        map.insert(key, value);
+        map
+    }
+
+    pub fn remove(&mut self, key: &Type) -> Option<Object> {
+        let (position, _, value) = self.entries.remove(&key_form(key))?;
+        self.order.remove(&position);
+        Some(value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Type, &Object)> {
+        self.order.values()
+            .map(move |form| {
+                let (_, key, value) = &self.entries[form];
+                (key, value)
+            })
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Type> {
+        self.iter().map(|(key, _)| key)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Object> {
+        self.iter().map(|(_, value)| value)
+    }
+}
+
+impl FromIterator<(Type, Object)> for OrderedMap {
+    fn from_iter<I: IntoIterator<Item = (Type, Object)>>(iter: I) -> Self {
+        let mut map = OrderedMap::new();
+        for (key, value) in iter {
+            map.insert(key, value);
+        }
+        map
+    }
+}
+
+// order of entries doesn't matter for equality
+impl PartialEq for OrderedMap {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self.iter().all(|(key, value)| other.get(key) == Some(value))
+    }
+}
+
+impl Debug for OrderedMap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
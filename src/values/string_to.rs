@@ -1,4 +1,5 @@
 use bigdecimal::{BigDecimal, Zero};
+use bigdecimal::num_bigint::BigInt;
 use nom::bytes::complete::tag;
 use nom::combinator::all_consuming;
 use nom::error::Error;
@@ -152,9 +153,12 @@ pub(crate) fn negative_integer(str: &str) -> Result<Type, ErrorInfo> {
 }
 
 pub(crate) fn integer(str: &str) -> Result<Type, ErrorInfo> {
-    match str.trim().parse() {
+    match str.trim().parse::<i128>() {
         Ok(num) => Ok(Type::Integer(num)),
-        Err(_) => Err((ErrorCode::FORG0001, format!("can't convert to integer {:?}", str)))
+        Err(_) => match str.trim().parse::<BigInt>() {
+            Ok(num) => Ok(Type::integer(num)),
+            Err(_) => Err((ErrorCode::FORG0001, format!("can't convert to integer {:?}", str)))
+        }
     }
 }
 
@@ -4,16 +4,18 @@ use std::convert::{Infallible, TryFrom};
 use std::fmt;
 use std::fmt::{Write, Debug, Formatter};
 use base64::DecodeError;
-use crate::values::{QName, QNameResolved};
+use im_rc::Vector;
+use crate::values::{OrderedMap, QName, QNameResolved};
 use crate::fns::Param;
 use crate::parser::op::{Representation};
 use crate::parser::errors::ErrorCode;
 use ordered_float::OrderedFloat;
 use bigdecimal::{BigDecimal, FromPrimitive, ToPrimitive, Zero};
+use bigdecimal::num_bigint::BigInt;
 use bigdecimal::num_traits::real::Real;
 use crate::eval::helpers::sort_and_dedup;
 use crate::eval::expression::Expression;
-use chrono::{Date, Datelike, DateTime, FixedOffset, Local, Offset, TimeZone};
+use chrono::{Date, Datelike, DateTime, FixedOffset, Local, Offset, TimeZone, Utc};
 use hex::FromHexError;
 use nom::bytes::complete::tag;
 use nom::character::complete::{alpha1, alphanumeric1};
@@ -152,6 +154,53 @@ impl Types {
             false
         }
     }
+    // type this one is derived from by restriction, numeric types are members of xs:numeric union
+    pub(crate) fn base(&self) -> Option<Types> {
+        match self {
+            Types::NormalizedString => Some(Types::String),
+            Types::Token => Some(Types::NormalizedString),
+            Types::Language |
+            Types::NMTOKEN |
+            Types::Name => Some(Types::Token),
+            Types::NCName => Some(Types::Name),
+            Types::ID |
+            Types::IDREF |
+            Types::ENTITY => Some(Types::NCName),
+
+            Types::Decimal |
+            Types::Float |
+            Types::Double => Some(Types::Numeric),
+            Types::Integer => Some(Types::Decimal),
+            Types::NonPositiveInteger |
+            Types::NonNegativeInteger |
+            Types::Long => Some(Types::Integer),
+            Types::NegativeInteger => Some(Types::NonPositiveInteger),
+            Types::PositiveInteger |
+            Types::UnsignedLong => Some(Types::NonNegativeInteger),
+            Types::Int => Some(Types::Long),
+            Types::Short => Some(Types::Int),
+            Types::Byte => Some(Types::Short),
+            Types::UnsignedInt => Some(Types::UnsignedLong),
+            Types::UnsignedShort => Some(Types::UnsignedInt),
+            Types::UnsignedByte => Some(Types::UnsignedShort),
+
+            Types::DateTimeStamp => Some(Types::DateTime),
+            Types::YearMonthDuration |
+            Types::DayTimeDuration => Some(Types::Duration),
+            _ => None
+        }
+    }
+
+    pub(crate) fn derives_from(&self, other: &Types) -> bool {
+        let mut current = Some(self.clone());
+        while let Some(t) = current {
+            if &t == other {
+                return true;
+            }
+            current = t.base();
+        }
+        false
+    }
 }
 
 #[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Hash)]
@@ -180,6 +229,8 @@ pub enum Type {
     NegativeInteger(i128),
 
     Integer(i128),
+    // xs:integer out of i128 range, smaller values are always Integer
+    BigInteger(BigInt),
     Decimal(BigDecimal),
     Float(OrderedFloat<f32>),
     Double(OrderedFloat<f64>),
@@ -221,22 +272,30 @@ pub enum Type {
 
 impl Type {
 
-    pub(crate) fn date_time_now() -> Type {
-        let now = Local::now();
-        let dt = DateTime::from_utc(now.naive_utc(), TimeZone::from_offset(now.offset()));
+    // xs:integer value, it's Integer whenever number fits i128
+    pub(crate) fn integer(number: BigInt) -> Type {
+        match number.to_i128() {
+            Some(number) => Type::Integer(number),
+            None => Type::BigInteger(number)
+        }
+    }
+
+    pub(crate) fn date_time_now(timezone: &FixedOffset) -> Type {
+        let dt = Utc::now().with_timezone(timezone);
 
         Type::DateTime { dt, offset: true }
     }
 
-    pub(crate) fn date_now() -> Type {
-        let now = Local::now();
-        let date = Date::from_utc(now.date().naive_utc(), TimeZone::from_offset(now.offset()));
+    pub(crate) fn date_now(timezone: &FixedOffset) -> Type {
+        let date = Utc::now().with_timezone(timezone).date();
 
         Type::Date { date, offset: true }
     }
 
-    pub(crate) fn time_now() -> Type {
-        Type::Time { time: Time::now(), offset: true }
+    pub(crate) fn time_now(timezone: &FixedOffset) -> Type {
+        let now = Utc::now().with_timezone(timezone);
+
+        Type::Time { time: Time::from(now.time(), *timezone), offset: true }
     }
 
     pub(crate) fn to_type(&self) -> Types {
@@ -264,6 +323,7 @@ impl Type {
             Type::NonNegativeInteger(_) => Types::NonNegativeInteger,
 
             Type::Integer(_) => Types::Integer,
+            Type::BigInteger(_) => Types::Integer,
             Type::Decimal(_) => Types::Decimal,
             Type::Float(_) => Types::Float,
             Type::Double(_) => Types::Double,
@@ -2491,6 +2551,40 @@ impl Type {
                     _ => Err((ErrorCode::XPTY0004, format!("can't convert Integer {:?} to {:?}", number, to)))
                 }
             }
+            Type::BigInteger(number) => {
+                match to {
+                    Types::Untyped => Ok(Type::Untyped(number.to_string())),
+                    Types::String => Ok(Type::String(number.to_string())),
+                    Types::NormalizedString => Ok(Type::NormalizedString(number.to_string())),
+                    Types::Token => Ok(Type::Token(number.to_string())),
+                    Types::Boolean => Ok(Type::Boolean(!number.is_zero())),
+
+                    Types::Numeric |
+                    Types::Integer => Ok(Type::BigInteger(number.clone())),
+                    Types::Decimal => Ok(Type::Decimal(BigDecimal::from(number.clone()))),
+                    Types::Float => {
+                        match number.to_f32() {
+                            Some(number) => Ok(Type::Float(OrderedFloat::from(number))),
+                            None => Err((ErrorCode::FOCA0002, String::from("TODO")))
+                        }
+                    },
+                    Types::Double => {
+                        match number.to_f64() {
+                            Some(number) => Ok(Type::Double(OrderedFloat::from(number))),
+                            None => Err((ErrorCode::FOCA0002, String::from("TODO")))
+                        }
+                    },
+
+                    // too big for any derived type
+                    Types::UnsignedLong | Types::UnsignedInt | Types::UnsignedShort | Types::UnsignedByte |
+                    Types::Long | Types::Int | Types::Short | Types::Byte |
+                    Types::PositiveInteger | Types::NonNegativeInteger |
+                    Types::NonPositiveInteger | Types::NegativeInteger => {
+                        Err((ErrorCode::FOCA0002, format!("{} is out of range of {:?}", number, to)))
+                    }
+                    _ => Err((ErrorCode::XPTY0004, format!("can't convert Integer {:?} to {:?}", number, to)))
+                }
+            }
             Type::Decimal(number) => {
                 match to {
                     Types::Untyped => {
@@ -2610,15 +2704,12 @@ impl Type {
                     },
 
                     Types::Integer => {
-                        if let Some(num) = number.to_i128() {
-                            Ok(Type::Integer(num))
-                        } else {
-                            Err((ErrorCode::FOCA0002, String::from("TODO")))
-                        }
+                        let (digits, _) = number.with_scale(0).as_bigint_and_exponent();
+                        Ok(Type::integer(digits))
                     },
                     Types::Decimal => Ok((Type::Decimal(number.clone()))),
                     Types::Float => {
-                        match number.to_f32() {
+                        match number.to_string().parse::<f32>().ok() {
                             Some(number) => {
                                 let number = OrderedFloat::from(number);
                                 Ok(Type::Float(number))
@@ -2627,7 +2718,7 @@ impl Type {
                         }
                     },
                     Types::Double => {
-                        match number.to_f64() {
+                        match number.to_string().parse::<f64>().ok() {
                             Some(number) => {
                                 let number = OrderedFloat::from(number);
                                 Ok(Type::Double(number))
@@ -2641,11 +2732,11 @@ impl Type {
             Type::Float(number) => {
                 match to {
                     Types::Untyped => {
-                        let data = float_to_string(number, false);
+                        let data = float_to_string(number);
                         Ok(Type::Untyped(data))
                     }
                     Types::String => {
-                        let data = float_to_string(number, false);
+                        let data = float_to_string(number);
                         Ok(Type::String(data))
                     }
                     Types::Language => Err((ErrorCode::FORG0001, String::from("TODO"))),
@@ -2815,6 +2906,9 @@ impl Type {
                         if number.is_zero()|| number.is_normal() {
                             if let Some(num) = number.0.to_i128() {
                                 Ok((Type::Integer(num)))
+                            } else if let Some(num) = BigDecimal::from_f64(number.0 as f64) {
+                                let (digits, _) = num.with_scale(0).as_bigint_and_exponent();
+                                Ok(Type::integer(digits))
                             } else {
                                 Err((ErrorCode::FOCA0002, String::from("TODO")))
                             }
@@ -2827,7 +2921,7 @@ impl Type {
                             Err((ErrorCode::FOCA0002, String::from("TODO")))
                         } else {
                             match BigDecimal::from_f32(number.into_inner()) {
-                                Some(number) => Ok((Type::Decimal(number))),
+                                Some(number) => Ok(Type::Decimal(number.normalized())),
                                 None => Err((ErrorCode::FORG0001, String::from("TODO")))
                             }
                         }
@@ -2840,11 +2934,11 @@ impl Type {
             Type::Double(number) => {
                 match to {
                     Types::Untyped => {
-                        let data = double_to_string(number, true);
+                        let data = double_to_string(number);
                         Ok(Type::Untyped(data))
                     }
                     Types::String => {
-                        let data = double_to_string(number, true);
+                        let data = double_to_string(number);
                         Ok(Type::String(data))
                     }
                     Types::Language => Err((ErrorCode::FORG0001, String::from("TODO"))),
@@ -3014,6 +3108,9 @@ impl Type {
                         if number.is_normal() || number.is_zero() {
                             if let Some(num) = number.0.to_i128() {
                                 Ok((Type::Integer(num)))
+                            } else if let Some(num) = BigDecimal::from_f64(number.0) {
+                                let (digits, _) = num.with_scale(0).as_bigint_and_exponent();
+                                Ok(Type::integer(digits))
                             } else {
                                 Err((ErrorCode::FOCA0002, String::from("TODO")))
                             }
@@ -3026,7 +3123,7 @@ impl Type {
                             Err((ErrorCode::FOCA0002, String::from("TODO")))
                         } else {
                             match BigDecimal::from_f64(number.into_inner()) {
-                                Some(number) => Ok((Type::Decimal(number))),
+                                Some(number) => Ok(Type::Decimal(number.normalized())),
                                 None => Err((ErrorCode::FORG0001, String::from("TODO")))
                             }
                         }
@@ -3355,6 +3452,7 @@ impl Type {
             }
             Type::QName { url, prefix, local_part } => {
                 match to {
+                    Types::Untyped => Ok(Type::Untyped(qname_to_string(prefix, local_part))),
                     Types::String => {
                         let str = qname_to_string(prefix, local_part);
                         Ok(Type::String(str))
@@ -3700,6 +3798,7 @@ impl Type {
     fn to_decimal(&self) -> Option<BigDecimal> {
         match self {
             Type::Integer(number) => BigDecimal::from_i128(*number),
+            Type::BigInteger(number) => Some(BigDecimal::from(number.clone())),
             Type::Decimal(number) => Some(number.clone()),
             Type::Float(number) => {
                 BigDecimal::from_f32(number.into_inner())
@@ -3714,8 +3813,10 @@ impl Type {
     fn to_float(&self) -> Option<OrderedFloat<f32>> {
         match self {
             Type::Integer(number) => OrderedFloat::from_i128(*number),
+            Type::BigInteger(number) => number.to_f32().map(OrderedFloat::from),
             Type::Decimal(number) => {
-                if let Some(number) = number.to_f32() {
+                // parse decimal digits to get the nearest value, to_f32() may lose precision
+                if let Ok(number) = number.to_string().parse::<f32>() {
                     OrderedFloat::from_f32(number)
                 } else {
                     None
@@ -3736,8 +3837,10 @@ impl Type {
     fn to_double(&self) -> Option<OrderedFloat<f64>> {
         match self {
             Type::Integer(number) => OrderedFloat::from_i128(*number),
+            Type::BigInteger(number) => number.to_f64().map(OrderedFloat::from),
             Type::Decimal(number) => {
-                if let Some(number) = number.to_f64() {
+                // parse decimal digits to get the nearest value, to_f64() may lose precision
+                if let Ok(number) = number.to_string().parse::<f64>() {
                     OrderedFloat::from_f64(number)
                 } else {
                     None
@@ -3755,7 +3858,7 @@ impl Type {
         }
     }
 
-    fn is_nan(&self) -> bool {
+    pub(crate) fn is_nan(&self) -> bool {
         match self {
             Type::Float(num) => num.is_nan(),
             Type::Double(num) => num.is_nan(),
@@ -3786,6 +3889,7 @@ impl Type {
             Type::NegativeInteger(_) |
 
             Type::Integer(_) |
+            Type::BigInteger(_) |
             Type::Decimal(_) |
             Type::Float(_) |
             Type::Double(_) => Types::Numeric,
@@ -3898,6 +4002,7 @@ impl Type {
             Type::NegativeInteger(_) |
 
             Type::Integer(_) |
+            Type::BigInteger(_) |
             Type::Decimal(_) |
             Type::Float(_) |
             Type::Double(_) => {
@@ -3984,6 +4089,12 @@ impl Type {
                                 return Ok(ValueOrdering::from(left_num.cmp(&right_num)));
                             }
                         }
+                        // one of them is big integer
+                        if let Some(left_num) = self.to_decimal() {
+                            if let Some(right_num) = other.to_decimal() {
+                                return Ok(ValueOrdering::from(left_num.cmp(&right_num)));
+                            }
+                        }
                         return Err((ErrorCode::XPTY0004, String::from("TODO")));
                     },
                     Types::Decimal => {
@@ -4228,8 +4339,8 @@ pub enum Object {
     Atomic(Type),
     Node(Reference),
 
-    Array(Vec<Object>),
-    Map(HashMap<Type, Object>),
+    Array(Vector<Object>),
+    Map(OrderedMap),
 
     Function { parameters: Vec<Param>, st: Option<SequenceType>, body: Box<dyn Expression> },
     FunctionRef { name: QNameResolved, arity: usize },
@@ -4271,6 +4382,7 @@ impl Object {
                     Type::NegativeInteger(number) => Ok(*number != 0),
 
                     Type::Integer(number) => Ok(!number.is_zero()),
+                    Type::BigInteger(number) => Ok(!number.is_zero()),
                     Type::Decimal(number) => Ok(!number.is_zero()),
                     Type::Float(number) => Ok(!(number.is_nan() || number.is_zero())),
                     Type::Double(number) => Ok(!(number.is_nan() || number.is_zero())),
@@ -4301,6 +4413,8 @@ impl Object {
         match self {
             Object::Empty => (Box::new(std::iter::empty()), 0),
             Object::Node(_) |
+            Object::Function { .. } |
+            Object::FunctionRef { .. } |
             Object::Array(_) |
             Object::Map(_) |
             Object::Atomic(_) => (Box::new(std::iter::once(self)), 1),
@@ -4378,12 +4492,17 @@ impl Object {
                 Ok(str)
             },
             Object::Node(rf) => {
-                match rf.to_typed_value() {
+                match rf.to_string_value() {
                     Ok(str) => Ok(str),
                     Err(msg) => Err((ErrorCode::XPTY0004, format!("can't convert to String: {}", msg)))
                 }
             },
-            Object::Array(items) |
+            Object::Array(items) => {
+                let buf = items.iter()
+                    .map(|item| item.to_string())
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(buf.join(" "))
+            }
             Object::Sequence(items) => {
                 let mut buf = Vec::with_capacity(items.len());
                 for item in items {
@@ -4425,7 +4544,7 @@ impl Object {
                 }
             },
             Object::Node(rf) => {
-                match rf.to_typed_value() {
+                match rf.to_string_value() {
                     Ok(num) => {
                         match num.parse() {
                             Ok(v) => Ok(v),
@@ -4688,13 +4807,7 @@ pub fn atomization_of_vec(env: &Box<Environment>, items: Vec<Object>) -> Result<
 pub(crate) fn atomization(env: &Box<Environment>, obj: Object) -> Result<Object, ErrorInfo> {
     match obj {
         Object::Atomic(_) => Ok(obj),
-        Object::Node(rf) => {
-            match rf.to_typed_value() {
-                Ok(data) => Ok(Object::Atomic(Type::Untyped(data))),
-                Err(msg) => Err((ErrorCode::TODO, msg))
-            }
-
-        },
+        Object::Node(rf) => typed_value_of_node(&rf),
         Object::Sequence(items) => atomization_of_vec(env, items),
         Object::Range { min, max } => {
             if min == max {
@@ -4718,17 +4831,28 @@ pub(crate) fn sequence_atomization(env: &Box<Environment>, obj: Object) -> Resul
         Object::Array(_) |
         Object::Sequence(_) |
         Object::Atomic(_) => Ok(obj),
-        Object::Node(rf) => {
-            match rf.to_typed_value() {
-                Ok(data) => Ok(Object::Atomic(Type::Untyped(data))),
-                Err(msg) => Err((ErrorCode::TODO, msg))
-            }
-        },
+        Object::Node(rf) => typed_value_of_node(&rf),
         Object::Empty => Ok(obj), // or it can be XPST0005?
         _ => todo!()
     }
 }
 
+// typed value of a node as single item, empty or sequence of atomic values (list types)
+pub(crate) fn typed_value_of_node(rf: &Reference) -> Result<Object, ErrorInfo> {
+    let mut items: Vec<Object> = rf.to_typed_value()?
+        .into_iter()
+        .map(Object::Atomic)
+        .collect();
+
+    if items.len() == 1 {
+        Ok(items.remove(0))
+    } else if items.is_empty() {
+        Ok(Object::Empty)
+    } else {
+        Ok(Object::Sequence(items))
+    }
+}
+
 pub(crate) fn normalizing_string(str: &String) -> String {
     str.trim_matches(|c| c == ' ' || c == '\t' || c == '\n' || c == '\r')
         .replace("\t", " ")
@@ -4740,11 +4864,9 @@ pub(crate) fn string_to_any_uri(str: &String) -> Result<String, ErrorInfo> {
     let str = str.trim();
     // workaround for ':' case
     if !str.starts_with(":") {
-        match URIReference::try_from(str) {
-            Ok(uri) => {
-                return Ok(uri.to_string())
-            },
-            Err(_) => {}
+        // keep lexical form, parsed reference is normalized (e.g. "http://x" to "http://x/")
+        if URIReference::try_from(str).is_ok() {
+            return Ok(str.to_string())
         }
     }
     Err(ErrorCode::forg0001(&str.to_string(), Types::AnyURI))